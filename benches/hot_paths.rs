@@ -0,0 +1,101 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::{ImageBuffer, Rgba};
+use primitive_image::cubic_bezier::CubicBezier;
+use primitive_image::ellipse::Ellipse;
+use primitive_image::primitive_image::PrimitiveImage;
+use primitive_image::quadratic_bezier::QuadraticBezier;
+use primitive_image::rectangle::Rectangle;
+use primitive_image::runner::run;
+use primitive_image::score_cache::ScoreCache;
+use primitive_image::shape::{RandomShape, Shape, ShapeType};
+use primitive_image::triangle::Triangle;
+use primitive_image::utilities::{get_rng, SizeLimits};
+
+const SIZE: u32 = 100;
+const SEED: u64 = 42;
+const BORDER_EXTENSION: i32 = 6;
+
+/// A fixed gradient, so that every run scores the same pixels
+fn target() -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    ImageBuffer::from_fn(SIZE, SIZE, |x, y| {
+        Rgba([
+            (x * 255 / SIZE) as u8,
+            (y * 255 / SIZE) as u8,
+            ((x + y) * 255 / (2 * SIZE)) as u8,
+            255,
+        ])
+    })
+}
+
+fn bench_shape<T>(c: &mut Criterion, name: &str)
+where
+    T: RandomShape + Shape,
+{
+    let mut rng = get_rng(SEED);
+    let shape = T::random(
+        SIZE,
+        SIZE,
+        BORDER_EXTENSION,
+        SizeLimits::default(),
+        &mut rng,
+    );
+    let canvas = target();
+
+    c.bench_function(&format!("{} get_pixels", name), |b| {
+        b.iter(|| black_box(shape.get_pixels()))
+    });
+    c.bench_function(&format!("{} paint_on", name), |b| {
+        b.iter(|| black_box(shape.paint_on(&canvas)))
+    });
+
+    // A hit skips painting and scoring, so it should cost a small fraction of `paint_on`
+    let mut cache = ScoreCache::new(64);
+    cache.insert(shape.canonical_key(), (0.0, 0.0));
+    c.bench_function(&format!("{} score cache hit", name), |b| {
+        b.iter(|| black_box(cache.get(shape.canonical_key())))
+    });
+}
+
+fn shapes(c: &mut Criterion) {
+    bench_shape::<Triangle>(c, "triangle");
+    bench_shape::<Ellipse>(c, "ellipse");
+    bench_shape::<Rectangle>(c, "rectangle");
+    bench_shape::<QuadraticBezier>(c, "quadratic");
+    bench_shape::<CubicBezier>(c, "cubic");
+}
+
+fn score(c: &mut Criterion) {
+    let image = PrimitiveImage::from_image(target(), 0, None);
+
+    c.bench_function("score", |b| b.iter(|| black_box(image.score())));
+}
+
+fn add_new_shape(c: &mut Criterion) {
+    let image = PrimitiveImage::from_image(target(), 0, None);
+
+    c.bench_function("add_new_shape triangle", |b| {
+        b.iter(|| {
+            let mut image = image.clone();
+            let mut rng = get_rng(SEED);
+            black_box(image.add_new_shape::<Triangle>(100, &mut rng))
+        })
+    });
+}
+
+fn full_run(c: &mut Criterion) {
+    let image = PrimitiveImage::from_image(target(), 0, None);
+
+    let mut group = c.benchmark_group("full run");
+    group.sample_size(10);
+    group.bench_function("100 triangles", |b| {
+        b.iter(|| {
+            let mut image = image.clone();
+            run(&mut image, 100, 100, SEED, ShapeType::Triangle);
+            black_box(image)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, shapes, score, add_new_shape, full_run);
+criterion_main!(benches);
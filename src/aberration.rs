@@ -0,0 +1,122 @@
+use image::{ImageBuffer, Rgba};
+
+/// The channels `aberration_svg` splits the approximation into, with the direction each is moved
+const CHANNELS: [(&str, &str, i32); 3] = [
+    ("red", "1 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 1 0", -1),
+    ("green", "0 0 0 0 0 0 1 0 0 0 0 0 0 0 0 0 0 0 1 0", 0),
+    ("blue", "0 0 0 0 0 0 0 0 0 0 0 0 1 0 0 0 0 0 1 0", 1),
+];
+
+///
+/// Returns `image` with its red channel moved `offset` pixels left and its blue channel moved
+/// `offset` pixels right, for a chromatic aberration look
+///
+/// Pixels moved in from past the edges repeat the edge. Green and alpha stay put
+///
+pub fn split_channels(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    offset: u32,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let width = image.width() as i64;
+    let offset = offset as i64;
+    let source_x =
+        |x: u32, direction: i64| (x as i64 - direction * offset).clamp(0, width - 1) as u32;
+
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y);
+
+        Rgba([
+            image.get_pixel(source_x(x, -1), y)[0],
+            pixel[1],
+            image.get_pixel(source_x(x, 1), y)[2],
+            pixel[3],
+        ])
+    })
+}
+
+///
+/// Returns SVG showing `content` with its channels moved like `split_channels`
+///
+/// Each channel is a filtered copy of `content`, moved `offset` pixels. The copies are screened
+/// over black, which adds them back together since they don't share channels
+///
+pub fn aberration_svg(content: &str, offset: u32, width: u32, height: u32) -> String {
+    format!(
+        "{}{}{}",
+        aberration_svg_start(),
+        content,
+        aberration_svg_end(offset, width, height)
+    )
+}
+
+///
+/// Returns the SVG that goes before the content in `aberration_svg`, so the content can be
+/// written between this and `aberration_svg_end` without building it as one string
+///
+pub fn aberration_svg_start() -> String {
+    let filters: String = CHANNELS
+        .iter()
+        .map(|(name, matrix, _)| {
+            format!(
+                "<filter id=\"{}-channel\" color-interpolation-filters=\"sRGB\"><feColorMatrix type=\"matrix\" values=\"{}\"/></filter>",
+                name, matrix
+            )
+        })
+        .collect();
+
+    format!("<defs>{}<g id=\"approximation\">", filters)
+}
+
+///
+/// Returns the SVG that goes after the content in `aberration_svg`
+///
+pub fn aberration_svg_end(offset: u32, width: u32, height: u32) -> String {
+    let copies: String = CHANNELS
+        .iter()
+        .map(|(name, _, direction)| {
+            format!(
+                "<g transform=\"translate({},0)\" filter=\"url(#{}-channel)\" style=\"mix-blend-mode:screen\"><use href=\"#approximation\"/></g>",
+                direction * offset as i32,
+                name
+            )
+        })
+        .collect();
+
+    format!(
+        "</g></defs><rect x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"#000000\" /><g style=\"isolation:isolate\">{}</g>",
+        width, height, copies
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_channels() {
+        let image = ImageBuffer::from_fn(5, 1, |x, _| {
+            let value = (x * 50) as u8;
+            Rgba([value, value, value, 255])
+        });
+        let split = split_channels(&image, 1);
+
+        assert_eq!(split.get_pixel(2, 0), &Rgba([150, 100, 50, 255]));
+        // Edges repeat
+        assert_eq!(split.get_pixel(0, 0), &Rgba([50, 0, 0, 255]));
+        assert_eq!(split.get_pixel(4, 0), &Rgba([200, 200, 150, 255]));
+
+        assert_eq!(split_channels(&image, 0), image);
+    }
+
+    #[test]
+    fn test_aberration_svg() {
+        let svg = aberration_svg("<path d=\"M0,0 L1,1\"/>", 3, 10, 20);
+
+        assert!(svg.contains("<g id=\"approximation\"><path d=\"M0,0 L1,1\"/></g>"));
+        assert!(svg.contains("width=\"10\" height=\"20\" fill=\"#000000\""));
+        assert!(svg.contains("translate(-3,0)\" filter=\"url(#red-channel)\""));
+        assert!(svg.contains("translate(0,0)\" filter=\"url(#green-channel)\""));
+        assert!(svg.contains("translate(3,0)\" filter=\"url(#blue-channel)\""));
+        assert_eq!(svg.matches("<use href=\"#approximation\"/>").count(), 3);
+    }
+}
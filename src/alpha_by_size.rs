@@ -0,0 +1,105 @@
+use std::str::FromStr;
+
+/// The curve's power when it isn't given. Below 1, alpha falls quickly as small shapes grow
+const DEFAULT_POWER: f64 = 0.5;
+
+///
+/// Shape alpha that depends on the shape's area, so large shapes can be more transparent and small
+/// shapes more opaque
+///
+/// Alpha goes from `smallest` for shapes with no area to `largest` for shapes covering the whole
+/// image, following the fraction of the image covered raised to `power`
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AlphaBySize {
+    pub smallest: u8,
+    pub largest: u8,
+    pub power: f64,
+}
+
+impl AlphaBySize {
+    ///
+    /// Returns the alpha for a shape with `area` in a `width` x `height` image
+    ///
+    pub fn alpha(&self, area: f64, width: u32, height: u32) -> u8 {
+        let fraction = (area / (width as f64 * height as f64)).clamp(0.0, 1.0);
+        let t = fraction.powf(self.power);
+
+        (self.smallest as f64 + (self.largest as f64 - self.smallest as f64) * t).round() as u8
+    }
+}
+
+impl FromStr for AlphaBySize {
+    type Err = String;
+
+    ///
+    /// Parse "smallest,largest" or "smallest,largest,power", like "220,60" or "220,60,1"
+    ///
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(',').map(|part| part.trim()).collect();
+        let error = || format!("Unsupported alpha by size: {}", s);
+
+        let (smallest, largest, power) = match parts[..] {
+            [smallest, largest] => (smallest, largest, None),
+            [smallest, largest, power] => (smallest, largest, Some(power)),
+            _ => return Err(error()),
+        };
+
+        let power = match power {
+            Some(power) => power.parse::<f64>().map_err(|_| error())?,
+            None => DEFAULT_POWER,
+        };
+        if power <= 0.0 || !power.is_finite() {
+            return Err(error());
+        }
+
+        Ok(AlphaBySize {
+            smallest: smallest.parse().map_err(|_| error())?,
+            largest: largest.parse().map_err(|_| error())?,
+            power,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alpha() {
+        let alpha_by_size = AlphaBySize {
+            smallest: 200,
+            largest: 40,
+            power: 1.0,
+        };
+
+        assert_eq!(alpha_by_size.alpha(0.0, 10, 10), 200);
+        assert_eq!(alpha_by_size.alpha(50.0, 10, 10), 120);
+        assert_eq!(alpha_by_size.alpha(100.0, 10, 10), 40);
+        // Shapes hanging off the image can have more area than it
+        assert_eq!(alpha_by_size.alpha(500.0, 10, 10), 40);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(
+            "220,60".parse(),
+            Ok(AlphaBySize {
+                smallest: 220,
+                largest: 60,
+                power: DEFAULT_POWER,
+            })
+        );
+        assert_eq!(
+            "220, 60, 2".parse(),
+            Ok(AlphaBySize {
+                smallest: 220,
+                largest: 60,
+                power: 2.0,
+            })
+        );
+        assert!("220".parse::<AlphaBySize>().is_err());
+        assert!("220,300".parse::<AlphaBySize>().is_err());
+        assert!("220,60,0".parse::<AlphaBySize>().is_err());
+    }
+}
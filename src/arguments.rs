@@ -1,5 +1,20 @@
 extern crate structopt;
 
+use primitive_image::alpha_by_size::AlphaBySize;
+use primitive_image::color_profile::ColorProfile;
+use primitive_image::color_sample::ColorSample;
+use primitive_image::fill_style::FillStyle;
+use primitive_image::flip::Flip;
+use primitive_image::mask::CropMask;
+use primitive_image::metrics::{ChannelWeights, Metric, ScoreRegion};
+use primitive_image::mutate_mode::MutateMode;
+use primitive_image::shape::ShapeType;
+use primitive_image::signature::Corner;
+use primitive_image::sort_order::SortOrder;
+use primitive_image::svg_format::SvgFormat;
+use primitive_image::symmetry::Symmetry;
+use primitive_image::utilities::AutoLevels;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -11,17 +26,20 @@ use structopt::StructOpt;
     rename_all = "kebab-case"
 )]
 pub struct Opt {
+    #[structopt(subcommand)]
+    pub cmd: Option<Command>,
+
     #[structopt(short = "i", long = "input", parse(from_os_str))]
-    /// Path to the image to be approximated (.jpg, .png, .tif, .gif, or .bmp)
-    pub in_path: PathBuf,
+    /// Path to the image to be approximated (.jpg, .png, .tif, .gif, or .bmp). Required without a subcommand
+    pub in_path: Option<PathBuf>,
 
     #[structopt(short = "o", long = "output", parse(from_os_str))]
-    /// Path to the output file (.jpg, .png, .bmp, .ico, .gif, or .svg)
-    pub out_path: PathBuf,
+    /// Path to the output file (.jpg, .png, .bmp, .ico, .gif, .svg, or .json). Required without a subcommand
+    pub out_path: Option<PathBuf>,
 
-    #[structopt(short = "n", default_value = "100")]
-    /// Number of polygons to use
-    pub n: u32,
+    #[structopt(short = "n")]
+    /// Number of polygons to use. Defaults to 100, and suggests a number for the image
+    pub n: Option<u32>,
 
     #[structopt(long, default_value = "100")]
     /// Maximum age for each hill climbing attempt
@@ -31,6 +49,26 @@ pub struct Opt {
     /// The value to scale the image's largest dimension to. <= 0 prevents scaling
     pub scale_to: u32,
 
+    #[structopt(long)]
+    /// Scale images smaller than --scale-to up to it, instead of leaving them at their own size
+    pub allow_upscale: bool,
+
+    #[structopt(long, default_value = "2000")]
+    /// Scale images down so the search runs at no more than this many pixels on the largest side, even with --scale-to 0. 0 for no limit
+    pub max_dimension: u32,
+
+    #[structopt(long, conflicts_with = "scale-to")]
+    /// Search at this fraction (0 to 1] of the image's size instead of --scale-to. Output is still full size
+    pub preview_scale: Option<f64>,
+
+    #[structopt(long, possible_values = &["horizontal", "vertical", "both"])]
+    /// Mirror the input image before approximating it, left to right (horizontal), top to bottom (vertical), or both
+    pub flip: Option<Flip>,
+
+    #[structopt(long, possible_values = &["rgb", "luma"])]
+    /// Stretch the input image's histogram to the full range before approximating it, for each channel (rgb) or by brightness (luma)
+    pub auto_levels: Option<AutoLevels>,
+
     #[structopt(long, default_value = "0")]
     /// The random seed. 0 picks a seed based on the time
     pub seed: u64,
@@ -43,7 +81,330 @@ pub struct Opt {
     /// Override the initial background color
     pub background_color: Option<String>,
 
-    #[structopt(long, default_value = "TRIANGLE", possible_values = &["TRIANGLE", "CUBIC", "QUADRATIC", "RECTANGLE", "ELLIPSE", "MIXED"])]
-    ///
-    pub shape: String,
+    #[structopt(long)]
+    /// Start from the target's most dominant color instead of its average, unless --background-color is given
+    pub dominant_background: bool,
+
+    #[structopt(long, conflicts_with = "background-color")]
+    /// Find this many dominant colors in the target, start from the most dominant one, and only color shapes with them
+    pub palette_extract: Option<NonZeroUsize>,
+
+    #[structopt(long)]
+    /// Try to start with a shape at each of these points in the input image, as "x,y;x,y"
+    pub seed_points: Option<String>,
+
+    #[structopt(long)]
+    /// Start with a rectangle covering the whole image, colored to match it, instead of searching for the first shape
+    pub seed_background_rect: bool,
+
+    #[structopt(long)]
+    /// Start with a rectangle in each cell of a grid with this many rows and columns, as "rows,cols"
+    pub seed_grid: Option<String>,
+
+    #[structopt(long)]
+    /// Print the names --shape takes and exit
+    pub list_shapes: bool,
+
+    #[structopt(long, default_value = "TRIANGLE")]
+    /// The type of shape to add, in any case. See --list-shapes for the names
+    pub shape: ShapeType,
+
+    #[structopt(long, possible_values = &["circle", "ellipse"])]
+    /// Crop the output to the circle or ellipse inscribed in it, leaving the rest transparent
+    pub crop_mask: Option<CropMask>,
+
+    #[structopt(long)]
+    /// Ignore pixels outside of the crop mask when scoring
+    pub crop_mask_score: bool,
+
+    #[structopt(long)]
+    /// Only approximate where the input's alpha is at least this (0-255), following the silhouette of transparent images
+    pub alpha_mask: Option<u8>,
+
+    #[structopt(long, default_value = "0")]
+    /// Minimum fraction of the image a shape's bounding box must cover during the coarse phase
+    pub min_shape_area_frac: f64,
+
+    #[structopt(long, default_value = "10")]
+    /// Number of shapes in the coarse phase. The minimum shape area decays to 0 over it
+    pub coarse_shapes: u32,
+
+    #[structopt(long, default_value = "0")]
+    /// Weight of the penalty for placing shapes where others already are. 0 disables it
+    pub overlap_penalty: f64,
+
+    #[structopt(long)]
+    /// Smallest size for shapes, as a fraction of the image's largest dimension
+    pub min_size: Option<f64>,
+
+    #[structopt(long)]
+    /// Largest size for shapes, as a fraction of the image's largest dimension
+    pub max_size: Option<f64>,
+
+    #[structopt(long, default_value = "single")]
+    /// How many of a shape's parameters each mutation changes: single, or multi for several at once with smaller steps
+    pub mutate_mode: MutateMode,
+
+    #[structopt(long)]
+    /// Don't rotate rectangles, ellipses, or other rotatable shapes, for tools that can't handle rotated SVG shapes
+    pub no_rotation: bool,
+
+    #[structopt(long, default_value = "0")]
+    /// The chance (0 to 1) of snapping each triangle vertex to the strongest edge nearby, so sides follow edges
+    pub edge_snap: f64,
+
+    #[structopt(long)]
+    /// Only use squares for rectangles, e.g. for mosaics
+    pub squares: bool,
+
+    #[structopt(long, default_value = "1")]
+    /// Width of curves (QUADRATIC, CUBIC, and PATH), in pixels of the scaled image
+    pub stroke_width: u32,
+
+    #[structopt(long, default_value = "0")]
+    /// Fade the alpha of RECTANGLE and ELLIPSE shapes out over this many pixels (of the scaled image) at their edges, softening seams where they overlap. 0 for hard edges
+    pub feather: u32,
+
+    #[structopt(long, alias = "restarts", default_value = "1")]
+    /// Number of random shapes to hill climb from for each added shape. The best one is kept (also accepted as --restarts)
+    pub pool: u32,
+
+    #[structopt(long, default_value = "1")]
+    /// Search for this many shapes against the same approximation, then add the best ones that don't overlap together
+    pub commit_batch: NonZeroU32,
+
+    #[structopt(long)]
+    /// Longest to hill climb for each shape, in seconds, before keeping the best found so far
+    pub time_budget_per_shape: Option<f64>,
+
+    #[structopt(long, default_value = "svg", possible_values = &["svg", "svg-path-merge", "svg-layers", "svg-templates"])]
+    /// How to write SVGs. svg-path-merge merges consecutive same-colored shapes into one path, svg-layers groups shapes by type, svg-templates defines repeated outlines once
+    pub format: SvgFormat,
+
+    #[structopt(long, default_value = "0")]
+    /// How far apart (per channel) colors can be and still be merged by svg-path-merge
+    pub merge_tolerance: u8,
+
+    #[structopt(long)]
+    /// Rasterize curves with exactly this many line segments, instead of basing it on their length
+    pub bezier_segments: Option<u32>,
+
+    #[structopt(long, default_value = "1")]
+    /// Minimum number of line segments to rasterize curves with
+    pub min_bezier_segments: u32,
+
+    #[structopt(long, default_value = "1000")]
+    /// Maximum number of line segments to rasterize curves with
+    pub max_bezier_segments: u32,
+
+    #[structopt(long)]
+    /// Keep at most this many shapes, removing the ones that help least after the run, for a smaller file
+    pub max_elements: Option<usize>,
+
+    #[structopt(long, possible_values = &["area-desc", "area-asc", "luminance-asc"])]
+    /// Repaint the shapes in this order after the run, instead of the order they were found in. Changes the result
+    pub paint_order: Option<SortOrder>,
+
+    #[structopt(long)]
+    /// Print how many distinct colors the shapes use, and the most frequent ones, after the run
+    pub stats: bool,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Save the run's seed, scores, per-shape improvements, time, and attempts to this JSON file
+    pub stats_json: Option<PathBuf>,
+
+    #[structopt(long)]
+    /// Print a line of JSON to stderr for each shape added, e.g. {"shape":1,"score":40.2,"type":"TRIANGLE"}, for other programs to follow the run
+    pub emit_events: bool,
+
+    #[structopt(long)]
+    /// Also save a preview whose largest dimension is this many pixels, next to the output
+    pub thumbnail: Option<u32>,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Also save the input image and the approximation side by side to this image file
+    pub compare_output: Option<PathBuf>,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Also save each shape as its own transparent PNG in this directory, numbered in paint order
+    pub layers_dir: Option<PathBuf>,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Also save the approximation after each shape as a numbered PNG in this directory, starting with just the background
+    pub frames_dir: Option<PathBuf>,
+
+    #[structopt(long)]
+    /// Physical resolution of the output, in dots per inch. Written to PNGs and SVGs
+    pub dpi: Option<f64>,
+
+    #[structopt(long)]
+    /// Colorspace to mark PNG output as being in (srgb or linear). Unmarked by default
+    pub color_profile: Option<ColorProfile>,
+
+    #[structopt(long, default_value = "0")]
+    /// Move the output's red channel this many pixels left and its blue channel right, for a glitchy look
+    pub aberration: u32,
+
+    #[structopt(long)]
+    /// Sign the output with this text, in a corner
+    pub signature: Option<String>,
+
+    #[structopt(long, default_value = "bottom-right", possible_values = &["top-left", "top-right", "bottom-left", "bottom-right"])]
+    /// The corner to sign the output in
+    pub signature_corner: Corner,
+
+    #[structopt(long, default_value = "0.8")]
+    /// Opacity of the signature, from 0 to 1
+    pub signature_opacity: f64,
+
+    #[structopt(long, default_value = "none", possible_values = &["none", "horizontal", "vertical"])]
+    /// Mirror every shape left to right (horizontal) or top to bottom (vertical). Curves aren't mirrored
+    pub symmetry: Symmetry,
+
+    #[structopt(long, default_value = "solid", possible_values = &["solid", "hatch"])]
+    /// Fill shapes solid, or with evenly spaced lines for a pen-and-ink look. Curves are unaffected
+    pub fill_style: FillStyle,
+
+    #[structopt(long)]
+    /// Color every shape this color (RRGGBB) instead of sampling the image, for a monochrome result
+    pub fixed_color: Option<String>,
+
+    #[structopt(long, default_value = "mean", possible_values = &["mean", "median", "mode"])]
+    /// How to pick each shape's color from the image under it: the mean, the per-channel median, or the most common color
+    pub color_sample: ColorSample,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Continue from the shapes in this .json file (from a previous run on the same image) instead of starting over
+    pub append: Option<PathBuf>,
+
+    #[structopt(long)]
+    /// Alpha (0-255) for triangles, instead of 128
+    pub alpha_triangle: Option<u8>,
+
+    #[structopt(long)]
+    /// Alpha (0-255) for cubic curves, instead of 128
+    pub alpha_cubic: Option<u8>,
+
+    #[structopt(long)]
+    /// Alpha (0-255) for quadratic curves, instead of 128
+    pub alpha_quadratic: Option<u8>,
+
+    #[structopt(long)]
+    /// Alpha (0-255) for rectangles, instead of 128
+    pub alpha_rectangle: Option<u8>,
+
+    #[structopt(long)]
+    /// Alpha (0-255) for ellipses, instead of 128
+    pub alpha_ellipse: Option<u8>,
+
+    #[structopt(long)]
+    /// Alpha (0-255) for hearts, instead of 128
+    pub alpha_heart: Option<u8>,
+
+    #[structopt(long)]
+    /// Alpha (0-255) for teardrops, instead of 128
+    pub alpha_teardrop: Option<u8>,
+
+    #[structopt(long)]
+    /// Alpha (0-255) for bezier paths, instead of 128
+    pub alpha_path: Option<u8>,
+
+    #[structopt(long)]
+    /// Alpha (0-255) for crosses, instead of 128
+    pub alpha_cross: Option<u8>,
+
+    #[structopt(long)]
+    /// Base each shape's alpha on its area instead, as "smallest,largest[,power]": alpha goes from smallest to largest as the fraction of the image covered (raised to power, default 0.5) goes from 0 to 1
+    pub alpha_by_size: Option<AlphaBySize>,
+
+    #[structopt(long)]
+    /// Reject shapes that would lower the approximation's contrast (the standard deviation of its luma, 0 to 127.5) below this
+    pub min_contrast: Option<f64>,
+
+    #[structopt(long)]
+    /// Include the alpha channel when scoring, instead of only red, green, and blue
+    pub score_alpha: bool,
+
+    #[structopt(long)]
+    /// Don't show a progress bar. It's also hidden with -v or when not run in a terminal
+    pub no_progress: bool,
+
+    #[structopt(long, default_value = "0")]
+    /// Number of mutations per hill climb that may be accepted without improving the score
+    pub plateau_moves: u32,
+
+    #[structopt(long)]
+    /// Reject shapes mostly over pixels whose error (RGB RMSE) is already below this
+    pub protect_converged: Option<f64>,
+
+    #[structopt(long, default_value = "0.5")]
+    /// Fraction of a shape's pixels that may be converged before --protect-converged rejects it
+    pub protected_overlap: f64,
+
+    #[structopt(long)]
+    /// Reject shapes that would cover any pixel with more than this many shapes
+    pub max_layers: Option<u32>,
+
+    #[structopt(long, default_value = "0")]
+    /// Weight errors near the center of the image more. 0 weights every pixel equally
+    pub center_bias: f64,
+
+    #[structopt(long)]
+    /// Only score this rectangle of the input, as x,y,width,height, while still rendering the whole image
+    pub score_region: Option<ScoreRegion>,
+
+    #[structopt(long, default_value = "1,1,1")]
+    /// How much the red, green, and blue errors count when scoring, as r,g,b
+    pub channel_weights: ChannelWeights,
+
+    #[structopt(long, default_value = "rgb", possible_values = &["rgb", "ycbcr"])]
+    /// The color space to measure error in. ycbcr weights luma (see --luma-weight) over chroma
+    pub metric: Metric,
+
+    #[structopt(long, default_value = "0.8")]
+    /// How much luma counts with --metric ycbcr, from 0 to 1. The two chroma channels split the rest
+    pub luma_weight: f64,
+
+    #[structopt(long, default_value = "1")]
+    /// Fraction of the pixels (picked using the seed) to compare candidate shapes on. Faster for large images
+    pub score_sample: f64,
+
+    #[structopt(long, default_value = "1000")]
+    /// Stop early after this many attempts in a row fail to add a shape
+    pub max_failed_attempts: u32,
+
+    #[structopt(long)]
+    /// Hide cracks between shapes in SVGs, by stroking them in their own color and rendering crisply
+    pub svg_seam_fix: bool,
+
+    #[structopt(long, possible_values = &["auto", "optimizeSpeed", "crispEdges", "geometricPrecision"])]
+    /// The SVG shape-rendering attribute for the shapes. Defaults to crispEdges with --svg-seam-fix
+    pub shape_rendering: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub enum Command {
+    /// Render a .json shape file without searching for new shapes
+    Render {
+        #[structopt(short = "i", long = "input", parse(from_os_str))]
+        /// Path to the .json shape file to render
+        in_path: PathBuf,
+
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        /// Path to the output file (.jpg, .png, .bmp, .ico, .gif, .svg, or .json)
+        out_path: PathBuf,
+
+        #[structopt(long)]
+        /// The size of the output's largest dimension. Defaults to the original image's size
+        size: Option<u32>,
+
+        #[structopt(long)]
+        /// Physical resolution of the output, in dots per inch. Written to PNGs and SVGs
+        dpi: Option<f64>,
+
+        #[structopt(long)]
+        /// Colorspace to mark PNG output as being in (srgb or linear). Unmarked by default
+        color_profile: Option<ColorProfile>,
+    },
 }
@@ -0,0 +1,384 @@
+use crate::cubic_bezier::CubicBezier;
+use crate::mutate_mode::MutateMode;
+use crate::point::PrimitivePoint;
+use crate::primitive_image::PrimitiveImage;
+use crate::shape::{shape_key, RandomShape, Shape, ShapeType};
+use crate::utilities::{
+    blend_with_coverage, default_stroke_width, points_size, rgb_to_hex, supersample_point,
+    supersampled_coverage, SegmentLimits, SizeLimits, SUPERSAMPLING,
+};
+use image::ImageBuffer;
+use image::Rgba;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::max;
+
+const MAXIMUM_MUTATION_ATTEMPTS: u32 = 100_000;
+/// Number of segments in random paths
+const PATH_SEGMENTS: usize = 3;
+
+///
+/// One cubic segment of a `BezierPath`
+///
+/// It starts where the previous segment ends (or at the path's start), so the path can't come
+/// apart
+///
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CubicSegment {
+    pub control1: PrimitivePoint,
+    pub control2: PrimitivePoint,
+    pub end: PrimitivePoint,
+}
+
+///
+/// Connected cubic bezier segments, stroked like `CubicBezier`, for following contours
+///
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct BezierPath {
+    #[serde(with = "crate::utilities::rgba_serde")]
+    pub color: image::Rgba<u8>,
+    pub start: PrimitivePoint,
+    pub segments: Vec<CubicSegment>,
+    #[serde(default = "default_stroke_width")]
+    pub width: u32,
+    #[serde(default)]
+    pub segment_limits: SegmentLimits,
+}
+
+impl BezierPath {
+    pub fn new(start: PrimitivePoint, segments: Vec<CubicSegment>) -> BezierPath {
+        if segments.is_empty() {
+            panic!("Bezier paths need at least one segment!");
+        }
+
+        BezierPath {
+            color: Rgba([0, 0, 0, 128]),
+            width: default_stroke_width(),
+            segment_limits: SegmentLimits::default(),
+            start,
+            segments,
+        }
+    }
+
+    ///
+    /// Returns this shape with its color set to `color`, for building shapes by hand
+    ///
+    pub fn with_color(mut self, color: Rgba<u8>) -> Self {
+        self.color = color;
+        self
+    }
+
+    ///
+    /// Returns each segment as its own curve, with the path's color, width, and segment limits
+    ///
+    pub fn curves(&self) -> Vec<CubicBezier> {
+        let mut start = self.start;
+
+        self.segments
+            .iter()
+            .map(|segment| {
+                let curve = CubicBezier {
+                    color: self.color,
+                    start,
+                    control1: segment.control1,
+                    control2: segment.control2,
+                    end: segment.end,
+                    width: self.width,
+                    segment_limits: self.segment_limits,
+                };
+                start = segment.end;
+                curve
+            })
+            .collect()
+    }
+
+    /// Returns the start, then each segment's controls and end
+    fn points(&self) -> Vec<PrimitivePoint> {
+        let mut points = vec![self.start];
+        for segment in self.segments.iter() {
+            points.extend([segment.control1, segment.control2, segment.end]);
+        }
+        points
+    }
+
+    /// Returns the point at `index` in `points`, to be changed
+    fn point_mut(&mut self, index: usize) -> &mut PrimitivePoint {
+        if index == 0 {
+            return &mut self.start;
+        }
+
+        let segment = &mut self.segments[(index - 1) / 3];
+        match (index - 1) % 3 {
+            0 => &mut segment.control1,
+            1 => &mut segment.control2,
+            _ => &mut segment.end,
+        }
+    }
+
+    /// Returns a copy with every point moved by `transform`, and the stroke `width` given
+    fn transformed(
+        &self,
+        transform: impl Fn(PrimitivePoint) -> PrimitivePoint,
+        width: u32,
+    ) -> BezierPath {
+        BezierPath {
+            color: self.color,
+            start: transform(self.start),
+            segments: self
+                .segments
+                .iter()
+                .map(|segment| CubicSegment {
+                    control1: transform(segment.control1),
+                    control2: transform(segment.control2),
+                    end: transform(segment.end),
+                })
+                .collect(),
+            width,
+            segment_limits: self.segment_limits,
+        }
+    }
+
+    /// Returns the size of the path, for `SizeLimits`
+    fn size(&self) -> u32 {
+        points_size(&self.points())
+    }
+}
+
+impl RandomShape for BezierPath {
+    ///
+    /// Generate a random BezierPath around `start`
+    ///
+    fn random_at(
+        start: PrimitivePoint,
+        width: u32,
+        height: u32,
+        border_extension: i32,
+        size_limits: SizeLimits,
+        rng: &mut impl Rng,
+    ) -> Self {
+        // Each segment stays near the end of the last, so the path starts no bigger than the
+        // maximum size
+        let (_, max_size) = size_limits.pixel_range(width, height, 1, 2 * border_extension as u32);
+        let radius = max(max_size as i32 / (2 * PATH_SEGMENTS as i32), 1);
+
+        let mut anchor = start;
+        let segments = (0..PATH_SEGMENTS)
+            .map(|_| {
+                let segment = CubicSegment {
+                    control1: anchor.random_point_in_radius(radius, rng),
+                    control2: anchor.random_point_in_radius(radius, rng),
+                    end: anchor.random_point_in_radius(radius, rng),
+                };
+                anchor = segment.end;
+                segment
+            })
+            .collect();
+
+        BezierPath::new(start, segments)
+    }
+}
+
+impl Shape for BezierPath {
+    ///
+    /// Move some of the anchors and controls. Segments share their anchors, so the path stays
+    /// connected
+    ///
+    fn mutate(
+        &mut self,
+        width: u32,
+        height: u32,
+        size_limits: SizeLimits,
+        mode: MutateMode,
+        rng: &mut impl Rng,
+    ) {
+        let point_count = 1 + 3 * self.segments.len();
+
+        let mut i = 0;
+        loop {
+            i += 1;
+            let previous = self.clone();
+
+            for r in mode.parameters(point_count, rng) {
+                self.point_mut(r).mutate(width, height, mode, rng);
+            }
+
+            let allowed = size_limits.allows(self.size(), width, height);
+            // Once the path is an allowed size, don't let it wander away from one
+            if !allowed && size_limits.allows(previous.size(), width, height) {
+                *self = previous;
+            } else if allowed {
+                break;
+            }
+            if i > MAXIMUM_MUTATION_ATTEMPTS {
+                panic!("Bezier Path: Too many mutation loops!");
+            }
+        }
+    }
+
+    fn get_pixels(&self) -> Vec<PrimitivePoint> {
+        let mut pixels: Vec<PrimitivePoint> = self
+            .curves()
+            .iter()
+            .flat_map(|curve| curve.get_pixels())
+            .collect();
+
+        // Neighboring segments both cover their shared anchor
+        pixels.sort_by(|a, b| a.x.cmp(&b.x).then(a.y.cmp(&b.y)));
+        pixels.dedup();
+
+        pixels
+    }
+
+    fn as_svg(&self, scale: f64) -> String {
+        let scaled = |point: PrimitivePoint| {
+            format!(
+                "{} {}",
+                (point.x as f64 * scale) as i32,
+                (point.y as f64 * scale) as i32
+            )
+        };
+
+        let mut path_data = format!("M{}", scaled(self.start));
+        for segment in self.segments.iter() {
+            path_data.push_str(&format!(
+                " C{}, {}, {}",
+                scaled(segment.control1),
+                scaled(segment.control2),
+                scaled(segment.end)
+            ));
+        }
+
+        format!(
+            "<path stroke=\"{}\" stroke-opacity=\"{:.5}\" fill=\"none\" d=\"{}\" stroke-width=\"{}\" />",
+            rgb_to_hex(self.color),
+            self.color.0[3] as f64 / 255.0,
+            path_data,
+            self.width as f64 * scale
+        )
+    }
+
+    fn paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
+        // Rasterize at a higher resolution, then blend each pixel by how much of it is covered
+        let supersampled_self =
+            self.transformed(supersample_point, self.width * SUPERSAMPLING as u32);
+        let coverage = supersampled_coverage(supersampled_self.get_pixels());
+
+        blend_with_coverage(image, &coverage, self.color);
+    }
+
+    fn scaled_paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, scale: f64) {
+        let scaled_self = self.transformed(
+            |point| {
+                PrimitivePoint::new(
+                    (point.x as f64 * scale) as i32,
+                    (point.y as f64 * scale) as i32,
+                )
+            },
+            ((self.width as f64 * scale).round() as u32).max(1),
+        );
+
+        scaled_self.paint_into(image);
+    }
+
+    fn set_color_using(&mut self, image: &PrimitiveImage) {
+        self.color = image.color_for_shape(self);
+    }
+
+    fn get_color(&self) -> Rgba<u8> {
+        self.color
+    }
+
+    fn set_stroke_width(&mut self, width: u32) {
+        self.width = width;
+    }
+
+    fn set_segment_limits(&mut self, segment_limits: SegmentLimits) {
+        self.segment_limits = segment_limits;
+    }
+
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::BezierPath
+    }
+
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap()
+    }
+
+    fn canonical_key(&self) -> u64 {
+        shape_key(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::get_rng;
+
+    fn two_segment_path() -> BezierPath {
+        BezierPath::new(
+            PrimitivePoint::new(0, 10),
+            vec![
+                CubicSegment {
+                    control1: PrimitivePoint::new(5, 0),
+                    control2: PrimitivePoint::new(15, 20),
+                    end: PrimitivePoint::new(20, 10),
+                },
+                CubicSegment {
+                    control1: PrimitivePoint::new(25, 0),
+                    control2: PrimitivePoint::new(35, 20),
+                    end: PrimitivePoint::new(40, 10),
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn test_continuity() {
+        let path = two_segment_path();
+        let curves = path.curves();
+
+        assert_eq!(curves.len(), 2);
+        assert_eq!(curves[0].start, path.start);
+        assert_eq!(curves[0].end, curves[1].start);
+
+        // Both segments' pixels reach the shared anchor, with no gap around it
+        let anchor = PrimitivePoint::new(20, 10);
+        assert!(curves
+            .iter()
+            .all(|curve| curve.get_pixels().contains(&anchor)));
+        assert!(path.get_pixels().contains(&anchor));
+    }
+
+    #[test]
+    fn test_mutate_keeps_continuity() {
+        let mut rng = get_rng(42);
+        let mut path = two_segment_path();
+
+        for _ in 0..50 {
+            path.mutate(100, 100, SizeLimits::default(), MutateMode::Multi, &mut rng);
+
+            let curves = path.curves();
+            assert_eq!(curves[0].end, curves[1].start);
+            assert_eq!(curves[1].end, path.segments[1].end);
+        }
+    }
+
+    #[test]
+    fn test_as_svg() {
+        let mut path = two_segment_path();
+        path.color = Rgba([240, 64, 15, 128]);
+
+        let expected = "<path stroke=\"#F0400F\" stroke-opacity=\"0.50196\" fill=\"none\" d=\"M0 20 C10 0, 30 40, 40 20 C50 0, 70 40, 80 20\" stroke-width=\"2\" />";
+        assert_eq!(path.as_svg(2.0).as_str(), expected);
+    }
+
+    #[test]
+    fn test_get_pixels_distinct() {
+        let pixels = two_segment_path().get_pixels();
+        let mut distinct = pixels.clone();
+        distinct.dedup();
+
+        assert_eq!(pixels, distinct);
+    }
+}
@@ -0,0 +1,100 @@
+use image::{ImageBuffer, Rgba};
+use std::io::Write;
+use std::str::FromStr;
+
+///
+/// The colorspace PNG output is marked as being in, for color-managed viewers and editors
+///
+/// sRGB writes an sRGB chunk (with matching gAMA and cHRM chunks). Linear writes a gamma of 1.0
+/// with sRGB's primaries, and converts the pixels to linear light so they look the same
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorProfile {
+    Srgb,
+    Linear,
+}
+
+impl FromStr for ColorProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "srgb" => Ok(ColorProfile::Srgb),
+            "linear" => Ok(ColorProfile::Linear),
+            _ => Err(format!("Unsupported color profile: {}", s)),
+        }
+    }
+}
+
+impl ColorProfile {
+    ///
+    /// Add the chunks marking this profile to `encoder`
+    ///
+    pub fn apply<W: Write>(&self, encoder: &mut png::Encoder<W>) {
+        match self {
+            ColorProfile::Srgb => encoder.set_srgb(png::SrgbRenderingIntent::Perceptual),
+            ColorProfile::Linear => {
+                encoder.set_source_gamma(png::ScaledFloat::new(1.0));
+                encoder.set_source_chromaticities(png::SourceChromaticities::new(
+                    (0.3127, 0.3290),
+                    (0.64, 0.33),
+                    (0.30, 0.60),
+                    (0.15, 0.06),
+                ));
+            }
+        }
+    }
+
+    ///
+    /// Returns `img` (which is in sRGB, like everything else here) converted to this profile
+    ///
+    /// Alpha isn't gamma encoded, so it's left alone
+    ///
+    pub fn convert(&self, img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        match self {
+            ColorProfile::Srgb => img.clone(),
+            ColorProfile::Linear => {
+                let mut converted = img.clone();
+                for pixel in converted.pixels_mut() {
+                    for c in 0..3 {
+                        pixel[c] = (srgb_to_linear(pixel[c] as f64 / 255.0) * 255.0).round() as u8;
+                    }
+                }
+                converted
+            }
+        }
+    }
+}
+
+/// The sRGB transfer function's inverse, for a channel in [0, 1]
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("sRGB".parse(), Ok(ColorProfile::Srgb));
+        assert_eq!("linear".parse(), Ok(ColorProfile::Linear));
+        assert!("cmyk".parse::<ColorProfile>().is_err());
+    }
+
+    #[test]
+    fn test_convert() {
+        let img = ImageBuffer::from_pixel(1, 1, Rgba([0, 128, 255, 100]));
+
+        assert_eq!(ColorProfile::Srgb.convert(&img), img);
+        // Mid gray in sRGB is about 22% of the light
+        assert_eq!(
+            ColorProfile::Linear.convert(&img).get_pixel(0, 0),
+            &Rgba([0, 55, 255, 100])
+        );
+    }
+}
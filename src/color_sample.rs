@@ -0,0 +1,180 @@
+use image::Rgba;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Levels per channel are grouped into buckets this wide when finding the most frequent color
+const MODE_BUCKET_WIDTH: u8 = 16;
+
+///
+/// How a shape's color is picked from the target pixels under it
+///
+/// The mean is closest to the target overall, but blends in outliers at the shape's edges. The
+/// median ignores them per channel, and the mode takes the most common color, for crisper results
+/// on textured regions
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorSample {
+    Mean,
+    Median,
+    Mode,
+}
+
+impl ColorSample {
+    ///
+    /// Returns the red, green, and blue picked from `pixels`, or None if there are none
+    ///
+    pub fn sample(&self, pixels: &[Rgba<u8>]) -> Option<[u8; 3]> {
+        if pixels.is_empty() {
+            return None;
+        }
+
+        Some(match self {
+            ColorSample::Mean => mean(pixels),
+            ColorSample::Median => median(pixels),
+            ColorSample::Mode => mode(pixels),
+        })
+    }
+}
+
+impl FromStr for ColorSample {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "mean" => Ok(ColorSample::Mean),
+            "median" => Ok(ColorSample::Median),
+            "mode" => Ok(ColorSample::Mode),
+            _ => Err(format!("Unsupported color sample: {}", s)),
+        }
+    }
+}
+
+fn mean(pixels: &[Rgba<u8>]) -> [u8; 3] {
+    let mut sums = [0u64; 3];
+    for pixel in pixels {
+        for (sum, &value) in sums.iter_mut().zip(pixel.0.iter()) {
+            *sum += value as u64;
+        }
+    }
+
+    sums.map(|sum| (sum / pixels.len() as u64) as u8)
+}
+
+///
+/// Returns each channel's median, found by counting how many pixels have each level
+///
+fn median(pixels: &[Rgba<u8>]) -> [u8; 3] {
+    let mut medians = [0u8; 3];
+
+    for (channel, median) in medians.iter_mut().enumerate() {
+        let mut counts = [0usize; 256];
+        for pixel in pixels {
+            counts[pixel[channel] as usize] += 1;
+        }
+
+        // The lower median, for an even number of pixels
+        let middle = pixels.len().div_ceil(2);
+        let mut seen = 0;
+        for (level, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= middle {
+                *median = level as u8;
+                break;
+            }
+        }
+    }
+
+    medians
+}
+
+///
+/// Returns the mean of the pixels in the most common bucket of `MODE_BUCKET_WIDTH` levels per
+/// channel
+///
+/// Averaging within the bucket keeps the exact color of flat regions, instead of snapping it to a
+/// bucket's corner. Ties go to the bucket seen first
+///
+fn mode(pixels: &[Rgba<u8>]) -> [u8; 3] {
+    let bucket = |pixel: &Rgba<u8>| {
+        [
+            pixel[0] / MODE_BUCKET_WIDTH,
+            pixel[1] / MODE_BUCKET_WIDTH,
+            pixel[2] / MODE_BUCKET_WIDTH,
+        ]
+    };
+
+    let mut counts: HashMap<[u8; 3], (usize, usize)> = HashMap::new();
+    for (i, pixel) in pixels.iter().enumerate() {
+        counts.entry(bucket(pixel)).or_insert((0, i)).0 += 1;
+    }
+
+    let (&most_common, _) = counts
+        .iter()
+        .max_by(|(_, (count_a, first_a)), (_, (count_b, first_b))| {
+            count_a.cmp(count_b).then(first_b.cmp(first_a))
+        })
+        .unwrap();
+
+    let in_bucket: Vec<Rgba<u8>> = pixels
+        .iter()
+        .filter(|pixel| bucket(pixel) == most_common)
+        .copied()
+        .collect();
+
+    mean(&in_bucket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seven red pixels and three slightly different blues
+    fn bimodal() -> Vec<Rgba<u8>> {
+        let mut pixels = vec![Rgba([200, 20, 20, 255]); 7];
+        pixels.extend([
+            Rgba([10, 20, 192, 255]),
+            Rgba([12, 22, 198, 255]),
+            Rgba([14, 24, 204, 255]),
+        ]);
+        pixels
+    }
+
+    #[test]
+    fn test_mean() {
+        assert_eq!(ColorSample::Mean.sample(&bimodal()), Some([143, 20, 73]));
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(ColorSample::Median.sample(&bimodal()), Some([200, 20, 20]));
+
+        let pixels = [
+            Rgba([0, 100, 30, 255]),
+            Rgba([50, 0, 10, 255]),
+            Rgba([255, 40, 20, 255]),
+        ];
+        assert_eq!(ColorSample::Median.sample(&pixels), Some([50, 40, 20]));
+    }
+
+    #[test]
+    fn test_mode() {
+        assert_eq!(ColorSample::Mode.sample(&bimodal()), Some([200, 20, 20]));
+
+        // Colors in the same bucket are averaged
+        let mut pixels = bimodal();
+        pixels.truncate(2);
+        pixels.extend(bimodal()[7..].iter().copied());
+        assert_eq!(ColorSample::Mode.sample(&pixels), Some([12, 22, 198]));
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(ColorSample::Median.sample(&[]), None);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("MEDIAN".parse(), Ok(ColorSample::Median));
+        assert!("average".parse::<ColorSample>().is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use crate::utilities::rgb_to_hex;
+use image::Rgba;
+use std::collections::HashMap;
+use std::fmt;
+
+/// The number of most frequent colors `ColorStats` keeps
+pub const TOP_COLORS: usize = 5;
+
+///
+/// Summary of the colors used by an approximation's shapes
+///
+/// `top` holds up to `TOP_COLORS` colors with the number of shapes using each, most frequent first
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorStats {
+    pub num_shapes: usize,
+    pub distinct_colors: usize,
+    pub top: Vec<(Rgba<u8>, usize)>,
+}
+
+impl ColorStats {
+    ///
+    /// Count the colors in `colors`, treating the same RGB at different alphas as different colors
+    ///
+    pub fn from_colors(colors: impl Iterator<Item = Rgba<u8>>) -> ColorStats {
+        let mut counts: HashMap<[u8; 4], usize> = HashMap::new();
+        let mut num_shapes = 0;
+
+        for color in colors {
+            *counts.entry(color.0).or_insert(0) += 1;
+            num_shapes += 1;
+        }
+
+        let mut top: Vec<(Rgba<u8>, usize)> = counts
+            .iter()
+            .map(|(color, count)| (Rgba(*color), *count))
+            .collect();
+
+        // Break ties by the color itself so the report is deterministic
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0 .0.cmp(&b.0 .0)));
+        top.truncate(TOP_COLORS);
+
+        ColorStats {
+            num_shapes,
+            distinct_colors: counts.len(),
+            top,
+        }
+    }
+}
+
+impl fmt::Display for ColorStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} distinct colors across {} shapes",
+            self.distinct_colors, self.num_shapes
+        )?;
+        for (color, count) in &self.top {
+            writeln!(
+                f,
+                "  {} (alpha {}): {} shapes",
+                rgb_to_hex(*color),
+                color[3],
+                count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_colors() {
+        let red = Rgba([255, 0, 0, 128]);
+        let blue = Rgba([0, 0, 255, 128]);
+        let faint_red = Rgba([255, 0, 0, 64]);
+
+        let stats = ColorStats::from_colors(vec![red, blue, red, faint_red, red].into_iter());
+        assert_eq!(stats.num_shapes, 5);
+        assert_eq!(stats.distinct_colors, 3);
+        assert_eq!(stats.top, vec![(red, 3), (blue, 1), (faint_red, 1)]);
+
+        let empty = ColorStats::from_colors(vec![].into_iter());
+        assert_eq!(empty.distinct_colors, 0);
+        assert!(empty.top.is_empty());
+    }
+
+    #[test]
+    fn test_top_is_truncated() {
+        let colors = (0..20).map(|i| Rgba([i, 0, 0, 128]));
+        let stats = ColorStats::from_colors(colors);
+        assert_eq!(stats.distinct_colors, 20);
+        assert_eq!(stats.top.len(), TOP_COLORS);
+    }
+}
@@ -0,0 +1,337 @@
+use crate::mutate_mode::MutateMode;
+use crate::point::PrimitivePoint;
+use crate::primitive_image::PrimitiveImage;
+use crate::shape::{shape_key, RandomShape, Shape, ShapeType};
+use crate::symmetry::Symmetry;
+use crate::utilities::{clamp, fill_polygon, polygon_contains, radians, rgb_to_hex, SizeLimits};
+use image::ImageBuffer;
+use image::Pixel;
+use image::Rgba;
+use rand::Rng;
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::max;
+
+const MAXIMUM_MUTATION_ATTEMPTS: u32 = 100_000;
+
+///
+/// A plus sign: a horizontal and a vertical bar crossing at their centers, for sparkles and
+/// markers
+///
+#[derive(Debug, Copy, Clone, Hash, Serialize, Deserialize)]
+pub struct Cross {
+    #[serde(with = "crate::utilities::rgba_serde")]
+    pub color: image::Rgba<u8>,
+    center: PrimitivePoint,
+    arm_length: u32, // From the center to the end of each arm
+    thickness: u32,
+    angle: u32, // In degrees, from 0 to 89
+}
+
+impl Cross {
+    ///
+    /// Create a cross centered on `center`, rotated clockwise by `angle` degrees
+    ///
+    /// Crosses look the same every 90 degrees, so `angle` is reduced to 0 to 89
+    ///
+    pub fn new(center: PrimitivePoint, arm_length: u32, thickness: u32, angle: u32) -> Cross {
+        Cross {
+            color: Rgba([0, 0, 0, 128]),
+            center,
+            arm_length,
+            thickness,
+            angle: angle % 90,
+        }
+    }
+
+    ///
+    /// Returns this shape with its color set to `color`, for building shapes by hand
+    ///
+    pub fn with_color(mut self, color: Rgba<u8>) -> Self {
+        self.color = color;
+        self
+    }
+
+    ///
+    /// Determine if this cross is valid
+    ///
+    /// A cross is valid if its arms stick out past the bars they cross, and it isn't too big for
+    /// the image or `size_limits` (which bound the length of the bars)
+    ///
+    fn is_valid(&self, width: u32, height: u32, size_limits: SizeLimits) -> bool {
+        let (min_arm, max_arm) =
+            size_limits.radius_range(width, height, 1, max_arm_length(width, height));
+
+        self.arm_length >= min_arm
+            && self.arm_length <= max_arm
+            && self.thickness >= 1
+            && self.thickness < 2 * self.arm_length
+            && self.angle < 90
+    }
+
+    ///
+    /// Returns the twelve corners of the cross, clockwise, in image coordinates
+    ///
+    /// Rotates the same way as `rotate_point`
+    ///
+    fn outline(&self) -> Vec<(f64, f64)> {
+        let arm = self.arm_length as f64;
+        let half = self.thickness as f64 / 2.0;
+        let (sin_a, cos_a) = radians(self.angle as f64).sin_cos();
+
+        [
+            (half, -arm),
+            (half, -half),
+            (arm, -half),
+            (arm, half),
+            (half, half),
+            (half, arm),
+            (-half, arm),
+            (-half, half),
+            (-arm, half),
+            (-arm, -half),
+            (-half, -half),
+            (-half, -arm),
+        ]
+        .iter()
+        .map(|(x, y)| {
+            (
+                self.center.x as f64 + x * cos_a - y * sin_a,
+                self.center.y as f64 + x * sin_a + y * cos_a,
+            )
+        })
+        .collect()
+    }
+}
+
+/// The longest arm a cross can have in a `width` x `height` image (at least 2, for tiny images)
+fn max_arm_length(width: u32, height: u32) -> u32 {
+    max(max(width, height) / 4, 2)
+}
+
+impl RandomShape for Cross {
+    ///
+    /// Generate a random Cross around `center`
+    ///
+    fn random_at(
+        center: PrimitivePoint,
+        width: u32,
+        height: u32,
+        _border_extension: i32,
+        size_limits: SizeLimits,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let (min_arm, max_arm) =
+            size_limits.radius_range(width, height, 1, max(max(width, height) / 8, 2) - 1);
+        let arm_length = rng.gen_range(min_arm..=max_arm);
+        let thickness = rng.gen_range(1..=max(2 * arm_length / 3, 1));
+        let angle = rng.gen_range(0..90);
+
+        Cross::new(center, arm_length, thickness, angle)
+    }
+}
+
+impl Shape for Cross {
+    fn mutate(
+        &mut self,
+        width: u32,
+        height: u32,
+        size_limits: SizeLimits,
+        mode: MutateMode,
+        rng: &mut impl Rng,
+    ) {
+        let normal = Normal::new(0.0, 5.0 * mode.step_scale()).unwrap();
+        let (min_arm, max_arm) =
+            size_limits.radius_range(width, height, 1, max_arm_length(width, height));
+
+        let mut i = 0;
+        loop {
+            i += 1;
+            for r in mode.parameters(4, rng) {
+                match r {
+                    0 => self.center.mutate(width, height, mode, rng),
+                    1 => {
+                        self.arm_length = clamp(
+                            self.arm_length as i32 + (rng.sample(normal) as i32),
+                            min_arm as i32,
+                            max_arm as i32,
+                        ) as u32
+                    }
+                    2 => {
+                        self.thickness = clamp(
+                            self.thickness as i32 + (rng.sample(normal) as i32),
+                            1,
+                            2 * self.arm_length as i32 - 1,
+                        ) as u32
+                    }
+                    3 => {
+                        self.angle =
+                            (self.angle as i32 + (rng.sample(normal) as i32)).rem_euclid(90) as u32
+                    }
+                    _ => {}
+                }
+            }
+
+            if self.is_valid(width, height, size_limits) {
+                break;
+            }
+            if i > MAXIMUM_MUTATION_ATTEMPTS {
+                panic!("Cross: Too many mutation loops!");
+            }
+        }
+    }
+
+    ///
+    /// Both bars, minus the square where they overlap
+    ///
+    fn area(&self) -> f64 {
+        let arm = self.arm_length as f64;
+        let thickness = self.thickness as f64;
+        4.0 * arm * thickness - thickness * thickness
+    }
+
+    fn contains_pixel(&self, x: i32, y: i32) -> bool {
+        polygon_contains(&self.outline(), x as f64, y as f64)
+    }
+
+    fn get_pixels(&self) -> Vec<PrimitivePoint> {
+        fill_polygon(&self.outline())
+    }
+
+    fn as_svg(&self, scale: f64) -> String {
+        format!(
+            "<path fill=\"{}\" fill-opacity=\"{:.5}\" d=\"{}\"/>",
+            rgb_to_hex(self.color),
+            self.color.0[3] as f64 / 255.0,
+            self.as_svg_path_data(scale).unwrap()
+        )
+    }
+
+    fn as_svg_path_data(&self, scale: f64) -> Option<String> {
+        let corners: Vec<String> = self
+            .outline()
+            .iter()
+            .map(|(x, y)| format!("{:.2},{:.2}", x * scale, y * scale))
+            .collect();
+
+        Some(format!("M{} Z", corners.join(" L")))
+    }
+
+    fn paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
+        let (width, height) = image.dimensions();
+
+        for pixel in self.get_pixels().iter() {
+            if pixel.x >= 0 && pixel.y >= 0 && pixel.x < width as i32 && pixel.y < height as i32 {
+                let pix = image.get_pixel_mut(pixel.x as u32, pixel.y as u32);
+                pix.blend(&self.color);
+            }
+        }
+    }
+
+    fn scaled_paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, scale: f64) {
+        let scaled_self = Cross {
+            center: PrimitivePoint::new(
+                (self.center.x as f64 * scale) as i32,
+                (self.center.y as f64 * scale) as i32,
+            ),
+            arm_length: (self.arm_length as f64 * scale) as u32,
+            thickness: ((self.thickness as f64 * scale) as u32).max(1),
+            color: self.color,
+            angle: self.angle,
+        };
+
+        scaled_self.paint_into(image);
+    }
+
+    fn set_color_using(&mut self, image: &PrimitiveImage) {
+        self.color = image.color_for_shape(self);
+    }
+
+    fn get_color(&self) -> Rgba<u8> {
+        self.color
+    }
+
+    fn clear_rotation(&mut self) {
+        self.angle = 0;
+    }
+
+    fn mirror(&self, symmetry: Symmetry, width: u32, height: u32) -> Option<Box<dyn Shape>> {
+        let mut mirrored = *self;
+        mirrored.center = self.center.mirrored(symmetry, width, height);
+        // Reflecting negates the rotation, and crosses look the same every 90 degrees
+        if symmetry != Symmetry::None {
+            mirrored.angle = (90 - self.angle % 90) % 90;
+        }
+        Some(Box::new(mirrored))
+    }
+
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::Cross
+    }
+
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap()
+    }
+
+    fn canonical_key(&self) -> u64 {
+        shape_key(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_pixels() {
+        let cross = Cross::new(PrimitivePoint::new(20, 20), 8, 4, 0);
+        let pixels = cross.get_pixels();
+
+        // The center and the end of each arm are filled
+        assert!(pixels.contains(&PrimitivePoint::new(20, 20)));
+        assert!(pixels.contains(&PrimitivePoint::new(27, 20)));
+        assert!(pixels.contains(&PrimitivePoint::new(13, 20)));
+        assert!(pixels.contains(&PrimitivePoint::new(20, 27)));
+        assert!(pixels.contains(&PrimitivePoint::new(20, 13)));
+
+        // The corners between the arms are empty
+        for (x, y) in [(25, 25), (15, 25), (15, 15), (25, 15)] {
+            assert!(!pixels.contains(&PrimitivePoint::new(x, y)));
+        }
+
+        // Rotated an eighth of a turn, the arms point into the corners
+        let rotated = Cross::new(PrimitivePoint::new(20, 20), 8, 4, 45);
+        assert!(rotated.get_pixels().contains(&PrimitivePoint::new(24, 24)));
+    }
+
+    #[test]
+    fn test_as_svg() {
+        let mut cross = Cross::new(PrimitivePoint::new(10, 10), 4, 2, 0);
+        cross.color = Rgba([240, 64, 15, 128]);
+
+        let expected = "<path fill=\"#F0400F\" fill-opacity=\"0.50196\" d=\"M22.00,12.00 L22.00,18.00 L28.00,18.00 L28.00,22.00 L22.00,22.00 L22.00,28.00 L18.00,28.00 L18.00,22.00 L12.00,22.00 L12.00,18.00 L18.00,18.00 L18.00,12.00 Z\"/>";
+        assert_eq!(cross.as_svg(2.0).as_str(), expected);
+    }
+
+    #[test]
+    fn test_area() {
+        for angle in [0, 30, 45] {
+            let cross = Cross::new(PrimitivePoint::new(80, 80), 30, 12, angle);
+
+            let pixels = cross.get_pixels().len() as f64;
+            assert!((cross.area() - pixels).abs() / pixels < 0.05);
+        }
+    }
+
+    #[test]
+    fn test_mutate_stays_valid() {
+        let mut rng = crate::utilities::get_rng(3);
+        let mut cross = Cross::random(100, 100, 1, SizeLimits::default(), &mut rng);
+
+        for _ in 0..100 {
+            cross.mutate(100, 100, SizeLimits::default(), MutateMode::Multi, &mut rng);
+            assert!(cross.is_valid(100, 100, SizeLimits::default()));
+        }
+    }
+}
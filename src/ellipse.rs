@@ -1,31 +1,111 @@
+use crate::feather::{feather_filter_attribute, feathered};
+use crate::mutate_mode::MutateMode;
 use crate::point::PrimitivePoint;
 use crate::primitive_image::PrimitiveImage;
-use crate::shape::{RandomShape, Shape};
-use crate::utilities::{clamp, rgb_to_hex, rotate_point};
+use crate::shape::{shape_key, RandomShape, Shape, ShapeType};
+use crate::symmetry::Symmetry;
+use crate::utilities::{clamp, radians, rgb_to_hex, rotate_point, SizeLimits};
 use image::ImageBuffer;
 use image::Pixel;
 use image::Rgba;
 use rand::Rng;
 use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::cmp::max;
+use std::f64::consts::PI;
 
 const MAXIMUM_MUTATION_ATTEMPTS: u32 = 100_000;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Hash, Serialize, Deserialize)]
 pub struct Ellipse {
+    #[serde(with = "crate::utilities::rgba_serde")]
     pub color: image::Rgba<u8>,
     center: PrimitivePoint,
     a: i32,
     b: i32,
     angle: u32, // In degrees
+    #[serde(default)]
+    feather: u32, // Pixels the alpha fades out over at the edges
 }
 
 impl Ellipse {
     ///
+    /// Create an ellipse centered on `center` with radii `a` (across) and `b` (down), rotated
+    /// clockwise by `angle` degrees
     ///
+    pub fn new(center: PrimitivePoint, a: u32, b: u32, angle: u32) -> Ellipse {
+        Ellipse {
+            color: Rgba([0, 0, 0, 128]),
+            center,
+            a: a as i32,
+            b: b as i32,
+            angle: angle % 360,
+            feather: 0,
+        }
+    }
+
+    ///
+    /// Returns this shape with its color set to `color`, for building shapes by hand
+    ///
+    pub fn with_color(mut self, color: Rgba<u8>) -> Self {
+        self.color = color;
+        self
+    }
+    ///
+    ///
+    ///
+    fn is_valid(&self, width: u32, height: u32, size_limits: SizeLimits) -> bool {
+        let (min_radius, max_radius) = size_limits.radius_range(width, height, 1, u32::MAX);
+
+        // Without a maximum size, each radius must be under 10% of its side of the image, but the
+        // smallest allowed ellipse is always valid, even on tiny images
+        let radius_is_valid = |radius: i32, side: u32| {
+            radius >= min_radius as i32
+                && match size_limits.max {
+                    Some(_) => radius <= max_radius as i32,
+                    None => radius == min_radius as i32 || (radius as f64) < side as f64 * 0.1,
+                }
+        };
+
+        radius_is_valid(self.a, width) && radius_is_valid(self.b, height)
+    }
+
+    ///
+    /// Returns the pixels of the ellipse before it's rotated, which `get_pixels` rotates
+    ///
+    fn un_rotated_pixels(&self) -> Vec<PrimitivePoint> {
+        let min_x = self.center.x - (self.a as i32);
+        let min_y = self.center.y - (self.b as i32);
+        let max_x = self.center.x + (self.a as i32);
+        let max_y = self.center.y + (self.b as i32);
+
+        let mut pixels = vec![];
+
+        for x in min_x..max_x {
+            for y in min_y..max_y {
+                if self.un_rotated_contains_pixel(x, y) {
+                    pixels.push(PrimitivePoint::new(x, y));
+                }
+            }
+        }
+
+        pixels
+    }
+
     ///
-    fn is_valid(&self, width: u32, height: u32) -> bool {
-        (self.a as f64) < (width as f64 * 0.1) && (self.b as f64) < (height as f64 * 0.1)
+    /// Returns about how far the un-rotated pixel (`x`, `y`) is inside the edge: the shorter of
+    /// the distances across and down to it, which is never closer than the edge really is
+    ///
+    fn un_rotated_edge_distance(&self, x: i32, y: i32) -> f64 {
+        let (a, b) = (self.a as f64, self.b as f64);
+        let dx = (x - self.center.x).abs() as f64;
+        let dy = (y - self.center.y).abs() as f64;
+
+        let across = a * (1.0 - (dy / b).powi(2)).max(0.0).sqrt() - dx;
+        let down = b * (1.0 - (dx / a).powi(2)).max(0.0).sqrt() - dy;
+
+        across.min(down)
     }
 
     fn un_rotated_contains_pixel(&self, x: i32, y: i32) -> bool {
@@ -37,62 +117,75 @@ impl Ellipse {
 
 impl RandomShape for Ellipse {
     ///
-    /// Generate a random Triangle within the bounds given
-    /// `border_extension` is the maximum distance outside of the border a triangle is allowed to go
-    ///     It must be >= 1
+    /// Generate a random Ellipse around `center`
     ///
-    fn random(width: u32, height: u32, _border_extension: i32, rng: &mut impl Rng) -> Self {
-        let center = PrimitivePoint::random_point(width, height, rng);
-        let a = rng.gen_range(1..max(width as i32, height as i32) / 10);
-        let b = rng.gen_range(1..max(width as i32, height as i32) / 10);
+    fn random_at(
+        center: PrimitivePoint,
+        width: u32,
+        height: u32,
+        _border_extension: i32,
+        size_limits: SizeLimits,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let (min_radius, max_radius) =
+            size_limits.radius_range(width, height, 1, max(max(width, height) / 10, 2) - 1);
+        let a = rng.gen_range(min_radius as i32..=max_radius as i32);
+        let b = rng.gen_range(min_radius as i32..=max_radius as i32);
         let angle = rng.gen_range(0..360);
 
-        let mut ellipse = Ellipse {
+        Ellipse {
             center,
             a,
             b,
             angle,
             color: Rgba([0, 0, 0, 128]),
-        };
-        ellipse.mutate(width, height, rng);
-
-        ellipse
+            feather: 0,
+        }
     }
 }
 
 impl Shape for Ellipse {
-    fn mutate(&mut self, width: u32, height: u32, rng: &mut impl Rng) {
-        let normal = Normal::new(0.0, 5.0).unwrap();
+    fn mutate(
+        &mut self,
+        width: u32,
+        height: u32,
+        size_limits: SizeLimits,
+        mode: MutateMode,
+        rng: &mut impl Rng,
+    ) {
+        let normal = Normal::new(0.0, 5.0 * mode.step_scale()).unwrap();
+        let (min_radius, max_radius) =
+            size_limits.radius_range(width, height, 1, max(width, height));
 
         let mut i = 0;
         loop {
             i += 1;
-            let r = rng.gen_range(0..4);
-
-            match r {
-                0 => self.center.mutate(width, height, rng),
-                1 => {
-                    self.a = clamp(
-                        self.a as i32 + (rng.sample(normal) as i32),
-                        1,
-                        max(width, height) as i32,
-                    )
-                }
-                2 => {
-                    self.b = clamp(
-                        self.b as i32 + (rng.sample(normal) as i32),
-                        1,
-                        max(width, height) as i32,
-                    )
+            for r in mode.parameters(4, rng) {
+                match r {
+                    0 => self.center.mutate(width, height, mode, rng),
+                    1 => {
+                        self.a = clamp(
+                            self.a as i32 + (rng.sample(normal) as i32),
+                            min_radius as i32,
+                            max_radius as i32,
+                        )
+                    }
+                    2 => {
+                        self.b = clamp(
+                            self.b as i32 + (rng.sample(normal) as i32),
+                            min_radius as i32,
+                            max_radius as i32,
+                        )
+                    }
+                    3 => {
+                        self.angle =
+                            clamp(self.angle as i32 + (rng.sample(normal) as i32), 0, 359) as u32
+                    }
+                    _ => {}
                 }
-                3 => {
-                    self.angle =
-                        clamp(self.angle as i32 + (rng.sample(normal) as i32), 0, 359) as u32
-                }
-                _ => {}
             }
 
-            if self.is_valid(width, height) {
+            if self.is_valid(width, height, size_limits) {
                 break;
             }
             if i > MAXIMUM_MUTATION_ATTEMPTS {
@@ -101,21 +194,37 @@ impl Shape for Ellipse {
         }
     }
 
-    fn get_pixels(&self) -> Vec<PrimitivePoint> {
-        let min_x = self.center.x - (self.a as i32);
-        let min_y = self.center.y - (self.b as i32);
-        let max_x = self.center.x + (self.a as i32);
-        let max_y = self.center.y + (self.b as i32);
+    fn area(&self) -> f64 {
+        PI * self.a as f64 * self.b as f64
+    }
 
-        let mut pixels = vec![];
+    ///
+    /// `get_pixels` rotates the un-rotated ellipse's pixels, rounding toward the center, so this
+    /// rotates (`x`, `y`) back and checks the pixels near there for one that lands on it
+    ///
+    fn contains_pixel(&self, x: i32, y: i32) -> bool {
+        let (sin_a, cos_a) = radians(self.angle as f64).sin_cos();
+        let dx = (x - self.center.x) as f64;
+        let dy = (y - self.center.y) as f64;
+        let from_x = (dx * cos_a + dy * sin_a).round() as i32 + self.center.x;
+        let from_y = (-dx * sin_a + dy * cos_a).round() as i32 + self.center.y;
 
-        for x in min_x..max_x {
-            for y in min_y..max_y {
-                if self.un_rotated_contains_pixel(x, y) {
-                    pixels.push(PrimitivePoint::new(x, y));
-                }
-            }
-        }
+        // Rounding moves pixels less than one in each direction, so they came from nearby
+        (from_x - 2..=from_x + 2).any(|un_rotated_x| {
+            (from_y - 2..=from_y + 2).any(|un_rotated_y| {
+                let mut pixel = PrimitivePoint::new(un_rotated_x, un_rotated_y);
+                rotate_point(&mut pixel, self.center, self.angle);
+
+                pixel == PrimitivePoint::new(x, y)
+                    && (self.center.x - self.a..self.center.x + self.a).contains(&un_rotated_x)
+                    && (self.center.y - self.b..self.center.y + self.b).contains(&un_rotated_y)
+                    && self.un_rotated_contains_pixel(un_rotated_x, un_rotated_y)
+            })
+        })
+    }
+
+    fn get_pixels(&self) -> Vec<PrimitivePoint> {
+        let mut pixels = self.un_rotated_pixels();
 
         for pixel in pixels.iter_mut() {
             rotate_point(pixel, self.center, self.angle);
@@ -129,37 +238,73 @@ impl Shape for Ellipse {
             (self.center.y as f64 * scale) as i32,
         );
 
-        format!("<ellipse fill=\"{}\" fill-opacity=\"{:.5}\" cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" transform=\"rotate({} {} {})\"/>",
+        format!("<ellipse fill=\"{}\" fill-opacity=\"{:.5}\" cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" transform=\"rotate({} {} {})\"{}/>",
                 rgb_to_hex(self.color),
                 self.color.0[3] as f64 / 255.0,
                 new_center.x, new_center.y,
                 self.a as f64 * scale, self.b as f64 * scale,
-                -(self.angle as i32), new_center.x, new_center.y)
+                -(self.angle as i32), new_center.x, new_center.y,
+                feather_filter_attribute(self.feather))
+    }
+
+    fn as_svg_path_data(&self, scale: f64) -> Option<String> {
+        // A path can't be blurred on its own once it's merged with others
+        if self.feather > 0 {
+            return None;
+        }
+
+        // Match `as_svg`: two half-ellipse arcs, rotated the same way as its transform
+        let center_x = ((self.center.x as f64 * scale) as i32) as f64;
+        let center_y = ((self.center.y as f64 * scale) as i32) as f64;
+        let a = self.a as f64 * scale;
+        let b = self.b as f64 * scale;
+        let angle = -(self.angle as i32);
+
+        let dx = a * radians(angle as f64).cos();
+        let dy = a * radians(angle as f64).sin();
+
+        Some(format!(
+            "M{:.2},{:.2} A{} {} {} 1 0 {:.2},{:.2} A{} {} {} 1 0 {:.2},{:.2} Z",
+            center_x + dx,
+            center_y + dy,
+            a,
+            b,
+            angle,
+            center_x - dx,
+            center_y - dy,
+            a,
+            b,
+            angle,
+            center_x + dx,
+            center_y + dy
+        ))
     }
 
     //noinspection RsTypeCheck
-    fn paint_on(&self, image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    fn paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
         let (width, height) = image.dimensions();
-        let mut output = image.clone();
 
-        let pixels = self.get_pixels();
+        for un_rotated in self.un_rotated_pixels() {
+            let color = match self.feather {
+                0 => self.color,
+                feather => feathered(
+                    self.color,
+                    self.un_rotated_edge_distance(un_rotated.x, un_rotated.y),
+                    feather,
+                ),
+            };
 
-        for pixel in pixels.iter() {
+            let mut pixel = un_rotated;
+            rotate_point(&mut pixel, self.center, self.angle);
             if pixel.x > 0 && pixel.y > 0 && pixel.x < width as i32 && pixel.y < height as i32 {
-                let pix = output.get_pixel_mut(pixel.x as u32, pixel.y as u32);
-                pix.blend(&self.color);
+                let pix = image.get_pixel_mut(pixel.x as u32, pixel.y as u32);
+                pix.blend(&color);
             }
         }
-
-        output
     }
 
     //noinspection RsTypeCheck
-    fn scaled_paint_on(
-        &self,
-        image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-        scale: f64,
-    ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    fn scaled_paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, scale: f64) {
         let scaled_self = Ellipse {
             center: PrimitivePoint::new(
                 (self.center.x as f64 * scale) as i32,
@@ -169,13 +314,52 @@ impl Shape for Ellipse {
             b: (self.b as f64 * scale) as i32,
             color: self.color,
             angle: self.angle,
+            feather: (self.feather as f64 * scale).round() as u32,
         };
 
-        scaled_self.paint_on(image)
+        scaled_self.paint_into(image);
     }
 
     fn set_color_using(&mut self, image: &PrimitiveImage) {
-        self.color = image.target_average_color_in_shape(self);
+        self.color = image.color_for_shape(self);
+    }
+
+    fn get_color(&self) -> Rgba<u8> {
+        self.color
+    }
+
+    fn set_feather(&mut self, feather: u32) {
+        self.feather = feather;
+    }
+
+    fn feather(&self) -> u32 {
+        self.feather
+    }
+
+    fn clear_rotation(&mut self) {
+        self.angle = 0;
+    }
+
+    fn mirror(&self, symmetry: Symmetry, width: u32, height: u32) -> Option<Box<dyn Shape>> {
+        let mut mirrored = *self;
+        mirrored.center = self.center.mirrored(symmetry, width, height);
+        // Reflecting negates the rotation
+        if symmetry != Symmetry::None {
+            mirrored.angle = (360 - self.angle % 360) % 360;
+        }
+        Some(Box::new(mirrored))
+    }
+
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::Ellipse
+    }
+
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap()
+    }
+
+    fn canonical_key(&self) -> u64 {
+        shape_key(self)
     }
 }
 
@@ -195,6 +379,7 @@ mod tests {
             b,
             angle,
             color: Rgba([0, 0, 0, 0]),
+            feather: 0,
         };
         assert_eq!(ellipse.un_rotated_contains_pixel(2, 2), true);
         assert_eq!(ellipse.un_rotated_contains_pixel(0, 0), false);
@@ -209,9 +394,52 @@ mod tests {
             b,
             angle,
             color: Rgba([0, 0, 0, 0]),
+            feather: 0,
         };
         assert_eq!(ellipse.un_rotated_contains_pixel(2, -8), true);
         assert_eq!(ellipse.un_rotated_contains_pixel(12, -8), false);
         assert_eq!(ellipse.un_rotated_contains_pixel(11, -7), false);
     }
+
+    #[test]
+    fn test_as_svg() {
+        let ellipse = Ellipse {
+            center: PrimitivePoint::new(10, 5),
+            a: 4,
+            b: 2,
+            angle: 30,
+            color: Rgba([240, 64, 15, 128]),
+            feather: 0,
+        };
+        let expected = "<ellipse fill=\"#F0400F\" fill-opacity=\"0.50196\" cx=\"20\" cy=\"10\" rx=\"8\" ry=\"4\" transform=\"rotate(-30 20 10)\"/>";
+        assert_eq!(ellipse.as_svg(2.0).as_str(), expected);
+    }
+
+    #[test]
+    fn test_area() {
+        let ellipse = Ellipse {
+            center: PrimitivePoint::new(50, 50),
+            a: 30,
+            b: 15,
+            angle: 40,
+            color: Rgba([0, 0, 0, 0]),
+            feather: 0,
+        };
+
+        let pixels = ellipse.get_pixels().len() as f64;
+        assert!((ellipse.area() - pixels).abs() / pixels < 0.05);
+    }
+
+    #[test]
+    fn test_feather() {
+        let mut ellipse =
+            Ellipse::new(PrimitivePoint::new(20, 20), 14, 9, 0).with_color(Rgba([255, 0, 0, 200]));
+        ellipse.set_feather(4);
+        let painted = ellipse.paint_on(&ImageBuffer::from_pixel(40, 40, Rgba([0, 0, 0, 0])));
+
+        // Pixels one in from the edge fade the same on the long and short axes
+        assert_eq!(painted.get_pixel(33, 20)[3], 50);
+        assert_eq!(painted.get_pixel(20, 12)[3], 50);
+        assert_eq!(painted.get_pixel(20, 20)[3], 200);
+    }
 }
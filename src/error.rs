@@ -0,0 +1,101 @@
+use crate::metrics::ScoreRegion;
+use image::ImageError;
+use std::fmt;
+use std::io;
+
+///
+/// Why an image couldn't be loaded for approximation, set up, rendered, or compared
+///
+#[derive(Debug)]
+pub enum PrimitiveError {
+    /// The file couldn't be read
+    Io(io::Error),
+    /// The data isn't a valid image of its format
+    Decode(String),
+    /// The image has no pixels
+    EmptyImage { width: u32, height: u32 },
+    /// The format (or a feature of it) isn't supported
+    Unsupported(String),
+    /// The image to render into is empty, or doesn't have the approximation's aspect ratio
+    RenderSize {
+        width: u32,
+        height: u32,
+        target_width: u32,
+        target_height: u32,
+    },
+    /// The preview scale isn't in (0, 1]
+    PreviewScale(f64),
+    /// Images being compared have different dimensions
+    SizeMismatch { a: (u32, u32), b: (u32, u32) },
+    /// The score region is entirely off of the `width` x `height` image
+    ScoreRegion {
+        region: ScoreRegion,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl fmt::Display for PrimitiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrimitiveError::Io(err) => write!(f, "Could not read image: {}", err),
+            PrimitiveError::Decode(err) => write!(f, "Could not decode image: {}", err),
+            PrimitiveError::EmptyImage { width, height } => {
+                write!(f, "Can't approximate an empty ({}x{}) image", width, height)
+            }
+            PrimitiveError::Unsupported(err) => write!(f, "Unsupported image: {}", err),
+            PrimitiveError::RenderSize {
+                width,
+                height,
+                target_width,
+                target_height,
+            } => write!(
+                f,
+                "Can't render a {}x{} approximation into a {}x{} image",
+                target_width, target_height, width, height
+            ),
+            PrimitiveError::PreviewScale(scale) => {
+                write!(f, "Preview scale must be in (0, 1], got {}", scale)
+            }
+            PrimitiveError::SizeMismatch { a, b } => write!(
+                f,
+                "Can't compare images of different sizes: {}x{} vs {}x{}",
+                a.0, a.1, b.0, b.1
+            ),
+            PrimitiveError::ScoreRegion {
+                region,
+                width,
+                height,
+            } => write!(
+                f,
+                "The score region {},{},{},{} is outside of the {}x{} image",
+                region.x, region.y, region.width, region.height, width, height
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PrimitiveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PrimitiveError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PrimitiveError {
+    fn from(err: io::Error) -> Self {
+        PrimitiveError::Io(err)
+    }
+}
+
+impl From<ImageError> for PrimitiveError {
+    fn from(err: ImageError) -> Self {
+        match err {
+            ImageError::IoError(err) => PrimitiveError::Io(err),
+            ImageError::Unsupported(err) => PrimitiveError::Unsupported(err.to_string()),
+            err => PrimitiveError::Decode(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use image::Rgba;
+use std::collections::BTreeSet;
+
+///
+/// Returns `color` with its alpha scaled down for a pixel `edge_distance` pixels inside a shape
+/// whose edges fade out over `feather` pixels
+///
+/// Pixels at least `feather` pixels in keep their alpha, and 0 leaves every pixel alone
+///
+pub fn feathered(color: Rgba<u8>, edge_distance: f64, feather: u32) -> Rgba<u8> {
+    if feather == 0 {
+        return color;
+    }
+
+    let fraction = (edge_distance / feather as f64).clamp(0.0, 1.0);
+    Rgba([
+        color[0],
+        color[1],
+        color[2],
+        (color[3] as f64 * fraction).round() as u8,
+    ])
+}
+
+///
+/// Returns the attribute that applies the filter from `feather_filters_svg` to a shape feathered
+/// `feather` pixels, or nothing if it isn't feathered
+///
+pub fn feather_filter_attribute(feather: u32) -> String {
+    if feather == 0 {
+        return String::new();
+    }
+
+    format!(" filter=\"url(#{})\"", filter_id(feather))
+}
+
+///
+/// Returns SVG defining a blur filter for each of `feathers` (in pixels, before `scale`), for
+/// `feather_filter_attribute` to refer to
+///
+/// A blur softens both sides of an edge, so it's only close to the raster's fade
+///
+pub fn feather_filters_svg(feathers: &BTreeSet<u32>, scale: f64) -> String {
+    let filters: String = feathers
+        .iter()
+        .filter(|&&feather| feather > 0)
+        .map(|&feather| {
+            // Leave room for the blur to spread past the shape's bounding box
+            format!(
+                "<filter id=\"{}\" x=\"-50%\" y=\"-50%\" width=\"200%\" height=\"200%\"><feGaussianBlur stdDeviation=\"{:.2}\"/></filter>",
+                filter_id(feather),
+                feather as f64 * scale / 2.0
+            )
+        })
+        .collect();
+
+    if filters.is_empty() {
+        return filters;
+    }
+
+    format!("<defs>{}</defs>", filters)
+}
+
+fn filter_id(feather: u32) -> String {
+    format!("feather-{}", feather)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feathered() {
+        let color = Rgba([10, 20, 30, 200]);
+        assert_eq!(feathered(color, 0.5, 0), color);
+        assert_eq!(feathered(color, 0.0, 4), Rgba([10, 20, 30, 0]));
+        assert_eq!(feathered(color, 1.0, 4), Rgba([10, 20, 30, 50]));
+        assert_eq!(feathered(color, 10.0, 4), color);
+    }
+
+    #[test]
+    fn test_feather_filters_svg() {
+        assert_eq!(feather_filter_attribute(0), "");
+        assert_eq!(feather_filter_attribute(3), " filter=\"url(#feather-3)\"");
+
+        assert_eq!(feather_filters_svg(&BTreeSet::from([0]), 1.0), "");
+        let svg = feather_filters_svg(&BTreeSet::from([2, 3]), 2.0);
+        assert!(svg.starts_with("<defs><filter id=\"feather-2\""));
+        assert!(svg.contains("<feGaussianBlur stdDeviation=\"2.00\"/>"));
+        assert!(svg.contains("<filter id=\"feather-3\""));
+        assert!(svg.contains("<feGaussianBlur stdDeviation=\"3.00\"/>"));
+        assert!(svg.ends_with("</filter></defs>"));
+    }
+}
@@ -0,0 +1,91 @@
+use image::{ImageBuffer, Rgba};
+use std::str::FromStr;
+
+/// Distance between the tops of consecutive hatch lines, in pixels of the searched image
+pub const HATCH_SPACING: f64 = 3.0;
+/// Thickness of each hatch line, in pixels of the searched image
+pub const HATCH_WIDTH: f64 = 1.0;
+
+///
+/// How filled shapes (everything but curves) are painted
+///
+/// Hatched shapes are only painted on evenly spaced horizontal lines, for a pen-and-ink look
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FillStyle {
+    Solid,
+    Hatch,
+}
+
+impl FromStr for FillStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "solid" => Ok(FillStyle::Solid),
+            "hatch" => Ok(FillStyle::Hatch),
+            _ => Err(format!("Unsupported fill style: {}", s)),
+        }
+    }
+}
+
+///
+/// Determine if row `y` of an image drawn at `scale` times the searched image's size is on a hatch
+/// line
+///
+/// The lines are the rows whose tops are within `HATCH_WIDTH` below a multiple of `HATCH_SPACING`,
+/// which is the same band the SVG pattern fills
+///
+pub fn is_hatch_row(y: u32, scale: f64) -> bool {
+    (y as f64 / scale) % HATCH_SPACING < HATCH_WIDTH
+}
+
+///
+/// Returns `painted` with the rows between hatch lines restored from `original`
+///
+/// `painted` is `original` with a shape painted on at `scale`, so only the shape's pixels on the
+/// hatch lines are kept
+///
+pub fn hatch(
+    mut painted: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    original: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    scale: f64,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    for (y, row) in painted.enumerate_rows_mut() {
+        if is_hatch_row(y, scale) {
+            continue;
+        }
+
+        for (x, _, pixel) in row {
+            *pixel = *original.get_pixel(x, y);
+        }
+    }
+
+    painted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(FillStyle::from_str("solid"), Ok(FillStyle::Solid));
+        assert_eq!(FillStyle::from_str("HATCH"), Ok(FillStyle::Hatch));
+        assert!(FillStyle::from_str("dots").is_err());
+    }
+
+    #[test]
+    fn test_is_hatch_row() {
+        assert!(is_hatch_row(0, 1.0));
+        assert!(!is_hatch_row(1, 1.0));
+        assert!(!is_hatch_row(2, 1.0));
+        assert!(is_hatch_row(3, 1.0));
+
+        // At twice the size, lines are twice as thick and twice as far apart
+        assert!(is_hatch_row(0, 2.0));
+        assert!(is_hatch_row(1, 2.0));
+        assert!(!is_hatch_row(2, 2.0));
+        assert!(is_hatch_row(6, 2.0));
+    }
+}
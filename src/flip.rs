@@ -0,0 +1,66 @@
+use image::imageops::{flip_horizontal, flip_vertical};
+use image::{ImageBuffer, Rgba};
+use std::str::FromStr;
+
+///
+/// How to mirror the source image before approximating it
+///
+/// Horizontal flips left to right, vertical flips top to bottom, and both does both (the same as
+/// turning it halfway around)
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Flip {
+    Horizontal,
+    Vertical,
+    Both,
+}
+
+impl Flip {
+    ///
+    /// Returns `image` flipped
+    ///
+    pub fn apply(&self, image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        match self {
+            Flip::Horizontal => flip_horizontal(image),
+            Flip::Vertical => flip_vertical(image),
+            Flip::Both => flip_vertical(&flip_horizontal(image)),
+        }
+    }
+}
+
+impl FromStr for Flip {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "horizontal" => Ok(Flip::Horizontal),
+            "vertical" => Ok(Flip::Vertical),
+            "both" => Ok(Flip::Both),
+            _ => Err(format!("Unsupported flip: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        // Each pixel's red is its x and its green is its y
+        let image = ImageBuffer::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+
+        assert_eq!(
+            Flip::Horizontal.apply(&image).get_pixel(0, 0),
+            &Rgba([2, 0, 0, 255])
+        );
+        assert_eq!(
+            Flip::Vertical.apply(&image).get_pixel(0, 0),
+            &Rgba([0, 1, 0, 255])
+        );
+        assert_eq!(
+            Flip::Both.apply(&image).get_pixel(0, 0),
+            &Rgba([2, 1, 0, 255])
+        );
+    }
+}
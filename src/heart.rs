@@ -0,0 +1,334 @@
+use crate::mutate_mode::MutateMode;
+use crate::point::PrimitivePoint;
+use crate::primitive_image::PrimitiveImage;
+use crate::shape::{shape_key, RandomShape, Shape, ShapeType};
+use crate::symmetry::Symmetry;
+use crate::utilities::{clamp, fill_polygon, polygon_contains, radians, rgb_to_hex, SizeLimits};
+use image::ImageBuffer;
+use image::Pixel;
+use image::Rgba;
+use rand::Rng;
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::max;
+use std::f64::consts::PI;
+
+const MAXIMUM_MUTATION_ATTEMPTS: u32 = 100_000;
+// Points sampled from the parametric curve to build the outline that's filled
+const OUTLINE_POINTS: usize = 64;
+// Points used for the SVG's bezier curve, which smooths between them
+const SVG_POINTS: usize = 16;
+
+#[derive(Debug, Copy, Clone, Hash, Serialize, Deserialize)]
+pub struct Heart {
+    #[serde(with = "crate::utilities::rgba_serde")]
+    pub color: image::Rgba<u8>,
+    center: PrimitivePoint,
+    size: u32,  // Half of the width, in pixels
+    angle: u32, // In degrees
+}
+
+impl Heart {
+    ///
+    /// Create a heart centered on `center`, `2 * size` pixels wide, rotated clockwise by `angle`
+    /// degrees
+    ///
+    pub fn new(center: PrimitivePoint, size: u32, angle: u32) -> Heart {
+        Heart {
+            color: Rgba([0, 0, 0, 128]),
+            center,
+            size,
+            angle,
+        }
+    }
+
+    ///
+    /// Returns this shape with its color set to `color`, for building shapes by hand
+    ///
+    pub fn with_color(mut self, color: Rgba<u8>) -> Self {
+        self.color = color;
+        self
+    }
+
+    ///
+    /// Determine if this heart is valid
+    ///
+    /// A heart is valid if it's big enough to look like one, and no wider than the image or
+    /// `size_limits` allow
+    ///
+    fn is_valid(&self, width: u32, height: u32, size_limits: SizeLimits) -> bool {
+        let (min_size, max_size) =
+            size_limits.radius_range(width, height, 2, max_size(width, height));
+        self.size >= min_size && self.size <= max_size
+    }
+
+    ///
+    /// Returns `points` evenly spaced (by parameter) points on the outline, in image coordinates
+    ///
+    /// Uses the classic parametric heart x = 16sin^3(t), y = 13cos(t) - 5cos(2t) - 2cos(3t) - cos(4t),
+    /// divided by 16 so the heart is `2 * size` wide, and flipped since image y points down
+    ///
+    fn outline(&self, points: usize) -> Vec<(f64, f64)> {
+        let size = self.size as f64 / 16.0;
+        let (sin_a, cos_a) = radians(self.angle as f64).sin_cos();
+
+        (0..points)
+            .map(|i| {
+                let t = 2.0 * PI * i as f64 / points as f64;
+
+                let x = size * 16.0 * t.sin().powi(3);
+                let y = -size
+                    * (13.0 * t.cos()
+                        - 5.0 * (2.0 * t).cos()
+                        - 2.0 * (3.0 * t).cos()
+                        - (4.0 * t).cos());
+
+                // Rotate the same way as `rotate_point`
+                (
+                    self.center.x as f64 + x * cos_a - y * sin_a,
+                    self.center.y as f64 + x * sin_a + y * cos_a,
+                )
+            })
+            .collect()
+    }
+}
+
+/// The largest `size` a heart can have in a `width` x `height` image (at least 2, for tiny images)
+fn max_size(width: u32, height: u32) -> u32 {
+    max(max(width, height) / 2, 2)
+}
+
+impl RandomShape for Heart {
+    ///
+    /// Generate a random Heart around `center`
+    ///
+    fn random_at(
+        center: PrimitivePoint,
+        width: u32,
+        height: u32,
+        _border_extension: i32,
+        size_limits: SizeLimits,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let (min_size, max_size) =
+            size_limits.radius_range(width, height, 2, max(max(width, height) / 4, 3) - 1);
+        let size = rng.gen_range(min_size..=max_size);
+        let angle = rng.gen_range(0..360);
+
+        Heart::new(center, size, angle)
+    }
+}
+
+impl Shape for Heart {
+    fn mutate(
+        &mut self,
+        width: u32,
+        height: u32,
+        size_limits: SizeLimits,
+        mode: MutateMode,
+        rng: &mut impl Rng,
+    ) {
+        let normal = Normal::new(0.0, 5.0 * mode.step_scale()).unwrap();
+        let (min_size, max_size) =
+            size_limits.radius_range(width, height, 2, max_size(width, height));
+
+        let mut i = 0;
+        loop {
+            i += 1;
+            for r in mode.parameters(3, rng) {
+                match r {
+                    0 => self.center.mutate(width, height, mode, rng),
+                    1 => {
+                        self.size = clamp(
+                            self.size as i32 + (rng.sample(normal) as i32),
+                            min_size as i32,
+                            max_size as i32,
+                        ) as u32
+                    }
+                    2 => {
+                        self.angle =
+                            (self.angle as i32 + (rng.sample(normal) as i32)).rem_euclid(360) as u32
+                    }
+                    _ => {}
+                }
+            }
+
+            if self.is_valid(width, height, size_limits) {
+                break;
+            }
+            if i > MAXIMUM_MUTATION_ATTEMPTS {
+                panic!("Heart: Too many mutation loops!");
+            }
+        }
+    }
+
+    ///
+    /// The parametric heart in `outline` encloses 180π before it's scaled by `size / 16`
+    ///
+    fn area(&self) -> f64 {
+        let size = self.size as f64 / 16.0;
+        180.0 * PI * size * size
+    }
+
+    fn contains_pixel(&self, x: i32, y: i32) -> bool {
+        polygon_contains(&self.outline(OUTLINE_POINTS), x as f64, y as f64)
+    }
+
+    fn get_pixels(&self) -> Vec<PrimitivePoint> {
+        fill_polygon(&self.outline(OUTLINE_POINTS))
+    }
+
+    fn as_svg(&self, scale: f64) -> String {
+        format!(
+            "<path fill=\"{}\" fill-opacity=\"{:.5}\" d=\"{}\"/>",
+            rgb_to_hex(self.color),
+            self.color.0[3] as f64 / 255.0,
+            self.as_svg_path_data(scale).unwrap()
+        )
+    }
+
+    fn as_svg_path_data(&self, scale: f64) -> Option<String> {
+        let points: Vec<(f64, f64)> = self
+            .outline(SVG_POINTS)
+            .iter()
+            .map(|(x, y)| (x * scale, y * scale))
+            .collect();
+        let n = points.len();
+
+        // Closed Catmull-Rom spline through the points, as cubic beziers
+        let mut path = format!("M{:.2},{:.2}", points[0].0, points[0].1);
+        for i in 0..n {
+            let p0 = points[(i + n - 1) % n];
+            let p1 = points[i];
+            let p2 = points[(i + 1) % n];
+            let p3 = points[(i + 2) % n];
+
+            path += &format!(
+                " C{:.2},{:.2} {:.2},{:.2} {:.2},{:.2}",
+                p1.0 + (p2.0 - p0.0) / 6.0,
+                p1.1 + (p2.1 - p0.1) / 6.0,
+                p2.0 - (p3.0 - p1.0) / 6.0,
+                p2.1 - (p3.1 - p1.1) / 6.0,
+                p2.0,
+                p2.1
+            );
+        }
+        path += " Z";
+
+        Some(path)
+    }
+
+    fn paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
+        let (width, height) = image.dimensions();
+
+        for pixel in self.get_pixels().iter() {
+            if pixel.x >= 0 && pixel.y >= 0 && pixel.x < width as i32 && pixel.y < height as i32 {
+                let pix = image.get_pixel_mut(pixel.x as u32, pixel.y as u32);
+                pix.blend(&self.color);
+            }
+        }
+    }
+
+    fn scaled_paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, scale: f64) {
+        let scaled_self = Heart {
+            center: PrimitivePoint::new(
+                (self.center.x as f64 * scale) as i32,
+                (self.center.y as f64 * scale) as i32,
+            ),
+            size: (self.size as f64 * scale) as u32,
+            color: self.color,
+            angle: self.angle,
+        };
+
+        scaled_self.paint_into(image);
+    }
+
+    fn set_color_using(&mut self, image: &PrimitiveImage) {
+        self.color = image.color_for_shape(self);
+    }
+
+    fn get_color(&self) -> Rgba<u8> {
+        self.color
+    }
+
+    fn clear_rotation(&mut self) {
+        self.angle = 0;
+    }
+
+    fn mirror(&self, symmetry: Symmetry, width: u32, height: u32) -> Option<Box<dyn Shape>> {
+        let mut mirrored = *self;
+        mirrored.center = self.center.mirrored(symmetry, width, height);
+
+        // Hearts are symmetric left to right, so flipping one upside down is the same as
+        // reflecting it and rotating it by 180 degrees
+        mirrored.angle = match symmetry {
+            Symmetry::None => self.angle,
+            Symmetry::Horizontal => (360 - self.angle % 360) % 360,
+            Symmetry::Vertical => (540 - self.angle % 360) % 360,
+        };
+
+        Some(Box::new(mirrored))
+    }
+
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::Heart
+    }
+
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap()
+    }
+
+    fn canonical_key(&self) -> u64 {
+        shape_key(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_pixels() {
+        let heart = Heart::new(PrimitivePoint::new(20, 20), 10, 0);
+        let pixels = heart.get_pixels();
+
+        // The center is inside, corners of the bounding box are not
+        assert!(pixels.contains(&PrimitivePoint::new(20, 20)));
+        assert!(!pixels.contains(&PrimitivePoint::new(10, 10)));
+        assert!(!pixels.contains(&PrimitivePoint::new(30, 30)));
+        assert!(!pixels.contains(&PrimitivePoint::new(0, 0)));
+
+        // The lobes are on top and the point is at the bottom
+        assert!(pixels.contains(&PrimitivePoint::new(15, 17)));
+        assert!(pixels.contains(&PrimitivePoint::new(20, 30)));
+        assert!(!pixels.contains(&PrimitivePoint::new(20, 12)));
+
+        // Upside down, the point is on top
+        let flipped = Heart::new(PrimitivePoint::new(20, 20), 10, 180);
+        assert!(flipped.get_pixels().contains(&PrimitivePoint::new(20, 10)));
+    }
+
+    #[test]
+    fn test_as_svg() {
+        let mut heart = Heart::new(PrimitivePoint::new(20, 20), 10, 0);
+        heart.color = Rgba([240, 64, 15, 128]);
+        let svg = heart.as_svg(2.0);
+
+        assert!(svg.starts_with("<path fill=\"#F0400F\" fill-opacity=\"0.50196\" d=\""));
+        assert_eq!(svg.matches(" C").count(), SVG_POINTS);
+
+        // The first point (t = 0) is the notch between the lobes, 5/16 of the size above the center
+        assert!(svg.contains("d=\"M40.00,33.75 C"));
+    }
+
+    #[test]
+    fn test_area() {
+        for angle in [0, 45] {
+            let heart = Heart::new(PrimitivePoint::new(50, 50), 30, angle);
+
+            let pixels = heart.get_pixels().len() as f64;
+            assert!((heart.area() - pixels).abs() / pixels < 0.05);
+        }
+    }
+}
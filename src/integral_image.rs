@@ -0,0 +1,87 @@
+use image::{ImageBuffer, Rgba};
+
+///
+/// Per-channel summed-area table of an image's RGB channels
+///
+/// Lets the sum (and so the mean) of any axis-aligned rectangle of pixels be found in O(1)
+///
+#[derive(Debug, Clone)]
+pub struct IntegralImage {
+    width: u32,
+    height: u32,
+    // (width + 1) x (height + 1), so the first row and column are 0 and lookups need no branches
+    sums: Vec<[u64; 3]>,
+}
+
+impl IntegralImage {
+    pub fn new(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> IntegralImage {
+        let (width, height) = image.dimensions();
+        let stride = (width + 1) as usize;
+        let mut sums = vec![[0; 3]; stride * (height + 1) as usize];
+
+        for y in 0..height as usize {
+            let mut row_sum = [0u64; 3];
+            for x in 0..width as usize {
+                let pixel = image.get_pixel(x as u32, y as u32);
+                for c in 0..3 {
+                    row_sum[c] += pixel[c] as u64;
+                    sums[(y + 1) * stride + x + 1][c] = sums[y * stride + x + 1][c] + row_sum[c];
+                }
+            }
+        }
+
+        IntegralImage {
+            width,
+            height,
+            sums,
+        }
+    }
+
+    ///
+    /// Returns the RGB sums and pixel count of the rectangle from (`min_x`, `min_y`) to
+    /// (`max_x`, `max_y`), inclusive, after clipping it to the image
+    ///
+    pub fn sum(&self, min_x: i32, min_y: i32, max_x: i32, max_y: i32) -> ([u64; 3], u64) {
+        let min_x = min_x.max(0) as usize;
+        let min_y = min_y.max(0) as usize;
+        let max_x = max_x.min(self.width as i32 - 1);
+        let max_y = max_y.min(self.height as i32 - 1);
+
+        if max_x < min_x as i32 || max_y < min_y as i32 {
+            return ([0; 3], 0);
+        }
+
+        // Exclusive ends, which are also the indices into the padded table
+        let end_x = max_x as usize + 1;
+        let end_y = max_y as usize + 1;
+        let stride = (self.width + 1) as usize;
+
+        let mut result = [0; 3];
+        for (c, channel) in result.iter_mut().enumerate() {
+            *channel = self.sums[end_y * stride + end_x][c] + self.sums[min_y * stride + min_x][c]
+                - self.sums[min_y * stride + end_x][c]
+                - self.sums[end_y * stride + min_x][c];
+        }
+
+        (result, ((end_x - min_x) * (end_y - min_y)) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sum() {
+        let image = ImageBuffer::from_fn(5, 4, |x, y| Rgba([x as u8, y as u8, 1, 255]));
+        let integral = IntegralImage::new(&image);
+
+        assert_eq!(integral.sum(0, 0, 4, 3), ([40, 30, 20], 20));
+        assert_eq!(integral.sum(1, 1, 2, 2), ([6, 6, 4], 4));
+        assert_eq!(integral.sum(3, 2, 3, 2), ([3, 2, 1], 1));
+
+        // Rectangles are clipped to the image
+        assert_eq!(integral.sum(-10, -10, 0, 0), ([0, 0, 1], 1));
+        assert_eq!(integral.sum(10, 10, 20, 20), ([0, 0, 0], 0));
+    }
+}
@@ -4,13 +4,37 @@ extern crate rand;
 #[macro_use]
 extern crate log;
 
+pub mod aberration;
+pub mod alpha_by_size;
+pub mod bezier_path;
+pub mod color_profile;
+pub mod color_sample;
+pub mod color_stats;
+pub mod cross;
 pub mod cubic_bezier;
 pub mod ellipse;
+pub mod error;
+pub mod feather;
+pub mod fill_style;
+pub mod flip;
+pub mod heart;
+pub mod integral_image;
+pub mod mask;
+pub mod metrics;
+pub mod mutate_mode;
 pub mod point;
 pub mod primitive_image;
 pub mod quadratic_bezier;
 pub mod rectangle;
+pub mod run_stats;
 pub mod runner;
+pub mod score_cache;
+pub mod serialization;
 pub mod shape;
+pub mod signature;
+pub mod sort_order;
+pub mod svg_format;
+pub mod symmetry;
+pub mod teardrop;
 pub mod triangle;
 pub mod utilities;
@@ -6,53 +6,356 @@ extern crate simplelog;
 mod arguments;
 
 use image::Rgba;
-use primitive_image::primitive_image::PrimitiveImage;
-use primitive_image::runner::run;
+use indicatif::{ProgressBar, ProgressStyle};
+use primitive_image::point::PrimitivePoint;
+use primitive_image::primitive_image::{thumbnail_path, LoadOptions, PrimitiveImage};
+use primitive_image::run_stats::RunStats;
+use primitive_image::runner::run_with_progress;
+use primitive_image::shape::ShapeType;
+use primitive_image::signature::Signature;
+use primitive_image::utilities::{get_rng, SegmentLimits, SizeLimits};
 use simplelog::*;
-use std::i64;
+use std::io::{stderr, IsTerminal, Write};
+use std::process;
+use std::time::{Duration, Instant};
+use structopt::clap::{Error, ErrorKind};
 use structopt::StructOpt;
 
+/// Width of the strokes --svg-seam-fix adds, in pixels of the output
+const SEAM_STROKE_WIDTH: f64 = 0.5;
+/// Shapes to add when -n isn't given
+const DEFAULT_SHAPES: u32 = 100;
+/// Clusters to find the dominant background color among, for --dominant-background
+const DOMINANT_COLORS: usize = 4;
+
 fn main() {
     let opt = arguments::Opt::from_args();
 
     let log_level = match opt.v {
-        0 => LevelFilter::Off,
+        0 => LevelFilter::Warn,
         1 => LevelFilter::Info,
         _ => LevelFilter::Trace,
     };
 
     SimpleLogger::init(log_level, Config::default()).unwrap();
 
-    let input_path = opt.in_path;
+    if let Some(arguments::Command::Render {
+        in_path,
+        out_path,
+        size,
+        dpi,
+        color_profile,
+    }) = opt.cmd
+    {
+        let dpi = dpi.map(|dpi| or_exit(check_positive("--dpi", dpi)));
+        let mut image = PrimitiveImage::load_shapes_json(in_path);
+        if let Some(size) = size {
+            image.set_output_size(size);
+        }
+        image.set_dpi(dpi);
+        image.set_color_profile(color_profile);
+        image.save_to(out_path);
+        return;
+    }
+
+    if opt.list_shapes {
+        print!("{}", ShapeType::list());
+        return;
+    }
 
-    // Parse background color if provided
-    let background = if opt.background_color.is_some() {
-        let background_color: String = opt.background_color.unwrap();
+    let (input_path, output_path) = match (opt.in_path, opt.out_path) {
+        (Some(input_path), Some(output_path)) => (input_path, output_path),
+        _ => Error::with_description(
+            "--input and --output are required unless a subcommand is used",
+            ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+
+    let background = opt
+        .background_color
+        .map(|color| or_exit(parse_color(&color)));
+    let fixed_color = opt.fixed_color.map(|color| or_exit(parse_color(&color)));
+    let seed_grid = opt.seed_grid.map(|grid| or_exit(parse_grid(&grid)));
+    let seed_points = opt.seed_points.map(|points| or_exit(parse_points(&points)));
+    let dpi = opt.dpi.map(|dpi| or_exit(check_positive("--dpi", dpi)));
+    let edge_snap = or_exit(check_fraction("--edge-snap", opt.edge_snap));
+    let luma_weight = or_exit(check_fraction("--luma-weight", opt.luma_weight));
+    let size_limits = or_exit(SizeLimits::try_new(opt.min_size, opt.max_size));
+    let signature_opacity = or_exit(check_fraction("--signature-opacity", opt.signature_opacity));
 
-        if background_color.len() != 6 {
-            panic!("Incorrect background color format: {}", background_color);
+    let scale_to = match opt.preview_scale {
+        Some(preview_scale) => PrimitiveImage::preview_scale_to(&input_path, preview_scale),
+        None => Ok(opt.scale_to),
+    };
+    let loaded = scale_to.and_then(|scale_to| {
+        PrimitiveImage::from_path(
+            input_path,
+            scale_to,
+            background,
+            LoadOptions {
+                max_dimension: opt.max_dimension,
+                allow_upscale: opt.allow_upscale,
+                flip: opt.flip,
+                auto_levels: opt.auto_levels,
+            },
+        )
+    });
+    let mut image = match loaded {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
         }
+    };
+    image.set_crop_mask(opt.crop_mask, opt.crop_mask_score);
+    image.set_alpha_mask(opt.alpha_mask);
+    image.set_min_shape_area(opt.min_shape_area_frac, opt.coarse_shapes);
+    image.set_overlap_penalty(opt.overlap_penalty);
+    image.set_stroke_width(opt.stroke_width);
+    image.set_feather(opt.feather);
+    image.set_pool(opt.pool);
+    image.set_commit_batch(opt.commit_batch.get());
+    image.set_shape_time_budget(opt.time_budget_per_shape.map(Duration::from_secs_f64));
+    image.set_svg_format(opt.format, opt.merge_tolerance);
+    image.set_dpi(dpi);
+    image.set_color_profile(opt.color_profile);
+    image.set_aberration(opt.aberration);
+    image.set_signature(
+        opt.signature
+            .map(|text| Signature::new(text, opt.signature_corner, signature_opacity)),
+    );
+    image.set_symmetry(opt.symmetry);
+    image.set_fill_style(opt.fill_style);
+    image.set_fixed_color(fixed_color);
+    image.set_color_sample(opt.color_sample);
+    let shape_alphas = [
+        (ShapeType::Triangle, opt.alpha_triangle),
+        (ShapeType::CubicBezier, opt.alpha_cubic),
+        (ShapeType::QuadraticBezier, opt.alpha_quadratic),
+        (ShapeType::Rectangle, opt.alpha_rectangle),
+        (ShapeType::Ellipse, opt.alpha_ellipse),
+        (ShapeType::Heart, opt.alpha_heart),
+        (ShapeType::Teardrop, opt.alpha_teardrop),
+        (ShapeType::BezierPath, opt.alpha_path),
+        (ShapeType::Cross, opt.alpha_cross),
+    ];
+    for (shape_type, alpha) in shape_alphas {
+        if let Some(alpha) = alpha {
+            image.set_shape_alpha(shape_type, alpha);
+        }
+    }
+    image.set_alpha_by_size(opt.alpha_by_size);
+    image.set_min_contrast(opt.min_contrast);
+    image.set_score_alpha(opt.score_alpha);
+    image.set_plateau_moves(opt.plateau_moves);
+    image.set_protect_converged(opt.protect_converged, opt.protected_overlap);
+    image.set_max_layers(opt.max_layers);
+    image.set_center_bias(opt.center_bias);
+    or_exit(
+        image
+            .set_score_region(opt.score_region)
+            .map_err(|err| err.to_string()),
+    );
+    image.set_channel_weights(opt.channel_weights);
+    image.set_metric(opt.metric, luma_weight);
+    image.set_score_sample(opt.score_sample, opt.seed);
+    if opt.svg_seam_fix {
+        let shape_rendering = opt
+            .shape_rendering
+            .or_else(|| Some(String::from("crispEdges")));
+        image.set_svg_seam_fix(shape_rendering, Some(SEAM_STROKE_WIDTH));
+    } else {
+        image.set_svg_seam_fix(opt.shape_rendering, None);
+    }
+    image.set_size_limits(size_limits);
+    image.set_mutate_mode(opt.mutate_mode);
+    image.set_rotation(!opt.no_rotation);
+    image.set_squares(opt.squares);
+    image.set_edge_snap(edge_snap);
+    image.set_segment_limits(SegmentLimits {
+        min: opt.min_bezier_segments,
+        max: opt.max_bezier_segments,
+        fixed: opt.bezier_segments,
+    });
 
-        let mut data: [u8; 4] = [0, 0, 0, 0];
+    if opt.dominant_background && background.is_none() {
+        if let Some(&color) = image.dominant_colors(DOMINANT_COLORS).first() {
+            image.set_background(color);
+        }
+    }
 
-        data[0] = i64::from_str_radix(&background_color[0..2], 16)
-            .ok()
-            .unwrap() as u8;
-        data[1] = i64::from_str_radix(&background_color[2..4], 16)
-            .ok()
-            .unwrap() as u8;
-        data[2] = i64::from_str_radix(&background_color[4..6], 16)
-            .ok()
-            .unwrap() as u8;
+    if let Some(k) = opt.palette_extract {
+        or_exit(
+            image
+                .extract_palette(k.get())
+                .ok_or_else(|| String::from("Found no colors in the input for --palette-extract")),
+        );
+    }
 
-        Some(Rgba(data))
-    } else {
+    if let Some(append) = opt.append {
+        image.append_shapes_json(append);
+    } else if opt.seed_background_rect {
+        image.seed_background_rect();
+    }
+
+    if let Some((rows, cols)) = seed_grid {
+        image.seed_grid(rows, cols);
+    }
+
+    if let Some(seed_points) = seed_points {
+        image.seed_points(&seed_points, &opt.shape, &mut get_rng(opt.seed));
+    }
+
+    let n = opt.n.unwrap_or_else(|| {
+        eprintln!(
+            "Adding {} shapes. This image's detail suggests about {} (set the number with -n)",
+            DEFAULT_SHAPES,
+            image.suggest_shape_count()
+        );
+        DEFAULT_SHAPES
+    });
+
+    // The bar would be garbled by log output, and is useless when nobody is watching
+    let progress = if opt.no_progress || opt.v > 0 || !stderr().is_terminal() {
         None
+    } else {
+        let progress = ProgressBar::new(n as u64);
+        progress.set_style(
+            ProgressStyle::with_template("[{elapsed_precise}] {bar:40} {pos}/{len} shapes, {msg}")
+                .unwrap(),
+        );
+        Some(progress)
     };
 
-    let mut image = PrimitiveImage::from_path(input_path, opt.scale_to, background);
+    let mut events = opt.emit_events.then(stderr);
+    let start = Instant::now();
+    let record = run_with_progress(
+        &mut image,
+        n,
+        opt.max_age,
+        opt.seed,
+        opt.shape,
+        opt.max_failed_attempts,
+        progress.as_ref(),
+        events.as_mut().map(|events| events as &mut dyn Write),
+    );
+    let elapsed = start.elapsed();
+    if record.added < n {
+        eprintln!(
+            "Stopped after {} failed attempts in a row, with {} of {} shapes added",
+            opt.max_failed_attempts, record.added, n
+        );
+    }
+
+    if let Some(max_elements) = opt.max_elements {
+        image.prune_to_budget(max_elements);
+    }
+
+    if let Some(paint_order) = opt.paint_order {
+        image.sort_shapes(paint_order);
+    }
+
+    if opt.stats {
+        print!("{}", image.color_stats());
+        println!("Shapes cover {:.1}% of the image", 100.0 * image.coverage());
+    }
+
+    if let Some(stats_json) = opt.stats_json {
+        RunStats::new(&image, opt.seed, &record, elapsed).save_json(stats_json);
+    }
+
+    if let Some(compare_output) = opt.compare_output {
+        image.save_side_by_side(compare_output);
+    }
+
+    if let Some(layers_dir) = opt.layers_dir {
+        image.save_layers(layers_dir);
+    }
+
+    if let Some(frames_dir) = opt.frames_dir {
+        image.save_frames(frames_dir);
+    }
+
+    if let Some(size) = opt.thumbnail {
+        image.save_thumbnail(thumbnail_path(&output_path), size);
+    }
+
+    image.save_to(output_path);
+}
+
+/// Returns the parsed value of an option, or exits with `parsed`'s error like other bad arguments
+fn or_exit<T>(parsed: Result<T, String>) -> T {
+    parsed.unwrap_or_else(|err| Error::with_description(&err, ErrorKind::InvalidValue).exit())
+}
+
+/// Returns `value` if it's positive, for options like --dpi that can't be 0
+fn check_positive(option: &str, value: f64) -> Result<f64, String> {
+    if value > 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("{} must be positive, got {}", option, value))
+    }
+}
+
+/// Returns `value` if it's from 0 to 1, for options like --edge-snap that are chances or weights
+fn check_fraction(option: &str, value: f64) -> Result<f64, String> {
+    if (0.0..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("{} must be from 0 to 1, got {}", option, value))
+    }
+}
+
+/// Parse a color in RRGGBB format
+fn parse_color(color: &str) -> Result<Rgba<u8>, String> {
+    let error = || format!("Incorrect color format (expected RRGGBB): {}", color);
+    if color.len() != 6 || !color.is_ascii() {
+        return Err(error());
+    }
+
+    let mut data: [u8; 4] = [0, 0, 0, 0];
+
+    data[0] = u8::from_str_radix(&color[0..2], 16).map_err(|_| error())?;
+    data[1] = u8::from_str_radix(&color[2..4], 16).map_err(|_| error())?;
+    data[2] = u8::from_str_radix(&color[4..6], 16).map_err(|_| error())?;
+
+    Ok(Rgba(data))
+}
+
+/// Parse a grid size in rows,cols format
+fn parse_grid(grid: &str) -> Result<(u32, u32), String> {
+    let error = || format!("Incorrect grid format (expected rows,cols): {}", grid);
+    let sizes = grid
+        .split(',')
+        .map(|size| size.trim().parse().map_err(|_| error()))
+        .collect::<Result<Vec<u32>, String>>()?;
+
+    match sizes[..] {
+        [0, _] | [_, 0] => Err(format!(
+            "A grid needs at least one row and column: {}",
+            grid
+        )),
+        [rows, cols] => Ok((rows, cols)),
+        _ => Err(error()),
+    }
+}
 
-    run(&mut image, opt.n, opt.max_age, opt.seed, opt.shape);
+/// Parse points in x,y;x,y format
+fn parse_points(points: &str) -> Result<Vec<PrimitivePoint>, String> {
+    points
+        .split(';')
+        .map(|point| {
+            let error = || format!("Incorrect point format (expected x,y): {}", point);
+            let coordinates = point
+                .split(',')
+                .map(|coordinate| coordinate.trim().parse().map_err(|_| error()))
+                .collect::<Result<Vec<i32>, String>>()?;
 
-    image.save_to(opt.out_path);
+            match coordinates[..] {
+                [x, y] => Ok(PrimitivePoint::new(x, y)),
+                _ => Err(error()),
+            }
+        })
+        .collect()
 }
@@ -0,0 +1,96 @@
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CropMask {
+    Circle,
+    Ellipse,
+}
+
+impl CropMask {
+    ///
+    /// Determine if the pixel at (`x`, `y`) survives the crop of a `width` x `height` image
+    ///
+    /// Circles are inscribed in the image's shorter side and centered, ellipses fill the whole image
+    ///
+    pub fn contains(&self, x: u32, y: u32, width: u32, height: u32) -> bool {
+        let (center_x, center_y, radius_x, radius_y) = self.geometry(width, height);
+
+        // Test the center of the pixel, not its corner
+        let dx = (x as f64 + 0.5 - center_x) / radius_x;
+        let dy = (y as f64 + 0.5 - center_y) / radius_y;
+
+        dx * dx + dy * dy <= 1.0
+    }
+
+    ///
+    /// Return an SVG `clipPath` matching this mask for a `width` x `height` image
+    ///
+    pub fn as_svg_clip_path(&self, id: &str, width: u32, height: u32) -> String {
+        let (center_x, center_y, radius_x, radius_y) = self.geometry(width, height);
+
+        format!(
+            "<clipPath id=\"{}\"><ellipse cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" /></clipPath>",
+            id, center_x, center_y, radius_x, radius_y
+        )
+    }
+
+    fn geometry(&self, width: u32, height: u32) -> (f64, f64, f64, f64) {
+        let center_x = width as f64 / 2.0;
+        let center_y = height as f64 / 2.0;
+
+        match self {
+            CropMask::Circle => {
+                let radius = center_x.min(center_y);
+                (center_x, center_y, radius, radius)
+            }
+            CropMask::Ellipse => (center_x, center_y, center_x, center_y),
+        }
+    }
+}
+
+impl FromStr for CropMask {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "circle" => Ok(CropMask::Circle),
+            "ellipse" => Ok(CropMask::Ellipse),
+            _ => Err(format!("Unsupported crop mask: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(CropMask::from_str("circle"), Ok(CropMask::Circle));
+        assert_eq!(CropMask::from_str("ELLIPSE"), Ok(CropMask::Ellipse));
+        assert!(CropMask::from_str("square").is_err());
+    }
+
+    #[test]
+    fn test_contains() {
+        // Corners are always cropped, the center never is
+        assert!(!CropMask::Circle.contains(0, 0, 20, 10));
+        assert!(!CropMask::Ellipse.contains(19, 9, 20, 10));
+        assert!(CropMask::Circle.contains(10, 5, 20, 10));
+        assert!(CropMask::Ellipse.contains(10, 5, 20, 10));
+
+        // A circle is limited by the shorter side, the ellipse stretches to fill the longer one
+        assert!(!CropMask::Circle.contains(1, 5, 20, 10));
+        assert!(CropMask::Ellipse.contains(1, 5, 20, 10));
+    }
+
+    #[test]
+    fn test_as_svg_clip_path() {
+        let expected =
+            "<clipPath id=\"crop\"><ellipse cx=\"10\" cy=\"5\" rx=\"5\" ry=\"5\" /></clipPath>";
+        assert_eq!(
+            CropMask::Circle.as_svg_clip_path("crop", 20, 10).as_str(),
+            expected
+        );
+    }
+}
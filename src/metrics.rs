@@ -0,0 +1,950 @@
+use crate::error::PrimitiveError;
+use crate::utilities::rgb_to_ycbcr;
+use image::{ImageBuffer, Rgba};
+use imageproc::stats::root_mean_squared_error;
+use rand::seq::index;
+use rand::Rng;
+use std::str::FromStr;
+
+const SSIM_WINDOW: u32 = 8;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+///
+/// Root Mean Squared Error between two images, over all four channels
+///
+/// Smaller is better; identical images score 0.0
+/// Returns an error if the images' dimensions don't match
+///
+pub fn rmse(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> Result<f64, PrimitiveError> {
+    check_same_dimensions(a, b)?;
+    Ok(root_mean_squared_error(a, b))
+}
+
+///
+/// Root Mean Squared Error between two images, only counting pixels for which `include` returns true
+///
+/// Identical images (or an empty selection) score 0.0
+/// Panics if the images' dimensions don't match
+///
+pub fn masked_rmse(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    include: impl Fn(u32, u32) -> bool,
+) -> f64 {
+    channel_rmse(a, b, 4, include)
+}
+
+///
+/// Root Mean Squared Error between two images' red, green, and blue channels
+///
+/// Alpha is ignored, so it can't add error that no shape color could fix
+/// Smaller is better; identical images score 0.0
+/// Panics if the images' dimensions don't match
+///
+pub fn rgb_rmse(a: &ImageBuffer<Rgba<u8>, Vec<u8>>, b: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> f64 {
+    channel_rmse(a, b, 3, |_, _| true)
+}
+
+///
+/// `rgb_rmse`, only counting pixels for which `include` returns true
+///
+pub fn masked_rgb_rmse(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    include: impl Fn(u32, u32) -> bool,
+) -> f64 {
+    channel_rmse(a, b, 3, include)
+}
+
+/// Root Mean Squared Error over the first `num_channels` channels of the included pixels
+fn channel_rmse(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    num_channels: usize,
+    include: impl Fn(u32, u32) -> bool,
+) -> f64 {
+    weighted_channel_rmse(
+        a,
+        b,
+        num_channels,
+        |x, y| if include(x, y) { 1.0 } else { 0.0 },
+    )
+}
+
+///
+/// Root Mean Squared Error between two images, with each pixel's squared error multiplied by
+/// `weight`
+///
+/// Alpha is only included if `include_alpha` is set. With every weight equal to 1.0 this is the
+/// same as `rmse` (or `rgb_rmse`)
+/// Identical images (or all zero weights) score 0.0
+/// Panics if the images' dimensions don't match
+///
+pub fn weighted_rmse(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    include_alpha: bool,
+    weight: impl Fn(u32, u32) -> f64,
+) -> f64 {
+    weighted_channel_rmse(a, b, if include_alpha { 4 } else { 3 }, weight)
+}
+
+///
+/// How much each of the red, green, and blue channels' errors count towards a score
+///
+/// Parsed from "r,g,b", e.g. "2,1,0.5". Only the ratios matter, so the default of equal weights
+/// scores the same as unweighted RMSE
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChannelWeights(pub [f64; 3]);
+
+impl ChannelWeights {
+    /// Whether every channel counts the same, so there's nothing to weight
+    pub fn is_uniform(&self) -> bool {
+        self.0.iter().all(|&w| w == self.0[0])
+    }
+}
+
+impl Default for ChannelWeights {
+    fn default() -> Self {
+        ChannelWeights([1.0, 1.0, 1.0])
+    }
+}
+
+impl FromStr for ChannelWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let weights: Vec<f64> = s
+            .split(',')
+            .map(|w| w.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("Unsupported channel weights: {}", s))?;
+
+        match weights[..] {
+            [r, g, b] if weights.iter().all(|&w| w >= 0.0) && r + g + b > 0.0 => {
+                Ok(ChannelWeights([r, g, b]))
+            }
+            _ => Err(format!("Unsupported channel weights: {}", s)),
+        }
+    }
+}
+
+///
+/// `weighted_rmse`, with each of the red, green, and blue channels' squared errors also multiplied
+/// by its weight in `channel_weights`
+///
+/// The weights are scaled to average 1.0, and alpha (if included) has a weight of 1.0
+///
+pub fn channel_weighted_rmse(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    channel_weights: ChannelWeights,
+    include_alpha: bool,
+    weight: impl Fn(u32, u32) -> f64,
+) -> f64 {
+    let weights = normalized_channel_weights(channel_weights);
+    let num_channels = if include_alpha { 4 } else { 3 };
+
+    per_channel_weighted_rmse(
+        a,
+        b,
+        &weights[..num_channels],
+        a.enumerate_pixels().map(|(x, y, _)| (x, y)),
+        weight,
+    )
+}
+
+///
+/// `channel_weighted_rmse`, only over the pixels in `sample`
+///
+/// `sample` holds row-major pixel indices, e.g. from `sample_pixels`. Scoring a small sample is
+/// much faster than scoring every pixel, and close to the full score on most images
+///
+pub fn sampled_rmse(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    sample: &[u32],
+    channel_weights: ChannelWeights,
+    include_alpha: bool,
+    weight: impl Fn(u32, u32) -> f64,
+) -> f64 {
+    let weights = normalized_channel_weights(channel_weights);
+    let num_channels = if include_alpha { 4 } else { 3 };
+    let width = a.width();
+
+    per_channel_weighted_rmse(
+        a,
+        b,
+        &weights[..num_channels],
+        sample.iter().map(|i| (i % width, i / width)),
+        weight,
+    )
+}
+
+///
+/// A rectangle of pixels to score, so the rest of the image doesn't count
+///
+/// Parsed from "x,y,width,height", with (x, y) its top left corner. Shapes can still be placed
+/// and rendered anywhere
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ScoreRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ScoreRegion {
+    /// Determine if the pixel at (`x`, `y`) is in the region
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x - self.x < self.width && y - self.y < self.height
+    }
+
+    /// Returns the region in an image scaled by `scale`, at least a pixel across
+    pub fn scaled(&self, scale: f64) -> ScoreRegion {
+        let scale = |value: u32| (value as f64 * scale) as u32;
+
+        ScoreRegion {
+            x: scale(self.x),
+            y: scale(self.y),
+            width: scale(self.width).max(1),
+            height: scale(self.height).max(1),
+        }
+    }
+}
+
+impl FromStr for ScoreRegion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<u32> = s
+            .split(',')
+            .map(|v| v.trim().parse::<u32>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("Unsupported score region: {}", s))?;
+
+        match values[..] {
+            [x, y, width, height] if width > 0 && height > 0 => Ok(ScoreRegion {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(format!("Unsupported score region: {}", s)),
+        }
+    }
+}
+
+///
+/// The color space the error between the target and approximation is measured in
+///
+/// RGB counts every channel (scaled by `ChannelWeights`). YCbCr splits luma from chroma, so
+/// luminance errors, which people notice more, can count for more than color errors
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Metric {
+    Rgb,
+    YCbCr,
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "rgb" => Ok(Metric::Rgb),
+            "ycbcr" => Ok(Metric::YCbCr),
+            _ => Err(format!("Unsupported metric: {}", s)),
+        }
+    }
+}
+
+///
+/// Root Mean Squared Error between two images in Y'CbCr, with luma weighted by `luma_weight` (0
+/// to 1) and each chroma channel by half of the rest
+///
+/// Like `channel_weighted_rmse`, the weights are scaled to average 1.0, alpha (if included) has a
+/// weight of 1.0, and each pixel's squared error is multiplied by `weight`
+/// Identical images (or all zero weights) score 0.0
+/// Panics if the images' dimensions don't match
+///
+pub fn ycbcr_rmse(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    luma_weight: f64,
+    include_alpha: bool,
+    weight: impl Fn(u32, u32) -> f64,
+) -> f64 {
+    per_channel_weighted_ycbcr_rmse(
+        a,
+        b,
+        luma_weight,
+        include_alpha,
+        a.enumerate_pixels().map(|(x, y, _)| (x, y)),
+        weight,
+    )
+}
+
+///
+/// `ycbcr_rmse`, only over the pixels in `sample` (see `sampled_rmse`)
+///
+pub fn sampled_ycbcr_rmse(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    sample: &[u32],
+    luma_weight: f64,
+    include_alpha: bool,
+    weight: impl Fn(u32, u32) -> f64,
+) -> f64 {
+    let width = a.width();
+
+    per_channel_weighted_ycbcr_rmse(
+        a,
+        b,
+        luma_weight,
+        include_alpha,
+        sample.iter().map(|i| (i % width, i / width)),
+        weight,
+    )
+}
+
+///
+/// Pick `fraction` (0 to 1) of a `width` x `height` image's pixels at random, without repeats
+///
+/// Returns their row-major indices in ascending order, for `sampled_rmse`. At least one pixel is
+/// picked from a non-empty image
+///
+pub fn sample_pixels(width: u32, height: u32, fraction: f64, rng: &mut impl Rng) -> Vec<u32> {
+    let num_pixels = (width * height) as usize;
+    let amount = ((num_pixels as f64 * fraction.clamp(0.0, 1.0)).round() as usize)
+        .clamp(num_pixels.min(1), num_pixels);
+
+    let mut sample: Vec<u32> = index::sample(rng, num_pixels, amount)
+        .iter()
+        .map(|i| i as u32)
+        .collect();
+    sample.sort_unstable();
+
+    sample
+}
+
+/// The red, green, and blue weights scaled to average 1.0, followed by alpha's weight of 1.0
+fn normalized_channel_weights(channel_weights: ChannelWeights) -> [f64; 4] {
+    let mean = channel_weights.0.iter().sum::<f64>() / 3.0;
+    let mut weights = [1.0; 4];
+    if mean > 0.0 {
+        for (weight, channel_weight) in weights.iter_mut().zip(channel_weights.0) {
+            *weight = channel_weight / mean;
+        }
+    }
+
+    weights
+}
+
+fn weighted_channel_rmse(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    num_channels: usize,
+    weight: impl Fn(u32, u32) -> f64,
+) -> f64 {
+    per_channel_weighted_rmse(
+        a,
+        b,
+        &[1.0; 4][..num_channels],
+        a.enumerate_pixels().map(|(x, y, _)| (x, y)),
+        weight,
+    )
+}
+
+/// Root Mean Squared Error over the channels in `channel_weights` of `pixels`, weighting each pixel
+/// and channel
+fn per_channel_weighted_rmse(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    channel_weights: &[f64],
+    pixels: impl Iterator<Item = (u32, u32)>,
+    weight: impl Fn(u32, u32) -> f64,
+) -> f64 {
+    assert_same_dimensions(a, b);
+
+    let total_channel_weight: f64 = channel_weights.iter().sum();
+
+    let mut sum_squared_error = 0.0;
+    let mut total_weight = 0.0;
+
+    for (x, y) in pixels {
+        let pixel_weight = weight(x, y);
+        if pixel_weight <= 0.0 {
+            continue;
+        }
+
+        let pixel_a = a.get_pixel(x, y);
+        let pixel_b = b.get_pixel(x, y);
+        for (c, channel_weight) in channel_weights.iter().enumerate() {
+            let error = pixel_a[c] as f64 - pixel_b[c] as f64;
+            sum_squared_error += pixel_weight * channel_weight * error * error;
+        }
+        total_weight += pixel_weight * total_channel_weight;
+    }
+
+    if total_weight == 0.0 {
+        0.0
+    } else {
+        (sum_squared_error / total_weight).sqrt()
+    }
+}
+
+/// `per_channel_weighted_rmse`, with the color channels converted to Y'CbCr first
+fn per_channel_weighted_ycbcr_rmse(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    luma_weight: f64,
+    include_alpha: bool,
+    pixels: impl Iterator<Item = (u32, u32)>,
+    weight: impl Fn(u32, u32) -> f64,
+) -> f64 {
+    assert_same_dimensions(a, b);
+
+    let chroma_weight = (1.0 - luma_weight) / 2.0;
+    let weights =
+        normalized_channel_weights(ChannelWeights([luma_weight, chroma_weight, chroma_weight]));
+    let num_channels = if include_alpha { 4 } else { 3 };
+    let total_channel_weight: f64 = weights[..num_channels].iter().sum();
+
+    let mut sum_squared_error = 0.0;
+    let mut total_weight = 0.0;
+
+    for (x, y) in pixels {
+        let pixel_weight = weight(x, y);
+        if pixel_weight <= 0.0 {
+            continue;
+        }
+
+        let pixel_a = a.get_pixel(x, y);
+        let pixel_b = b.get_pixel(x, y);
+        let channels = rgb_to_ycbcr(pixel_a).into_iter().zip(rgb_to_ycbcr(pixel_b));
+        for ((value_a, value_b), channel_weight) in channels.zip(weights) {
+            let error = value_a - value_b;
+            sum_squared_error += pixel_weight * channel_weight * error * error;
+        }
+        if include_alpha {
+            let error = pixel_a[3] as f64 - pixel_b[3] as f64;
+            sum_squared_error += pixel_weight * weights[3] * error * error;
+        }
+        total_weight += pixel_weight * total_channel_weight;
+    }
+
+    if total_weight == 0.0 {
+        0.0
+    } else {
+        (sum_squared_error / total_weight).sqrt()
+    }
+}
+
+///
+/// Gaussian falloff weights for a `width` x `height` image, in row-major order
+///
+/// Each weight is exp(-strength * d^2), where d is the distance from the center with the image
+/// normalized to [-1, 1] on both axes. A strength of 0 weights every pixel 1.0
+///
+pub fn center_weights(width: u32, height: u32, strength: f64) -> Vec<f64> {
+    let center_x = width as f64 / 2.0;
+    let center_y = height as f64 / 2.0;
+
+    let mut weights = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            // Measure from the center of the pixel
+            let dx = (x as f64 + 0.5 - center_x) / center_x;
+            let dy = (y as f64 + 0.5 - center_y) / center_y;
+            weights.push((-strength * (dx * dx + dy * dy)).exp());
+        }
+    }
+
+    weights
+}
+
+///
+/// Mean Structural Similarity between the luma of two images
+///
+/// Computed over non-overlapping `SSIM_WINDOW` x `SSIM_WINDOW` windows (smaller at the edges)
+/// Larger is better; identical images score 1.0
+/// Returns an error if the images' dimensions don't match
+///
+pub fn ssim(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> Result<f64, PrimitiveError> {
+    check_same_dimensions(a, b)?;
+
+    let (width, height) = a.dimensions();
+
+    let mut total = 0.0;
+    let mut num_windows = 0;
+
+    for window_y in (0..height).step_by(SSIM_WINDOW as usize) {
+        for window_x in (0..width).step_by(SSIM_WINDOW as usize) {
+            let mut luma_a = vec![];
+            let mut luma_b = vec![];
+
+            for y in window_y..(window_y + SSIM_WINDOW).min(height) {
+                for x in window_x..(window_x + SSIM_WINDOW).min(width) {
+                    luma_a.push(luma(a.get_pixel(x, y)));
+                    luma_b.push(luma(b.get_pixel(x, y)));
+                }
+            }
+
+            total += window_ssim(&luma_a, &luma_b);
+            num_windows += 1;
+        }
+    }
+
+    if num_windows == 0 {
+        Ok(1.0)
+    } else {
+        Ok(total / num_windows as f64)
+    }
+}
+
+///
+/// Mean CIE76 color difference (Delta E) between two images, computed in CIE L*a*b*
+///
+/// Unlike `rmse`, this ignores alpha and weights differences the way people perceive them
+/// Smaller is better; identical images score 0.0
+/// Returns an error if the images' dimensions don't match
+///
+pub fn lab_delta_e(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> Result<f64, PrimitiveError> {
+    check_same_dimensions(a, b)?;
+
+    let mut total = 0.0;
+    let mut num_pixels = 0;
+
+    for (pixel_a, pixel_b) in a.pixels().zip(b.pixels()) {
+        let lab_a = rgb_to_lab(pixel_a);
+        let lab_b = rgb_to_lab(pixel_b);
+
+        total += ((lab_a[0] - lab_b[0]).powi(2)
+            + (lab_a[1] - lab_b[1]).powi(2)
+            + (lab_a[2] - lab_b[2]).powi(2))
+        .sqrt();
+        num_pixels += 1;
+    }
+
+    if num_pixels == 0 {
+        Ok(0.0)
+    } else {
+        Ok(total / num_pixels as f64)
+    }
+}
+
+///
+/// Global contrast of an image: the standard deviation of its pixels' luma
+///
+/// Ranges from 0.0 for a flat image to 127.5 for one that's half black and half white
+///
+pub fn luma_contrast(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> f64 {
+    let num_pixels = image.pixels().len() as f64;
+    if num_pixels == 0.0 {
+        return 0.0;
+    }
+
+    let mean = image.pixels().map(luma).sum::<f64>() / num_pixels;
+    let variance = image
+        .pixels()
+        .map(|pixel| (luma(pixel) - mean).powi(2))
+        .sum::<f64>()
+        / num_pixels;
+
+    variance.sqrt()
+}
+
+fn check_same_dimensions(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> Result<(), PrimitiveError> {
+    if a.dimensions() != b.dimensions() {
+        return Err(PrimitiveError::SizeMismatch {
+            a: a.dimensions(),
+            b: b.dimensions(),
+        });
+    }
+
+    Ok(())
+}
+
+fn assert_same_dimensions(a: &ImageBuffer<Rgba<u8>, Vec<u8>>, b: &ImageBuffer<Rgba<u8>, Vec<u8>>) {
+    if let Err(err) = check_same_dimensions(a, b) {
+        panic!("{}", err);
+    }
+}
+
+/// ITU-R BT.601 luma of a pixel, ignoring alpha
+pub fn luma(pixel: &Rgba<u8>) -> f64 {
+    0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64
+}
+
+fn window_ssim(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+
+    let mean_a = a.iter().sum::<f64>() / n;
+    let mean_b = b.iter().sum::<f64>() / n;
+
+    let mut variance_a = 0.0;
+    let mut variance_b = 0.0;
+    let mut covariance = 0.0;
+
+    for (value_a, value_b) in a.iter().zip(b.iter()) {
+        variance_a += (value_a - mean_a) * (value_a - mean_a);
+        variance_b += (value_b - mean_b) * (value_b - mean_b);
+        covariance += (value_a - mean_a) * (value_b - mean_b);
+    }
+
+    variance_a /= n;
+    variance_b /= n;
+    covariance /= n;
+
+    ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+        / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (variance_a + variance_b + SSIM_C2))
+}
+
+///
+/// Convert an sRGB pixel to CIE L*a*b* (D65 white point)
+///
+fn rgb_to_lab(pixel: &Rgba<u8>) -> [f64; 3] {
+    let linearize = |channel: u8| {
+        let c = channel as f64 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+
+    let r = linearize(pixel[0]);
+    let g = linearize(pixel[1]);
+    let b = linearize(pixel[2]);
+
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f64| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+
+    let fx = f(x);
+    let fy = f(y);
+    let fz = f(z);
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::{get_rng, sqrt};
+
+    #[test]
+    fn test_score_region() {
+        let region = ScoreRegion::from_str("10, 20,30,5").unwrap();
+        assert_eq!(
+            region,
+            ScoreRegion {
+                x: 10,
+                y: 20,
+                width: 30,
+                height: 5
+            }
+        );
+        assert!(region.contains(10, 20));
+        assert!(region.contains(39, 24));
+        assert!(!region.contains(40, 24));
+        assert!(!region.contains(9, 22));
+        assert!(!region.contains(20, 25));
+
+        assert_eq!(
+            region.scaled(0.1),
+            ScoreRegion {
+                x: 1,
+                y: 2,
+                width: 3,
+                height: 1
+            }
+        );
+
+        assert!(ScoreRegion::from_str("1,2,3").is_err());
+        assert!(ScoreRegion::from_str("1,2,0,4").is_err());
+        assert!(ScoreRegion::from_str("1,2,-3,4").is_err());
+    }
+
+    #[test]
+    fn test_identical() {
+        let a = ImageBuffer::from_pixel(10, 10, Rgba([255, 128, 0, 128]));
+        let b = a.clone();
+
+        assert_eq!(rmse(&a, &b).unwrap(), 0.0);
+        assert_eq!(masked_rmse(&a, &b, |_, _| true), 0.0);
+        assert_eq!(rgb_rmse(&a, &b), 0.0);
+        assert_eq!(masked_rgb_rmse(&a, &b, |_, _| true), 0.0);
+        assert_eq!(ssim(&a, &b).unwrap(), 1.0);
+        assert_eq!(lab_delta_e(&a, &b).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_rmse_mismatched_size() {
+        let a = ImageBuffer::from_pixel(10, 10, Rgba([0, 0, 0, 128]));
+        let b = ImageBuffer::from_pixel(10, 11, Rgba([0, 0, 0, 128]));
+        assert!(matches!(
+            rmse(&a, &b),
+            Err(PrimitiveError::SizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_ssim_mismatched_size() {
+        let a = ImageBuffer::from_pixel(10, 10, Rgba([0, 0, 0, 128]));
+        let b = ImageBuffer::from_pixel(11, 10, Rgba([0, 0, 0, 128]));
+        assert!(matches!(
+            ssim(&a, &b),
+            Err(PrimitiveError::SizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_lab_delta_e_mismatched_size() {
+        let a = ImageBuffer::from_pixel(10, 10, Rgba([0, 0, 0, 128]));
+        let b = ImageBuffer::from_pixel(1, 1, Rgba([0, 0, 0, 128]));
+        assert!(matches!(
+            lab_delta_e(&a, &b),
+            Err(PrimitiveError::SizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_luma_contrast() {
+        let flat = ImageBuffer::from_pixel(4, 4, Rgba([90, 20, 200, 255]));
+        assert!(luma_contrast(&flat) < 1e-9);
+
+        let half = ImageBuffer::from_fn(4, 4, |x, _| {
+            if x < 2 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        assert!((luma_contrast(&half) - 127.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rgb_rmse_ignores_alpha() {
+        let a = ImageBuffer::from_pixel(2, 2, Rgba([0, 0, 0, 128]));
+        let b = ImageBuffer::from_pixel(2, 2, Rgba([10, 10, 10, 128]));
+        let b_opaque = ImageBuffer::from_pixel(2, 2, Rgba([10, 10, 10, 255]));
+
+        assert_eq!(rgb_rmse(&a, &b), 10.0);
+        assert_eq!(rgb_rmse(&a, &b_opaque), rgb_rmse(&a, &b));
+        assert_eq!(
+            masked_rgb_rmse(&a, &b_opaque, |x, _| x == 0),
+            masked_rgb_rmse(&a, &b, |x, _| x == 0)
+        );
+
+        // Alpha alone makes no difference
+        let a_opaque = ImageBuffer::from_pixel(2, 2, Rgba([0, 0, 0, 255]));
+        assert_eq!(rgb_rmse(&a, &a_opaque), 0.0);
+        assert!(rmse(&a, &a_opaque).unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_weighted_rmse() {
+        let a = ImageBuffer::from_pixel(4, 4, Rgba([0, 0, 0, 128]));
+        let mut b = ImageBuffer::from_pixel(4, 4, Rgba([10, 10, 10, 255]));
+
+        // Uniform weights match the unweighted scores
+        assert_eq!(
+            weighted_rmse(&a, &b, true, |_, _| 1.0),
+            rmse(&a, &b).unwrap()
+        );
+        assert_eq!(weighted_rmse(&a, &b, false, |_, _| 2.0), rgb_rmse(&a, &b));
+        assert_eq!(weighted_rmse(&a, &b, false, |_, _| 0.0), 0.0);
+
+        // Errors where the weight is 0 are ignored
+        b.put_pixel(0, 0, Rgba([200, 200, 200, 255]));
+        let weights = center_weights(4, 4, 0.0);
+        assert!(weights.iter().all(|&w| w == 1.0));
+        assert!(weighted_rmse(&a, &b, false, |_, _| 1.0) > 10.0);
+        assert_eq!(weighted_rmse(&a, &b, false, |x, _| x as f64), 10.0);
+    }
+
+    #[test]
+    fn test_channel_weighted_rmse() {
+        let a = ImageBuffer::from_pixel(4, 4, Rgba([0, 0, 0, 128]));
+        let blue = ImageBuffer::from_pixel(4, 4, Rgba([0, 0, 50, 128]));
+        let red = ImageBuffer::from_pixel(4, 4, Rgba([50, 0, 0, 128]));
+
+        // Equal weights match the unweighted score
+        let equal = ChannelWeights::default();
+        assert!(equal.is_uniform());
+        assert_eq!(
+            channel_weighted_rmse(&a, &red, equal, false, |_, _| 1.0),
+            rgb_rmse(&a, &red)
+        );
+        assert_eq!(
+            channel_weighted_rmse(&a, &red, ChannelWeights([3.0, 3.0, 3.0]), true, |_, _| 1.0),
+            rmse(&a, &red).unwrap()
+        );
+
+        // Blue-only differences don't count when blue's weight is 0
+        let no_blue = ChannelWeights([1.0, 1.0, 0.0]);
+        assert!(!no_blue.is_uniform());
+        assert_eq!(
+            channel_weighted_rmse(&a, &blue, no_blue, false, |_, _| 1.0),
+            0.0
+        );
+        assert!(channel_weighted_rmse(&a, &red, no_blue, false, |_, _| 1.0) > 0.0);
+    }
+
+    #[test]
+    fn test_ycbcr_rmse() {
+        let gray = ImageBuffer::from_pixel(4, 4, Rgba([128, 128, 128, 255]));
+        // 10 levels brighter, with the same chroma
+        let brighter = ImageBuffer::from_pixel(4, 4, Rgba([138, 138, 138, 255]));
+        // About 10 levels redder (Cr), with the same luma and Cb
+        let redder = ImageBuffer::from_pixel(4, 4, Rgba([142, 121, 128, 255]));
+
+        let luma_error = ycbcr_rmse(&gray, &brighter, 0.8, false, |_, _| 1.0);
+        let chroma_error = ycbcr_rmse(&gray, &redder, 0.8, false, |_, _| 1.0);
+        assert!(
+            luma_error > 2.0 * chroma_error,
+            "{} vs {}",
+            luma_error,
+            chroma_error
+        );
+
+        // Equal weights, scaled to average 1, give plain RMSE over the three channels
+        let expected = sqrt(100.0 / 3.0);
+        assert!(
+            (ycbcr_rmse(&gray, &brighter, 1.0 / 3.0, false, |_, _| 1.0) - expected).abs() < 1e-9
+        );
+
+        // The whole image is the same as a sample of every pixel
+        let sample: Vec<u32> = (0..16).collect();
+        assert_eq!(
+            sampled_ycbcr_rmse(&gray, &redder, &sample, 0.8, true, |_, _| 1.0),
+            ycbcr_rmse(&gray, &redder, 0.8, true, |_, _| 1.0)
+        );
+        assert_eq!(ycbcr_rmse(&gray, &gray, 0.8, true, |_, _| 1.0), 0.0);
+        assert_eq!("YCbCr".parse(), Ok(Metric::YCbCr));
+        assert!("lab".parse::<Metric>().is_err());
+    }
+
+    #[test]
+    fn test_sampled_rmse() {
+        // Noise, plus a smooth difference, so every sample is a little different
+        let mut rng = get_rng(7);
+        let a = ImageBuffer::from_fn(100, 100, |x, _| {
+            Rgba([rng.gen(), rng.gen(), (x * 2) as u8, 255])
+        });
+        let b = ImageBuffer::from_fn(100, 100, |_, y| {
+            Rgba([rng.gen(), rng.gen(), (y * 2) as u8, 255])
+        });
+        let full = rgb_rmse(&a, &b);
+
+        let sample = sample_pixels(100, 100, 0.1, &mut get_rng(42));
+        assert_eq!(sample.len(), 1000);
+        assert!(sample.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let sampled = sampled_rmse(&a, &b, &sample, ChannelWeights::default(), false, |_, _| {
+            1.0
+        });
+        assert!(
+            (sampled - full).abs() < full * 0.05,
+            "{} vs {}",
+            sampled,
+            full
+        );
+
+        // The same seed picks the same pixels, and every pixel scores the same as the full score
+        assert_eq!(sample_pixels(100, 100, 0.1, &mut get_rng(42)), sample);
+        let everything = sample_pixels(100, 100, 1.0, &mut get_rng(42));
+        assert_eq!(everything.len(), 10_000);
+        assert!(
+            (sampled_rmse(
+                &a,
+                &b,
+                &everything,
+                ChannelWeights::default(),
+                false,
+                |_, _| 1.0
+            ) - full)
+                .abs()
+                < 1e-9
+        );
+
+        // Tiny fractions still pick a pixel
+        assert_eq!(sample_pixels(10, 10, 0.001, &mut get_rng(1)).len(), 1);
+    }
+
+    #[test]
+    fn test_channel_weights_from_str() {
+        assert_eq!(
+            ChannelWeights::from_str("2,1,0.5"),
+            Ok(ChannelWeights([2.0, 1.0, 0.5]))
+        );
+        assert_eq!(
+            ChannelWeights::from_str("1, 1, 0"),
+            Ok(ChannelWeights([1.0, 1.0, 0.0]))
+        );
+        assert!(ChannelWeights::from_str("1,1").is_err());
+        assert!(ChannelWeights::from_str("1,-1,1").is_err());
+        assert!(ChannelWeights::from_str("0,0,0").is_err());
+        assert!(ChannelWeights::from_str("red").is_err());
+    }
+
+    #[test]
+    fn test_center_weights() {
+        let weights = center_weights(10, 10, 2.0);
+        assert_eq!(weights.len(), 100);
+
+        // Symmetric, and largest in the middle
+        assert_eq!(weights[0], weights[99]);
+        assert!(weights[5 * 10 + 5] > weights[5 * 10]);
+        assert!(weights[5 * 10 + 5] > weights[0]);
+    }
+
+    #[test]
+    fn test_known_delta() {
+        let a = ImageBuffer::from_pixel(2, 2, Rgba([0, 0, 0, 128]));
+        let b = ImageBuffer::from_pixel(2, 2, Rgba([10, 10, 10, 128]));
+
+        // Three of four channels are off by 10 in every pixel
+        assert_eq!(rmse(&a, &b).unwrap(), sqrt((10.0 * 10.0 * 3.0) / 4.0));
+
+        // Masking out every pixel but one doesn't change a uniform error
+        assert_eq!(
+            masked_rmse(&a, &b, |x, y| x == 0 && y == 0),
+            rmse(&a, &b).unwrap()
+        );
+        assert_eq!(masked_rmse(&a, &b, |_, _| false), 0.0);
+
+        // Black to white spans the entire lightness axis
+        let black = ImageBuffer::from_pixel(2, 2, Rgba([0, 0, 0, 255]));
+        let white = ImageBuffer::from_pixel(2, 2, Rgba([255, 255, 255, 255]));
+        assert!((lab_delta_e(&black, &white).unwrap() - 100.0).abs() < 0.01);
+
+        // Any difference lowers the similarity
+        assert!(ssim(&black, &white).unwrap() < 0.01);
+    }
+}
@@ -0,0 +1,85 @@
+use rand::Rng;
+use std::str::FromStr;
+
+/// How much smaller each parameter's change is when several change at once
+const MULTI_STEP_SCALE: f64 = 0.5;
+
+///
+/// How many of a shape's parameters (vertices, sides, angle, ...) one mutation changes
+///
+/// Single changes one parameter at random. Multi changes each parameter with even odds (and at
+/// least one), with smaller steps, which can converge faster when parameters depend on each other
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MutateMode {
+    Single,
+    Multi,
+}
+
+impl FromStr for MutateMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "single" => Ok(MutateMode::Single),
+            "multi" => Ok(MutateMode::Multi),
+            _ => Err(format!("Unsupported mutate mode: {}", s)),
+        }
+    }
+}
+
+impl MutateMode {
+    ///
+    /// Returns the indices of the parameters to change, out of `count`, in increasing order
+    ///
+    pub fn parameters(&self, count: usize, rng: &mut impl Rng) -> Vec<usize> {
+        match self {
+            MutateMode::Single => vec![rng.gen_range(0..count)],
+            MutateMode::Multi => {
+                let chosen: Vec<usize> = (0..count).filter(|_| rng.gen_bool(0.5)).collect();
+                if chosen.is_empty() {
+                    vec![rng.gen_range(0..count)]
+                } else {
+                    chosen
+                }
+            }
+        }
+    }
+
+    ///
+    /// Returns the factor to scale the standard deviation of each parameter's change by
+    ///
+    pub fn step_scale(&self) -> f64 {
+        match self {
+            MutateMode::Single => 1.0,
+            MutateMode::Multi => MULTI_STEP_SCALE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::get_rng;
+
+    #[test]
+    fn test_parameters() {
+        let mut rng = get_rng(42);
+
+        for _ in 0..100 {
+            assert_eq!(MutateMode::Single.parameters(4, &mut rng).len(), 1);
+
+            let chosen = MutateMode::Multi.parameters(4, &mut rng);
+            assert!(!chosen.is_empty());
+            assert!(chosen.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(chosen.iter().all(|&i| i < 4));
+        }
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("Single".parse(), Ok(MutateMode::Single));
+        assert_eq!("multi".parse(), Ok(MutateMode::Multi));
+        assert!("all".parse::<MutateMode>().is_err());
+    }
+}
@@ -1,9 +1,12 @@
+use super::mutate_mode::MutateMode;
+use super::symmetry::Symmetry;
 use super::utilities::*;
 use imageproc::point::Point;
 use rand::Rng;
 use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Serialize, Deserialize)]
 pub struct PrimitivePoint {
     pub x: i32,
     pub y: i32,
@@ -17,12 +20,13 @@ impl PrimitivePoint {
     ///
     /// Mutate the point's x and y coordinates
     /// Keeps the point within 5 pixels outside of the standard image border
-    /// Uses a standard deviation of 16, with a mean of 0, for the mutation
+    /// Uses a standard deviation of 16 (scaled by `mode`'s step scale), with a mean of 0, for the
+    /// mutation
     ///
-    pub fn mutate(&mut self, width: u32, height: u32, rng: &mut impl Rng) {
+    pub fn mutate(&mut self, width: u32, height: u32, mode: MutateMode, rng: &mut impl Rng) {
         let border_extension = 5;
 
-        let normal = Normal::new(0.0, 16.0).unwrap();
+        let normal = Normal::new(0.0, 16.0 * mode.step_scale()).unwrap();
 
         self.x = clamp(
             self.x + (rng.sample(normal) as i32),
@@ -36,6 +40,14 @@ impl PrimitivePoint {
         );
     }
 
+    ///
+    /// Returns this point mirrored according to `symmetry` in a `width` x `height` image
+    ///
+    pub fn mirrored(&self, symmetry: Symmetry, width: u32, height: u32) -> PrimitivePoint {
+        let (x, y) = symmetry.mirror_coordinates(self.x, self.y, width, height);
+        PrimitivePoint::new(x, y)
+    }
+
     ///
     /// Convert this point to the `Point` format used by imageproc
     ///
@@ -141,7 +153,7 @@ mod tests {
 
         let mut p = PrimitivePoint::new(0, 0);
         let mut rng = get_rng(seed);
-        p.mutate(10, 10, &mut rng);
+        p.mutate(10, 10, MutateMode::Single, &mut rng);
         assert_eq!(p.x, 1); // Based on prior executions
         assert_eq!(p.y, 2);
     }
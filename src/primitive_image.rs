@@ -1,33 +1,331 @@
-use crate::shape::{RandomShape, Shape};
-use crate::utilities::rgb_to_hex;
-use image::imageops::{resize, Nearest};
-use image::{open, ImageBuffer, Rgba};
-use imageproc::stats::root_mean_squared_error;
+use crate::aberration::{aberration_svg_end, aberration_svg_start, split_channels};
+use crate::alpha_by_size::AlphaBySize;
+use crate::bezier_path::BezierPath;
+use crate::color_profile::ColorProfile;
+use crate::color_sample::ColorSample;
+use crate::color_stats::ColorStats;
+use crate::cross::Cross;
+use crate::cubic_bezier::CubicBezier;
+use crate::ellipse::Ellipse;
+use crate::error::PrimitiveError;
+use crate::feather::feather_filters_svg;
+use crate::fill_style::{hatch, FillStyle};
+use crate::flip::Flip;
+use crate::heart::Heart;
+use crate::integral_image::IntegralImage;
+use crate::mask::CropMask;
+use crate::metrics::{
+    center_weights, channel_weighted_rmse, luma_contrast, masked_rgb_rmse, masked_rmse, rgb_rmse,
+    rmse, sample_pixels, sampled_rmse, sampled_ycbcr_rmse, ycbcr_rmse, ChannelWeights, Metric,
+    ScoreRegion,
+};
+use crate::mutate_mode::MutateMode;
+use crate::point::PrimitivePoint;
+use crate::quadratic_bezier::QuadraticBezier;
+use crate::rectangle::Rectangle;
+use crate::runner::{RunConfig, StepResult};
+use crate::score_cache::ScoreCache;
+use crate::serialization::{ShapeEntry, ShapeFile};
+use crate::shape::{RandomShape, Shape, ShapeType};
+use crate::signature::{contrasting_color, Signature};
+use crate::sort_order::SortOrder;
+use crate::svg_format::{
+    hatched_shapes_svg, layered_shapes_svg, merged_shapes_svg, seamless_shapes_svg,
+    templated_shapes_svg, SvgFormat,
+};
+use crate::symmetry::Symmetry;
+use crate::teardrop::Teardrop;
+use crate::triangle::Triangle;
+use crate::utilities::{
+    default_stroke_width, dither_to_rgba8, get_rng, rgb_to_hex, stretch_levels, tone_map,
+    AutoLevels, SegmentLimits, SizeLimits,
+};
+use image::codecs::hdr::HdrDecoder;
+use image::imageops::{grayscale, replace, resize, Nearest};
+use image::{
+    guess_format, image_dimensions, load_from_memory, open, DynamicImage, ImageBuffer, ImageFormat,
+    Luma, Rgba,
+};
+use imageproc::gradients::sobel_gradients;
 use rand::Rng;
-use std::cmp::max;
-use std::fs::OpenOptions;
-use std::io::Write;
+use std::cmp::{max, min, Reverse};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::iter::once;
 use std::option::Option;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const BORDER_EXTENSION: i32 = 6;
+const MAXIMUM_RANDOM_SHAPE_ATTEMPTS: u32 = 1000;
+const MM_PER_INCH: f64 = 25.4;
+// k-means stops after this many iterations, even if the clusters are still moving
+const K_MEANS_ITERATIONS: u32 = 20;
+// Only every nth pixel is clustered, so large images stay fast
+const K_MEANS_MAX_SAMPLES: usize = 10_000;
+const SHAPE_ALPHA: u8 = 128;
+// Gradient magnitude (from a Sobel filter) above which a pixel counts as an edge
+const EDGE_THRESHOLD: u16 = 128;
+const MIN_SUGGESTED_SHAPES: u32 = 50;
+const MAX_SUGGESTED_SHAPES: u32 = 1000;
+// Scores each hill climb remembers, for mutations that go back to a shape it already tried
+const SCORE_CACHE_SIZE: usize = 64;
+// How far, in pixels, edge snapping looks for a stronger edge around a vertex
+const EDGE_SNAP_RADIUS: i32 = 4;
+/// How much luma counts in the YCbCr metric, leaving a tenth for each chroma channel
+pub const DEFAULT_LUMA_WEIGHT: f64 = 0.8;
+
+///
+/// How `from_path` and `from_bytes` prepare the decoded image before approximating it
+///
+/// The default leaves it as it is
+///
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LoadOptions {
+    /// The most pixels the search runs at on the largest side, 0 for no limit. Larger images are
+    /// scaled down to it with a warning, even if `scale_to` is 0, since searching a huge image can
+    /// run out of memory
+    pub max_dimension: u32,
+    /// Scale images smaller than `scale_to` up to it. Otherwise they're searched at their own size
+    /// (with a warning), since scaling up can't add detail and just makes the search slower
+    pub allow_upscale: bool,
+    /// Mirror the image
+    pub flip: Option<Flip>,
+    /// Stretch the image's histogram to cover 0-255, after flipping it
+    pub auto_levels: Option<AutoLevels>,
+}
+
+/// The result of a single hill climb
+struct Climb<T> {
+    shape: Box<T>,
+    raw_score: f64,
+    // Including penalties
+    score: f64,
+    // The initial shape, plus each mutation that was accepted
+    shapes_visited: u32,
+}
 
 #[derive(Clone)]
 pub struct PrimitiveImage {
     target: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    // Shared, since the image is cloned for every candidate shape but the target never changes
+    target_integral: Arc<IntegralImage>,
     approximation: ImageBuffer<Rgba<u8>, Vec<u8>>,
     scale: f64,
     shapes: Vec<Box<dyn Shape>>,
     background: Rgba<u8>,
+    crop_mask: Option<CropMask>,
+    mask_score: bool,
+    // The source's alpha at each pixel of the target, row-major. The target itself is opaque
+    source_alpha: Arc<Vec<u8>>,
+    // Source alpha below which pixels are ignored, if approximating only the opaque parts
+    alpha_mask: Option<u8>,
+    min_shape_area_frac: f64,
+    coarse_shapes: u32,
+    coverage: Vec<f64>,
+    // How many shapes cover each pixel
+    layer_counts: Vec<u32>,
+    max_layers: Option<u32>,
+    overlap_penalty: f64,
+    stroke_width: u32,
+    // Pixels new rectangles' and ellipses' edges fade out over
+    feather: u32,
+    pool: u32,
+    // Shapes searched for against the same approximation, then committed together
+    commit_batch: u32,
+    // Longest each added shape's hill climbs may take together
+    shape_time_budget: Option<Duration>,
+    svg_format: SvgFormat,
+    merge_tolerance: u8,
+    segment_limits: SegmentLimits,
+    size_limits: SizeLimits,
+    mutate_mode: MutateMode,
+    rotation: bool,
+    squares: bool,
+    // The chance of snapping each vertex to the strongest nearby edge, and the target's Sobel
+    // gradients to find them with
+    edge_snap: f64,
+    gradients: Option<Arc<ImageBuffer<Luma<u16>, Vec<u16>>>>,
+    // Score improvement per pixel of the shape the last `add_new_shape` added
+    last_efficiency: Option<f64>,
+    dpi: Option<f64>,
+    color_profile: Option<ColorProfile>,
+    aberration: u32,
+    signature: Option<Signature>,
+    symmetry: Symmetry,
+    fixed_color: Option<Rgba<u8>>,
+    palette: Option<Vec<Rgba<u8>>>,
+    color_sample: ColorSample,
+    shape_alphas: HashMap<ShapeType, u8>,
+    alpha_by_size: Option<AlphaBySize>,
+    min_contrast: Option<f64>,
+    score_alpha: bool,
+    plateau_moves: u32,
+    protect_converged: Option<f64>,
+    protected_overlap: f64,
+    // Per-pixel score weights, when the score is biased towards the center
+    center_weights: Option<Arc<Vec<f64>>>,
+    // In pixels of the scaled image
+    score_region: Option<ScoreRegion>,
+    channel_weights: ChannelWeights,
+    metric: Metric,
+    luma_weight: f64,
+    // Row-major indices of the pixels hill climbing scores candidates on, if not every pixel
+    score_sample: Option<Arc<Vec<u32>>>,
+    fill_style: FillStyle,
+    shape_rendering: Option<String>,
+    seam_stroke: Option<f64>,
 }
 impl PrimitiveImage {
-    pub fn from_path(path: PathBuf, scale_to: u32, background: Option<Rgba<u8>>) -> PrimitiveImage {
-        let original = open(&path)
-            .unwrap_or_else(|_| panic!("Could not load image at {:?}", path))
-            .to_rgba8();
+    ///
+    /// Load the image at `path` (in any format supported by the image library) to approximate
+    ///
+    /// High dynamic range images (e.g. .exr and .hdr) are tone mapped to 8-bit sRGB, then the
+    /// image is prepared as `options` says
+    /// See `from_image` for `scale_to` and `background`
+    ///
+    pub fn from_path(
+        path: PathBuf,
+        scale_to: u32,
+        background: Option<Rgba<u8>>,
+        options: LoadOptions,
+    ) -> Result<PrimitiveImage, PrimitiveError> {
+        let original = match ImageFormat::from_path(&path) {
+            Ok(ImageFormat::Hdr) => decode_hdr(BufReader::new(File::open(&path)?))?,
+            _ => to_rgba8(open(&path)?),
+        };
+
+        PrimitiveImage::from_loaded_image(original, scale_to, background, options)
+    }
+
+    ///
+    /// Returns the `scale_to` (see `from_image`) that searches the image at `path` at
+    /// `preview_scale` times its size, reading only its header
+    ///
+    /// Output is still rendered at the full size, so this trades search time for detail. Returns an
+    /// error if `preview_scale` isn't in (0, 1]
+    ///
+    pub fn preview_scale_to(path: &Path, preview_scale: f64) -> Result<u32, PrimitiveError> {
+        if !(preview_scale > 0.0 && preview_scale <= 1.0) {
+            return Err(PrimitiveError::PreviewScale(preview_scale));
+        }
+
+        let (width, height) = image_dimensions(path)?;
+        Ok(scale_to_for_preview(width, height, preview_scale))
+    }
+
+    ///
+    /// Decode an encoded image (e.g. the contents of a PNG file) to approximate
+    ///
+    /// See `from_path` for `options`, and `from_image` for `scale_to` and `background`
+    ///
+    pub fn from_bytes(
+        bytes: &[u8],
+        scale_to: u32,
+        background: Option<Rgba<u8>>,
+        options: LoadOptions,
+    ) -> Result<PrimitiveImage, PrimitiveError> {
+        let original = match guess_format(bytes) {
+            Ok(ImageFormat::Hdr) => decode_hdr(bytes)?,
+            _ => to_rgba8(load_from_memory(bytes)?),
+        };
+
+        PrimitiveImage::from_loaded_image(original, scale_to, background, options)
+    }
+
+    ///
+    /// Same as `from_image` after preparing the image as `options` says, but returns an error for
+    /// empty images instead of panicking
+    ///
+    fn from_loaded_image(
+        original: ImageBuffer<Rgba<u8>, Vec<u8>>,
+        scale_to: u32,
+        background: Option<Rgba<u8>>,
+        options: LoadOptions,
+    ) -> Result<PrimitiveImage, PrimitiveError> {
+        let (width, height) = original.dimensions();
+        if width == 0 || height == 0 {
+            return Err(PrimitiveError::EmptyImage { width, height });
+        }
+
+        let original = match options.flip {
+            Some(flip) => flip.apply(&original),
+            None => original,
+        };
+        let original = match options.auto_levels {
+            Some(auto_levels) => stretch_levels(&original, auto_levels),
+            None => original,
+        };
+
+        let kept = if options.allow_upscale {
+            scale_to
+        } else {
+            limit_upscaling(width, height, scale_to)
+        };
+        if kept != scale_to {
+            warn!(
+                "Not scaling the {}x{} image up to {} pixels",
+                width, height, scale_to
+            );
+        }
+
+        let limited = limit_dimension(width, height, kept, options.max_dimension);
+        if limited != kept {
+            warn!(
+                "Searching the {}x{} image at {} pixels instead of {}, the maximum dimension",
+                width,
+                height,
+                limited,
+                if kept > 0 { kept } else { max(width, height) }
+            );
+        }
+
+        Ok(PrimitiveImage::from_scaled_image(
+            original, limited, background,
+        ))
+    }
+
+    ///
+    /// Approximate `original`, searching it at `scale_to` pixels on its largest side (0 for its own
+    /// size), starting from `background` (or the image's average color)
+    ///
+    /// Unlike `from_path` and `from_bytes`, this scales small images up to `scale_to`, with a
+    /// warning since it can't add detail
+    ///
+    pub fn from_image(
+        original: ImageBuffer<Rgba<u8>, Vec<u8>>,
+        scale_to: u32,
+        background: Option<Rgba<u8>>,
+    ) -> PrimitiveImage {
+        let (width, height) = original.dimensions();
+        if limit_upscaling(width, height, scale_to) != scale_to {
+            warn!(
+                "Scaling the {}x{} image up to {} pixels",
+                width, height, scale_to
+            );
+        }
 
+        PrimitiveImage::from_scaled_image(original, scale_to, background)
+    }
+
+    /// Same as `from_image`, without warning about scaling up
+    fn from_scaled_image(
+        original: ImageBuffer<Rgba<u8>, Vec<u8>>,
+        scale_to: u32,
+        background: Option<Rgba<u8>>,
+    ) -> PrimitiveImage {
         let (original_width, original_height) = original.dimensions();
 
+        if original_width == 0 || original_height == 0 {
+            panic!(
+                "Can't approximate an empty ({}x{}) image",
+                original_width, original_height
+            );
+        }
+
         let background = background.unwrap_or_else(|| average_color(&original));
 
         // Set the scale so that when the image is resized, the largest
@@ -39,287 +337,4609 @@ impl PrimitiveImage {
             1.0
         };
 
-        let new_width = (original_width as f64 * scale) as u32;
-        let new_height = (original_height as f64 * scale) as u32;
+        // Very narrow images could otherwise be scaled down to nothing
+        let new_width = max((original_width as f64 * scale) as u32, 1);
+        let new_height = max((original_height as f64 * scale) as u32, 1);
 
         let resized = resize(&original, new_width, new_height, Nearest);
+        let source_alpha = resized.pixels().map(|pixel| pixel[3]).collect();
+
+        // Transparent parts of the source show the background, so that's what to approximate there
+        let resized = composite_over(&resized, background);
 
         let approximation = ImageBuffer::from_pixel(new_width, new_height, background);
 
         PrimitiveImage {
+            target_integral: Arc::new(IntegralImage::new(&resized)),
             target: resized,
             approximation,
             scale,
             background,
             shapes: vec![],
+            crop_mask: None,
+            mask_score: false,
+            source_alpha: Arc::new(source_alpha),
+            alpha_mask: None,
+            min_shape_area_frac: 0.0,
+            coarse_shapes: 0,
+            coverage: vec![0.0; (new_width * new_height) as usize],
+            layer_counts: vec![0; (new_width * new_height) as usize],
+            max_layers: None,
+            overlap_penalty: 0.0,
+            stroke_width: default_stroke_width(),
+            feather: 0,
+            pool: 1,
+            commit_batch: 1,
+            shape_time_budget: None,
+            svg_format: SvgFormat::Plain,
+            merge_tolerance: 0,
+            segment_limits: SegmentLimits::default(),
+            size_limits: SizeLimits::default(),
+            mutate_mode: MutateMode::Single,
+            rotation: true,
+            squares: false,
+            edge_snap: 0.0,
+            gradients: None,
+            last_efficiency: None,
+            dpi: None,
+            color_profile: None,
+            aberration: 0,
+            signature: None,
+            symmetry: Symmetry::None,
+            fixed_color: None,
+            palette: None,
+            color_sample: ColorSample::Mean,
+            shape_alphas: HashMap::new(),
+            alpha_by_size: None,
+            min_contrast: None,
+            score_alpha: false,
+            plateau_moves: 0,
+            protect_converged: None,
+            protected_overlap: 0.5,
+            center_weights: None,
+            score_region: None,
+            channel_weights: ChannelWeights::default(),
+            metric: Metric::Rgb,
+            luma_weight: DEFAULT_LUMA_WEIGHT,
+            score_sample: None,
+            fill_style: FillStyle::Solid,
+            shape_rendering: None,
+            seam_stroke: None,
         }
     }
 
-    pub fn target_average_color_in_shape(&self, shape: &impl Shape) -> Rgba<u8> {
-        average_color_in_shape(&self.target, shape)
+    ///
+    /// Move the red and blue channels of saved output `pixels` pixels apart, for a glitchy look
+    ///
+    /// Only the output changes, the search and the score ignore it. 0 turns it off
+    ///
+    pub fn set_aberration(&mut self, pixels: u32) {
+        self.aberration = pixels;
     }
 
-    pub fn save_to(&self, path: PathBuf) {
-        let extension = path.extension();
+    ///
+    /// Sign saved output with `signature`, in black or white (whichever stands out against the
+    /// background)
+    ///
+    /// Like aberration, only the output changes. `None` leaves it unsigned
+    ///
+    pub fn set_signature(&mut self, signature: Option<Signature>) {
+        self.signature = signature;
+    }
 
-        match extension {
-            None => panic!("Can't save to file {:?} (no extension found!)", path),
-            Some(os_str) => match os_str.to_str() {
-                Some("svg") => self.save_to_svg(path),
-                Some("png") | Some("jpg") | Some("bmp") | Some("ico") | Some("gif") => {
-                    self.save_to_img(path)
-                }
-                _ => error!("Invalid save file type: {:?}", extension),
-            },
+    ///
+    /// Record the physical resolution of saved output, in dots per inch
+    ///
+    /// PNGs get a pHYs chunk and SVGs get their width and height in millimeters (plus a viewBox)
+    ///
+    pub fn set_dpi(&mut self, dpi: Option<f64>) {
+        if let Some(dpi) = dpi {
+            if dpi <= 0.0 {
+                panic!("DPI must be positive, got {}", dpi);
+            }
         }
+        self.dpi = dpi;
     }
 
-    fn as_svg(&self) -> String {
-        let mut result = String::new();
-
-        let (scaled_width, scaled_height) = self.target.dimensions();
-        let inverted_scale = 1.0 / self.scale;
-        let original_width = (scaled_width as f64 * inverted_scale) as u32;
-        let original_height = (scaled_height as f64 * inverted_scale) as u32;
-
-        result += &format!(
-            "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"{}\" height=\"{}\">",
-            original_width, original_height
-        );
+    ///
+    /// Mark saved PNGs as being in `color_profile`. None (the default) leaves them unmarked
+    ///
+    pub fn set_color_profile(&mut self, color_profile: Option<ColorProfile>) {
+        self.color_profile = color_profile;
+    }
 
-        // Use an SVG transform to resize all of the polygons (it's easier to have someone else do the math)
-        //result += &format!("<g transform=\"scale({})\">", inverted_scale);
+    ///
+    /// Crop the saved output to `crop_mask`, making everything outside of it transparent
+    ///
+    /// If `mask_score` is set, pixels outside of the mask are also ignored when scoring
+    ///
+    pub fn set_crop_mask(&mut self, crop_mask: Option<CropMask>, mask_score: bool) {
+        self.crop_mask = crop_mask;
+        self.mask_score = mask_score;
+    }
 
-        // Add the background
-        result += &format!(
-            "<rect x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"{}\" />",
-            original_width,
-            original_height,
-            rgb_to_hex(self.background)
-        );
+    ///
+    /// Only approximate where the source's alpha is at least `threshold`, so the shapes follow the
+    /// subject's silhouette in sources that are already transparent
+    ///
+    /// Other pixels are ignored when scoring, and shapes can't cover them. `None` uses every pixel
+    ///
+    pub fn set_alpha_mask(&mut self, threshold: Option<u8>) {
+        self.alpha_mask = threshold;
+    }
 
-        result += "<g>";
+    /// Determine if the pixel at row-major `index` is ignored because of the alpha mask
+    fn alpha_masked(&self, index: usize) -> bool {
+        self.alpha_mask
+            .is_some_and(|threshold| self.source_alpha[index] < threshold)
+    }
 
-        // Add the polygons!
-        for polygon in self.shapes.iter() {
-            result += &polygon.as_svg(inverted_scale);
+    /// Returns true if `shape` (or its mirror, if any) covers a pixel the alpha mask ignores
+    fn outside_alpha_mask(&self, shape: &dyn Shape) -> bool {
+        if self.alpha_mask.is_none() {
+            return false;
         }
 
-        //result += &format!("</g></g></svg>");
-        result += "</g></svg>";
+        let mut indices = self.pixel_indices(shape);
+        if let Some(mirror) = self.mirror_of(shape) {
+            indices.extend(self.pixel_indices(&*mirror));
+        }
 
-        result
+        indices.into_iter().any(|index| self.alpha_masked(index))
     }
 
-    pub fn save_to_svg(&self, path: PathBuf) {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(path);
+    ///
+    /// Reject candidate shapes whose clipped bounding box covers less than `min_shape_area_frac`
+    /// of the image while the first `coarse_shapes` shapes are added
+    ///
+    /// The minimum decays linearly to zero over the coarse phase, and is disabled after it
+    ///
+    pub fn set_min_shape_area(&mut self, min_shape_area_frac: f64, coarse_shapes: u32) {
+        self.min_shape_area_frac = min_shape_area_frac;
+        self.coarse_shapes = coarse_shapes;
+    }
 
-        if let Ok(mut svg) = file {
-            write!(&svg, "{}", self.as_svg()).unwrap();
-            svg.flush().unwrap();
-        } else {
-            let err = file.unwrap_err();
-            panic!("{}", err.to_string());
+    /// Returns the minimum area (in pixels) the next shape must cover
+    fn min_shape_area(&self) -> f64 {
+        let num_shapes = self.shapes.len() as u32;
+        if num_shapes >= self.coarse_shapes {
+            return 0.0;
         }
+
+        let decay = 1.0 - num_shapes as f64 / self.coarse_shapes as f64;
+        self.min_shape_area_frac * decay * (self.width() * self.height()) as f64
     }
 
-    /// Save the current approximation in an image format
     ///
-    ///  # Arguments
+    /// Penalize candidate shapes by `overlap_penalty` times the average opacity already covering
+    /// their pixels, to discourage stacking shapes in the same spot. 0 disables the penalty
     ///
-    /// * `path` - The path to save the image to. It must be a format supported by the image library
+    pub fn set_overlap_penalty(&mut self, overlap_penalty: f64) {
+        self.overlap_penalty = overlap_penalty;
+    }
+
     ///
-    pub fn save_to_img(&self, path: PathBuf) {
-        let (scaled_width, scaled_height) = self.target.dimensions();
-        let inverted_scale = 1.0 / self.scale;
-        let original_width = (scaled_width as f64 * inverted_scale) as u32;
-        let original_height = (scaled_height as f64 * inverted_scale) as u32;
+    /// Reject shapes that would leave any pixel covered by more than `max_layers` shapes
+    ///
+    /// E.g. for screen printing, where only so many inks can be layered. `None` allows any number
+    ///
+    pub fn set_max_layers(&mut self, max_layers: Option<u32>) {
+        self.max_layers = max_layers;
+    }
 
-        let mut img = ImageBuffer::from_pixel(original_width, original_height, self.background);
+    /// Returns true if adding `shape` (and its mirror, if any) would cover a pixel too many times
+    fn exceeds_max_layers(&self, shape: &dyn Shape) -> bool {
+        let max_layers = match self.max_layers {
+            Some(max_layers) => max_layers,
+            None => return false,
+        };
 
-        for poly in self.shapes.iter() {
-            img = poly.scaled_paint_on(&img, inverted_scale);
+        let mut indices = self.pixel_indices(shape);
+        if let Some(mirror) = self.mirror_of(shape) {
+            indices.extend(self.pixel_indices(&*mirror));
+            indices.sort_unstable();
         }
 
-        img.save(path).unwrap();
+        // A shape and its mirror may overlap, so count each pixel's new layers together
+        indices
+            .chunk_by(|a, b| a == b)
+            .any(|layers| self.layer_counts[layers[0]] + layers.len() as u32 > max_layers)
     }
 
-    /// Returns the current approximation's score.
-    ///
-    /// Uses imageproc's Root Mean Squared Error function on the target and approximation images
-    pub fn score(&self) -> f64 {
-        root_mean_squared_error(&self.target, &self.approximation)
-    }
+    /// Returns the sorted, distinct, row-major indices of the pixels `shape` covers on the image
+    fn pixel_indices(&self, shape: &dyn Shape) -> Vec<usize> {
+        let (width, height) = self.target.dimensions();
 
-    pub fn add_new_shape<T>(&mut self, max_age: u32, rng: &mut impl Rng) -> bool
-    where
-        T: RandomShape + Shape + Clone + 'static,
-    {
-        // Initialize a random shape and give it a color
-        let mut shape = Box::new(T::random(
-            self.width(),
-            self.height(),
-            BORDER_EXTENSION,
-            rng,
-        ));
+        let mut indices: Vec<usize> = shape
+            .clipped_pixels(width, height)
+            .iter()
+            .map(|pixel| (pixel.y as u32 * width + pixel.x as u32) as usize)
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
 
-        shape.set_color_using(self);
+        indices
+    }
 
-        // The initial triangle is the best so far
-        let mut best_shape = shape.clone();
-        let mut best_image = self.clone();
-        best_image.approximation = best_shape.paint_on(&best_image.approximation);
-        let mut best_score = best_image.score();
+    ///
+    /// Reject shapes where more than `max_overlap_frac` of their pixels (on the image) already have
+    /// an error below `threshold`, so effort goes where the approximation is still wrong
+    ///
+    /// A pixel's error is the RMSE of its RGB channels. `None` disables the protection
+    ///
+    pub fn set_protect_converged(&mut self, threshold: Option<f64>, max_overlap_frac: f64) {
+        self.protect_converged = threshold;
+        self.protected_overlap = max_overlap_frac;
+    }
 
-        let mut age = 0;
-        // Loop until max_age mutations fail to yield and improvement
-        while age < max_age {
-            // Mutate the shape and update its color
-            shape.mutate(self.width(), self.height(), rng);
-            shape.set_color_using(self);
+    /// Returns true if `shape` covers too many converged pixels, and converged pixels are protected
+    fn covers_converged(&self, shape: &dyn Shape) -> bool {
+        let threshold = match self.protect_converged {
+            Some(threshold) => threshold,
+            None => return false,
+        };
 
-            // Determine its score
-            let mut new_image = self.clone();
-            new_image.approximation = shape.paint_on(&new_image.approximation);
-            let new_score = new_image.score();
+        let (width, height) = self.target.dimensions();
 
-            // Trying to minimize score (smaller score = closer approximation to the target)
-            if new_score < best_score {
-                best_score = new_score;
-                best_shape = shape.clone();
+        let mut num_converged = 0;
+        let mut num_pixels = 0;
 
-                // Reset age if an improvement was made
-                age = 0;
-            } else {
-                // Reset the shape and increment age
-                shape = best_shape.clone();
-                age += 1;
+        for pixel in shape.get_pixels() {
+            if pixel.x < 0 || pixel.x >= width as i32 || pixel.y < 0 || pixel.y >= height as i32 {
+                continue;
             }
 
-            trace!("Age: {}, best score: {}", age, best_score);
-        }
-
-        // Only add the shape if it is an improvement over the current approximation
-        // Return true if a shape was added
-        if best_score < self.score() {
-            trace!("Returning shape {:?}", best_shape);
-            self.approximation = best_shape.paint_on(&self.approximation);
-            self.shapes.push(best_shape);
-            true
-        } else {
-            false
+            let (x, y) = (pixel.x as u32, pixel.y as u32);
+            if pixel_error(
+                self.target.get_pixel(x, y),
+                self.approximation.get_pixel(x, y),
+            ) < threshold
+            {
+                num_converged += 1;
+            }
+            num_pixels += 1;
         }
-    }
 
-    fn width(&self) -> u32 {
-        self.target.dimensions().0
+        num_pixels > 0 && num_converged as f64 / num_pixels as f64 > self.protected_overlap
     }
 
-    fn height(&self) -> u32 {
-        self.target.dimensions().1
+    ///
+    /// Scale the red, green, and blue channels' errors when scoring
+    ///
+    /// E.g. weighting green more than blue focuses on luminance over chroma. Equal weights score
+    /// every channel the same
+    ///
+    pub fn set_channel_weights(&mut self, channel_weights: ChannelWeights) {
+        self.channel_weights = channel_weights;
     }
-}
 
-fn average_color_in_shape(image: &ImageBuffer<Rgba<u8>, Vec<u8>>, shape: &impl Shape) -> Rgba<u8> {
-    let (width, height) = image.dimensions();
+    ///
+    /// Measure the error in `metric`'s color space when scoring
+    ///
+    /// For YCbCr, `luma_weight` (0 to 1) is how much luma counts, and the chroma channels split
+    /// the rest, so a high weight favors structure over exact colors. Channel weights only apply
+    /// to RGB
+    ///
+    pub fn set_metric(&mut self, metric: Metric, luma_weight: f64) {
+        if !(0.0..=1.0).contains(&luma_weight) {
+            panic!("Luma weight must be from 0 to 1, not {}", luma_weight);
+        }
 
-    let pixels = shape.get_pixels();
+        self.metric = metric;
+        self.luma_weight = luma_weight;
+    }
 
-    let mut channel_sums: [i64; 4] = [0, 0, 0, 0];
+    ///
+    /// Paint filled shapes solid, or only on hatch lines
+    ///
+    /// Hatching is used when scoring too, so shapes are chosen for how they'll look. SVGs use a
+    /// hatch pattern for each color, instead of the configured SVG format
+    ///
+    pub fn set_fill_style(&mut self, fill_style: FillStyle) {
+        self.fill_style = fill_style;
+    }
 
-    let mut num_pixels: i64 = 0;
+    ///
+    /// Score candidate shapes on a random `fraction` (0 to 1) of the pixels while hill climbing
+    ///
+    /// The pixels are picked using `seed` (0 picks a seed based on the time). Whether the best
+    /// candidate is added is still decided by the full score. A fraction of 1 scores every pixel
+    ///
+    pub fn set_score_sample(&mut self, fraction: f64, seed: u64) {
+        self.score_sample = if fraction < 1.0 {
+            Some(Arc::new(sample_pixels(
+                self.width(),
+                self.height(),
+                fraction,
+                &mut get_rng(seed),
+            )))
+        } else {
+            None
+        };
+    }
 
-    for pixel in pixels {
-        if pixel.x < 0 || pixel.x >= width as i32 || pixel.y < 0 || pixel.y >= height as i32 {
-            continue;
-        }
+    ///
+    /// Weight each pixel's error by a Gaussian falloff from the center of the image when scoring
+    ///
+    /// Larger strengths focus more on the center. 0 scores every pixel equally
+    ///
+    pub fn set_center_bias(&mut self, strength: f64) {
+        self.center_weights = if strength > 0.0 {
+            Some(Arc::new(center_weights(
+                self.width(),
+                self.height(),
+                strength,
+            )))
+        } else {
+            None
+        };
+    }
 
-        num_pixels += 1;
-        let image_pixel = image.get_pixel(pixel.x as u32, pixel.y as u32);
+    ///
+    /// Only score the pixels in `score_region`, given in pixels of the original image, while
+    /// shapes are still placed and rendered everywhere
+    ///
+    /// Returns an error, leaving the region unchanged, if it's entirely off of the image. `None`
+    /// scores every pixel
+    ///
+    pub fn set_score_region(
+        &mut self,
+        score_region: Option<ScoreRegion>,
+    ) -> Result<(), PrimitiveError> {
+        let scaled = match score_region {
+            Some(region) => {
+                let scaled = region.scaled(self.scale);
+                if scaled.x >= self.width() || scaled.y >= self.height() {
+                    let (width, height) = self.output_dimensions();
+                    return Err(PrimitiveError::ScoreRegion {
+                        region,
+                        width,
+                        height,
+                    });
+                }
+                Some(scaled)
+            }
+            None => None,
+        };
 
-        channel_sums[0] += image_pixel[0] as i64;
-        channel_sums[1] += image_pixel[1] as i64;
-        channel_sums[2] += image_pixel[2] as i64;
+        self.score_region = scaled;
+        Ok(())
     }
 
-    let mut average_pixels: [u8; 4] = [0, 0, 0, 0];
+    ///
+    /// Set the `shape-rendering` attribute of the SVG group holding the shapes (e.g. "crispEdges"),
+    /// and stroke filled shapes `seam_stroke` wide in their own color
+    ///
+    /// Both hide the hairline cracks some viewers leave between adjacent shapes
+    ///
+    pub fn set_svg_seam_fix(&mut self, shape_rendering: Option<String>, seam_stroke: Option<f64>) {
+        self.shape_rendering = shape_rendering;
+        self.seam_stroke = seam_stroke;
+    }
 
-    if num_pixels > 0 {
-        average_pixels[0] = (channel_sums[0] / num_pixels) as u8;
-        average_pixels[1] = (channel_sums[1] / num_pixels) as u8;
-        average_pixels[2] = (channel_sums[2] / num_pixels) as u8;
-        average_pixels[3] = 128;
+    /// Set the width, in pixels, of new stroked shapes (i.e. curves)
+    pub fn set_stroke_width(&mut self, stroke_width: u32) {
+        self.stroke_width = stroke_width;
     }
 
-    Rgba(average_pixels)
-}
+    ///
+    /// Set how many pixels new rectangles and ellipses fade out over at their edges, softening the
+    /// seams where they overlap. 0 (the default) keeps their edges hard
+    ///
+    /// SVGs blur feathered shapes instead, which is only close to the fade
+    ///
+    pub fn set_feather(&mut self, feather: u32) {
+        self.feather = feather;
+    }
 
-fn average_color(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Rgba<u8> {
-    let mut channel_sums: [i64; 4] = [0, 0, 0, 0];
+    ///
+    /// Set how shapes are written to SVGs
+    ///
+    /// With `SvgFormat::PathMerge`, shapes whose color channels are within `merge_tolerance` of
+    /// each other can be merged into one path
+    ///
+    pub fn set_svg_format(&mut self, svg_format: SvgFormat, merge_tolerance: u8) {
+        self.svg_format = svg_format;
+        self.merge_tolerance = merge_tolerance;
+    }
 
-    let mut num_pixels: i64 = 0;
+    /// Set how many line segments new curves are rasterized with
+    pub fn set_segment_limits(&mut self, segment_limits: SegmentLimits) {
+        self.segment_limits = segment_limits;
+    }
 
-    for pixel in image.pixels() {
-        num_pixels += 1;
+    /// Set how big new shapes can be
+    pub fn set_size_limits(&mut self, size_limits: SizeLimits) {
+        self.size_limits = size_limits;
+    }
 
-        channel_sums[0] += pixel[0] as i64;
-        channel_sums[1] += pixel[1] as i64;
-        channel_sums[2] += pixel[2] as i64;
+    /// Set how many parameters of a shape each mutation changes
+    pub fn set_mutate_mode(&mut self, mutate_mode: MutateMode) {
+        self.mutate_mode = mutate_mode;
     }
 
-    let mut average_pixels: [u8; 4] = [0, 0, 0, 0];
+    ///
+    /// Allow or forbid rotating shapes while searching. Without rotation, rectangles, ellipses, and
+    /// other rotatable shapes always have an angle of 0
+    ///
+    pub fn set_rotation(&mut self, rotation: bool) {
+        self.rotation = rotation;
+    }
+
+    ///
+    /// Snap each vertex of triangles to the strongest edge within `EDGE_SNAP_RADIUS` pixels, with
+    /// a chance of `strength` (0 to 1), whenever they're generated or mutated
+    ///
+    /// Edges are found with a Sobel filter over the target, so shapes' sides follow them, like in
+    /// low-poly portraits. 0 turns snapping off
+    ///
+    pub fn set_edge_snap(&mut self, strength: f64) {
+        if !(0.0..=1.0).contains(&strength) {
+            panic!("Edge snap strength must be from 0 to 1, not {}", strength);
+        }
+
+        self.edge_snap = strength;
+        self.gradients = if strength > 0.0 {
+            Some(Arc::new(sobel_gradients(&grayscale(&self.target))))
+        } else {
+            None
+        };
+    }
+
+    ///
+    /// Only search for square rectangles, for mosaic-like results
+    ///
+    pub fn set_squares(&mut self, squares: bool) {
+        self.squares = squares;
+    }
+
+    ///
+    /// Mirror every added shape according to `symmetry`, scoring each shape and its mirror together
+    ///
+    /// Shapes that can't be mirrored (curves) are added alone
+    ///
+    pub fn set_symmetry(&mut self, symmetry: Symmetry) {
+        self.symmetry = symmetry;
+    }
+
+    ///
+    /// Color every shape `fixed_color` instead of the target's average color under it
+    ///
+    /// Only the color's RGB is used, shapes keep their usual alpha
+    ///
+    pub fn set_fixed_color(&mut self, fixed_color: Option<Rgba<u8>>) {
+        self.fixed_color = fixed_color;
+    }
+
+    ///
+    /// Snap every shape's color to the closest of `palette`, for renders with a limited set of
+    /// colors
+    ///
+    /// Only the palette's RGB is used, shapes keep their usual alpha
+    ///
+    pub fn set_palette(&mut self, palette: Option<Vec<Rgba<u8>>>) {
+        if palette.as_ref().is_some_and(|palette| palette.is_empty()) {
+            panic!("A palette needs at least one color");
+        }
+
+        self.palette = palette;
+    }
+
+    ///
+    /// Derive a palette of up to `k` colors from the target with `dominant_colors`, start from its
+    /// most dominant color, and snap shapes to it (see `set_palette`)
+    ///
+    /// Returns the palette, most dominant first, or None (changing nothing) if no colors were found,
+    /// like when `k` is 0
+    ///
+    pub fn extract_palette(&mut self, k: usize) -> Option<Vec<Rgba<u8>>> {
+        let palette = self.dominant_colors(k);
+        let &dominant = palette.first()?;
+
+        self.set_palette(Some(palette.clone()));
+        self.set_background(dominant);
+
+        Some(palette)
+    }
+
+    ///
+    /// Pick each shape's color from the target pixels under it with `color_sample`, instead of
+    /// their mean
+    ///
+    pub fn set_color_sample(&mut self, color_sample: ColorSample) {
+        self.color_sample = color_sample;
+    }
+
+    ///
+    /// Give shapes of `shape_type` an alpha of `alpha`, instead of the usual 128
+    ///
+    pub fn set_shape_alpha(&mut self, shape_type: ShapeType, alpha: u8) {
+        self.shape_alphas.insert(shape_type, alpha);
+    }
+
+    ///
+    /// Give shapes an alpha based on their area (see `AlphaBySize`), instead of a fixed one
+    ///
+    /// Takes precedence over alphas set for shape types
+    ///
+    pub fn set_alpha_by_size(&mut self, alpha_by_size: Option<AlphaBySize>) {
+        self.alpha_by_size = alpha_by_size;
+    }
+
+    ///
+    /// Reject shapes that would leave the approximation's contrast (see `luma_contrast`) below
+    /// `min_contrast`, so many translucent layers don't wash it out
+    ///
+    /// Shapes that raise the contrast are always allowed, since the approximation starts flat
+    ///
+    pub fn set_min_contrast(&mut self, min_contrast: Option<f64>) {
+        self.min_contrast = min_contrast;
+    }
+
+    ///
+    /// Include the alpha channel when scoring
+    ///
+    /// Off by default: every shape has the same alpha, so alpha differences only add constant error
+    ///
+    pub fn set_score_alpha(&mut self, score_alpha: bool) {
+        self.score_alpha = score_alpha;
+    }
+
+    ///
+    /// Let each hill climb accept up to `plateau_moves` mutations that don't change the score
+    ///
+    /// This lets the search drift across flat regions instead of stopping at their edges. The
+    /// shape that's kept is still the first one with the best score
+    ///
+    pub fn set_plateau_moves(&mut self, plateau_moves: u32) {
+        self.plateau_moves = plateau_moves;
+    }
+
+    /// Hill climb from `pool` random shapes each time a shape is added, and keep the best one
+    pub fn set_pool(&mut self, pool: u32) {
+        self.pool = pool;
+    }
+
+    ///
+    /// Have `step_batch` search for `commit_batch` shapes against the same approximation, then
+    /// commit the best ones that don't overlap each other together
+    ///
+    /// Candidates are scored independently, so later ones don't see the earlier ones' changes.
+    /// 1 commits each shape before searching for the next, like `step`
+    ///
+    pub fn set_commit_batch(&mut self, commit_batch: u32) {
+        if commit_batch == 0 {
+            panic!("The commit batch needs at least one shape");
+        }
+
+        self.commit_batch = commit_batch;
+    }
+
+    ///
+    /// Stop hill climbing for a shape after `shape_time_budget`, even if it's still improving, and
+    /// use the best shape found so far
+    ///
+    /// The budget covers every climb in the pool. `None` climbs until `max_age` mutations fail
+    ///
+    pub fn set_shape_time_budget(&mut self, shape_time_budget: Option<Duration>) {
+        self.shape_time_budget = shape_time_budget;
+    }
+
+    /// Returns the overlap penalty for adding `shape` to the approximation
+    fn overlap_penalty_for(&self, shape: &dyn Shape) -> f64 {
+        if self.overlap_penalty <= 0.0 {
+            return 0.0;
+        }
+
+        let (width, height) = self.target.dimensions();
+
+        let mut total_coverage = 0.0;
+        let mut num_pixels = 0;
+
+        for pixel in shape.get_pixels() {
+            if pixel.x < 0 || pixel.x >= width as i32 || pixel.y < 0 || pixel.y >= height as i32 {
+                continue;
+            }
+
+            total_coverage += self.coverage[(pixel.y as u32 * width + pixel.x as u32) as usize];
+            num_pixels += 1;
+        }
+
+        if num_pixels == 0 {
+            0.0
+        } else {
+            self.overlap_penalty * total_coverage / num_pixels as f64
+        }
+    }
+
+    /// Returns `shape` mirrored according to the configured symmetry, if there should be a mirror
+    fn mirror_of(&self, shape: &dyn Shape) -> Option<Box<dyn Shape>> {
+        if self.symmetry == Symmetry::None {
+            return None;
+        }
+
+        shape.mirror(self.symmetry, self.width(), self.height())
+    }
+
+    ///
+    /// Returns the pixel with the strongest gradient within `EDGE_SNAP_RADIUS` of `point`, with a
+    /// chance of the edge snap strength, or else `point`
+    ///
+    /// `point` is also kept if there's no edge near it, or edge snapping is off
+    ///
+    fn edge_snapped(&self, point: PrimitivePoint, rng: &mut impl Rng) -> PrimitivePoint {
+        let gradients = match &self.gradients {
+            Some(gradients) if rng.gen::<f64>() < self.edge_snap => gradients,
+            _ => return point,
+        };
+        let (width, height) = gradients.dimensions();
+
+        let mut best = (0, point);
+        for y in point.y - EDGE_SNAP_RADIUS..=point.y + EDGE_SNAP_RADIUS {
+            for x in point.x - EDGE_SNAP_RADIUS..=point.x + EDGE_SNAP_RADIUS {
+                if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+                    continue;
+                }
+
+                let gradient = gradients.get_pixel(x as u32, y as u32)[0];
+                if gradient > best.0 {
+                    best = (gradient, PrimitivePoint::new(x, y));
+                }
+            }
+        }
+
+        best.1
+    }
+
+    /// Returns the area of `shape` plus its mirror's, if it has one
+    fn area_with_mirror(&self, shape: &dyn Shape) -> f64 {
+        shape.area() + self.mirror_of(shape).map_or(0.0, |mirror| mirror.area())
+    }
+
+    ///
+    /// Returns how much adding `shape` (and its mirror, if any) would lower the score, per pixel
+    /// of its area
+    ///
+    /// Later shapes usually improve less per pixel, so this shows the diminishing returns of
+    /// adding more. Negative if `shape` makes the approximation worse
+    ///
+    pub fn shape_efficiency(&self, shape: &dyn Shape) -> f64 {
+        let mut new_image = self.clone();
+        new_image.approximation = self.paint_candidate(shape);
+
+        efficiency(
+            self.score() - new_image.score(),
+            self.area_with_mirror(shape),
+        )
+    }
+
+    ///
+    /// Returns the efficiency (see `shape_efficiency`) of the shape the last call to
+    /// `add_new_shape` added, or None if it didn't add one
+    ///
+    pub fn last_efficiency(&self) -> Option<f64> {
+        self.last_efficiency
+    }
+
+    /// Returns the approximation with `shape` (and its mirror, if any) painted on
+    fn paint_candidate(&self, shape: &dyn Shape) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        let approximation = self.apply_fill_style(
+            shape,
+            shape.paint_on(&self.approximation),
+            &self.approximation,
+            1.0,
+        );
+
+        match self.mirror_of(shape) {
+            Some(mirror) => self.apply_fill_style(
+                &*mirror,
+                mirror.paint_on(&approximation),
+                &approximation,
+                1.0,
+            ),
+            None => approximation,
+        }
+    }
+
+    /// Returns `painted`, which is `original` with `shape` painted on at `scale`, in the fill style
+    fn apply_fill_style(
+        &self,
+        shape: &dyn Shape,
+        painted: ImageBuffer<Rgba<u8>, Vec<u8>>,
+        original: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+        scale: f64,
+    ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        match self.fill_style {
+            FillStyle::Hatch if shape.shape_type().is_filled() => hatch(painted, original, scale),
+            _ => painted,
+        }
+    }
+
+    /// Determine if changing the approximation to `candidate` would take its contrast below the
+    /// minimum
+    fn washes_out(&self, candidate: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> bool {
+        match self.min_contrast {
+            Some(min_contrast) => {
+                let contrast = luma_contrast(candidate);
+                contrast < min_contrast && contrast < luma_contrast(&self.approximation)
+            }
+            None => false,
+        }
+    }
+
+    ///
+    /// Paint `shape` (and its mirror, if any) onto the approximation, accumulate its opacity in
+    /// the coverage map, and add it to the list of shapes
+    ///
+    fn commit_shape(&mut self, shape: Box<dyn Shape>) {
+        let mirror = self.mirror_of(&*shape);
+
+        self.push_shape(shape);
+        if let Some(mirror) = mirror {
+            self.push_shape(mirror);
+        }
+    }
+
+    fn push_shape(&mut self, shape: Box<dyn Shape>) {
+        let (width, height) = self.target.dimensions();
+        let alpha = shape.get_color().0[3] as f64 / 255.0;
+
+        for pixel in shape.get_pixels() {
+            if pixel.x < 0 || pixel.x >= width as i32 || pixel.y < 0 || pixel.y >= height as i32 {
+                continue;
+            }
+
+            // Composite the shape's opacity over what's already there
+            let coverage = &mut self.coverage[(pixel.y as u32 * width + pixel.x as u32) as usize];
+            *coverage += alpha * (1.0 - *coverage);
+        }
+
+        for index in self.pixel_indices(&*shape) {
+            self.layer_counts[index] += 1;
+        }
+
+        self.approximation = self.apply_fill_style(
+            &*shape,
+            shape.paint_on(&self.approximation),
+            &self.approximation,
+            1.0,
+        );
+        self.shapes.push(shape);
+    }
+
+    pub fn target_average_color_in_shape(&self, shape: &impl Shape) -> Rgba<u8> {
+        // Axis-aligned rectangles can skip visiting every pixel
+        if let Some((min_x, min_y, max_x, max_y)) = shape.axis_aligned_bounds() {
+            let (sums, num_pixels) = self.target_integral.sum(min_x, min_y, max_x, max_y);
+
+            // Like average_color_in_shape, a shape entirely off of the image is transparent
+            if num_pixels == 0 {
+                return Rgba([0, 0, 0, 0]);
+            }
+
+            return Rgba([
+                (sums[0] / num_pixels) as u8,
+                (sums[1] / num_pixels) as u8,
+                (sums[2] / num_pixels) as u8,
+                SHAPE_ALPHA,
+            ]);
+        }
+
+        average_color_in_shape(&self.target, shape)
+    }
+
+    ///
+    /// Returns the target's color under `shape`, picked with the color sample
+    ///
+    /// Like `target_average_color_in_shape`, a shape entirely off of the image is transparent
+    ///
+    pub fn target_color_in_shape(&self, shape: &impl Shape) -> Rgba<u8> {
+        if self.color_sample == ColorSample::Mean {
+            return self.target_average_color_in_shape(shape);
+        }
+
+        let (width, height) = self.target.dimensions();
+        let pixels: Vec<Rgba<u8>> = shape
+            .clipped_pixels(width, height)
+            .iter()
+            .map(|p| *self.target.get_pixel(p.x as u32, p.y as u32))
+            .collect();
+
+        match self.color_sample.sample(&pixels) {
+            Some([r, g, b]) => Rgba([r, g, b, SHAPE_ALPHA]),
+            None => Rgba([0, 0, 0, 0]),
+        }
+    }
+
+    /// Returns the color `shape` should be: the fixed color if there is one, or else the target's
+    /// color under it (see `target_color_in_shape`), snapped to the palette if there is one. Its
+    /// alpha comes from its area with alpha by size, or else is the one set for its type, if any
+    pub fn color_for_shape(&self, shape: &impl Shape) -> Rgba<u8> {
+        let mut color = match self.fixed_color {
+            Some(color) => Rgba([color[0], color[1], color[2], SHAPE_ALPHA]),
+            None => self.target_color_in_shape(shape),
+        };
+
+        // Shapes entirely off of the image stay transparent
+        if color[3] != 0 {
+            if let Some(palette) = &self.palette {
+                let closest = closest_color(palette, &color);
+                color = Rgba([closest[0], closest[1], closest[2], color[3]]);
+            }
+
+            if let Some(alpha_by_size) = self.alpha_by_size {
+                color[3] = alpha_by_size.alpha(shape.area(), self.width(), self.height());
+            } else if let Some(&alpha) = self.shape_alphas.get(&shape.shape_type()) {
+                color[3] = alpha;
+            }
+        }
+
+        color
+    }
+
+    pub fn save_to(&self, path: PathBuf) {
+        let extension = path.extension();
+
+        match extension {
+            None => panic!("Can't save to file {:?} (no extension found!)", path),
+            Some(os_str) => match os_str.to_str() {
+                Some("svg") => self.save_to_svg(path),
+                Some("json") => self.save_shapes_json(path),
+                Some("png") | Some("jpg") | Some("bmp") | Some("ico") | Some("gif") => {
+                    self.save_to_img(path)
+                }
+                _ => error!("Invalid save file type: {:?}", extension),
+            },
+        }
+    }
+
+    /// Returns the approximation as an SVG document. See `write_svg` to stream it instead
+    pub fn as_svg(&self) -> String {
+        let mut svg = vec![];
+        self.write_svg(&mut svg).unwrap();
+
+        String::from_utf8(svg).unwrap()
+    }
+
+    ///
+    /// Write the approximation as an SVG document to `writer`
+    ///
+    /// Each shape's markup is written as it's generated, so the whole document never has to be
+    /// held in memory. Only `--format`s that group shapes build their groups first
+    ///
+    pub fn write_svg<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let (scaled_width, scaled_height) = self.target.dimensions();
+        let inverted_scale = 1.0 / self.scale;
+        let original_width = (scaled_width as f64 * inverted_scale) as u32;
+        let original_height = (scaled_height as f64 * inverted_scale) as u32;
+
+        match self.dpi {
+            Some(dpi) => {
+                // Keep drawing in pixels, but tell viewers how large those pixels are
+                let to_mm = |pixels: u32| pixels as f64 / dpi * MM_PER_INCH;
+                write!(
+                    writer,
+                    "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"{}mm\" height=\"{}mm\" viewBox=\"0 0 {} {}\">",
+                    to_mm(original_width),
+                    to_mm(original_height),
+                    original_width,
+                    original_height
+                )?;
+            }
+            None => {
+                write!(
+                    writer,
+                    "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"{}\" height=\"{}\">",
+                    original_width, original_height
+                )?;
+            }
+        }
+
+        let feathers: BTreeSet<u32> = self.shapes.iter().map(|shape| shape.feather()).collect();
+        writer.write_all(feather_filters_svg(&feathers, inverted_scale).as_bytes())?;
+
+        // Clip everything (including the background) to the crop mask
+        if let Some(crop_mask) = self.crop_mask {
+            write!(
+                writer,
+                "<defs>{}</defs><g clip-path=\"url(#crop)\">",
+                crop_mask.as_svg_clip_path("crop", original_width, original_height)
+            )?;
+        }
+
+        if self.aberration > 0 {
+            writer.write_all(aberration_svg_start().as_bytes())?;
+        }
+
+        // Add the background
+        write!(
+            writer,
+            "<rect x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"{}\" />",
+            original_width,
+            original_height,
+            rgb_to_hex(self.background)
+        )?;
+
+        match &self.shape_rendering {
+            Some(shape_rendering) => write!(writer, "<g shape-rendering=\"{}\">", shape_rendering)?,
+            None => writer.write_all(b"<g>")?,
+        }
+
+        // Add the polygons!
+        let grouped = match self.svg_format {
+            _ if self.fill_style == FillStyle::Hatch => {
+                Some(hatched_shapes_svg(&self.shapes, inverted_scale))
+            }
+            SvgFormat::Plain => self
+                .seam_stroke
+                .map(|seam_stroke| seamless_shapes_svg(&self.shapes, inverted_scale, seam_stroke)),
+            SvgFormat::PathMerge => Some(merged_shapes_svg(
+                &self.shapes,
+                inverted_scale,
+                self.merge_tolerance,
+                self.seam_stroke,
+            )),
+            SvgFormat::Layers => Some(layered_shapes_svg(
+                &self.shapes,
+                inverted_scale,
+                self.seam_stroke,
+            )),
+            SvgFormat::Templates => Some(templated_shapes_svg(
+                &self.shapes,
+                inverted_scale,
+                self.seam_stroke,
+            )),
+        };
+        match grouped {
+            Some(grouped) => writer.write_all(grouped.as_bytes())?,
+            None => {
+                for polygon in self.shapes.iter() {
+                    polygon.write_svg(writer, inverted_scale)?;
+                }
+            }
+        }
+
+        writer.write_all(b"</g>")?;
+
+        if self.aberration > 0 {
+            writer.write_all(
+                aberration_svg_end(self.aberration, original_width, original_height).as_bytes(),
+            )?;
+        }
+
+        if self.crop_mask.is_some() {
+            writer.write_all(b"</g>")?;
+        }
+
+        if let Some(signature) = &self.signature {
+            writer.write_all(
+                signature
+                    .as_svg(
+                        original_width,
+                        original_height,
+                        contrasting_color(self.background),
+                    )
+                    .as_bytes(),
+            )?;
+        }
+
+        writer.write_all(b"</svg>")
+    }
+
+    pub fn save_to_svg(&self, path: PathBuf) {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(path);
+
+        if let Ok(svg) = file {
+            let mut svg = BufWriter::new(svg);
+            self.write_svg(&mut svg).unwrap();
+            svg.flush().unwrap();
+        } else {
+            let err = file.unwrap_err();
+            panic!("{}", err.to_string());
+        }
+    }
+
+    /// Save the current approximation in an image format
+    ///
+    ///  # Arguments
+    ///
+    /// * `path` - The path to save the image to. It must be a format supported by the image library
+    ///
+    pub fn save_to_img(&self, path: PathBuf) {
+        let (original_width, original_height) = self.output_dimensions();
+
+        let mut img = self.render_at(original_width, original_height);
+        if self.aberration > 0 {
+            img = split_channels(&img, self.aberration);
+        }
+        if let Some(signature) = &self.signature {
+            signature.draw_on(&mut img, contrasting_color(self.background));
+        }
+
+        let is_png = path.extension().and_then(|e| e.to_str()) == Some("png");
+        // The image library can't write pHYs or color chunks, so encode PNGs ourselves
+        if is_png && (self.dpi.is_some() || self.color_profile.is_some()) {
+            let file = File::create(&path)
+                .unwrap_or_else(|e| panic!("Could not create {:?}: {}", path, e));
+            write_png(&img, self.dpi, self.color_profile, BufWriter::new(file));
+        } else {
+            img.save(path).unwrap();
+        }
+    }
+
+    /// Render the current approximation to a `width` x `height` image
+    ///
+    /// Shapes are scaled uniformly, by the smaller of the horizontal and vertical ratios
+    pub fn render_at(&self, width: u32, height: u32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        let mut img = ImageBuffer::new(width, height);
+        self.paint_shapes_into(&mut img);
+
+        img
+    }
+
+    ///
+    /// Render the current approximation into `buf`, at its size, like `render_at`
+    ///
+    /// Everything in `buf` is painted over, and shapes are blended straight into it, so it can be
+    /// reused between renders without allocating, e.g. for live previews. Returns an error,
+    /// leaving `buf` as it was, if it's empty or its aspect ratio doesn't match the approximation's
+    ///
+    pub fn render_into(
+        &self,
+        buf: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    ) -> Result<(), PrimitiveError> {
+        let (width, height) = buf.dimensions();
+        let (target_width, target_height) = self.target.dimensions();
+
+        // Sizes are rounded to whole pixels, so the ratios can be off by up to a pixel's worth
+        let mismatch =
+            (width as i64 * target_height as i64 - height as i64 * target_width as i64).abs();
+        if width == 0 || height == 0 || mismatch >= max(target_width, target_height) as i64 {
+            return Err(PrimitiveError::RenderSize {
+                width,
+                height,
+                target_width,
+                target_height,
+            });
+        }
+
+        self.paint_shapes_into(buf);
+
+        Ok(())
+    }
+
+    /// Paint the background and every shape over all of `img`, scaled to its size
+    fn paint_shapes_into(&self, img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
+        let (width, height) = img.dimensions();
+        let render_scale = self.render_scale(width, height);
+
+        for pixel in img.pixels_mut() {
+            *pixel = self.background;
+        }
+
+        for poly in self.shapes.iter() {
+            self.paint_styled_into(&**poly, img, render_scale);
+        }
+
+        self.apply_crop_mask(img);
+    }
+
+    /// Paint `shape` onto `img` at `scale` in the fill style. Only hatching needs a copy of what
+    /// was under the shape, every other shape is blended in place
+    fn paint_styled_into(
+        &self,
+        shape: &dyn Shape,
+        img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+        scale: f64,
+    ) {
+        match self.fill_style {
+            FillStyle::Hatch if shape.shape_type().is_filled() => {
+                *img = hatch(shape.scaled_paint_on(img, scale), img, scale);
+            }
+            _ => shape.scaled_paint_into(img, scale),
+        }
+    }
+
+    /// Returns how much shapes are scaled by when rendering a `width` x `height` image
+    fn render_scale(&self, width: u32, height: u32) -> f64 {
+        let (scaled_width, scaled_height) = self.target.dimensions();
+
+        f64::min(
+            width as f64 / scaled_width as f64,
+            height as f64 / scaled_height as f64,
+        )
+    }
+
+    /// Make the pixels of `img` outside of the crop mask (if there is one) transparent
+    fn apply_crop_mask(&self, img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
+        let (width, height) = img.dimensions();
+
+        if let Some(crop_mask) = self.crop_mask {
+            for (x, y, pixel) in img.enumerate_pixels_mut() {
+                if !crop_mask.contains(x, y, width, height) {
+                    pixel[3] = 0;
+                }
+            }
+        }
+    }
+
+    ///
+    /// Returns each step of the approximation at the size of saved output: the background, then
+    /// the image after each shape is added
+    ///
+    /// Frames are rendered as they're iterated, and match the image `save_to_img` would save with
+    /// that many shapes
+    ///
+    pub fn iter_frames(&self) -> impl Iterator<Item = ImageBuffer<Rgba<u8>, Vec<u8>>> + '_ {
+        let (width, height) = self.output_dimensions();
+        let render_scale = self.render_scale(width, height);
+        let mut canvas = ImageBuffer::from_pixel(width, height, self.background);
+
+        once(None)
+            .chain(self.shapes.iter().map(Some))
+            .map(move |shape| {
+                if let Some(shape) = shape {
+                    canvas = self.apply_fill_style(
+                        &**shape,
+                        shape.scaled_paint_on(&canvas, render_scale),
+                        &canvas,
+                        render_scale,
+                    );
+                }
+
+                let mut frame = canvas.clone();
+                self.apply_crop_mask(&mut frame);
+                if self.aberration > 0 {
+                    frame = split_channels(&frame, self.aberration);
+                }
+                if let Some(signature) = &self.signature {
+                    signature.draw_on(&mut frame, contrasting_color(self.background));
+                }
+                frame
+            })
+    }
+
+    ///
+    /// Save each frame from `iter_frames` as a PNG in `dir`, for making videos with other tools
+    ///
+    /// The files are numbered from 0000.png (just the background), padded so they all have the
+    /// same number of digits
+    ///
+    pub fn save_frames(&self, dir: PathBuf) {
+        create_dir_all(&dir).unwrap_or_else(|e| panic!("Could not create {:?}: {}", dir, e));
+
+        let digits = max(4, self.shapes.len().to_string().len());
+        for (i, frame) in self.iter_frames().enumerate() {
+            frame
+                .save(dir.join(format!("{:0digits$}.png", i, digits = digits)))
+                .unwrap();
+        }
+    }
+
+    ///
+    /// Save each shape to its own transparent PNG in `dir`, at the size of saved output, so they
+    /// can be animated or recolored elsewhere
+    ///
+    /// The files are numbered in the order the shapes are painted, starting with 0000.png at the
+    /// bottom. The background isn't included
+    ///
+    pub fn save_layers(&self, dir: PathBuf) {
+        create_dir_all(&dir).unwrap_or_else(|e| panic!("Could not create {:?}: {}", dir, e));
+
+        let (width, height) = self.output_dimensions();
+        let render_scale = self.render_scale(width, height);
+        let clear = ImageBuffer::from_pixel(width, height, Rgba([0, 0, 0, 0]));
+
+        for (i, shape) in self.shapes.iter().enumerate() {
+            let mut layer = self.apply_fill_style(
+                &**shape,
+                shape.scaled_paint_on(&clear, render_scale),
+                &clear,
+                render_scale,
+            );
+            self.apply_crop_mask(&mut layer);
+
+            layer.save(dir.join(format!("{:04}.png", i))).unwrap();
+        }
+    }
+
+    ///
+    /// Render the current approximation so its largest dimension is `size` pixels, for a preview
+    ///
+    pub fn thumbnail(&self, size: u32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        let (width, height) = self.target.dimensions();
+        let ratio = size as f64 / max(width, height) as f64;
+
+        self.render_at(
+            max((width as f64 * ratio).round() as u32, 1),
+            max((height as f64 * ratio).round() as u32, 1),
+        )
+    }
+
+    /// Save a thumbnail (see `thumbnail`) to `path`, which must be an image format
+    pub fn save_thumbnail(&self, path: PathBuf, size: u32) {
+        self.thumbnail(size).save(path).unwrap();
+    }
+
+    ///
+    /// Returns the target on the left and the approximation on the right, both at the size shapes
+    /// are searched at, for judging how close they are
+    ///
+    pub fn side_by_side(&self) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        let (width, height) = self.target.dimensions();
+
+        let mut comparison = ImageBuffer::new(width * 2, height);
+        replace(&mut comparison, &self.target, 0, 0);
+        replace(
+            &mut comparison,
+            &self.render_at(width, height),
+            width as i64,
+            0,
+        );
+
+        comparison
+    }
+
+    /// Save a side by side comparison (see `side_by_side`) to `path`, which must be an image format
+    pub fn save_side_by_side(&self, path: PathBuf) {
+        self.side_by_side().save(path).unwrap();
+    }
+
+    /// Returns the width and height of saved raster output
+    fn output_dimensions(&self) -> (u32, u32) {
+        let (scaled_width, scaled_height) = self.target.dimensions();
+        let inverted_scale = 1.0 / self.scale;
+
+        (
+            (scaled_width as f64 * inverted_scale) as u32,
+            (scaled_height as f64 * inverted_scale) as u32,
+        )
+    }
+
+    /// Change the size of saved output so its largest dimension is `size` pixels
+    pub fn set_output_size(&mut self, size: u32) {
+        let (scaled_width, scaled_height) = self.target.dimensions();
+        self.scale = max(scaled_width, scaled_height) as f64 / size as f64;
+    }
+
+    /// Save the background and shapes as JSON, so they can be re-rendered later without searching
+    pub fn save_shapes_json(&self, path: PathBuf) {
+        let (width, height) = self.target.dimensions();
+
+        let shape_file = ShapeFile {
+            width,
+            height,
+            scale: self.scale,
+            background: self.background,
+            shapes: self
+                .shapes
+                .iter()
+                .map(|shape| ShapeEntry::from_shape(&**shape))
+                .collect(),
+        };
+
+        let file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(path);
+
+        match file {
+            Ok(file) => serde_json::to_writer(file, &shape_file).unwrap(),
+            Err(err) => panic!("{}", err.to_string()),
+        }
+    }
+
+    /// Load an approximation saved with `save_shapes_json`
+    ///
+    /// The loaded approximation has no target, so it can be rendered and saved, but not improved
+    pub fn load_shapes_json(path: PathBuf) -> PrimitiveImage {
+        let shape_file = read_shape_file(&path);
+
+        let blank =
+            ImageBuffer::from_pixel(shape_file.width, shape_file.height, shape_file.background);
+        let mut image = PrimitiveImage::from_image(blank, 0, Some(shape_file.background));
+        image.scale = shape_file.scale;
+        image.replay_shapes(&shape_file);
+
+        image.target = image.approximation.clone();
+        image.target_integral = Arc::new(IntegralImage::new(&image.target));
+
+        image
+    }
+
+    ///
+    /// Replace the approximation with one saved by `save_shapes_json`, so the search continues
+    /// from it instead of starting over
+    ///
+    /// The shape file must have been generated from the same image at the same size
+    ///
+    pub fn append_shapes_json(&mut self, path: PathBuf) {
+        let shape_file = read_shape_file(&path);
+
+        if (shape_file.width, shape_file.height) != self.target.dimensions() {
+            panic!(
+                "Shape file {:?} is {}x{}, but the image is {}x{}. Use the same image and --scale-to",
+                path,
+                shape_file.width,
+                shape_file.height,
+                self.width(),
+                self.height()
+            );
+        }
+
+        self.background = shape_file.background;
+        self.replay_shapes(&shape_file);
+    }
+
+    /// Replace the shapes with those in `shape_file`, and repaint the approximation with them
+    fn replay_shapes(&mut self, shape_file: &ShapeFile) {
+        self.shapes = shape_file
+            .shapes
+            .iter()
+            .map(|entry| {
+                entry.to_shape().unwrap_or_else(|err| {
+                    panic!("Invalid {:?} in shape file: {}", entry.shape_type, err)
+                })
+            })
+            .collect();
+
+        self.repaint();
+    }
+
+    ///
+    /// Clear the approximation to the background, then paint every shape on it again
+    ///
+    /// Shapes are added as they are, since any mirrors were already added
+    ///
+    fn repaint(&mut self) {
+        let (width, height) = self.target.dimensions();
+
+        self.approximation = ImageBuffer::from_pixel(width, height, self.background);
+        self.coverage = vec![0.0; (width * height) as usize];
+        self.layer_counts = vec![0; (width * height) as usize];
+
+        for shape in std::mem::take(&mut self.shapes) {
+            self.push_shape(shape);
+        }
+    }
+
+    /// Returns the color the approximation starts from, under all of the shapes
+    pub fn background(&self) -> Rgba<u8> {
+        self.background
+    }
+
+    ///
+    /// Change the background color, repainting the shapes over it so the score stays consistent
+    ///
+    /// The target isn't changed, so transparent parts of the input image still show the original
+    /// background
+    ///
+    pub fn set_background(&mut self, color: Rgba<u8>) {
+        self.background = color;
+        self.repaint();
+    }
+
+    ///
+    /// Remove the most recently added shape, repainting the others so the score is what it was
+    /// before the shape was added
+    ///
+    /// With symmetry, a shape's mirror is added after it, so it's removed first
+    /// Returns None if there are no shapes
+    ///
+    pub fn pop_shape(&mut self) -> Option<Box<dyn Shape>> {
+        let shape = self.shapes.pop()?;
+        self.repaint();
+
+        Some(shape)
+    }
+
+    ///
+    /// Remove the shapes that help the score least until there are at most `max_elements`, for a
+    /// smaller output file, then repaint the rest
+    ///
+    /// Each shape's impact is how much the score worsens without it, measured once against all of
+    /// the shapes. Shapes that make the score worse go first. A shape and its mirror are pruned
+    /// separately
+    /// Returns how many shapes were removed
+    ///
+    pub fn prune_to_budget(&mut self, max_elements: usize) -> usize {
+        let excess = self.shapes.len().saturating_sub(max_elements);
+        if excess == 0 {
+            return 0;
+        }
+
+        let score = self.score();
+        let mut without = self.clone();
+        let mut impacts: Vec<(usize, f64)> = (0..self.shapes.len())
+            .map(|i| {
+                without.shapes = self.shapes.clone();
+                without.shapes.remove(i);
+                without.repaint();
+
+                (i, without.score() - score)
+            })
+            .collect();
+        impacts.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        let pruned: Vec<usize> = impacts[..excess].iter().map(|&(i, _)| i).collect();
+        self.shapes = std::mem::take(&mut self.shapes)
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !pruned.contains(i))
+            .map(|(_, shape)| shape)
+            .collect();
+        self.repaint();
+
+        excess
+    }
+
+    ///
+    /// Reorder the shapes by `sort_order` and repaint them, to change how they overlap
+    ///
+    /// Blending depends on the order shapes are painted in, so this changes the approximation and
+    /// usually makes the score worse. Shapes with the same key keep their order
+    ///
+    pub fn sort_shapes(&mut self, sort_order: SortOrder) {
+        self.shapes
+            .sort_by(|a, b| sort_order.key(&**a).total_cmp(&sort_order.key(&**b)));
+        self.repaint();
+    }
+
+    ///
+    /// Add `other`'s shapes on top of these, and repaint so the score reflects the composite
+    ///
+    /// `other` must come from the same image at the same scale, like another run with a different
+    /// shape. Its background is ignored
+    ///
+    pub fn merge_shapes(&mut self, other: &PrimitiveImage) {
+        if other.target.dimensions() != self.target.dimensions() || other.scale != self.scale {
+            panic!(
+                "Can't merge shapes from a {}x{} image scaled by {} into a {}x{} image scaled by {}",
+                other.width(),
+                other.height(),
+                other.scale,
+                self.width(),
+                self.height(),
+                self.scale
+            );
+        }
+
+        self.shapes.extend(other.shapes.iter().cloned());
+        self.repaint();
+    }
+
+    /// Returns the current approximation's score.
+    ///
+    /// Uses the Root Mean Squared Error between the target and approximation images, over the RGB
+    /// channels (or all four, if alpha is scored)
+    /// If the score is masked, pixels outside of the crop mask are ignored, as are pixels outside of
+    /// the alpha mask
+    /// If there's a score region, pixels outside of it are ignored
+    /// If there's a center bias, each pixel's error is weighted by its distance from the center
+    /// If the channel weights aren't equal, each channel's error is weighted by them
+    /// With the YCbCr metric, the error is measured in Y'CbCr instead, weighted by the luma weight
+    pub fn score(&self) -> f64 {
+        self.score_approximation(&self.approximation)
+    }
+
+    ///
+    /// Returns the score the approximation had before any shapes were added, when it was just the
+    /// background
+    ///
+    /// Scored the same way as `score`, so it's the starting point for measuring improvement, e.g.
+    /// as a percentage
+    ///
+    pub fn initial_score(&self) -> f64 {
+        let (width, height) = self.target.dimensions();
+
+        self.score_approximation(&ImageBuffer::from_pixel(width, height, self.background))
+    }
+
+    /// Returns the score `approximation` would have, as described in `score`
+    fn score_approximation(&self, approximation: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> f64 {
+        if self.metric == Metric::YCbCr {
+            return ycbcr_rmse(
+                &self.target,
+                approximation,
+                self.luma_weight,
+                self.score_alpha,
+                |x, y| self.pixel_weight(x, y),
+            );
+        }
+
+        if self.center_weights.is_some()
+            || !self.channel_weights.is_uniform()
+            || self.alpha_mask.is_some()
+            || self.score_region.is_some()
+        {
+            return channel_weighted_rmse(
+                &self.target,
+                approximation,
+                self.channel_weights,
+                self.score_alpha,
+                |x, y| self.pixel_weight(x, y),
+            );
+        }
+
+        match self.crop_mask {
+            Some(crop_mask) if self.mask_score => {
+                let (width, height) = self.target.dimensions();
+                let include = |x, y| crop_mask.contains(x, y, width, height);
+
+                if self.score_alpha {
+                    masked_rmse(&self.target, approximation, include)
+                } else {
+                    masked_rgb_rmse(&self.target, approximation, include)
+                }
+            }
+            _ if self.score_alpha => rmse(&self.target, approximation).unwrap(),
+            _ => rgb_rmse(&self.target, approximation),
+        }
+    }
+
+    ///
+    /// Returns the score used to compare candidates while hill climbing
+    ///
+    /// The same as `score`, but only over the sampled pixels if there's a score sample
+    ///
+    fn climb_score(&self) -> f64 {
+        match &self.score_sample {
+            Some(sample) if self.metric == Metric::YCbCr => sampled_ycbcr_rmse(
+                &self.target,
+                &self.approximation,
+                sample,
+                self.luma_weight,
+                self.score_alpha,
+                |x, y| self.pixel_weight(x, y),
+            ),
+            Some(sample) => sampled_rmse(
+                &self.target,
+                &self.approximation,
+                sample,
+                self.channel_weights,
+                self.score_alpha,
+                |x, y| self.pixel_weight(x, y),
+            ),
+            None => self.score(),
+        }
+    }
+
+    /// How much the error at (`x`, `y`) counts towards the score, from the crop mask, alpha mask,
+    /// score region, and center bias
+    fn pixel_weight(&self, x: u32, y: u32) -> f64 {
+        let (width, height) = self.target.dimensions();
+
+        if self.alpha_masked((y * width + x) as usize) {
+            return 0.0;
+        }
+        if self
+            .score_region
+            .is_some_and(|region| !region.contains(x, y))
+        {
+            return 0.0;
+        }
+
+        match self.crop_mask {
+            Some(crop_mask) if self.mask_score && !crop_mask.contains(x, y, width, height) => 0.0,
+            _ => self
+                .center_weights
+                .as_ref()
+                .map_or(1.0, |weights| weights[(y * width + x) as usize]),
+        }
+    }
+
+    ///
+    /// Try to add exactly one shape of the configured type
+    ///
+    /// Lets callers (e.g. a GUI) drive the run themselves, doing other work between shapes
+    ///
+    pub fn step(&mut self, config: &mut RunConfig) -> StepResult {
+        let max_age = config.max_age;
+        let rng = &mut config.rng;
+
+        let found = self.find_new_shape_of_type(&config.shape, max_age, rng);
+        let shape_type = found.as_ref().map(|(shape, _)| shape.shape_type().name());
+        let added = self.commit_found(found);
+
+        StepResult {
+            added,
+            score: self.score(),
+            efficiency: self.last_efficiency,
+            shape_type,
+        }
+    }
+
+    ///
+    /// Search for up to `max_shapes` shapes (at most the commit batch) against the current
+    /// approximation, and commit the best ones that don't overlap each other
+    ///
+    /// Candidates are committed from the best score to the worst, skipping any that cover a pixel
+    /// (or a mirror's pixel) an earlier one did, or that would wash out (see `set_min_contrast`) the
+    /// approximation with the earlier ones painted on. Returns one `StepResult` per added shape, in the
+    /// order they were added, so it's empty if none were
+    ///
+    pub fn step_batch(&mut self, config: &mut RunConfig, max_shapes: u32) -> Vec<StepResult> {
+        let max_age = config.max_age;
+        let rng = &mut config.rng;
+
+        let mut candidates: Vec<(Box<dyn Shape>, f64)> = (0..min(self.commit_batch, max_shapes))
+            .filter_map(|_| self.find_new_shape_of_type(&config.shape, max_age, rng))
+            .collect();
+        candidates.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        let score = self.score();
+        let mut covered: HashSet<usize> = HashSet::new();
+        let mut results = vec![];
+
+        for (shape, raw_score) in candidates {
+            let mut indices = self.pixel_indices(&*shape);
+            if let Some(mirror) = self.mirror_of(&*shape) {
+                indices.extend(self.pixel_indices(&*mirror));
+            }
+            if indices.iter().any(|index| covered.contains(index)) {
+                trace!("Skipping shape that overlaps the batch {:?}", shape);
+                continue;
+            }
+            // Each candidate was only checked against the approximation before the batch
+            if self.washes_out(&self.paint_candidate(&*shape)) {
+                trace!(
+                    "Skipping shape that lowers contrast with the batch {:?}",
+                    shape
+                );
+                continue;
+            }
+            covered.extend(indices);
+            let shape_type = shape.shape_type().name();
+
+            self.last_efficiency = Some(efficiency(
+                score - raw_score,
+                self.area_with_mirror(&*shape),
+            ));
+            self.commit_shape(shape);
+            results.push(StepResult {
+                added: true,
+                score: self.score(),
+                efficiency: self.last_efficiency,
+                shape_type: Some(shape_type),
+            });
+        }
+
+        results
+    }
+
+    ///
+    /// Same as `add_new_shape`, with the type chosen at runtime
+    ///
+    /// For a mix, one of its types is picked at random
+    ///
+    pub fn add_new_shape_of_type(
+        &mut self,
+        shape_type: &ShapeType,
+        max_age: u32,
+        rng: &mut impl Rng,
+    ) -> bool {
+        let found = self.find_new_shape_of_type(shape_type, max_age, rng);
+        self.commit_found(found)
+    }
+
+    pub fn add_new_shape<T>(&mut self, max_age: u32, rng: &mut impl Rng) -> bool
+    where
+        T: RandomShape + Shape + Clone + 'static,
+    {
+        let found = self.find_new_shape::<T>(max_age, rng);
+        self.commit_found(found)
+    }
+
+    /// Commit the shape `find_new_shape` found, if it found one, and record its efficiency
+    fn commit_found(&mut self, found: Option<(Box<dyn Shape>, f64)>) -> bool {
+        self.last_efficiency = None;
+
+        match found {
+            Some((shape, raw_score)) => {
+                trace!("Returning shape {:?}", shape);
+                self.last_efficiency = Some(efficiency(
+                    self.score() - raw_score,
+                    self.area_with_mirror(&*shape),
+                ));
+                self.commit_shape(shape);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Same as `find_new_shape`, with the type chosen at runtime
+    fn find_new_shape_of_type(
+        &self,
+        shape_type: &ShapeType,
+        max_age: u32,
+        rng: &mut impl Rng,
+    ) -> Option<(Box<dyn Shape>, f64)> {
+        match shape_type {
+            ShapeType::Triangle => self.find_new_shape::<Triangle>(max_age, rng),
+            ShapeType::QuadraticBezier => self.find_new_shape::<QuadraticBezier>(max_age, rng),
+            ShapeType::CubicBezier => self.find_new_shape::<CubicBezier>(max_age, rng),
+            ShapeType::Rectangle => self.find_new_shape::<Rectangle>(max_age, rng),
+            ShapeType::Ellipse => self.find_new_shape::<Ellipse>(max_age, rng),
+            ShapeType::Heart => self.find_new_shape::<Heart>(max_age, rng),
+            ShapeType::Teardrop => self.find_new_shape::<Teardrop>(max_age, rng),
+            ShapeType::BezierPath => self.find_new_shape::<BezierPath>(max_age, rng),
+            ShapeType::Cross => self.find_new_shape::<Cross>(max_age, rng),
+            ShapeType::Mixed(shape_types) => {
+                if shape_types.is_empty() {
+                    panic!("A mix needs at least one shape type");
+                }
+
+                let shape_type = &shape_types[rng.gen_range(0..shape_types.len())];
+                self.find_new_shape_of_type(shape_type, max_age, rng)
+            }
+        }
+    }
+
+    ///
+    /// Search for a shape that improves the current approximation, without adding it
+    ///
+    /// Returns the best shape found and the approximation's score with it painted on, or None if
+    /// it wouldn't be an improvement
+    ///
+    fn find_new_shape<T>(&self, max_age: u32, rng: &mut impl Rng) -> Option<(Box<dyn Shape>, f64)>
+    where
+        T: RandomShape + Shape + Clone + 'static,
+    {
+        let deadline = self
+            .shape_time_budget
+            .map(|shape_time_budget| Instant::now() + shape_time_budget);
+
+        // Hill climb from each shape in the pool, keeping the best result
+        let mut best: Option<(Box<T>, f64, f64)> = None;
+        let mut pool_scores = vec![];
+
+        for _ in 0..max(self.pool, 1) {
+            if let Some(Climb {
+                shape,
+                raw_score,
+                score,
+                shapes_visited,
+            }) = self.hill_climb::<T>(max_age, deadline, rng)
+            {
+                trace!("Hill climb visited {} shapes", shapes_visited);
+                pool_scores.push(score);
+
+                if best
+                    .as_ref()
+                    .is_none_or(|(_, _, best_score)| score < *best_score)
+                {
+                    best = Some((shape, raw_score, score));
+                }
+            }
+        }
+
+        if pool_scores.len() > 1 {
+            pool_scores.sort_by(|a, b| a.total_cmp(b));
+            info!("Pool scores: {:?}", pool_scores);
+        }
+
+        // Sampled scores only rank the candidates, so check the best one against every pixel
+        if let (Some((best_shape, best_raw_score, _)), Some(_)) = (&mut best, &self.score_sample) {
+            let mut new_image = self.clone();
+            new_image.approximation = self.paint_candidate(&**best_shape);
+            *best_raw_score = new_image.score();
+        }
+
+        if let Some((best_shape, _, _)) = &best {
+            if self.washes_out(&self.paint_candidate(&**best_shape)) {
+                trace!("Rejecting shape that lowers contrast {:?}", best_shape);
+                return None;
+            }
+        }
+
+        // Only return the shape if it is an improvement over the current approximation
+        match best {
+            Some((best_shape, best_raw_score, _)) if best_raw_score < self.score() => {
+                Some((best_shape as Box<dyn Shape>, best_raw_score))
+            }
+            _ => None,
+        }
+    }
+
+    ///
+    /// Estimate how many shapes the target needs, from how much of it is edges
+    ///
+    /// Flat images get `MIN_SUGGESTED_SHAPES`, and images that are all edges get
+    /// `MAX_SUGGESTED_SHAPES`. The suggestion is rounded to a multiple of 10
+    ///
+    pub fn suggest_shape_count(&self) -> u32 {
+        let gradients = sobel_gradients(&grayscale(&self.target));
+        let edges = gradients.pixels().filter(|p| p[0] > EDGE_THRESHOLD).count();
+        let edge_fraction = edges as f64 / gradients.len() as f64;
+
+        let count = MIN_SUGGESTED_SHAPES as f64
+            + edge_fraction * (MAX_SUGGESTED_SHAPES - MIN_SUGGESTED_SHAPES) as f64;
+        (count / 10.0).round() as u32 * 10
+    }
+
+    ///
+    /// Find up to `k` dominant colors of the target, using k-means clustering
+    ///
+    /// The colors are ordered from the largest cluster to the smallest, so the first is a good
+    /// background. Fewer are returned if the target has fewer than `k` distinct colors
+    ///
+    pub fn dominant_colors(&self, k: usize) -> Vec<Rgba<u8>> {
+        let step = max(self.target.pixels().len() / K_MEANS_MAX_SAMPLES, 1);
+        let samples: Vec<[f64; 3]> = self
+            .target
+            .pixels()
+            .step_by(step)
+            .map(|pixel| [pixel[0] as f64, pixel[1] as f64, pixel[2] as f64])
+            .collect();
+
+        k_means(&samples, k)
+            .iter()
+            .map(|color| {
+                Rgba([
+                    color[0].round() as u8,
+                    color[1].round() as u8,
+                    color[2].round() as u8,
+                    255,
+                ])
+            })
+            .collect()
+    }
+
+    ///
+    /// Count the distinct colors used by the shapes, and find the most frequent ones
+    ///
+    pub fn color_stats(&self) -> ColorStats {
+        ColorStats::from_colors(self.shapes.iter().map(|shape| shape.get_color()))
+    }
+
+    ///
+    /// Returns the fraction of the image's pixels (from 0 to 1) that at least one shape covers,
+    /// the rest being background
+    ///
+    /// Fully transparent shapes don't count, since the background shows through them
+    ///
+    pub fn coverage(&self) -> f64 {
+        let covered = self
+            .coverage
+            .iter()
+            .filter(|coverage| **coverage > 0.0)
+            .count();
+
+        covered as f64 / self.coverage.len() as f64
+    }
+
+    ///
+    /// Color `shape` using the target, and add it if it improves the approximation
+    ///
+    /// This skips the random search entirely, so library users can place shapes themselves
+    /// Returns true if the shape was added
+    ///
+    pub fn add_specific_shape(&mut self, mut shape: Box<dyn Shape>) -> bool {
+        if self.covers_converged(&*shape) {
+            trace!("Rejecting shape over converged pixels {:?}", shape);
+            return false;
+        }
+        if self.exceeds_max_layers(&*shape) {
+            trace!("Rejecting shape over too many layers {:?}", shape);
+            return false;
+        }
+        if self.outside_alpha_mask(&*shape) {
+            trace!("Rejecting shape outside of the alpha mask {:?}", shape);
+            return false;
+        }
+
+        shape.set_color_using(self);
+
+        let mut new_image = self.clone();
+        new_image.approximation = self.paint_candidate(&*shape);
+
+        if self.washes_out(&new_image.approximation) {
+            trace!("Rejecting shape that lowers contrast {:?}", shape);
+            false
+        } else if new_image.score() < self.score() {
+            trace!("Adding specific shape {:?}", shape);
+            self.commit_shape(shape);
+            true
+        } else {
+            false
+        }
+    }
+
+    ///
+    /// Add a rectangle covering the whole image, colored to bring it closest to the target
+    ///
+    /// This does the first shape's usual job of matching the overall color without searching.
+    /// Returns false, adding nothing, if it wouldn't improve the score (like when the background
+    /// is already the target's average color)
+    ///
+    pub fn seed_background_rect(&mut self) -> bool {
+        let (width, height) = self.target.dimensions();
+        let center = PrimitivePoint::new(width as i32 / 2, height as i32 / 2);
+
+        self.add_specific_shape(Box::new(Rectangle::new(center, width, height, 0)))
+    }
+
+    ///
+    /// Try to add a rectangle covering each cell of a `rows` x `cols` grid over the image, colored
+    /// to the target under it, for coarse structure before searching
+    ///
+    /// The grid is clamped to between one cell and one cell per pixel in each direction, since the
+    /// scaled image's size usually isn't known in advance. Like `add_specific_shape`, each is only
+    /// added if it improves the score
+    /// Returns how many were added
+    ///
+    pub fn seed_grid(&mut self, rows: u32, cols: u32) -> usize {
+        let (width, height) = self.target.dimensions();
+        let (rows, cols) = (rows.clamp(1, height), cols.clamp(1, width));
+
+        let mut added = 0;
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let (left, right) = (col * width / cols, (col + 1) * width / cols);
+                let (top, bottom) = (row * height / rows, (row + 1) * height / rows);
+                let (cell_width, cell_height) = (right - left, bottom - top);
+                let center = PrimitivePoint::new(
+                    (left + cell_width / 2) as i32,
+                    (top + cell_height / 2) as i32,
+                );
+
+                let cell = Rectangle::new(center, cell_width, cell_height, 0);
+                if self.add_specific_shape(Box::new(cell)) {
+                    added += 1;
+                }
+            }
+        }
+
+        added
+    }
+
+    ///
+    /// Try to add a random shape of `shape_type` around each of `points`, given in pixels of the
+    /// original image, to guide where detail goes
+    ///
+    /// For a mix, each point's type is picked at random. Like `add_specific_shape`, each is only
+    /// added if it improves the score
+    /// Returns how many were added
+    ///
+    pub fn seed_points(
+        &mut self,
+        points: &[PrimitivePoint],
+        shape_type: &ShapeType,
+        rng: &mut impl Rng,
+    ) -> usize {
+        let mut added = 0;
+
+        for point in points {
+            let center = PrimitivePoint::new(
+                (point.x as f64 * self.scale) as i32,
+                (point.y as f64 * self.scale) as i32,
+            );
+            let shape = self.seed_shape_of_type(shape_type, center, rng);
+
+            if self.add_specific_shape(shape) {
+                added += 1;
+            }
+        }
+
+        added
+    }
+
+    /// Same as `seed_shape`, with the type chosen at runtime
+    fn seed_shape_of_type(
+        &self,
+        shape_type: &ShapeType,
+        center: PrimitivePoint,
+        rng: &mut impl Rng,
+    ) -> Box<dyn Shape> {
+        match shape_type {
+            ShapeType::Triangle => self.seed_shape::<Triangle>(center, rng),
+            ShapeType::QuadraticBezier => self.seed_shape::<QuadraticBezier>(center, rng),
+            ShapeType::CubicBezier => self.seed_shape::<CubicBezier>(center, rng),
+            ShapeType::Rectangle => self.seed_shape::<Rectangle>(center, rng),
+            ShapeType::Ellipse => self.seed_shape::<Ellipse>(center, rng),
+            ShapeType::Heart => self.seed_shape::<Heart>(center, rng),
+            ShapeType::Teardrop => self.seed_shape::<Teardrop>(center, rng),
+            ShapeType::BezierPath => self.seed_shape::<BezierPath>(center, rng),
+            ShapeType::Cross => self.seed_shape::<Cross>(center, rng),
+            ShapeType::Mixed(shape_types) => {
+                if shape_types.is_empty() {
+                    panic!("A mix needs at least one shape type");
+                }
+
+                let shape_type = &shape_types[rng.gen_range(0..shape_types.len())];
+                self.seed_shape_of_type(shape_type, center, rng)
+            }
+        }
+    }
+
+    ///
+    /// Generate a random shape around `center` (in pixels of the scaled image), set up the same
+    /// way hill climbing sets up its shapes, but not yet colored
+    ///
+    fn seed_shape<T>(&self, center: PrimitivePoint, rng: &mut impl Rng) -> Box<dyn Shape>
+    where
+        T: RandomShape + Shape + Clone + 'static,
+    {
+        let mut shape = T::random_at(
+            center,
+            self.width(),
+            self.height(),
+            BORDER_EXTENSION,
+            self.size_limits,
+            rng,
+        );
+        shape.set_stroke_width(self.stroke_width);
+        shape.set_feather(self.feather);
+        shape.set_segment_limits(self.segment_limits);
+        shape.set_square(self.squares);
+        if !self.rotation {
+            shape.clear_rotation();
+        }
+
+        Box::new(shape)
+    }
+
+    ///
+    /// Hill climb from a single random shape until `max_age` mutations in a row fail to improve it
+    ///
+    /// Returns the best shape found, its score, and its score including penalties
+    /// Returns None if no acceptable initial shape could be generated
+    ///
+    fn hill_climb<T>(
+        &self,
+        max_age: u32,
+        deadline: Option<Instant>,
+        rng: &mut impl Rng,
+    ) -> Option<Climb<T>>
+    where
+        T: RandomShape + Shape + Clone + 'static,
+    {
+        let min_area = self.min_shape_area();
+
+        // Initialize a random shape (large enough, if that's required) and give it a color
+        let mut attempts = 0;
+        let mut shape = loop {
+            let mut shape = Box::new(T::random(
+                self.width(),
+                self.height(),
+                BORDER_EXTENSION,
+                self.size_limits,
+                rng,
+            ));
+            shape.set_stroke_width(self.stroke_width);
+            shape.set_feather(self.feather);
+            shape.set_segment_limits(self.segment_limits);
+            shape.set_square(self.squares);
+            if !self.rotation {
+                shape.clear_rotation();
+            }
+            if self.edge_snap > 0.0 {
+                shape.snap_vertices(&mut |point| self.edge_snapped(point, rng));
+            }
+
+            if clipped_bounding_box_area(&*shape, self.width(), self.height()) as f64 >= min_area
+                && !self.covers_converged(&*shape)
+                && !self.exceeds_max_layers(&*shape)
+                && !self.outside_alpha_mask(&*shape)
+            {
+                break shape;
+            }
+
+            attempts += 1;
+            if attempts >= MAXIMUM_RANDOM_SHAPE_ATTEMPTS {
+                trace!(
+                    "Couldn't generate an unprotected shape with an area of at least {}",
+                    min_area
+                );
+                return None;
+            }
+        };
+
+        shape.set_color_using(self);
+
+        // The initial triangle is the best so far
+        let mut score_cache = ScoreCache::new(SCORE_CACHE_SIZE);
+        let mut best_shape = shape.clone();
+        let (mut best_raw_score, mut best_score) =
+            self.score_candidate(&*best_shape, &mut score_cache);
+
+        // Where mutations start from. It only drifts away from the best shape across plateaus
+        let mut current_shape = best_shape.clone();
+        let mut plateau_moves = self.plateau_moves;
+        let mut shapes_visited = 1;
+
+        let mut age = 0;
+        // Loop until max_age mutations fail to yield and improvement
+        while age < max_age {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                trace!(
+                    "Stopping hill climb at the time budget, after {} shapes",
+                    shapes_visited
+                );
+                break;
+            }
+
+            // Mutate the shape and update its color
+            shape.mutate(
+                self.width(),
+                self.height(),
+                self.size_limits,
+                self.mutate_mode,
+                rng,
+            );
+            if !self.rotation {
+                shape.clear_rotation();
+            }
+            if self.edge_snap > 0.0 {
+                shape.snap_vertices(&mut |point| self.edge_snapped(point, rng));
+            }
+
+            // Too small shapes, and shapes over protected regions, too many layers, or outside of
+            // the alpha mask, count as failed mutations
+            if (clipped_bounding_box_area(&*shape, self.width(), self.height()) as f64) < min_area
+                || self.covers_converged(&*shape)
+                || self.exceeds_max_layers(&*shape)
+                || self.outside_alpha_mask(&*shape)
+            {
+                shape = current_shape.clone();
+                age += 1;
+                continue;
+            }
+
+            shape.set_color_using(self);
+
+            // Determine its score
+            let (new_raw_score, new_score) = self.score_candidate(&*shape, &mut score_cache);
+
+            // Trying to minimize score (smaller score = closer approximation to the target)
+            if new_score < best_score {
+                best_score = new_score;
+                best_raw_score = new_raw_score;
+                best_shape = shape.clone();
+                current_shape = shape.clone();
+                shapes_visited += 1;
+
+                // Reset age if an improvement was made
+                age = 0;
+            } else if new_score == best_score && plateau_moves > 0 {
+                // Drift across the plateau, but keep the best shape and keep aging
+                current_shape = shape.clone();
+                plateau_moves -= 1;
+                shapes_visited += 1;
+                age += 1;
+            } else {
+                // Reset the shape and increment age
+                shape = current_shape.clone();
+                age += 1;
+            }
+
+            trace!("Age: {}, best score: {}", age, best_score);
+        }
+
+        Some(Climb {
+            shape: best_shape,
+            raw_score: best_raw_score,
+            score: best_score,
+            shapes_visited,
+        })
+    }
+
+    ///
+    /// Returns the score of the approximation with `shape` painted on, and that score including
+    /// penalties
+    ///
+    /// Shapes already in `cache` aren't painted again
+    ///
+    fn score_candidate(&self, shape: &dyn Shape, cache: &mut ScoreCache) -> (f64, f64) {
+        let key = shape.canonical_key();
+        if let Some(scores) = cache.get(key) {
+            return scores;
+        }
+
+        let mut new_image = self.clone();
+        new_image.approximation = self.paint_candidate(shape);
+        let raw_score = new_image.climb_score();
+        let scores = (raw_score, raw_score + self.overlap_penalty_for(shape));
+
+        cache.insert(key, scores);
+        scores
+    }
+
+    fn width(&self) -> u32 {
+        self.target.dimensions().0
+    }
+
+    fn height(&self) -> u32 {
+        self.target.dimensions().1
+    }
+}
+
+///
+/// Returns `scale_to`, or the largest dimension of a `width` x `height` image if that's smaller
+///
+/// 0 (no scaling) is unchanged
+///
+fn limit_upscaling(width: u32, height: u32, scale_to: u32) -> u32 {
+    if scale_to == 0 {
+        return 0;
+    }
+
+    scale_to.min(max(width, height))
+}
+
+/// Returns the score improvement per pixel of `area`, or 0 for shapes without any area
+fn efficiency(score_delta: f64, area: f64) -> f64 {
+    if area > 0.0 {
+        score_delta / area
+    } else {
+        0.0
+    }
+}
+
+/// Returns the color in `palette` with the least squared RGB distance to `color`
+fn closest_color(palette: &[Rgba<u8>], color: &Rgba<u8>) -> Rgba<u8> {
+    let distance = |candidate: &&Rgba<u8>| -> i32 {
+        (0..3)
+            .map(|c| (candidate[c] as i32 - color[c] as i32).pow(2))
+            .sum()
+    };
+
+    *palette.iter().min_by_key(distance).unwrap()
+}
+
+///
+/// Returns `scale_to`, or `max_dimension` if a `width` x `height` image would otherwise be
+/// searched at a larger size
+///
+/// `scale_to` 0 searches at the image's own size, and `max_dimension` 0 means no limit
+///
+fn limit_dimension(width: u32, height: u32, scale_to: u32, max_dimension: u32) -> u32 {
+    let searched = if scale_to > 0 {
+        scale_to
+    } else {
+        max(width, height)
+    };
+
+    if max_dimension > 0 && searched > max_dimension {
+        max_dimension
+    } else {
+        scale_to
+    }
+}
+
+///
+/// Returns the `scale_to` that makes a `width` x `height` image `preview_scale` times as big
+///
+fn scale_to_for_preview(width: u32, height: u32, preview_scale: f64) -> u32 {
+    max(
+        (max(width, height) as f64 * preview_scale).round() as u32,
+        1,
+    )
+}
+
+///
+/// Encode `img` as a PNG, with a pHYs chunk recording `dpi` and chunks marking `color_profile`
+/// (converting the pixels to it) when they're given
+///
+fn write_png(
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    dpi: Option<f64>,
+    color_profile: Option<ColorProfile>,
+    writer: impl Write,
+) {
+    let (width, height) = img.dimensions();
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    if let Some(color_profile) = color_profile {
+        color_profile.apply(&mut encoder);
+    }
+
+    let mut writer = encoder.write_header().unwrap();
+
+    if let Some(dpi) = dpi {
+        // pHYs is pixels per meter along each axis, followed by the unit (1 = meter)
+        let pixels_per_meter = (dpi / MM_PER_INCH * 1000.0).round() as u32;
+        let mut phys = Vec::with_capacity(9);
+        phys.extend_from_slice(&pixels_per_meter.to_be_bytes());
+        phys.extend_from_slice(&pixels_per_meter.to_be_bytes());
+        phys.push(png::Unit::Meter as u8);
+        writer.write_chunk(png::chunk::pHYs, &phys).unwrap();
+    }
+
+    match color_profile {
+        Some(color_profile) => writer
+            .write_image_data(color_profile.convert(img).as_raw())
+            .unwrap(),
+        None => writer.write_image_data(img.as_raw()).unwrap(),
+    }
+}
+
+/// Root Mean Squared Error between two pixels' RGB channels
+fn pixel_error(a: &Rgba<u8>, b: &Rgba<u8>) -> f64 {
+    let sum_squared_error: f64 = (0..3)
+        .map(|c| (a[c] as f64 - b[c] as f64) * (a[c] as f64 - b[c] as f64))
+        .sum();
+
+    (sum_squared_error / 3.0).sqrt()
+}
+
+fn read_shape_file(path: &PathBuf) -> ShapeFile {
+    let file =
+        File::open(path).unwrap_or_else(|_| panic!("Could not open shape file at {:?}", path));
+    serde_json::from_reader(BufReader::new(file))
+        .unwrap_or_else(|err| panic!("Could not parse shape file at {:?}: {}", path, err))
+}
+
+///
+/// Returns the area of the shape's bounding box, after clipping it to the image
+///
+fn clipped_bounding_box_area(shape: &dyn Shape, width: u32, height: u32) -> u32 {
+    let pixels = shape
+        .get_pixels()
+        .into_iter()
+        .filter(|p| p.x >= 0 && p.x < width as i32 && p.y >= 0 && p.y < height as i32)
+        .collect::<Vec<_>>();
+
+    if pixels.is_empty() {
+        return 0;
+    }
+
+    let min_x = pixels.iter().map(|p| p.x).min().unwrap();
+    let max_x = pixels.iter().map(|p| p.x).max().unwrap();
+    let min_y = pixels.iter().map(|p| p.y).min().unwrap();
+    let max_y = pixels.iter().map(|p| p.y).max().unwrap();
+
+    ((max_x - min_x + 1) * (max_y - min_y + 1)) as u32
+}
+
+fn average_color_in_shape(image: &ImageBuffer<Rgba<u8>, Vec<u8>>, shape: &impl Shape) -> Rgba<u8> {
+    let (width, height) = image.dimensions();
+
+    let pixels = shape.get_pixels();
+
+    let mut channel_sums: [i64; 4] = [0, 0, 0, 0];
+
+    let mut num_pixels: i64 = 0;
+
+    for pixel in pixels {
+        if pixel.x < 0 || pixel.x >= width as i32 || pixel.y < 0 || pixel.y >= height as i32 {
+            continue;
+        }
+
+        num_pixels += 1;
+        let image_pixel = image.get_pixel(pixel.x as u32, pixel.y as u32);
+
+        channel_sums[0] += image_pixel[0] as i64;
+        channel_sums[1] += image_pixel[1] as i64;
+        channel_sums[2] += image_pixel[2] as i64;
+    }
+
+    let mut average_pixels: [u8; 4] = [0, 0, 0, 0];
+
+    if num_pixels > 0 {
+        average_pixels[0] = (channel_sums[0] / num_pixels) as u8;
+        average_pixels[1] = (channel_sums[1] / num_pixels) as u8;
+        average_pixels[2] = (channel_sums[2] / num_pixels) as u8;
+        average_pixels[3] = SHAPE_ALPHA;
+    }
+
+    Rgba(average_pixels)
+}
+
+///
+/// Composite `image` over a solid `background` using the "over" operator
+///
+/// Opaque pixels are unchanged, fully transparent ones become the background
+///
+fn composite_over(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    background: Rgba<u8>,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let background_alpha = background[3] as f64 / 255.0;
+
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y);
+        let alpha = pixel[3] as f64 / 255.0;
+
+        let out_alpha = alpha + background_alpha * (1.0 - alpha);
+        if out_alpha <= 0.0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+
+        let mut data: [u8; 4] = [0, 0, 0, (out_alpha * 255.0).round() as u8];
+        for c in 0..3 {
+            data[c] = ((pixel[c] as f64 * alpha
+                + background[c] as f64 * background_alpha * (1.0 - alpha))
+                / out_alpha)
+                .round() as u8;
+        }
+
+        Rgba(data)
+    })
+}
+
+///
+/// Returns where to save the thumbnail for output saved to `path`: next to it, with a `.thumb`
+/// infix
+///
+/// Outputs that aren't raster images (SVG and JSON) get PNG thumbnails
+///
+pub fn thumbnail_path(path: &Path) -> PathBuf {
+    let extension = match path.extension().and_then(|e| e.to_str()) {
+        Some("svg") | Some("json") | None => "png",
+        Some(extension) => extension,
+    };
+
+    path.with_extension(format!("thumb.{}", extension))
+}
+
+///
+/// Decode and tone map a Radiance HDR image
+///
+/// The image library's usual decoding clips Radiance images to 8 bits, so read the floats directly
+///
+fn decode_hdr(reader: impl BufRead) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PrimitiveError> {
+    let decoder = HdrDecoder::new(reader)?;
+    let metadata = decoder.metadata();
+    let pixels = decoder.read_image_hdr()?;
+
+    let hdr = ImageBuffer::from_fn(metadata.width, metadata.height, |x, y| {
+        let pixel = pixels[(y * metadata.width + x) as usize];
+        Rgba([pixel[0], pixel[1], pixel[2], 1.0])
+    });
+
+    Ok(tone_map(&hdr))
+}
+
+///
+/// Convert a decoded image to 8-bit RGBA, tone mapping floating point (high dynamic range) images
+/// and dithering 16-bit ones
+///
+/// Grayscale is copied to red, green, and blue, keeping its alpha if it has any
+///
+fn to_rgba8(image: DynamicImage) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    match image {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            tone_map(&image.to_rgba32f())
+        }
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => dither_to_rgba8(&image.to_rgba16()),
+        _ => image.to_rgba8(),
+    }
+}
+
+///
+/// Cluster `samples` into up to `k` groups, returning their centers from largest group to smallest
+///
+/// Deterministic: the first center is the sample farthest from the mean, and each following one is
+/// the sample farthest from every center so far
+///
+fn k_means(samples: &[[f64; 3]], k: usize) -> Vec<[f64; 3]> {
+    let distance =
+        |a: &[f64; 3], b: &[f64; 3]| -> f64 { (0..3).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum() };
+    let nearest = |centers: &[[f64; 3]], sample: &[f64; 3]| -> (usize, f64) {
+        centers
+            .iter()
+            .enumerate()
+            .map(|(i, center)| (i, distance(center, sample)))
+            .fold(
+                (0, f64::MAX),
+                |best, next| if next.1 < best.1 { next } else { best },
+            )
+    };
+
+    if samples.is_empty() || k == 0 {
+        return vec![];
+    }
+
+    let mut mean = [0.0; 3];
+    for sample in samples.iter() {
+        for c in 0..3 {
+            mean[c] += sample[c] / samples.len() as f64;
+        }
+    }
+
+    let mut centers = vec![];
+    let mut reference = vec![mean];
+    while centers.len() < k {
+        let (farthest, farthest_distance) = samples
+            .iter()
+            .map(|sample| nearest(&reference, sample).1)
+            .enumerate()
+            .fold(
+                (0, -1.0),
+                |best, next| if next.1 > best.1 { next } else { best },
+            );
+
+        // Every remaining sample is already a center
+        if !centers.is_empty() && farthest_distance == 0.0 {
+            break;
+        }
+
+        centers.push(samples[farthest]);
+        reference = centers.clone();
+    }
+
+    let mut counts = vec![0; centers.len()];
+    for _ in 0..K_MEANS_ITERATIONS {
+        let mut sums = vec![[0.0; 3]; centers.len()];
+        counts = vec![0; centers.len()];
+
+        for sample in samples.iter() {
+            let (i, _) = nearest(&centers, sample);
+            counts[i] += 1;
+            for c in 0..3 {
+                sums[i][c] += sample[c];
+            }
+        }
+
+        let mut moved = false;
+        for (i, center) in centers.iter_mut().enumerate() {
+            if counts[i] == 0 {
+                continue;
+            }
+
+            let new_center = sums[i].map(|sum| sum / counts[i] as f64);
+            moved |= new_center != *center;
+            *center = new_center;
+        }
+
+        if !moved {
+            break;
+        }
+    }
+
+    let mut clusters: Vec<([f64; 3], usize)> = centers.into_iter().zip(counts).collect();
+    clusters.retain(|(_, count)| *count > 0);
+    clusters.sort_by_key(|cluster| Reverse(cluster.1));
+
+    clusters.into_iter().map(|(center, _)| center).collect()
+}
+
+///
+/// Returns the average color of `image`, weighting each pixel by its alpha
+///
+/// Falls back to an unweighted average if the image is entirely transparent
+///
+fn average_color(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Rgba<u8> {
+    let mut channel_sums: [i64; 4] = [0, 0, 0, 0];
+
+    let mut num_pixels: i64 = 0;
+
+    let fully_transparent = image.pixels().all(|pixel| pixel[3] == 0);
+
+    for pixel in image.pixels() {
+        let weight = if fully_transparent {
+            1
+        } else {
+            pixel[3] as i64
+        };
+
+        num_pixels += weight;
+
+        channel_sums[0] += pixel[0] as i64 * weight;
+        channel_sums[1] += pixel[1] as i64 * weight;
+        channel_sums[2] += pixel[2] as i64 * weight;
+    }
+
+    let mut average_pixels: [u8; 4] = [0, 0, 0, 0];
 
     if num_pixels > 0 {
         average_pixels[0] = (channel_sums[0] / num_pixels) as u8;
         average_pixels[1] = (channel_sums[1] / num_pixels) as u8;
         average_pixels[2] = (channel_sums[2] / num_pixels) as u8;
-        average_pixels[3] = 128;
+        average_pixels[3] = SHAPE_ALPHA;
+    }
+
+    Rgba(average_pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signature::Corner;
+    use crate::utilities::{get_rng, make_test_image, sqrt, TestPattern};
+
+    #[test]
+    fn test_average_color() {
+        // A the average of a solid colored image should be that color
+        let test_im_1 = ImageBuffer::from_pixel(10, 10, Rgba([255, 128, 0, 128]));
+        assert_eq!(average_color(&test_im_1), Rgba([255, 128, 0, 128]));
+
+        // Average color should always return a color with alpha=128
+        let test_im_2 = ImageBuffer::from_pixel(10, 10, Rgba([255, 128, 0, 255]));
+        assert_eq!(average_color(&test_im_2), Rgba([255, 128, 0, 128]));
+
+        // Test with two pixels, each a different color
+        let mut test_im_3 = ImageBuffer::from_pixel(1, 2, Rgba([0, 0, 0, 128]));
+        test_im_3.get_pixel_mut(0, 1).0 = [10, 10, 10, 128];
+        assert_eq!(average_color(&test_im_3), Rgba([5, 5, 5, 128]));
+
+        // Transparent pixels don't count towards the average
+        let mut test_im_4 = ImageBuffer::from_pixel(1, 2, Rgba([0, 0, 0, 0]));
+        test_im_4.get_pixel_mut(0, 1).0 = [10, 10, 10, 255];
+        assert_eq!(average_color(&test_im_4), Rgba([10, 10, 10, 128]));
+    }
+
+    #[test]
+    fn test_composite_over() {
+        let mut source = ImageBuffer::from_pixel(3, 1, Rgba([255, 0, 0, 255]));
+        source.get_pixel_mut(1, 0).0 = [255, 0, 0, 0];
+        source.get_pixel_mut(2, 0).0 = [255, 0, 0, 128];
+
+        let composited = composite_over(&source, Rgba([0, 0, 255, 255]));
+        assert_eq!(composited.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(composited.get_pixel(1, 0), &Rgba([0, 0, 255, 255]));
+        assert_eq!(composited.get_pixel(2, 0), &Rgba([128, 0, 127, 255]));
+    }
+
+    #[test]
+    fn test_transparent_source() {
+        // The left half of the source is transparent
+        let source: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_fn(20, 10, |x, _| {
+            if x < 10 {
+                Rgba([255, 255, 255, 0])
+            } else {
+                Rgba([255, 0, 0, 255])
+            }
+        });
+
+        let path = std::env::temp_dir().join("primitive_image_test_transparent_source.png");
+        source.save(&path).unwrap();
+        let background = Rgba([0, 128, 0, 128]);
+        let primitive =
+            PrimitiveImage::from_path(path.clone(), 0, Some(background), LoadOptions::default())
+                .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(primitive.target.get_pixel(0, 0), &background);
+        assert_eq!(primitive.target.get_pixel(15, 5), &Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_score() {
+        let approximation = ImageBuffer::from_pixel(2, 2, Rgba([0, 0, 0, 128]));
+        let target = ImageBuffer::from_pixel(2, 2, Rgba([10, 10, 10, 128]));
+        let mut primitive = PrimitiveImage::from_image(target.clone(), 0, None);
+        primitive.target = target;
+        primitive.approximation = approximation;
+
+        // By default only the RGB channels are scored, and all of them are off by 10
+        assert_eq!(primitive.score(), 10.0);
+
+        primitive.set_score_alpha(true);
+
+        // sqrt((Error(10.0)*Error(10.0)*NumChannelsWithError(3.0)*NumPixels(4.0))/(NumChannels(4.0)*NumPixels(4.0))
+        let expected_score = sqrt((10.0 * 10.0 * 3.0 * 4.0) / (4.0 * 4.0));
+
+        assert_eq!(primitive.score(), expected_score);
+    }
+
+    #[test]
+    fn test_min_shape_area() {
+        let target =
+            ImageBuffer::from_fn(50, 50, |x, y| Rgba([(x * 5) as u8, (y * 5) as u8, 0, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        primitive.set_min_shape_area(0.2, 4);
+
+        // Decays linearly over the coarse phase
+        assert_eq!(primitive.min_shape_area(), 0.2 * 50.0 * 50.0);
+
+        let mut rng = get_rng(42);
+        while primitive.shapes.len() < 4 {
+            let min_area = primitive.min_shape_area();
+            if primitive.add_new_shape::<Triangle>(20, &mut rng) {
+                let shape = primitive.shapes.last().unwrap();
+                assert!(clipped_bounding_box_area(&**shape, 50, 50) as f64 >= min_area);
+            }
+        }
+
+        // And is disabled afterwards
+        assert_eq!(primitive.min_shape_area(), 0.0);
+    }
+
+    #[test]
+    fn test_overlap_penalty() {
+        // Returns the average opacity stacked on the pixels covered by at least one shape
+        let mean_coverage = |overlap_penalty: f64| {
+            let target = make_test_image(40, 40, TestPattern::Gradient);
+            let mut primitive = PrimitiveImage::from_image(target, 0, None);
+            primitive.set_overlap_penalty(overlap_penalty);
+
+            let mut rng = get_rng(42);
+            while primitive.shapes.len() < 10 {
+                primitive.add_new_shape::<Triangle>(50, &mut rng);
+            }
+
+            let covered = primitive.coverage.iter().filter(|c| **c > 0.0).count();
+            primitive.coverage.iter().sum::<f64>() / covered as f64
+        };
+
+        // Shapes that are spread out stack less opacity on the pixels they cover
+        assert!(mean_coverage(100.0) < mean_coverage(0.0));
+    }
+
+    #[test]
+    fn test_pool() {
+        let target = make_test_image(40, 40, TestPattern::Gradient);
+        let primitive = PrimitiveImage::from_image(target, 0, None);
+
+        // Returns the score after adding a shape with a pool of `pool`
+        let score_with_pool = |pool: u32| {
+            let mut primitive = primitive.clone();
+            primitive.set_pool(pool);
+            primitive.add_new_shape::<Triangle>(20, &mut get_rng(42));
+            primitive.score()
+        };
+
+        // The first shape in the pool is the one a pool of 1 would find
+        let single_score = score_with_pool(1);
+        assert!(score_with_pool(2) <= single_score);
+        assert!(score_with_pool(5) <= single_score);
+    }
+
+    #[test]
+    fn test_score_region() {
+        let target = make_test_image(40, 20, TestPattern::Solid(Rgba([255, 0, 0, 255])));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 255, 255])));
+        primitive
+            .set_score_region(Some(ScoreRegion {
+                x: 0,
+                y: 0,
+                width: 20,
+                height: 20,
+            }))
+            .unwrap();
+        let score = primitive.score();
+
+        // Only the left half counts, so fixing the right half doesn't lower the score
+        let right_half = Rectangle::new(PrimitivePoint::new(30, 10), 20, 20, 0);
+        assert!(!primitive.add_specific_shape(Box::new(right_half)));
+        assert_eq!(primitive.score(), score);
+
+        let left_half = Rectangle::new(PrimitivePoint::new(10, 10), 20, 20, 0);
+        assert!(primitive.add_specific_shape(Box::new(left_half)));
+        assert!(primitive.score() < score);
+    }
+
+    #[test]
+    fn test_score_region_off_image() {
+        let target = make_test_image(40, 20, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        let err = primitive
+            .set_score_region(Some(ScoreRegion {
+                x: 10,
+                y: 20,
+                width: 5,
+                height: 5,
+            }))
+            .unwrap_err();
+
+        assert!(matches!(err, PrimitiveError::ScoreRegion { .. }));
+        assert!(err.to_string().contains("is outside of the 40x20 image"));
+        assert_eq!(primitive.score_region, None);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let target = make_test_image(40, 30, TestPattern::Solid(Rgba([255, 0, 0, 255])));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 255, 255])));
+        assert_eq!(primitive.coverage(), 0.0);
+
+        let left_half = Rectangle::new(PrimitivePoint::new(10, 15), 20, 30, 0);
+        assert!(primitive.add_specific_shape(Box::new(left_half)));
+        assert_eq!(primitive.coverage(), 0.5);
+
+        assert!(primitive.seed_background_rect());
+        assert!(primitive.coverage() > 0.99);
+    }
+
+    #[test]
+    fn test_add_specific_shape() {
+        let target = ImageBuffer::from_pixel(40, 40, Rgba([255, 0, 0, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 255, 255])));
+        let initial_score = primitive.score();
+
+        let rect = Rectangle::new(PrimitivePoint::new(20, 20), 30, 30, 0);
+        assert!(primitive.add_specific_shape(Box::new(rect)));
+        assert!(primitive.score() < initial_score);
+        assert_eq!(primitive.shapes.len(), 1);
+
+        // A shape entirely off of the image can't improve anything
+        let rect = Rectangle::new(PrimitivePoint::new(-100, -100), 10, 10, 0);
+        assert!(!primitive.add_specific_shape(Box::new(rect)));
+        assert_eq!(primitive.shapes.len(), 1);
+    }
+
+    #[test]
+    fn test_seed_background_rect() {
+        let target = make_test_image(41, 30, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 0, 255])));
+        let initial_score = primitive.score();
+
+        assert!(primitive.seed_background_rect());
+        assert!(primitive.score() < initial_score);
+        assert_eq!(primitive.shapes.len(), 1);
+
+        let pixels = primitive.shapes[0].get_pixels();
+        for x in 0..41 {
+            for y in 0..30 {
+                assert!(pixels.contains(&PrimitivePoint::new(x, y)));
+            }
+        }
+
+        // Starting from the average color, the rectangle can't improve anything
+        let target = make_test_image(40, 40, TestPattern::Solid(Rgba([10, 200, 30, 255])));
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        assert!(!primitive.seed_background_rect());
+        assert!(primitive.shapes.is_empty());
+    }
+
+    #[test]
+    fn test_seed_grid() {
+        let colors = [
+            Rgba([255, 0, 0, 255]),
+            Rgba([0, 255, 0, 255]),
+            Rgba([0, 0, 255, 255]),
+            Rgba([255, 255, 0, 255]),
+        ];
+        let target = ImageBuffer::from_fn(40, 30, |x, y| colors[(y / 15 * 2 + x / 20) as usize]);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        let initial_score = primitive.score();
+
+        assert_eq!(primitive.seed_grid(2, 2), 4);
+        assert_eq!(primitive.shapes.len(), 4);
+        assert!(primitive.score() < initial_score);
+
+        // Each cell covers its quadrant exactly, in the quadrant's color
+        for (i, shape) in primitive.shapes.iter().enumerate() {
+            let (left, top) = (i as i32 % 2 * 20, i as i32 / 2 * 15);
+            let mut expected = vec![];
+            for x in left..left + 20 {
+                for y in top..top + 15 {
+                    expected.push(PrimitivePoint::new(x, y));
+                }
+            }
+            let mut pixels = shape.get_pixels();
+            pixels.sort_by_key(|p| (p.x, p.y));
+
+            assert_eq!(pixels, expected);
+            assert_eq!(
+                shape.get_color(),
+                Rgba([colors[i][0], colors[i][1], colors[i][2], 128])
+            );
+        }
+    }
+
+    #[test]
+    fn test_seed_grid_clamped() {
+        let target = make_test_image(4, 4, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target.clone(), 0, None);
+
+        // Clamped to 4x2, so every cell has pixels
+        assert!(primitive.seed_grid(5, 2) <= 8);
+        for shape in &primitive.shapes {
+            assert!(!shape.get_pixels().is_empty());
+        }
+
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        assert!(primitive.seed_grid(0, 0) <= 1);
+    }
+
+    #[test]
+    fn test_shape_alpha() {
+        let target = make_test_image(40, 40, TestPattern::Solid(Rgba([255, 0, 0, 255])));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 255, 255])));
+        primitive.set_shape_alpha(ShapeType::QuadraticBezier, 220);
+        primitive.set_shape_alpha(ShapeType::Rectangle, 100);
+
+        let curve = QuadraticBezier {
+            color: Rgba([0, 0, 0, 128]),
+            start: PrimitivePoint::new(5, 20),
+            control: PrimitivePoint::new(20, 5),
+            end: PrimitivePoint::new(35, 20),
+            width: 3,
+            segment_limits: SegmentLimits::default(),
+        };
+        assert!(primitive.add_specific_shape(Box::new(curve)));
+        assert_eq!(primitive.shapes[0].get_color(), Rgba([255, 0, 0, 220]));
+
+        let rect = Rectangle::new(PrimitivePoint::new(20, 20), 30, 30, 0);
+        assert!(primitive.add_specific_shape(Box::new(rect)));
+        assert_eq!(primitive.shapes[1].get_color(), Rgba([255, 0, 0, 100]));
+
+        // Types without an override keep the default
+        let teardrop = Teardrop::new(PrimitivePoint::new(20, 20), 5, 2.0, 0);
+        assert_eq!(primitive.color_for_shape(&teardrop)[3], SHAPE_ALPHA);
+    }
+
+    #[test]
+    fn test_alpha_by_size() {
+        let target = make_test_image(40, 40, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        primitive.set_shape_alpha(ShapeType::Rectangle, 100);
+        primitive.set_alpha_by_size(Some("220,40".parse().unwrap()));
+
+        let small = Rectangle::new(PrimitivePoint::new(20, 20), 4, 4, 0);
+        let large = Rectangle::new(PrimitivePoint::new(20, 20), 30, 30, 0);
+        let small_alpha = primitive.color_for_shape(&small)[3];
+        let large_alpha = primitive.color_for_shape(&large)[3];
+
+        assert!(large_alpha < small_alpha);
+        assert!(small_alpha <= 220 && large_alpha >= 40);
+    }
+
+    #[test]
+    fn test_min_contrast() {
+        // The target is flat gray, but the approximation so far is a black and white checkerboard
+        let target = make_test_image(20, 20, TestPattern::Solid(Rgba([128, 128, 128, 255])));
+        let checkerboard = make_test_image(
+            20,
+            20,
+            TestPattern::Checkerboard(2, Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255])),
+        );
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        primitive.approximation = checkerboard.clone();
+        let gray_cover = Rectangle::new(PrimitivePoint::new(10, 10), 20, 20, 0);
+
+        // Covering it in gray gets closer to the target, but halves the contrast
+        primitive.set_min_contrast(Some(100.0));
+        assert!(!primitive.add_specific_shape(Box::new(gray_cover)));
+        assert_eq!(primitive.approximation, checkerboard);
+
+        primitive.set_min_contrast(Some(10.0));
+        assert!(primitive.add_specific_shape(Box::new(gray_cover)));
+    }
+
+    #[test]
+    fn test_score_cache() {
+        let target = make_test_image(20, 20, TestPattern::Gradient);
+        let primitive = PrimitiveImage::from_image(target, 0, None);
+        let mut cache = ScoreCache::new(SCORE_CACHE_SIZE);
+
+        let mut rect = Rectangle::new(PrimitivePoint::new(10, 10), 8, 6, 0);
+        rect.set_color_using(&primitive);
+        let scores = primitive.score_candidate(&rect, &mut cache);
+        assert_eq!(cache.hits(), 0);
+
+        // The same shape again isn't repainted
+        let same = rect;
+        assert_eq!(primitive.score_candidate(&same, &mut cache), scores);
+        assert_eq!(cache.hits(), 1);
+
+        let other = Rectangle::new(PrimitivePoint::new(5, 5), 8, 6, 0);
+        primitive.score_candidate(&other, &mut cache);
+        assert_eq!(cache.hits(), 1);
+    }
+
+    #[test]
+    fn test_merge_shapes() {
+        let target = ImageBuffer::from_fn(40, 40, |x, _| {
+            if x < 20 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        });
+        let background = Some(Rgba([0, 0, 0, 255]));
+
+        // Each run only approximates one half
+        let mut left = PrimitiveImage::from_image(target.clone(), 0, background);
+        assert!(left.add_specific_shape(Box::new(Rectangle::new(
+            PrimitivePoint::new(10, 20),
+            20,
+            40,
+            0
+        ))));
+        let mut right = PrimitiveImage::from_image(target, 0, background);
+        for center in [PrimitivePoint::new(30, 10), PrimitivePoint::new(30, 30)] {
+            assert!(right.add_specific_shape(Box::new(Rectangle::new(center, 20, 20, 0))));
+        }
+
+        // Starting from the better run, the other's shapes only improve the parts it left alone
+        let (mut merged, other) = if left.score() <= right.score() {
+            (left.clone(), &right)
+        } else {
+            (right.clone(), &left)
+        };
+        merged.merge_shapes(other);
+
+        assert_eq!(merged.shapes.len(), 3);
+        assert!(merged.score() <= left.score());
+        assert!(merged.score() <= right.score());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_merge_shapes_different_size() {
+        let mut image = PrimitiveImage::from_image(
+            ImageBuffer::from_pixel(40, 40, Rgba([0, 0, 0, 255])),
+            0,
+            None,
+        );
+        let other = PrimitiveImage::from_image(
+            ImageBuffer::from_pixel(30, 40, Rgba([0, 0, 0, 255])),
+            0,
+            None,
+        );
+        image.merge_shapes(&other);
+    }
+
+    #[test]
+    fn test_seed_points() {
+        let target = make_test_image(
+            100,
+            50,
+            TestPattern::Checkerboard(5, Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])),
+        );
+        let mut primitive = PrimitiveImage::from_image(target, 50, Some(Rgba([0, 0, 0, 255])));
+
+        // Points are in the original image, which is twice the size of the searched one
+        let points = [PrimitivePoint::new(20, 30), PrimitivePoint::new(70, 10)];
+        assert_eq!(
+            primitive.seed_points(&points, &ShapeType::Triangle, &mut get_rng(42)),
+            2
+        );
+        assert_eq!(primitive.shapes.len(), 2);
+
+        for (shape, point) in primitive.shapes.iter().zip(points.iter()) {
+            let pixels = shape.get_pixels();
+            let center_x = pixels.iter().map(|p| p.x as f64).sum::<f64>() / pixels.len() as f64;
+            let center_y = pixels.iter().map(|p| p.y as f64).sum::<f64>() / pixels.len() as f64;
+
+            assert!((center_x - point.x as f64 / 2.0).abs() <= BORDER_EXTENSION as f64);
+            assert!((center_y - point.y as f64 / 2.0).abs() <= BORDER_EXTENSION as f64);
+        }
+    }
+
+    #[test]
+    fn test_seed_points_shape_type() {
+        let target = make_test_image(
+            100,
+            50,
+            TestPattern::Checkerboard(5, Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])),
+        );
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 0, 255])));
+
+        let points = [PrimitivePoint::new(20, 30), PrimitivePoint::new(70, 10)];
+        assert_eq!(
+            primitive.seed_points(&points, &ShapeType::Ellipse, &mut get_rng(42)),
+            2
+        );
+
+        for (shape, point) in primitive.shapes.iter().zip(points.iter()) {
+            assert_eq!(shape.shape_type(), ShapeType::Ellipse);
+
+            // Ellipses are centered on the point itself
+            let pixels = shape.get_pixels();
+            let center_x = pixels.iter().map(|p| p.x as f64).sum::<f64>() / pixels.len() as f64;
+            let center_y = pixels.iter().map(|p| p.y as f64).sum::<f64>() / pixels.len() as f64;
+
+            assert!((center_x - point.x as f64).abs() <= 1.0);
+            assert!((center_y - point.y as f64).abs() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn test_svg_dpi() {
+        let target = ImageBuffer::from_pixel(100, 50, Rgba([255, 0, 0, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        assert!(primitive.as_svg().contains("width=\"100\" height=\"50\">"));
+
+        primitive.set_dpi(Some(254.0));
+        let svg = primitive.as_svg();
+        assert!(svg.contains("width=\"10mm\" height=\"5mm\""));
+        assert!(svg.contains("viewBox=\"0 0 100 50\""));
+    }
+
+    #[test]
+    fn test_write_svg_streams_shapes() {
+        let target = make_test_image(40, 20, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        let mut rng = get_rng(11);
+        for _ in 0..5 {
+            primitive.add_new_shape::<Triangle>(20, &mut rng);
+        }
+        let concatenated: String = primitive
+            .shapes
+            .iter()
+            .map(|shape| shape.as_svg(1.0 / primitive.scale))
+            .collect();
+
+        let mut streamed = vec![];
+        primitive.write_svg(&mut streamed).unwrap();
+        let streamed = String::from_utf8(streamed).unwrap();
+
+        assert!(streamed.contains(&concatenated));
+        assert_eq!(streamed, primitive.as_svg());
+    }
+
+    #[test]
+    fn test_png_dpi() {
+        let img = ImageBuffer::from_pixel(4, 2, Rgba([255, 0, 0, 255]));
+        let mut encoded = vec![];
+        write_png(&img, Some(254.0), None, &mut encoded);
+
+        let reader = png::Decoder::new(encoded.as_slice()).read_info().unwrap();
+        let pixel_dims = reader.info().pixel_dims.unwrap();
+        assert_eq!(pixel_dims.xppu, 10000);
+        assert_eq!(pixel_dims.yppu, 10000);
+        assert_eq!(pixel_dims.unit, png::Unit::Meter);
+        assert!(reader.info().srgb.is_none());
+    }
+
+    #[test]
+    fn test_png_color_profile() {
+        let img = ImageBuffer::from_pixel(4, 2, Rgba([255, 0, 0, 255]));
+        let mut encoded = vec![];
+        write_png(&img, None, Some(ColorProfile::Srgb), &mut encoded);
+
+        // The chunk type is written right after its length
+        assert!(encoded.windows(4).any(|chunk_type| chunk_type == b"sRGB"));
+        let reader = png::Decoder::new(encoded.as_slice()).read_info().unwrap();
+        assert_eq!(
+            reader.info().srgb,
+            Some(png::SrgbRenderingIntent::Perceptual)
+        );
+        assert!(reader.info().pixel_dims.is_none());
+
+        let mut encoded = vec![];
+        write_png(&img, None, Some(ColorProfile::Linear), &mut encoded);
+        let reader = png::Decoder::new(encoded.as_slice()).read_info().unwrap();
+        assert!(reader.info().srgb.is_none());
+        assert_eq!(reader.info().gama_chunk, Some(png::ScaledFloat::new(1.0)));
+    }
+
+    #[test]
+    fn test_horizontal_symmetry() {
+        let target = ImageBuffer::from_pixel(40, 20, Rgba([255, 0, 0, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 255, 255])));
+        primitive.set_symmetry(Symmetry::Horizontal);
+
+        let rect = Rectangle::new(PrimitivePoint::new(5, 10), 6, 6, 30);
+        assert!(primitive.add_specific_shape(Box::new(rect)));
+        assert_eq!(primitive.shapes.len(), 2);
+
+        // The mirror is reflected across x = 19.5 and keeps the original's color
+        let mut mirror = Rectangle::new(PrimitivePoint::new(34, 10), 6, 6, 150);
+        mirror.color = primitive.shapes[0].get_color();
+        assert_eq!(primitive.shapes[1].as_svg(1.0), mirror.as_svg(1.0));
+
+        // Random search adds pairs too
+        let mut config = RunConfig::new(10, 42, ShapeType::Triangle);
+        if primitive.step(&mut config).added {
+            assert_eq!(primitive.shapes.len(), 4);
+        }
+    }
+
+    #[test]
+    fn test_color_sample() {
+        // The left 70% is red, the right 30% blue
+        let target = ImageBuffer::from_fn(20, 10, |x, _| {
+            if x < 14 {
+                Rgba([200, 20, 20, 255])
+            } else {
+                Rgba([20, 20, 200, 255])
+            }
+        });
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        let rect = Rectangle::new(PrimitivePoint::new(10, 5), 20, 10, 0);
+
+        // The mean is a blend, the mode is the dominant color
+        assert_eq!(
+            primitive.color_for_shape(&rect),
+            Rgba([146, 20, 74, SHAPE_ALPHA])
+        );
+
+        primitive.set_color_sample(ColorSample::Mode);
+        assert_eq!(
+            primitive.color_for_shape(&rect),
+            Rgba([200, 20, 20, SHAPE_ALPHA])
+        );
+
+        primitive.set_color_sample(ColorSample::Median);
+        assert_eq!(
+            primitive.color_for_shape(&rect),
+            Rgba([200, 20, 20, SHAPE_ALPHA])
+        );
+    }
+
+    #[test]
+    fn test_fixed_color() {
+        let target = make_test_image(40, 40, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 0, 255])));
+        primitive.set_fixed_color(Some(Rgba([200, 100, 50, 255])));
+
+        let mut config = RunConfig::new(10, 42, "MIXED".parse().unwrap());
+        for _ in 0..5 {
+            primitive.step(&mut config);
+        }
+
+        assert!(!primitive.shapes.is_empty());
+        for shape in primitive.shapes.iter() {
+            assert_eq!(shape.get_color(), Rgba([200, 100, 50, SHAPE_ALPHA]));
+        }
+    }
+
+    #[test]
+    fn test_integral_average_color() {
+        let target = ImageBuffer::from_fn(40, 30, |x, y| {
+            Rgba([(x * 6) as u8, (y * 8) as u8, ((x * y) % 256) as u8, 255])
+        });
+        let primitive = PrimitiveImage::from_image(target.clone(), 0, None);
+
+        let rects = [
+            Rectangle::new(PrimitivePoint::new(20, 15), 10, 6, 0),
+            Rectangle::new(PrimitivePoint::new(3, 4), 11, 15, 0),
+            Rectangle::new(PrimitivePoint::new(38, 28), 8, 8, 0),
+            Rectangle::new(PrimitivePoint::new(0, 0), 200, 200, 0),
+            Rectangle::new(PrimitivePoint::new(-50, 10), 6, 6, 0),
+        ];
+        for rect in rects.iter() {
+            assert!(rect.axis_aligned_bounds().is_some());
+            assert_eq!(
+                primitive.target_average_color_in_shape(rect),
+                average_color_in_shape(&target, rect)
+            );
+        }
+
+        // So do quarter turns
+        let quarter_turn = Rectangle::new(PrimitivePoint::new(20, 15), 9, 6, 90);
+        assert!(quarter_turn.axis_aligned_bounds().is_some());
+        assert_eq!(
+            primitive.target_average_color_in_shape(&quarter_turn),
+            average_color_in_shape(&target, &quarter_turn)
+        );
+
+        // Other rotated rectangles use the pixels
+        let rotated = Rectangle::new(PrimitivePoint::new(20, 15), 10, 6, 30);
+        assert!(rotated.axis_aligned_bounds().is_none());
+    }
+
+    #[test]
+    fn test_plateau_moves() {
+        // The approximation already matches the target, so every shape scores the same
+        let target = ImageBuffer::from_pixel(40, 40, Rgba([100, 150, 200, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([100, 150, 200, 255])));
+
+        let mut rng = get_rng(42);
+        let climb = primitive
+            .hill_climb::<Rectangle>(20, None, &mut rng)
+            .unwrap();
+        assert_eq!(climb.shapes_visited, 1);
+
+        primitive.set_plateau_moves(10);
+        let mut rng = get_rng(42);
+        let plateau_climb = primitive
+            .hill_climb::<Rectangle>(20, None, &mut rng)
+            .unwrap();
+        assert_eq!(plateau_climb.shapes_visited, 11);
+
+        // The kept shape is still the first with the best score
+        assert_eq!(plateau_climb.score, climb.score);
+        assert_eq!(plateau_climb.shape.as_svg(1.0), climb.shape.as_svg(1.0));
+    }
+
+    #[test]
+    fn test_hatch_fill_style() {
+        let target = ImageBuffer::from_pixel(40, 40, Rgba([255, 0, 0, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 255, 255])));
+        primitive.set_fill_style(FillStyle::Hatch);
+
+        let rect = Rectangle::new(PrimitivePoint::new(20, 20), 10, 10, 0);
+        assert!(primitive.add_specific_shape(Box::new(rect)));
+
+        // Only every third row of the rectangle is painted, leaving gaps between the lines
+        let background = Rgba([0, 0, 255, 255]);
+        assert_ne!(*primitive.approximation.get_pixel(20, 18), background);
+        assert_eq!(*primitive.approximation.get_pixel(20, 19), background);
+        assert_eq!(*primitive.approximation.get_pixel(20, 20), background);
+        assert_ne!(*primitive.approximation.get_pixel(20, 21), background);
+
+        // Renders at twice the size have lines twice as thick
+        let render = primitive.render_at(80, 80);
+        assert_eq!(render.get_pixel(40, 36), render.get_pixel(40, 37));
+        assert_ne!(render.get_pixel(40, 37), render.get_pixel(40, 38));
+
+        assert!(primitive.as_svg().contains("fill=\"url(#hatch-"));
+    }
+
+    #[test]
+    fn test_from_16_bit_bytes() {
+        use image::codecs::png::PngEncoder;
+        use image::{ColorType, ImageEncoder};
+
+        // The encoder takes 16-bit samples in native byte order
+        let encode = |samples: Vec<u16>, width: u32, height: u32, color_type: ColorType| {
+            let data: Vec<u8> = samples.iter().flat_map(|s| s.to_ne_bytes()).collect();
+            let mut bytes = vec![];
+            PngEncoder::new(&mut bytes)
+                .write_image(&data, width, height, color_type)
+                .unwrap();
+            bytes
+        };
+
+        // A shallow 16-bit gradient, across only 4 of the 8-bit levels
+        let gradient = (0..8)
+            .flat_map(|_| (0..256).map(|x| 257 * 100 + 4 * x))
+            .collect();
+        let bytes = encode(gradient, 256, 8, ColorType::L16);
+        let primitive =
+            PrimitiveImage::from_bytes(&bytes, 0, None, LoadOptions::default()).unwrap();
+
+        // Every 4x4 tile averages close to the source, instead of rounding to flat bands
+        for tile in 0..64 {
+            let mut sum = 0.0;
+            for x in tile * 4..tile * 4 + 4 {
+                for y in 0..4 {
+                    let pixel = primitive.target.get_pixel(x, y);
+                    assert_eq!(pixel[0], pixel[1]);
+                    assert_eq!(pixel[0], pixel[2]);
+                    sum += pixel[0] as f64;
+                }
+            }
+
+            let expected = (257.0 * 100.0 + 4.0 * (tile as f64 * 4.0 + 1.5)) / 257.0;
+            assert!((sum / 16.0 - expected).abs() < 0.1, "Tile {}", tile);
+        }
+
+        // Grayscale with alpha keeps its alpha
+        let bytes = encode(vec![65535, 0, 65535, 0], 2, 1, ColorType::La16);
+        let primitive = PrimitiveImage::from_bytes(
+            &bytes,
+            0,
+            Some(Rgba([0, 0, 0, 255])),
+            LoadOptions::default(),
+        )
+        .unwrap();
+        assert!(primitive.source_alpha.iter().all(|&alpha| alpha == 0));
+    }
+
+    #[test]
+    fn test_from_hdr_bytes() {
+        use image::codecs::hdr::HdrEncoder;
+        use image::Rgb;
+
+        // Brightness from 0 to 8 times white, which would mostly clip without tone mapping
+        let pixels: Vec<Rgb<f32>> = (0..64)
+            .map(|i| {
+                let value = (i % 8) as f32;
+                Rgb([value, value * 0.5, 0.1])
+            })
+            .collect();
+        let mut bytes = vec![];
+        HdrEncoder::new(&mut bytes).encode(&pixels, 8, 8).unwrap();
+
+        let primitive =
+            PrimitiveImage::from_bytes(&bytes, 0, None, LoadOptions::default()).unwrap();
+        assert_eq!(primitive.target.dimensions(), (8, 8));
+
+        let row: Vec<u8> = (0..8)
+            .map(|x| primitive.target.get_pixel(x, 0)[0])
+            .collect();
+        assert_eq!(row[0], 0, "{:?}", row);
+        assert!(row.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", row);
+        assert!(row[7] < 255);
+        assert!(primitive.target.pixels().all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn test_max_layers() {
+        let target = ImageBuffer::from_fn(30, 30, |x, y| {
+            Rgba([(x * 8) as u8, (y * 8) as u8, ((x * y) % 256) as u8, 255])
+        });
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        primitive.set_max_layers(Some(2));
+
+        let mut config = RunConfig::new(10, 42, ShapeType::Rectangle);
+        for _ in 0..30 {
+            primitive.step(&mut config);
+        }
+        assert!(primitive.shapes.len() > 2);
+
+        // Count the layers from scratch
+        let mut layer_counts = vec![0; 30 * 30];
+        for shape in primitive.shapes.iter() {
+            for index in primitive.pixel_indices(&**shape) {
+                layer_counts[index] += 1;
+            }
+        }
+        assert_eq!(layer_counts, primitive.layer_counts);
+        assert!(layer_counts.iter().all(|&count| count <= 2));
+
+        // A shape over the whole image can't be added once anything is there
+        let everything = Rectangle::new(PrimitivePoint::new(15, 15), 30, 30, 0);
+        primitive.set_max_layers(Some(1));
+        assert!(!primitive.add_specific_shape(Box::new(everything)));
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let target = ImageBuffer::from_fn(40, 30, |x, y| {
+            Rgba([(x * 6) as u8, (y * 8) as u8, 128, 255])
+        });
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        let mut config = RunConfig::new(10, 42, ShapeType::Triangle);
+        primitive.step(&mut config);
+
+        // The largest dimension is the requested size, whatever the output size is
+        primitive.set_output_size(400);
+        assert_eq!(primitive.thumbnail(16).dimensions(), (16, 12));
+        assert_eq!(primitive.thumbnail(1).dimensions(), (1, 1));
+
+        let path = std::env::temp_dir().join("primitive_image_test_thumbnail.png");
+        primitive.save_thumbnail(path.clone(), 20);
+        let saved = image::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((saved.width(), saved.height()), (20, 15));
+    }
+
+    #[test]
+    fn test_signature() {
+        let target = make_test_image(60, 40, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([250, 250, 250, 255])));
+        let unsigned = primitive.render_at(60, 40);
+
+        primitive.set_signature(Some(Signature::new(
+            String::from("S.G."),
+            Corner::TopLeft,
+            1.0,
+        )));
+        let svg = primitive.as_svg();
+        assert!(svg.contains("<text x=\"6\" y=\""));
+        assert!(svg.contains("fill=\"#000000\" fill-opacity=\"1.00000\">S.G.</text></svg>"));
+
+        let path = std::env::temp_dir().join("primitive_image_test_signature.png");
+        primitive.save_to_img(path.clone());
+        let saved = image::open(&path).unwrap().to_rgba8();
+        std::fs::remove_file(&path).unwrap();
+
+        // Dark text in the top left corner, and nothing else changed
+        let changed: Vec<(u32, u32)> = saved
+            .enumerate_pixels()
+            .filter(|(x, y, pixel)| *pixel != unsigned.get_pixel(*x, *y))
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert!(!changed.is_empty());
+        assert!(changed.iter().all(|&(x, y)| x < 40 && y < 20));
+
+        // Frames are signed too, so the last one matches the output
+        assert_eq!(primitive.iter_frames().last().unwrap(), saved);
+    }
+
+    #[test]
+    fn test_feather() {
+        let target = make_test_image(40, 40, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        primitive.set_feather(3);
+        let mut rng = get_rng(42);
+        assert!(primitive.add_new_shape::<Ellipse>(10, &mut rng));
+        assert_eq!(primitive.shapes[0].feather(), 3);
+
+        // The filter is defined once, for the shapes to refer to
+        let svg = primitive.as_svg();
+        assert_eq!(svg.matches("<filter id=\"feather-3\"").count(), 1);
+        assert!(svg.contains("filter=\"url(#feather-3)\""));
+
+        // Shapes that can't be feathered ignore it
+        assert!(primitive.add_new_shape::<Triangle>(10, &mut rng));
+        assert_eq!(primitive.shapes[1].feather(), 0);
+    }
+
+    #[test]
+    fn test_aberration() {
+        // Vertical stripes, so moving a channel sideways changes it
+        let target = make_test_image(
+            40,
+            10,
+            TestPattern::Checkerboard(10, Rgba([255, 255, 255, 255]), Rgba([0, 0, 0, 255])),
+        );
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 0, 255])));
+        for center in [PrimitivePoint::new(5, 5), PrimitivePoint::new(25, 5)] {
+            assert!(primitive.add_specific_shape(Box::new(Rectangle::new(center, 10, 10, 0))));
+        }
+        let score = primitive.score();
+
+        primitive.set_aberration(2);
+        assert_eq!(primitive.score(), score);
+        assert!(primitive.as_svg().contains("filter=\"url(#red-channel)\""));
+
+        let path = std::env::temp_dir().join("primitive_image_test_aberration.png");
+        primitive.save_to_img(path.clone());
+        let saved = image::open(&path).unwrap().to_rgba8();
+        std::fs::remove_file(&path).unwrap();
+
+        // Red comes from 2 pixels right, and blue from 2 pixels left, so they disagree at edges
+        let unshifted = primitive.render_at(40, 10);
+        for x in 2..38 {
+            assert_eq!(saved.get_pixel(x, 5)[0], unshifted.get_pixel(x + 2, 5)[0]);
+            assert_eq!(saved.get_pixel(x, 5)[1], unshifted.get_pixel(x, 5)[1]);
+            assert_eq!(saved.get_pixel(x, 5)[2], unshifted.get_pixel(x - 2, 5)[2]);
+        }
+        assert_ne!(saved.get_pixel(9, 5)[0], saved.get_pixel(9, 5)[2]);
+    }
+
+    #[test]
+    fn test_save_layers() {
+        let target = make_test_image(40, 30, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        let mut config = RunConfig::new(10, 42, "MIXED".parse().unwrap());
+        while primitive.shapes.len() < 3 {
+            primitive.step(&mut config);
+        }
+        primitive.set_output_size(80);
+
+        let dir = std::env::temp_dir().join("primitive_image_test_save_layers");
+        primitive.save_layers(dir.clone());
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["0000.png", "0001.png", "0002.png"]);
+
+        // Each layer is only its shape, on a clear canvas at the output size
+        let clear = ImageBuffer::from_pixel(80, 60, Rgba([0, 0, 0, 0]));
+        for (i, shape) in primitive.shapes.iter().enumerate() {
+            let layer = image::open(dir.join(&files[i])).unwrap().to_rgba8();
+            assert_eq!(layer.dimensions(), primitive.output_dimensions());
+            assert_eq!(layer, shape.scaled_paint_on(&clear, 2.0));
+            assert!(layer.pixels().any(|pixel| pixel[3] > 0));
+            assert!(layer.pixels().any(|pixel| pixel[3] == 0));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_frames() {
+        let target = make_test_image(40, 30, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        let mut config = RunConfig::new(10, 42, ShapeType::Triangle);
+        while primitive.shapes.len() < 3 {
+            primitive.step(&mut config);
+        }
+        primitive.set_output_size(80);
+
+        let dir = std::env::temp_dir().join("primitive_image_test_save_frames");
+        primitive.save_frames(dir.clone());
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["0000.png", "0001.png", "0002.png", "0003.png"]);
+
+        let frame = |i: usize| image::open(dir.join(&files[i])).unwrap().to_rgba8();
+        assert_eq!(
+            frame(0),
+            ImageBuffer::from_pixel(80, 60, primitive.background)
+        );
+        assert_eq!(frame(3), primitive.render_at(80, 60));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_side_by_side() {
+        use image::GenericImageView;
+
+        let target = make_test_image(40, 30, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target.clone(), 0, None);
+        let mut config = RunConfig::new(10, 42, ShapeType::Triangle);
+        primitive.step(&mut config);
+        // The comparison doesn't depend on the output size
+        primitive.set_output_size(400);
+
+        let comparison = primitive.side_by_side();
+        assert_eq!(comparison.dimensions(), (80, 30));
+        assert_eq!(comparison.view(0, 0, 40, 30).to_image(), target);
+        assert_eq!(
+            comparison.view(40, 0, 40, 30).to_image(),
+            primitive.render_at(40, 30)
+        );
+    }
+
+    #[test]
+    fn test_suggest_shape_count() {
+        let flat = PrimitiveImage::from_image(
+            make_test_image(40, 40, TestPattern::Solid(Rgba([90, 120, 200, 255]))),
+            0,
+            None,
+        );
+        let busy = PrimitiveImage::from_image(
+            make_test_image(
+                40,
+                40,
+                TestPattern::Checkerboard(2, Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255])),
+            ),
+            0,
+            None,
+        );
+
+        assert_eq!(flat.suggest_shape_count(), MIN_SUGGESTED_SHAPES);
+        assert!(busy.suggest_shape_count() > flat.suggest_shape_count());
+        assert!(busy.suggest_shape_count() <= MAX_SUGGESTED_SHAPES);
+    }
+
+    #[test]
+    fn test_thumbnail_path() {
+        assert_eq!(
+            thumbnail_path(Path::new("out/cat.png")),
+            PathBuf::from("out/cat.thumb.png")
+        );
+        assert_eq!(
+            thumbnail_path(Path::new("cat.jpg")),
+            PathBuf::from("cat.thumb.jpg")
+        );
+        assert_eq!(
+            thumbnail_path(Path::new("cat.svg")),
+            PathBuf::from("cat.thumb.png")
+        );
+    }
+
+    #[test]
+    fn test_pop_shape() {
+        let target = ImageBuffer::from_pixel(40, 40, Rgba([255, 0, 0, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 255, 255])));
+        assert!(primitive.pop_shape().is_none());
+
+        let mut config = RunConfig::new(10, 42, ShapeType::Triangle);
+        primitive.step(&mut config);
+        let score = primitive.score();
+        let approximation = primitive.approximation.clone();
+
+        let rect = Rectangle::new(PrimitivePoint::new(20, 20), 10, 10, 0);
+        assert!(primitive.add_specific_shape(Box::new(rect)));
+        assert_eq!(primitive.shapes.len(), 2);
+        assert!(primitive.score() < score);
+
+        let popped = primitive.pop_shape().unwrap();
+        assert_eq!(popped.shape_type(), crate::shape::ShapeType::Rectangle);
+        assert_eq!(primitive.shapes.len(), 1);
+        assert_eq!(primitive.score(), score);
+        assert_eq!(primitive.approximation, approximation);
+    }
+
+    #[test]
+    fn test_prune_to_budget() {
+        let target = make_test_image(30, 30, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        let mut rng = get_rng(5);
+        for _ in 0..12 {
+            primitive.add_new_shape::<Triangle>(20, &mut rng);
+        }
+        assert_eq!(primitive.shapes.len(), 12);
+        assert_eq!(primitive.prune_to_budget(20), 0);
+
+        let mut score = primitive.score();
+        for max_elements in [9, 6, 3, 0] {
+            let removed = primitive.shapes.len() - max_elements;
+            assert_eq!(primitive.prune_to_budget(max_elements), removed);
+            assert_eq!(primitive.shapes.len(), max_elements);
+
+            let pruned_score = primitive.score();
+            assert!(pruned_score.is_finite());
+            assert!(pruned_score >= score);
+            score = pruned_score;
+        }
+    }
+
+    #[test]
+    fn test_sort_shapes() {
+        let target = make_test_image(30, 30, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        let mut rng = get_rng(8);
+        for _ in 0..10 {
+            primitive.add_new_shape::<Rectangle>(20, &mut rng);
+        }
+
+        primitive.sort_shapes(SortOrder::AreaDesc);
+        let areas: Vec<f64> = primitive.shapes.iter().map(|shape| shape.area()).collect();
+        assert_eq!(areas.len(), 10);
+        assert!(areas.windows(2).all(|pair| pair[0] >= pair[1]));
+
+        // The approximation is repainted in the new order
+        let mut repainted = primitive.clone();
+        repainted.repaint();
+        assert_eq!(primitive.approximation, repainted.approximation);
+    }
+
+    #[test]
+    fn test_initial_score() {
+        let target = make_test_image(30, 20, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        let score = primitive.score();
+        assert_eq!(primitive.initial_score(), score);
+
+        let mut config = RunConfig::new(20, 42, ShapeType::Triangle);
+        for _ in 0..3 {
+            primitive.step(&mut config);
+        }
+        assert!(primitive.score() < score);
+        assert_eq!(primitive.initial_score(), score);
+    }
+
+    #[test]
+    fn test_shape_time_budget() {
+        let target = make_test_image(40, 40, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 0, 255])));
+        primitive.set_shape_time_budget(Some(Duration::ZERO));
+        let score = primitive.score();
+
+        // With no time to climb, the first random shape is used as is
+        let mut rng = get_rng(2);
+        assert!(primitive.add_new_shape::<Ellipse>(1_000_000, &mut rng));
+        assert_eq!(primitive.shapes.len(), 1);
+        assert!(primitive.score() < score);
+    }
+
+    #[test]
+    fn test_no_rotation() {
+        let target = make_test_image(
+            40,
+            40,
+            TestPattern::Checkerboard(5, Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255])),
+        );
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        primitive.set_rotation(false);
+
+        let mut rng = get_rng(6);
+        for _ in 0..5 {
+            primitive.add_new_shape::<Rectangle>(30, &mut rng);
+            primitive.add_new_shape::<Ellipse>(30, &mut rng);
+        }
+
+        assert!(!primitive.shapes.is_empty());
+        for shape in primitive.shapes.iter() {
+            assert_eq!(shape.to_json()["angle"], 0);
+            assert!(shape.as_svg(1.0).contains("transform=\"rotate(0 "));
+        }
     }
 
-    Rgba(average_pixels)
-}
+    #[test]
+    fn test_commit_batch_of_one() {
+        let target = make_test_image(30, 30, TestPattern::Gradient);
+        let mut greedy = PrimitiveImage::from_image(target.clone(), 0, None);
+        let mut batched = PrimitiveImage::from_image(target, 0, None);
+        batched.set_commit_batch(1);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::utilities::sqrt;
+        let mut greedy_config = RunConfig::new(10, 42, ShapeType::Triangle);
+        let mut batched_config = RunConfig::new(10, 42, ShapeType::Triangle);
+        for _ in 0..4 {
+            let step = greedy.step(&mut greedy_config);
+            let results = batched.step_batch(&mut batched_config, 4);
+            assert_eq!(results, vec![step]);
+        }
+
+        let json = |image: &PrimitiveImage| -> Vec<serde_json::Value> {
+            image.shapes.iter().map(|shape| shape.to_json()).collect()
+        };
+        assert_eq!(json(&batched), json(&greedy));
+    }
 
     #[test]
-    fn test_average_color() {
-        // A the average of a solid colored image should be that color
-        let test_im_1 = ImageBuffer::from_pixel(10, 10, Rgba([255, 128, 0, 128]));
-        assert_eq!(average_color(&test_im_1), Rgba([255, 128, 0, 128]));
+    fn test_commit_batch() {
+        let target = make_test_image(40, 40, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        primitive.set_commit_batch(4);
+        let initial_score = primitive.score();
 
-        // Average color should always return a color with alpha=128
-        let test_im_2 = ImageBuffer::from_pixel(10, 10, Rgba([255, 128, 0, 255]));
-        assert_eq!(average_color(&test_im_2), Rgba([255, 128, 0, 128]));
+        let mut config = RunConfig::new(10, 42, ShapeType::Rectangle);
+        let results = primitive.step_batch(&mut config, 4);
+        assert!(!results.is_empty() && results.len() <= 4);
+        assert_eq!(primitive.shapes.len(), results.len());
+        assert_eq!(results.last().unwrap().score, primitive.score());
+        assert!(primitive.score() < initial_score);
 
-        // Test with two pixels, each a different color
-        let mut test_im_3 = ImageBuffer::from_pixel(1, 2, Rgba([0, 0, 0, 128]));
-        test_im_3.get_pixel_mut(0, 1).0 = [10, 10, 10, 128];
-        assert_eq!(average_color(&test_im_3), Rgba([5, 5, 5, 128]));
+        // The shapes committed together don't overlap
+        let mut covered = HashSet::new();
+        for shape in primitive.shapes.iter() {
+            for index in primitive.pixel_indices(&**shape) {
+                assert!(covered.insert(index));
+            }
+        }
+
+        // No more than the shapes left are added
+        assert!(primitive.step_batch(&mut config, 1).len() <= 1);
     }
 
     #[test]
-    fn test_score() {
-        let approximation = ImageBuffer::from_pixel(2, 2, Rgba([0, 0, 0, 128]));
-        let target = ImageBuffer::from_pixel(2, 2, Rgba([10, 10, 10, 128]));
-        let primitive = PrimitiveImage {
-            target,
-            approximation,
-            background: Rgba([0, 0, 0, 0]),
-            scale: 1.0,
-            shapes: vec![],
+    fn test_commit_batch_min_contrast() {
+        // The target is flat gray, so every shape that helps lowers the checkerboard's contrast
+        let target = make_test_image(40, 40, TestPattern::Solid(Rgba([128, 128, 128, 255])));
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        primitive.approximation = make_test_image(
+            40,
+            40,
+            TestPattern::Checkerboard(4, Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255])),
+        );
+        let min_contrast = luma_contrast(&primitive.approximation) * 0.95;
+        primitive.set_min_contrast(Some(min_contrast));
+        primitive.set_commit_batch(8);
+        primitive.set_size_limits(SizeLimits::new(None, Some(0.15)));
+
+        // Small shapes can each keep enough contrast, but not all of a batch together
+        let mut config = RunConfig::new(10, 42, ShapeType::Rectangle);
+        for _ in 0..5 {
+            primitive.step_batch(&mut config, 8);
+            assert!(luma_contrast(&primitive.approximation) >= min_contrast);
+        }
+        assert!(!primitive.shapes.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "The commit batch needs at least one shape")]
+    fn test_commit_batch_zero() {
+        let target = make_test_image(10, 10, TestPattern::Gradient);
+        PrimitiveImage::from_image(target, 0, None).set_commit_batch(0);
+    }
+
+    #[test]
+    fn test_shape_efficiency() {
+        let target = make_test_image(40, 40, TestPattern::Solid(Rgba([255, 0, 0, 255])));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 255, 255])));
+
+        let mut large = Rectangle::new(PrimitivePoint::new(20, 20), 30, 30, 0);
+        large.set_color_using(&primitive);
+        // Mostly off of the image, so most of its area can't help
+        let mut edge = Rectangle::new(PrimitivePoint::new(0, 0), 4, 4, 0);
+        edge.set_color_using(&primitive);
+
+        let large_efficiency = primitive.shape_efficiency(&large);
+        let edge_efficiency = primitive.shape_efficiency(&edge);
+        assert!(edge_efficiency > 0.0);
+        assert!(
+            large_efficiency > 2.0 * edge_efficiency,
+            "{} vs {}",
+            large_efficiency,
+            edge_efficiency
+        );
+
+        // Adding a shape records its efficiency, and failing to add one clears it
+        let mut rng = get_rng(42);
+        assert!(primitive.add_new_shape::<Rectangle>(20, &mut rng));
+        assert!(primitive.last_efficiency().unwrap() > 0.0);
+
+        let mut perfect = PrimitiveImage::from_image(
+            make_test_image(10, 10, TestPattern::Solid(Rgba([9, 9, 9, 255]))),
+            0,
+            None,
+        );
+        perfect.last_efficiency = Some(1.0);
+        assert!(!perfect.add_new_shape::<Rectangle>(5, &mut rng));
+        assert_eq!(perfect.last_efficiency(), None);
+    }
+
+    #[test]
+    fn test_edge_snap() {
+        // A vertical edge down the middle
+        let target = ImageBuffer::from_fn(40, 40, |x, _| {
+            if x < 20 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+
+        let mean_edge_distance = |edge_snap: f64| {
+            let mut primitive = PrimitiveImage::from_image(target.clone(), 0, None);
+            primitive.set_edge_snap(edge_snap);
+            let mut rng = get_rng(42);
+            for _ in 0..10 {
+                primitive.add_new_shape::<Triangle>(20, &mut rng);
+            }
+
+            let distances: Vec<f64> = primitive
+                .shapes
+                .iter()
+                .flat_map(|shape| {
+                    let path = shape.to_json()["path"].as_array().unwrap().clone();
+                    path.into_iter()
+                        .map(|point| (point["x"].as_f64().unwrap() - 19.5).abs())
+                })
+                .collect();
+            distances.iter().sum::<f64>() / distances.len() as f64
         };
 
-        // sqrt((Error(10.0)*Error(10.0)*NumChannelsWithError(3.0)*NumPixels(4.0))/(NumChannels(4.0)*NumPixels(4.0))
-        let expected_score = sqrt((10.0 * 10.0 * 3.0 * 4.0) / (4.0 * 4.0));
+        let without = mean_edge_distance(0.0);
+        let with = mean_edge_distance(1.0);
+        assert!(with < without, "{} vs {}", with, without);
+    }
 
-        assert_eq!(primitive.score(), expected_score);
+    #[test]
+    #[should_panic]
+    fn test_edge_snap_too_strong() {
+        let target = make_test_image(4, 4, TestPattern::Gradient);
+        PrimitiveImage::from_image(target, 0, None).set_edge_snap(2.0);
+    }
+
+    #[test]
+    fn test_squares() {
+        let target = make_test_image(
+            40,
+            30,
+            TestPattern::Checkerboard(8, Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255])),
+        );
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        primitive.set_squares(true);
+        let mut rng = get_rng(42);
+        for _ in 0..5 {
+            primitive.add_new_shape::<Rectangle>(20, &mut rng);
+        }
+
+        assert!(!primitive.shapes.is_empty());
+        for shape in &primitive.shapes {
+            let json = shape.to_json();
+            assert_eq!(json["width"], json["height"]);
+        }
+    }
+
+    #[test]
+    fn test_set_background() {
+        let target = ImageBuffer::from_pixel(40, 40, Rgba([255, 0, 0, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 255, 255])));
+        let rect = Rectangle::new(PrimitivePoint::new(20, 20), 10, 10, 0);
+        assert!(primitive.add_specific_shape(Box::new(rect)));
+        assert_eq!(primitive.background(), Rgba([0, 0, 255, 255]));
+
+        primitive.set_background(Rgba([255, 0, 0, 255]));
+        assert_eq!(primitive.background(), Rgba([255, 0, 0, 255]));
+        assert!(primitive
+            .as_svg()
+            .contains("<rect x=\"0\" y=\"0\" width=\"40\" height=\"40\" fill=\"#FF0000\" />"));
+
+        // The shape is still there, painted over the new background
+        assert_eq!(primitive.shapes.len(), 1);
+        assert_eq!(
+            primitive.approximation.get_pixel(0, 0),
+            &Rgba([255, 0, 0, 255])
+        );
+        assert_eq!(primitive.approximation, primitive.render_at(40, 40));
+        assert_eq!(primitive.score(), 0.0);
+    }
+
+    #[test]
+    fn test_render_into() {
+        let target = make_test_image(40, 30, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        primitive.set_crop_mask(Some(CropMask::Circle), false);
+        let mut rng = get_rng(42);
+        for _ in 0..3 {
+            primitive.add_new_shape::<Triangle>(10, &mut rng);
+        }
+
+        // Whatever was in the buffer is painted over
+        let mut buf = ImageBuffer::from_pixel(80, 60, Rgba([1, 2, 3, 4]));
+        primitive.render_into(&mut buf).unwrap();
+        assert_eq!(buf, primitive.render_at(80, 60));
+
+        // And it can be reused
+        primitive.add_new_shape::<Ellipse>(10, &mut rng);
+        primitive.render_into(&mut buf).unwrap();
+        assert_eq!(buf, primitive.render_at(80, 60));
+
+        // Hatched shapes are painted the same way as by render_at
+        primitive.set_fill_style(FillStyle::Hatch);
+        primitive.render_into(&mut buf).unwrap();
+        assert_eq!(buf, primitive.render_at(80, 60));
+    }
+
+    #[test]
+    fn test_render_into_size() {
+        let target = make_test_image(40, 30, TestPattern::Gradient);
+        let primitive = PrimitiveImage::from_image(target, 0, None);
+
+        // A pixel off from the aspect ratio is fine, since sizes are rounded
+        assert!(primitive.render_into(&mut ImageBuffer::new(41, 30)).is_ok());
+        assert!(primitive.render_into(&mut ImageBuffer::new(20, 15)).is_ok());
+
+        let mut wide = ImageBuffer::from_pixel(80, 30, Rgba([1, 2, 3, 4]));
+        assert!(matches!(
+            primitive.render_into(&mut wide),
+            Err(PrimitiveError::RenderSize {
+                width: 80,
+                height: 30,
+                ..
+            })
+        ));
+        assert!(wide.pixels().all(|pixel| *pixel == Rgba([1, 2, 3, 4])));
+        assert!(primitive.render_into(&mut ImageBuffer::new(0, 10)).is_err());
+    }
+
+    #[test]
+    fn test_protect_converged() {
+        // Everything but the first 4 columns already matches the target
+        let target = ImageBuffer::from_pixel(40, 40, Rgba([255, 0, 0, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([255, 0, 0, 255])));
+        for y in 0..40 {
+            for x in 0..4 {
+                primitive
+                    .approximation
+                    .put_pixel(x, y, Rgba([0, 0, 255, 255]));
+            }
+        }
+        primitive.set_fixed_color(Some(Rgba([255, 0, 0, 255])));
+        primitive.set_protect_converged(Some(1.0), 0.5);
+
+        // Entirely over converged pixels
+        let rect = Rectangle::new(PrimitivePoint::new(20, 20), 10, 10, 0);
+        assert!(primitive.covers_converged(&rect));
+        assert!(!primitive.add_specific_shape(Box::new(rect)));
+
+        // Mostly over converged pixels. Painting red on red changes nothing, so this would improve
+        // the score without the protection
+        let rect = Rectangle::new(PrimitivePoint::new(8, 20), 16, 16, 0);
+        assert!(!primitive.add_specific_shape(Box::new(rect)));
+        primitive.set_protect_converged(None, 0.5);
+        assert!(primitive.add_specific_shape(Box::new(rect)));
+
+        // Mostly over pixels that are still wrong
+        primitive.set_protect_converged(Some(1.0), 0.5);
+        let rect = Rectangle::new(PrimitivePoint::new(2, 30), 4, 4, 0);
+        assert!(!primitive.covers_converged(&rect));
+    }
+
+    #[test]
+    fn test_center_bias() {
+        let target = ImageBuffer::from_pixel(40, 40, Rgba([255, 0, 0, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 255, 255])));
+
+        let center = Rectangle::new(PrimitivePoint::new(20, 20), 8, 8, 0);
+        let edge = Rectangle::new(PrimitivePoint::new(5, 20), 8, 8, 0);
+
+        let improvement = |primitive: &PrimitiveImage, rect: Rectangle| {
+            let mut image = primitive.clone();
+            let before = image.score();
+            assert!(image.add_specific_shape(Box::new(rect)));
+            before - image.score()
+        };
+
+        // Without a bias, the same shape improves the score the same anywhere
+        let uniform = primitive.score();
+        assert!((improvement(&primitive, center) - improvement(&primitive, edge)).abs() < 1e-9);
+
+        primitive.set_center_bias(0.0);
+        assert_eq!(primitive.score(), uniform);
+
+        primitive.set_center_bias(5.0);
+        assert!(improvement(&primitive, center) > 2.0 * improvement(&primitive, edge));
+    }
+
+    #[test]
+    fn test_svg_seam_fix() {
+        let target = ImageBuffer::from_pixel(40, 40, Rgba([255, 0, 0, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 255, 255])));
+        let rect = Rectangle::new(PrimitivePoint::new(20, 20), 10, 10, 0);
+        assert!(primitive.add_specific_shape(Box::new(rect)));
+        assert!(primitive.as_svg().contains("<g><"));
+
+        primitive.set_svg_seam_fix(Some(String::from("crispEdges")), Some(0.5));
+        let svg = primitive.as_svg();
+        assert!(svg.contains("<g shape-rendering=\"crispEdges\"><path "));
+        assert!(svg.contains("stroke-width=\"0.5\""));
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let missing = std::env::temp_dir().join("primitive_image_test_missing.png");
+        assert!(matches!(
+            PrimitiveImage::from_path(missing, 0, None, LoadOptions::default()),
+            Err(PrimitiveError::Io(_))
+        ));
+
+        // Text pretending to be a PNG
+        let path = std::env::temp_dir().join("primitive_image_test_not_an_image.png");
+        std::fs::write(&path, "not an image").unwrap();
+        let result = PrimitiveImage::from_path(path.clone(), 0, None, LoadOptions::default());
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(PrimitiveError::Decode(_))));
+
+        // An extension the image library doesn't know
+        let path = std::env::temp_dir().join("primitive_image_test_not_an_image.txt");
+        std::fs::write(&path, "not an image").unwrap();
+        let result = PrimitiveImage::from_path(path.clone(), 0, None, LoadOptions::default());
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(PrimitiveError::Unsupported(_))));
+
+        assert!(
+            PrimitiveImage::from_bytes(b"not an image", 0, None, LoadOptions::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let source: ImageBuffer<Rgba<u8>, Vec<u8>> =
+            ImageBuffer::from_pixel(4, 2, Rgba([255, 0, 0, 255]));
+        let mut bytes = vec![];
+        write_png(&source, Some(72.0), None, &mut bytes);
+
+        let primitive =
+            PrimitiveImage::from_bytes(&bytes, 0, None, LoadOptions::default()).unwrap();
+        assert_eq!(primitive.target, source);
+    }
+
+    #[test]
+    fn test_max_dimension() {
+        assert_eq!(limit_dimension(3000, 2000, 0, 2000), 2000);
+        assert_eq!(limit_dimension(3000, 2000, 2500, 2000), 2000);
+        assert_eq!(limit_dimension(3000, 2000, 100, 2000), 100);
+        assert_eq!(limit_dimension(1500, 1000, 0, 2000), 0);
+        assert_eq!(limit_dimension(3000, 2000, 0, 0), 0);
+
+        // Scaled down to the cap even though scaling is off
+        let source: ImageBuffer<Rgba<u8>, Vec<u8>> =
+            ImageBuffer::from_pixel(2500, 20, Rgba([255, 0, 0, 255]));
+        let mut bytes = vec![];
+        write_png(&source, None, None, &mut bytes);
+
+        let primitive = PrimitiveImage::from_bytes(
+            &bytes,
+            0,
+            None,
+            LoadOptions {
+                max_dimension: 2000,
+                ..LoadOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(primitive.target.dimensions(), (2000, 16));
+        assert_eq!(primitive.scale, 0.8);
+    }
+
+    #[test]
+    fn test_preview_scale() {
+        let target = make_test_image(200, 100, TestPattern::Gradient);
+        let scale_to = scale_to_for_preview(200, 100, 0.25);
+        let mut primitive = PrimitiveImage::from_image(target.clone(), scale_to, None);
+        let mut config = RunConfig::new(10, 42, ShapeType::Triangle);
+        primitive.step(&mut config);
+
+        // Searched small, rendered full size
+        assert_eq!(primitive.target.dimensions(), (50, 25));
+        assert_eq!(primitive.output_dimensions(), (200, 100));
+        assert_eq!(
+            primitive.iter_frames().last().unwrap().dimensions(),
+            (200, 100)
+        );
+
+        let path = std::env::temp_dir().join("primitive_image_test_preview_scale.png");
+        target.save(&path).unwrap();
+        let from_file = PrimitiveImage::preview_scale_to(&path, 0.1);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(from_file.unwrap(), 20);
+    }
+
+    #[test]
+    fn test_scale_to_without_upscaling() {
+        assert_eq!(limit_upscaling(40, 30, 100), 40);
+        assert_eq!(limit_upscaling(400, 300, 100), 100);
+        assert_eq!(limit_upscaling(40, 30, 0), 0);
+
+        let source = make_test_image(40, 30, TestPattern::Gradient);
+        let path = std::env::temp_dir().join("primitive_image_test_no_upscale.png");
+        source.save(&path).unwrap();
+        let primitive =
+            PrimitiveImage::from_path(path.clone(), 100, None, LoadOptions::default()).unwrap();
+        let upscaled = PrimitiveImage::from_path(
+            path.clone(),
+            100,
+            None,
+            LoadOptions {
+                allow_upscale: true,
+                ..LoadOptions::default()
+            },
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(primitive.target.dimensions(), (40, 30));
+        assert_eq!(primitive.scale, 1.0);
+        assert_eq!(upscaled.target.dimensions(), (100, 75));
+    }
+
+    #[test]
+    fn test_preview_scale_out_of_range() {
+        // Checked before the file is read
+        let path = Path::new("does_not_exist.png");
+        for preview_scale in [0.0, -0.5, 1.5, f64::NAN] {
+            assert!(matches!(
+                PrimitiveImage::preview_scale_to(path, preview_scale),
+                Err(PrimitiveError::PreviewScale(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_flip() {
+        // Red in the top left, blue in the top right, and white elsewhere
+        let source = ImageBuffer::from_fn(4, 3, |x, y| match (x, y) {
+            (0, 0) => Rgba([255, 0, 0, 255]),
+            (3, 0) => Rgba([0, 0, 255, 255]),
+            _ => Rgba([255, 255, 255, 255]),
+        });
+        let mut bytes = vec![];
+        write_png(&source, None, None, &mut bytes);
+
+        let flipped = PrimitiveImage::from_bytes(
+            &bytes,
+            0,
+            None,
+            LoadOptions {
+                flip: Some(Flip::Horizontal),
+                ..LoadOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(flipped.target.get_pixel(0, 0), &Rgba([0, 0, 255, 255]));
+        assert_eq!(flipped.target.get_pixel(3, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(flipped.target.get_pixel(0, 2), &Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn test_auto_levels() {
+        // A low contrast gradient, from 100 to 150
+        let source = ImageBuffer::from_fn(51, 10, |x, _| {
+            let value = 100 + x as u8;
+            Rgba([value, value, value, 255])
+        });
+        let mut bytes = vec![];
+        write_png(&source, None, None, &mut bytes);
+
+        for auto_levels in [AutoLevels::Rgb, AutoLevels::Luma] {
+            let leveled = PrimitiveImage::from_bytes(
+                &bytes,
+                0,
+                None,
+                LoadOptions {
+                    auto_levels: Some(auto_levels),
+                    ..LoadOptions::default()
+                },
+            )
+            .unwrap();
+            for channel in 0..3 {
+                let values = leveled.target.pixels().map(|pixel| pixel[channel]);
+                assert_eq!(values.clone().min(), Some(0));
+                assert_eq!(values.max(), Some(255));
+            }
+        }
+
+        let unleveled =
+            PrimitiveImage::from_bytes(&bytes, 0, None, LoadOptions::default()).unwrap();
+        assert_eq!(
+            unleveled.target.pixels().map(|pixel| pixel[0]).max(),
+            Some(150)
+        );
+    }
+
+    #[test]
+    fn test_metric() {
+        let target = make_test_image(20, 20, TestPattern::Solid(Rgba([128, 128, 128, 255])));
+        let mut brighter =
+            PrimitiveImage::from_image(target.clone(), 0, Some(Rgba([138, 138, 138, 255])));
+        let mut redder = PrimitiveImage::from_image(target, 0, Some(Rgba([142, 121, 128, 255])));
+
+        // In RGB, they're about as far off
+        assert!((redder.score() - brighter.score()).abs() < 1.0);
+
+        // Weighting luma makes the brighter one much worse
+        brighter.set_metric(Metric::YCbCr, DEFAULT_LUMA_WEIGHT);
+        redder.set_metric(Metric::YCbCr, DEFAULT_LUMA_WEIGHT);
+        assert!(brighter.score() > 2.0 * redder.score());
+        assert_eq!(brighter.initial_score(), brighter.score());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_luma_weight_too_big() {
+        let target = make_test_image(4, 4, TestPattern::Gradient);
+        PrimitiveImage::from_image(target, 0, None).set_metric(Metric::YCbCr, 1.5);
+    }
+
+    #[test]
+    fn test_channel_weights() {
+        let target = ImageBuffer::from_pixel(10, 10, Rgba([100, 100, 100, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([100, 100, 100, 255])));
+        primitive.approximation = ImageBuffer::from_pixel(10, 10, Rgba([100, 100, 200, 255]));
+        assert!(primitive.score() > 0.0);
+
+        // A blue-only difference doesn't count when blue isn't weighted
+        primitive.set_channel_weights(ChannelWeights([1.0, 1.0, 0.0]));
+        assert_eq!(primitive.score(), 0.0);
+    }
+
+    #[test]
+    fn test_score_sample() {
+        let mut rng = get_rng(3);
+        let target =
+            ImageBuffer::from_fn(60, 60, |x, _| Rgba([rng.gen(), (x * 4) as u8, 128, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        primitive.set_score_sample(0.2, 42);
+        assert_eq!(primitive.score_sample.as_ref().unwrap().len(), 720);
+
+        // The full score never gets worse, even though candidates are compared on the sample
+        let mut config = RunConfig::new(20, 42, ShapeType::Triangle);
+        let mut previous_score = primitive.score();
+        for _ in 0..5 {
+            let result = primitive.step(&mut config);
+            assert!(result.score <= previous_score);
+            previous_score = result.score;
+        }
+        assert!(
+            previous_score < PrimitiveImage::from_image(primitive.target.clone(), 0, None).score()
+        );
+
+        primitive.set_score_sample(1.0, 42);
+        assert!(primitive.score_sample.is_none());
+    }
+
+    #[test]
+    fn test_svg_layers() {
+        let target = make_test_image(40, 40, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        primitive.set_svg_format(SvgFormat::Layers, 0);
+
+        let mut config = RunConfig::new(10, 42, "MIXED".parse().unwrap());
+        for _ in 0..10 {
+            primitive.step(&mut config);
+        }
+
+        let mut shape_types = vec![];
+        for shape in primitive.shapes.iter() {
+            if !shape_types.contains(&shape.shape_type()) {
+                shape_types.push(shape.shape_type());
+            }
+        }
+
+        assert!(shape_types.len() > 1);
+        assert_eq!(
+            primitive.as_svg().matches("<g id=").count(),
+            shape_types.len()
+        );
+    }
+
+    #[test]
+    fn test_extract_palette() {
+        let target = make_test_image(40, 40, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+
+        assert_eq!(primitive.extract_palette(0), None);
+        assert_eq!(primitive.palette, None);
+
+        let palette = primitive.extract_palette(4).unwrap();
+        assert_eq!(palette.len(), 4);
+        assert_eq!(primitive.background, palette[0]);
+
+        let mut rng = get_rng(42);
+        for _ in 0..5 {
+            primitive.add_new_shape::<Triangle>(10, &mut rng);
+        }
+        primitive.add_new_shape::<Ellipse>(10, &mut rng);
+
+        assert!(!primitive.shapes.is_empty());
+        for shape in &primitive.shapes {
+            let color = shape.get_color();
+            assert!(
+                palette
+                    .iter()
+                    .any(|p| p[0] == color[0] && p[1] == color[1] && p[2] == color[2]),
+                "{:?} isn't in the palette",
+                color
+            );
+            assert_eq!(color[3], SHAPE_ALPHA);
+        }
+
+        assert_eq!(
+            closest_color(&palette, &palette[2]),
+            palette[2],
+            "Palette colors snap to themselves"
+        );
+    }
+
+    #[test]
+    fn test_dominant_colors() {
+        // Mostly dark red, with a slightly noisy light blue stripe
+        let target = ImageBuffer::from_fn(40, 40, |x, y| {
+            if x < 30 {
+                Rgba([200, 20, 20, 255])
+            } else {
+                Rgba([100, 150, 240 + (y % 3) as u8, 255])
+            }
+        });
+        let primitive = PrimitiveImage::from_image(target, 0, None);
+
+        let colors = primitive.dominant_colors(2);
+        assert_eq!(colors.len(), 2);
+        assert_eq!(colors[0], Rgba([200, 20, 20, 255]));
+        assert!(pixel_error(&colors[1], &Rgba([100, 150, 241, 255])) < 1.0);
+
+        // There aren't more clusters than distinct colors
+        let flat = PrimitiveImage::from_image(
+            ImageBuffer::from_pixel(10, 10, Rgba([1, 2, 3, 255])),
+            0,
+            None,
+        );
+        assert_eq!(flat.dominant_colors(3), vec![Rgba([1, 2, 3, 255])]);
+    }
+
+    #[test]
+    fn test_color_stats() {
+        // Red on the left, blue on the right, so shapes within one half get that half's color
+        let target = ImageBuffer::from_fn(40, 40, |x, _| {
+            if x < 20 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        });
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 0, 255])));
+        assert_eq!(primitive.color_stats().distinct_colors, 0);
+
+        let rects = [
+            Rectangle::new(PrimitivePoint::new(10, 10), 10, 10, 0),
+            Rectangle::new(PrimitivePoint::new(30, 10), 10, 10, 0),
+            Rectangle::new(PrimitivePoint::new(10, 30), 10, 10, 0),
+        ];
+        for rect in rects {
+            assert!(primitive.add_specific_shape(Box::new(rect)));
+        }
+
+        let stats = primitive.color_stats();
+        assert_eq!(stats.num_shapes, 3);
+        assert_eq!(stats.distinct_colors, 2);
+        assert_eq!(stats.top[0], (Rgba([255, 0, 0, 128]), 2));
+    }
+
+    #[test]
+    fn test_mixed() {
+        let target = make_test_image(40, 40, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        let mix = ShapeType::Mixed(vec![ShapeType::Rectangle, ShapeType::Heart]);
+        let mut config = RunConfig::new(10, 42, mix);
+
+        for _ in 0..10 {
+            primitive.step(&mut config);
+        }
+
+        let mut shape_types = vec![];
+        for shape in primitive.shapes.iter() {
+            assert!([ShapeType::Rectangle, ShapeType::Heart].contains(&shape.shape_type()));
+            if !shape_types.contains(&shape.shape_type()) {
+                shape_types.push(shape.shape_type());
+            }
+        }
+        assert_eq!(shape_types.len(), 2);
+    }
+
+    #[test]
+    fn test_step() {
+        let target = make_test_image(40, 40, TestPattern::Gradient);
+        let mut primitive = PrimitiveImage::from_image(target, 0, None);
+        let mut config = RunConfig::new(20, 42, "MIXED".parse().unwrap());
+
+        let mut num_added = 0;
+        let mut previous_score = primitive.score();
+        for _ in 0..5 {
+            let result = primitive.step(&mut config);
+            if result.added {
+                num_added += 1;
+            }
+
+            assert_eq!(result.score, primitive.score());
+            assert!(result.score <= previous_score);
+            previous_score = result.score;
+        }
+
+        assert!(num_added <= 5);
+        assert_eq!(primitive.shapes.len(), num_added);
+    }
+
+    #[test]
+    fn test_tiny_images() {
+        for (width, height, scale_to) in [(1, 1, 0), (1, 1, 100), (1, 300, 100)] {
+            let target = ImageBuffer::from_pixel(width, height, Rgba([255, 0, 0, 255]));
+            let mut primitive = PrimitiveImage::from_image(target, scale_to, None);
+            assert!(primitive.width() >= 1 && primitive.height() >= 1);
+
+            for shape in [
+                ShapeType::Triangle,
+                ShapeType::QuadraticBezier,
+                ShapeType::CubicBezier,
+                ShapeType::Rectangle,
+                ShapeType::Ellipse,
+                ShapeType::Heart,
+                ShapeType::Teardrop,
+                ShapeType::BezierPath,
+                ShapeType::Cross,
+            ] {
+                let mut config = RunConfig::new(5, 42, shape);
+                primitive.step(&mut config);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_empty_image() {
+        PrimitiveImage::from_image(ImageBuffer::new(0, 10), 0, None);
+    }
+
+    #[test]
+    fn test_circle_crop_mask() {
+        let target = ImageBuffer::from_pixel(20, 20, Rgba([255, 0, 0, 255]));
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 255, 255])));
+        primitive.set_crop_mask(Some(CropMask::Circle), false);
+
+        let path = std::env::temp_dir().join("primitive_image_test_circle_crop_mask.png");
+        primitive.save_to_img(path.clone());
+        let saved = open(&path).unwrap().to_rgba8();
+        std::fs::remove_file(&path).unwrap();
+
+        // Corners are cropped out, the center is kept
+        assert_eq!(saved.get_pixel(0, 0)[3], 0);
+        assert_eq!(saved.get_pixel(10, 10), &Rgba([0, 0, 255, 255]));
+
+        assert!(primitive.as_svg().contains("clip-path=\"url(#crop)\""));
+    }
+
+    #[test]
+    fn test_alpha_mask() {
+        // The left half is opaque, the right half transparent
+        let source = ImageBuffer::from_fn(40, 20, |x, y| {
+            if x < 20 {
+                Rgba([(x * 12) as u8, (y * 12) as u8, 100, 255])
+            } else {
+                Rgba([255, 255, 255, 0])
+            }
+        });
+        let mut primitive = PrimitiveImage::from_image(source, 0, Some(Rgba([0, 0, 0, 255])));
+        primitive.set_alpha_mask(Some(128));
+
+        let mut config = RunConfig::new(20, 42, ShapeType::Triangle);
+        for _ in 0..10 {
+            primitive.step(&mut config);
+        }
+        assert!(!primitive.shapes.is_empty());
+        for shape in primitive.shapes.iter() {
+            assert!(primitive
+                .pixel_indices(&**shape)
+                .iter()
+                .all(|&index| index % 40 < 20));
+        }
+
+        // The transparent half doesn't count towards the score
+        let score = primitive.score();
+        for x in 20..40 {
+            for y in 0..20 {
+                primitive
+                    .approximation
+                    .put_pixel(x, y, Rgba([255, 0, 255, 255]));
+            }
+        }
+        assert_eq!(primitive.score(), score);
+    }
+
+    #[test]
+    fn test_masked_score() {
+        // The target only differs from the approximation in its corner
+        let mut target = ImageBuffer::from_pixel(20, 20, Rgba([0, 0, 0, 255]));
+        target.get_pixel_mut(0, 0).0 = [255, 255, 255, 255];
+        let mut primitive = PrimitiveImage::from_image(target, 0, Some(Rgba([0, 0, 0, 255])));
+
+        primitive.set_crop_mask(Some(CropMask::Circle), false);
+        assert!(primitive.score() > 0.0);
+
+        primitive.set_crop_mask(Some(CropMask::Circle), true);
+        assert_eq!(primitive.score(), 0.0);
     }
 }
@@ -1,34 +1,62 @@
+use crate::mutate_mode::MutateMode;
 use crate::point::PrimitivePoint;
 use crate::primitive_image::PrimitiveImage;
-use crate::shape::{RandomShape, Shape};
-use crate::utilities::rgb_to_hex;
-use image::imageops::overlay;
+use crate::shape::{shape_key, RandomShape, Shape, ShapeType};
+use crate::utilities::{
+    blend_with_coverage, default_stroke_width, points_size, rgb_to_hex, supersample_point,
+    supersampled_coverage, thicken, SegmentLimits, SizeLimits, SUPERSAMPLING,
+};
 use image::ImageBuffer;
 use image::Rgba;
-use imageproc::drawing::draw_cubic_bezier_curve;
 use imageproc::drawing::BresenhamLineIter;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::max;
 
 const MAXIMUM_MUTATION_ATTEMPTS: u32 = 100_000;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Hash, Serialize, Deserialize)]
 pub struct QuadraticBezier {
+    #[serde(with = "crate::utilities::rgba_serde")]
     pub color: image::Rgba<u8>,
     pub start: PrimitivePoint,
     pub control: PrimitivePoint,
     pub end: PrimitivePoint,
+    #[serde(default = "default_stroke_width")]
+    pub width: u32,
+    #[serde(default)]
+    pub segment_limits: SegmentLimits,
 }
 
 impl QuadraticBezier {
-    fn new(start: PrimitivePoint, end: PrimitivePoint, control: PrimitivePoint) -> Self {
+    ///
+    /// Create a curve from `start` to `end`, pulled toward `control`
+    ///
+    pub fn new(start: PrimitivePoint, end: PrimitivePoint, control: PrimitivePoint) -> Self {
         QuadraticBezier {
             color: Rgba([0, 0, 0, 128]),
+            width: default_stroke_width(),
+            segment_limits: SegmentLimits::default(),
             start,
             end,
             control,
         }
     }
 
+    ///
+    /// Returns this shape with its color set to `color`, for building shapes by hand
+    ///
+    pub fn with_color(mut self, color: Rgba<u8>) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Returns the size of the curve, for `SizeLimits`
+    fn size(&self) -> u32 {
+        points_size(&[self.start, self.end, self.control])
+    }
+
     /// Currently no validation for CubicBezier is required, so this always returns true
     fn is_valid(&self) -> bool {
         let dx12 = self.start.x - self.control.x;
@@ -46,33 +74,55 @@ impl QuadraticBezier {
 }
 
 impl RandomShape for QuadraticBezier {
-    fn random(width: u32, height: u32, border_extension: i32, rng: &mut impl Rng) -> Self {
-        let start = PrimitivePoint::random_point(width, height, rng);
-        let control = start.random_point_in_radius(border_extension, rng);
-        let end = start.random_point_in_radius(border_extension, rng);
-
-        let mut bezier = QuadraticBezier::new(start, end, control);
-        bezier.mutate(width, height, rng);
-
-        bezier
+    ///
+    /// Generate a random QuadraticBezier around `start`
+    ///
+    fn random_at(
+        start: PrimitivePoint,
+        width: u32,
+        height: u32,
+        border_extension: i32,
+        size_limits: SizeLimits,
+        rng: &mut impl Rng,
+    ) -> Self {
+        // Start no bigger than the maximum size
+        let (_, max_size) = size_limits.pixel_range(width, height, 1, 2 * border_extension as u32);
+        let radius = max(max_size as i32 / 2, 1);
+
+        let control = start.random_point_in_radius(radius, rng);
+        let end = start.random_point_in_radius(radius, rng);
+
+        QuadraticBezier::new(start, end, control)
     }
 }
 
 impl Shape for QuadraticBezier {
-    fn mutate(&mut self, width: u32, height: u32, rng: &mut impl Rng) {
+    fn mutate(
+        &mut self,
+        width: u32,
+        height: u32,
+        size_limits: SizeLimits,
+        mode: MutateMode,
+        rng: &mut impl Rng,
+    ) {
         let mut i = 0;
         loop {
             i += 1;
-            let r = rng.gen_range(0..3);
-
-            match r {
-                0 => self.start.mutate(width, height, rng),
-                1 => self.end.mutate(width, height, rng),
-                2 => self.control.mutate(width, height, rng),
-                _ => {}
+            let previous = *self;
+            for r in mode.parameters(3, rng) {
+                match r {
+                    0 => self.start.mutate(width, height, mode, rng),
+                    1 => self.end.mutate(width, height, mode, rng),
+                    2 => self.control.mutate(width, height, mode, rng),
+                    _ => {}
+                }
             }
 
-            if self.is_valid() {
+            let allowed = size_limits.allows(self.size(), width, height);
+            // Once the curve is an allowed size, don't let it wander away from one
+            if !allowed && size_limits.allows(previous.size(), width, height) {
+                *self = previous;
+            } else if allowed && self.is_valid() {
                 break;
             }
             if i > MAXIMUM_MUTATION_ATTEMPTS {
@@ -110,8 +160,7 @@ impl Shape for QuadraticBezier {
             (self.end.x as f32, self.end.y as f32),
         );
 
-        // Use hyperbola function to give shorter curves a bias in number of line segments.
-        let num_segments: i32 = ((curve_length_bound.powi(2) + 800.0).sqrt() / 8.0) as i32;
+        let num_segments = self.segment_limits.segments_for(curve_length_bound);
 
         // Sample points along the curve and connect them with line segments.
         let t_interval = 1f32 / (num_segments as f32);
@@ -134,84 +183,162 @@ impl Shape for QuadraticBezier {
             t1 = t2;
         }
 
-        pixels
+        thicken(pixels, self.width)
     }
 
     fn as_svg(&self, scale: f64) -> String {
-        format!("<path stroke=\"{}\" stroke-opacity=\"{}\" fill=\"none\" d=\"M{} {} Q{} {}, {} {}\" stroke-width=\"{}\" />",
+        format!("<path stroke=\"{}\" stroke-opacity=\"{:.5}\" fill=\"none\" d=\"M{} {} Q{} {}, {} {}\" stroke-width=\"{}\" />",
                 rgb_to_hex(self.color),
                 self.color.0[3] as f64 / 255.0,
                 (self.start.x as f64 * scale) as i32, (self.start.y as f64 * scale) as i32,
                 (self.control.x as f64 * scale) as i32, (self.control.y as f64 * scale) as i32,
                 (self.end.x as f64 * scale) as i32, (self.end.y as f64 * scale) as i32,
-                scale / 2.0)
+                self.width as f64 * scale)
     }
 
-    //noinspection RsTypeCheck
-    fn paint_on(&self, image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-        let (width, height) = image.dimensions();
+    fn paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
+        // Rasterize at a higher resolution, then blend each pixel by how much of it is covered
+        let supersampled_self = QuadraticBezier {
+            color: self.color,
+            start: supersample_point(self.start),
+            control: supersample_point(self.control),
+            end: supersample_point(self.end),
+            width: self.width * SUPERSAMPLING as u32,
+            segment_limits: self.segment_limits,
+        };
+        let coverage = supersampled_coverage(supersampled_self.get_pixels());
 
-        let mut tri_image: ImageBuffer<Rgba<u8>, Vec<u8>> =
-            image::ImageBuffer::from_pixel(width as u32, height as u32, image::Rgba([0, 0, 0, 0]));
-        let mut output = image.clone();
+        blend_with_coverage(image, &coverage, self.color);
+    }
 
-        tri_image = draw_cubic_bezier_curve(
-            &tri_image,
-            (self.start.x as f32, self.start.y as f32),
-            (self.end.x as f32, self.end.y as f32),
-            (
-                (self.control.x as f32 - self.start.x as f32) * 2.0 / 3.0,
-                (self.control.y as f32 - self.start.y as f32) * 2.0 / 3.0,
-            ),
-            (
-                (self.control.x as f32 - self.end.x as f32) * 2.0 / 3.0,
-                (self.control.y as f32 - self.end.y as f32) * 2.0 / 3.0,
-            ),
-            self.color,
-        );
+    fn scaled_paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, scale: f64) {
+        let scale_point = |point: PrimitivePoint| {
+            PrimitivePoint::new(
+                (point.x as f64 * scale) as i32,
+                (point.y as f64 * scale) as i32,
+            )
+        };
 
-        overlay(&mut output, &tri_image, 0, 0);
+        let scaled_self = QuadraticBezier {
+            color: self.color,
+            start: scale_point(self.start),
+            control: scale_point(self.control),
+            end: scale_point(self.end),
+            width: ((self.width as f64 * scale).round() as u32).max(1),
+            segment_limits: self.segment_limits,
+        };
 
-        output
+        scaled_self.paint_into(image);
     }
 
-    //noinspection RsTypeCheck
-    fn scaled_paint_on(
-        &self,
-        image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-        scale: f64,
-    ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-        let (width, height) = image.dimensions();
+    fn set_color_using(&mut self, image: &PrimitiveImage) {
+        self.color = image.color_for_shape(self);
+    }
 
-        let mut curve_image: ImageBuffer<Rgba<u8>, Vec<u8>> =
-            image::ImageBuffer::from_pixel(width as u32, height as u32, image::Rgba([0, 0, 0, 0]));
-        let mut output = image.clone();
+    fn get_color(&self) -> Rgba<u8> {
+        self.color
+    }
 
-        let start = (
-            self.start.x as f32 * scale as f32,
-            self.start.y as f32 * scale as f32,
-        );
-        let end = (
-            self.end.x as f32 * scale as f32,
-            self.end.y as f32 * scale as f32,
-        );
-        let c1 = (
-            (self.control.x as f32 - self.start.x as f32) * 2.0 / 3.0 * scale as f32,
-            (self.control.y as f32 - self.start.y as f32) * 2.0 / 3.0 * scale as f32,
-        );
-        let c2 = (
-            (self.control.x as f32 - self.end.x as f32) * 2.0 / 3.0 * scale as f32,
-            (self.control.y as f32 - self.end.y as f32) * 2.0 / 3.0 * scale as f32,
+    fn set_stroke_width(&mut self, width: u32) {
+        self.width = width;
+    }
+
+    fn set_segment_limits(&mut self, segment_limits: SegmentLimits) {
+        self.segment_limits = segment_limits;
+    }
+
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::QuadraticBezier
+    }
+
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap()
+    }
+
+    fn canonical_key(&self) -> u64 {
+        shape_key(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stroke_width() {
+        // A horizontal curve, so the perpendicular is vertical
+        let mut curve = QuadraticBezier::new(
+            PrimitivePoint::new(0, 10),
+            PrimitivePoint::new(20, 10),
+            PrimitivePoint::new(10, 10),
         );
 
-        curve_image = draw_cubic_bezier_curve(&curve_image, start, end, c1, c2, self.color);
+        let pixels = curve.get_pixels();
+        assert!(pixels.contains(&PrimitivePoint::new(10, 10)));
+        assert!(!pixels.contains(&PrimitivePoint::new(10, 9)));
+        assert!(!pixels.contains(&PrimitivePoint::new(10, 11)));
+
+        curve.set_stroke_width(3);
+        let pixels = curve.get_pixels();
+        assert!(pixels.contains(&PrimitivePoint::new(10, 9)));
+        assert!(pixels.contains(&PrimitivePoint::new(10, 11)));
+        assert!(!pixels.contains(&PrimitivePoint::new(10, 12)));
+
+        // Painting covers the same band
+        let image = ImageBuffer::from_pixel(21, 21, Rgba([0, 0, 0, 255]));
+        curve.color = Rgba([255, 255, 255, 255]);
+        let painted = curve.paint_on(&image);
+        assert_eq!(painted.get_pixel(10, 9), &Rgba([255, 255, 255, 255]));
+        assert_eq!(painted.get_pixel(10, 12), &Rgba([0, 0, 0, 255]));
+
+        assert!(curve.as_svg(2.0).contains("stroke-width=\"6\""));
+    }
 
-        overlay(&mut output, &curve_image, 0, 0);
+    #[test]
+    fn test_as_svg() {
+        let mut curve = QuadraticBezier::new(
+            PrimitivePoint::new(0, 10),
+            PrimitivePoint::new(20, 10),
+            PrimitivePoint::new(10, 0),
+        );
+        curve.color = Rgba([240, 64, 15, 128]);
 
-        output
+        // Same color and opacity formatting as the filled shapes, as a stroke
+        let expected = "<path stroke=\"#F0400F\" stroke-opacity=\"0.50196\" fill=\"none\" d=\"M0 20 Q20 0, 40 20\" stroke-width=\"2\" />";
+        assert_eq!(curve.as_svg(2.0).as_str(), expected);
     }
 
-    fn set_color_using(&mut self, image: &PrimitiveImage) {
-        self.color = image.target_average_color_in_shape(self);
+    #[test]
+    fn test_tiny_curve() {
+        // All three points are the same, so the curve has no length
+        let point = PrimitivePoint::new(5, 5);
+        let mut curve = QuadraticBezier::new(point, point, point);
+        assert_eq!(curve.get_pixels(), vec![point]);
+
+        // Even when forced to use 0 segments
+        curve.set_segment_limits(SegmentLimits {
+            min: 0,
+            max: 0,
+            fixed: Some(0),
+        });
+        assert_eq!(curve.get_pixels(), vec![point]);
+    }
+
+    #[test]
+    fn test_anti_aliasing() {
+        // A diagonal curve can't line up with the pixel grid
+        let mut curve = QuadraticBezier::new(
+            PrimitivePoint::new(0, 0),
+            PrimitivePoint::new(20, 13),
+            PrimitivePoint::new(10, 4),
+        );
+        curve.color = Rgba([255, 255, 255, 255]);
+
+        let image = ImageBuffer::from_pixel(21, 21, Rgba([0, 0, 0, 0]));
+        let painted = curve.paint_on(&image);
+
+        let alphas = painted.pixels().map(|p| p[3]).collect::<Vec<_>>();
+        assert!(alphas.contains(&255));
+        assert!(alphas.iter().any(|a| *a > 0 && *a < 255));
     }
 }
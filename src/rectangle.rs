@@ -1,86 +1,238 @@
+use crate::feather::{feather_filter_attribute, feathered};
+use crate::mutate_mode::MutateMode;
 use crate::point::PrimitivePoint;
 use crate::primitive_image::PrimitiveImage;
-use crate::shape::{RandomShape, Shape};
-use crate::utilities::{clamp, rgb_to_hex, rotate_point};
+use crate::shape::{shape_key, RandomShape, Shape, ShapeType};
+use crate::symmetry::Symmetry;
+use crate::utilities::{clamp, radians, rgb_to_hex, SizeLimits};
 use image::ImageBuffer;
 use image::Pixel;
 use image::Rgba;
 use rand::Rng;
 use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::cmp::max;
+use std::ops::Range;
 
 const MAXIMUM_MUTATION_ATTEMPTS: u32 = 100_000;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Hash, Serialize, Deserialize)]
 pub struct Rectangle {
+    #[serde(with = "crate::utilities::rgba_serde")]
     pub color: image::Rgba<u8>,
     center: PrimitivePoint,
     width: u32,
     height: u32,
-    angle: u32, // In degrees
+    angle: u32, // In degrees, from 0 to 179
+    #[serde(default)]
+    square: bool, // Whether mutating keeps the width and height equal
+    #[serde(default)]
+    feather: u32, // Pixels the alpha fades out over at the edges
 }
 
 impl Rectangle {
+    ///
+    /// Create a rectangle centered on `center`, rotated clockwise by `angle` degrees
+    ///
+    /// Rectangles look the same every 180 degrees, so `angle` is reduced to 0 to 179
+    ///
+    pub fn new(center: PrimitivePoint, width: u32, height: u32, angle: u32) -> Rectangle {
+        Rectangle {
+            color: Rgba([0, 0, 0, 128]),
+            center,
+            width,
+            height,
+            angle: angle % 180,
+            square: false,
+            feather: 0,
+        }
+    }
+
+    ///
+    /// Returns this shape with its color set to `color`, for building shapes by hand
+    ///
+    pub fn with_color(mut self, color: Rgba<u8>) -> Self {
+        self.color = color;
+        self
+    }
+
     ///
     /// Determine if this rectangle is valid
     ///
     fn is_valid(&self) -> bool {
-        true
+        self.angle < 180
+    }
+
+    ///
+    /// Returns the top left corner before rotating, and the center it's rotated around
+    ///
+    /// `as_svg` places the rectangle at the same point, so the SVG and raster agree
+    ///
+    fn corner_and_center(&self) -> ((f64, f64), (f64, f64)) {
+        let min_x = (self.center.x - (self.width as i32 / 2)) as f64;
+        let min_y = (self.center.y - (self.height as i32 / 2)) as f64;
+
+        (
+            (min_x, min_y),
+            (
+                min_x + self.width as f64 / 2.0,
+                min_y + self.height as f64 / 2.0,
+            ),
+        )
+    }
+
+    /// Returns where the rectangle is rasterized, for `get_pixels` and `contains_pixel`
+    fn frame(&self) -> Frame {
+        let (_, center) = self.corner_and_center();
+        let (sin_a, cos_a, half_width, half_height) = match self.angle {
+            // A quarter turn swaps the sides, keeping the edges exact to match `axis_aligned_bounds`
+            90 => (0.0, 1.0, self.height as f64 / 2.0, self.width as f64 / 2.0),
+            angle => {
+                let (sin_a, cos_a) = radians(angle as f64).sin_cos();
+                (
+                    sin_a,
+                    cos_a,
+                    self.width as f64 / 2.0,
+                    self.height as f64 / 2.0,
+                )
+            }
+        };
+
+        Frame {
+            center,
+            sin_a,
+            cos_a,
+            half_width,
+            half_height,
+        }
+    }
+}
+
+/// A rectangle's center, rotation, and half of its sides, as it's rasterized
+struct Frame {
+    center: (f64, f64),
+    sin_a: f64,
+    cos_a: f64,
+    half_width: f64,
+    half_height: f64,
+}
+
+impl Frame {
+    /// Returns the columns and rows of the rotated rectangle's bounding box
+    fn pixel_ranges(&self) -> (Range<i32>, Range<i32>) {
+        let (center_x, center_y) = self.center;
+        // Half the size of the rotated rectangle's bounding box
+        let extent_x = self.half_width * self.cos_a.abs() + self.half_height * self.sin_a.abs();
+        let extent_y = self.half_width * self.sin_a.abs() + self.half_height * self.cos_a.abs();
+
+        (
+            (center_x - extent_x).floor() as i32..(center_x + extent_x).ceil() as i32,
+            (center_y - extent_y).floor() as i32..(center_y + extent_y).ceil() as i32,
+        )
+    }
+
+    /// Determine if the center of the pixel (`x`, `y`) is inside the rotated rectangle
+    fn contains_center_of(&self, x: i32, y: i32) -> bool {
+        let (un_rotated_x, un_rotated_y) = self.un_rotated_center_of(x, y);
+
+        (-self.half_width..self.half_width).contains(&un_rotated_x)
+            && (-self.half_height..self.half_height).contains(&un_rotated_y)
+    }
+
+    /// Returns how far the center of the pixel (`x`, `y`) is inside the nearest edge
+    fn edge_distance(&self, x: i32, y: i32) -> f64 {
+        let (un_rotated_x, un_rotated_y) = self.un_rotated_center_of(x, y);
+
+        (self.half_width - un_rotated_x.abs()).min(self.half_height - un_rotated_y.abs())
+    }
+
+    /// Rotate the pixel's center back, to compare against the un-rotated rectangle
+    fn un_rotated_center_of(&self, x: i32, y: i32) -> (f64, f64) {
+        let dx = x as f64 + 0.5 - self.center.0;
+        let dy = y as f64 + 0.5 - self.center.1;
+
+        (
+            dx * self.cos_a + dy * self.sin_a,
+            -dx * self.sin_a + dy * self.cos_a,
+        )
     }
 }
 
 impl RandomShape for Rectangle {
     ///
-    /// Generate a random Triangle within the bounds given
-    /// `border_extension` is the maximum distance outside of the border a triangle is allowed to go
-    ///     It must be >= 1
+    /// Generate a random Rectangle around `center`
     ///
-    fn random(width: u32, height: u32, _border_extension: i32, rng: &mut impl Rng) -> Self {
-        let center = PrimitivePoint::random_point(width, height, rng);
-        let width = rng.gen_range(5..max(width, height) / 2);
-        let height = rng.gen_range(5..max(width, height) / 2);
+    fn random_at(
+        center: PrimitivePoint,
+        width: u32,
+        height: u32,
+        _border_extension: i32,
+        size_limits: SizeLimits,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let (min_side, max_side) =
+            size_limits.pixel_range(width, height, 5, max(max(width, height) / 2, 6) - 1);
+        let rect_width = rng.gen_range(min_side..=max_side);
+        let rect_height = rng.gen_range(min_side..=max_side);
         let angle = rng.gen_range(0..180);
 
-        let mut rect = Rectangle {
+        Rectangle {
             center,
-            width,
-            height,
+            width: rect_width,
+            height: rect_height,
             angle,
             color: Rgba([0, 0, 0, 128]),
-        };
-        rect.mutate(width, height, rng);
-
-        rect
+            square: false,
+            feather: 0,
+        }
     }
 }
 
 impl Shape for Rectangle {
-    fn mutate(&mut self, width: u32, height: u32, rng: &mut impl Rng) {
-        let normal = Normal::new(0.0, 16.0).unwrap();
+    fn mutate(
+        &mut self,
+        width: u32,
+        height: u32,
+        size_limits: SizeLimits,
+        mode: MutateMode,
+        rng: &mut impl Rng,
+    ) {
+        let normal = Normal::new(0.0, 16.0 * mode.step_scale()).unwrap();
+        let (min_side, max_side) = size_limits.pixel_range(width, height, 5, max(width, height));
 
         let mut i = 0;
         loop {
             i += 1;
-            let r = rng.gen_range(0..4);
-
-            match r {
-                0 => self.center.mutate(width, height, rng),
-                1 => {
-                    self.width = clamp(
-                        self.width as i32 + (rng.sample(normal) as i32),
-                        5,
-                        max(width, height) as i32,
-                    ) as u32
+            for r in mode.parameters(4, rng) {
+                match r {
+                    0 => self.center.mutate(width, height, mode, rng),
+                    // Squares only have one side to change
+                    1 | 2 if self.square => {
+                        self.width = clamp(
+                            self.width as i32 + (rng.sample(normal) as i32),
+                            min_side as i32,
+                            max_side as i32,
+                        ) as u32;
+                        self.height = self.width;
+                    }
+                    1 => {
+                        self.width = clamp(
+                            self.width as i32 + (rng.sample(normal) as i32),
+                            min_side as i32,
+                            max_side as i32,
+                        ) as u32
+                    }
+                    2 => {
+                        self.height = clamp(
+                            self.height as i32 + (rng.sample(normal) as i32),
+                            min_side as i32,
+                            max_side as i32,
+                        ) as u32
+                    }
+                    3 => self.angle = rng.gen_range(0..180),
+                    _ => {}
                 }
-                2 => {
-                    self.height = clamp(
-                        self.height as i32 + (rng.sample(normal) as i32),
-                        5,
-                        max(width, height) as i32,
-                    ) as u32
-                }
-                3 => self.angle = rng.gen_range(0..180),
-                _ => {}
             }
 
             if self.is_valid() {
@@ -92,24 +244,34 @@ impl Shape for Rectangle {
         }
     }
 
+    fn area(&self) -> f64 {
+        self.width as f64 * self.height as f64
+    }
+
+    fn contains_pixel(&self, x: i32, y: i32) -> bool {
+        let frame = self.frame();
+        let (x_range, y_range) = frame.pixel_ranges();
+
+        x_range.contains(&x) && y_range.contains(&y) && frame.contains_center_of(x, y)
+    }
+
+    ///
+    /// Returns the pixels whose centers are inside the rotated rectangle
+    ///
     fn get_pixels(&self) -> Vec<PrimitivePoint> {
-        let min_x = self.center.x - (self.width as i32 / 2);
-        let min_y = self.center.y - (self.height as i32 / 2);
-        let max_x = self.center.x + (self.width as i32 / 2);
-        let max_y = self.center.y + (self.height as i32 / 2);
+        let frame = self.frame();
+        let (x_range, y_range) = frame.pixel_ranges();
 
         let mut pixels = vec![];
 
-        for x in min_x..(max_x + 1) {
-            for y in min_y..(max_y + 1) {
-                pixels.push(PrimitivePoint::new(x, y));
+        for x in x_range {
+            for y in y_range.clone() {
+                if frame.contains_center_of(x, y) {
+                    pixels.push(PrimitivePoint::new(x, y));
+                }
             }
         }
 
-        for pixel in pixels.iter_mut() {
-            rotate_point(pixel, self.center, self.angle);
-        }
-
         pixels
     }
 
@@ -124,36 +286,80 @@ impl Shape for Rectangle {
 
         let p1 = PrimitivePoint::new(min_x, min_y);
 
-        format!("<rect fill=\"{}\" fill-opacity=\"{:.5}\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" transform=\"rotate({} {} {})\"/>",
+        format!("<rect fill=\"{}\" fill-opacity=\"{:.5}\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" transform=\"rotate({} {} {})\"{}/>",
                 rgb_to_hex(self.color),
                 self.color.0[3] as f64 / 255.0,
                 p1.x, p1.y,
                 self.width as f64 * scale, self.height as f64 * scale,
-                self.angle, p1.x as f64 + self.width as f64 * scale / 2.0, p1.y as f64 + self.height as f64 * scale / 2.0)
+                self.angle, p1.x as f64 + self.width as f64 * scale / 2.0, p1.y as f64 + self.height as f64 * scale / 2.0,
+                feather_filter_attribute(self.feather))
+    }
+
+    fn as_svg_path_data(&self, scale: f64) -> Option<String> {
+        // A path can't be blurred on its own once it's merged with others
+        if self.feather > 0 {
+            return None;
+        }
+
+        // Match `as_svg`: the corners of the scaled rectangle, rotated around its center
+        let min_x = ((self.center.x as f64 * scale) as i32
+            - ((self.width as f64 * scale) as i32 / 2)) as f64;
+        let min_y = ((self.center.y as f64 * scale) as i32
+            - ((self.height as f64 * scale) as i32 / 2)) as f64;
+        let width = self.width as f64 * scale;
+        let height = self.height as f64 * scale;
+        let center_x = min_x + width / 2.0;
+        let center_y = min_y + height / 2.0;
+
+        let cos_a = radians(self.angle as f64).cos();
+        let sin_a = radians(self.angle as f64).sin();
+
+        let corners = [
+            (min_x, min_y),
+            (min_x + width, min_y),
+            (min_x + width, min_y + height),
+            (min_x, min_y + height),
+        ]
+        .iter()
+        .map(|(x, y)| {
+            let dx = x - center_x;
+            let dy = y - center_y;
+            format!(
+                "{:.2},{:.2}",
+                center_x + dx * cos_a - dy * sin_a,
+                center_y + dx * sin_a + dy * cos_a
+            )
+        })
+        .collect::<Vec<_>>();
+
+        Some(format!(
+            "M{} L{} L{} L{} Z",
+            corners[0], corners[1], corners[2], corners[3]
+        ))
     }
 
-    fn paint_on(&self, image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    fn paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
         let (width, height) = image.dimensions();
-        let mut output = image.clone();
 
         let pixels = self.get_pixels();
+        let frame = self.frame();
 
         for pixel in pixels.iter() {
             if pixel.x > 0 && pixel.y > 0 && pixel.x < width as i32 && pixel.y < height as i32 {
-                let pix = output.get_pixel_mut(pixel.x as u32, pixel.y as u32);
-                pix.blend(&self.color);
+                let color = match self.feather {
+                    0 => self.color,
+                    feather => {
+                        feathered(self.color, frame.edge_distance(pixel.x, pixel.y), feather)
+                    }
+                };
+                let pix = image.get_pixel_mut(pixel.x as u32, pixel.y as u32);
+                pix.blend(&color);
             }
         }
-
-        output
     }
 
     //noinspection RsTypeCheck
-    fn scaled_paint_on(
-        &self,
-        image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-        scale: f64,
-    ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    fn scaled_paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, scale: f64) {
         let scaled_self = Rectangle {
             center: PrimitivePoint::new(
                 (self.center.x as f64 * scale) as i32,
@@ -163,19 +369,86 @@ impl Shape for Rectangle {
             height: (self.height as f64 * scale) as u32,
             color: self.color,
             angle: self.angle,
+            square: self.square,
+            feather: (self.feather as f64 * scale).round() as u32,
         };
 
-        scaled_self.paint_on(image)
+        scaled_self.paint_into(image);
     }
 
     fn set_color_using(&mut self, image: &PrimitiveImage) {
-        self.color = image.target_average_color_in_shape(self);
+        self.color = image.color_for_shape(self);
+    }
+
+    fn get_color(&self) -> Rgba<u8> {
+        self.color
+    }
+
+    fn axis_aligned_bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        // A quarter turn swaps the sides
+        let (half_width, half_height) = match self.angle {
+            0 => (self.width as f64 / 2.0, self.height as f64 / 2.0),
+            90 => (self.height as f64 / 2.0, self.width as f64 / 2.0),
+            _ => return None,
+        };
+        let (_, (center_x, center_y)) = self.corner_and_center();
+
+        // Must match get_pixels: the pixels whose centers are inside
+        Some((
+            (center_x - half_width - 0.5).ceil() as i32,
+            (center_y - half_height - 0.5).ceil() as i32,
+            (center_x + half_width - 0.5).ceil() as i32 - 1,
+            (center_y + half_height - 0.5).ceil() as i32 - 1,
+        ))
+    }
+
+    fn set_square(&mut self, square: bool) {
+        self.square = square;
+        if square {
+            self.height = self.width;
+        }
+    }
+
+    fn set_feather(&mut self, feather: u32) {
+        self.feather = feather;
+    }
+
+    fn feather(&self) -> u32 {
+        self.feather
+    }
+
+    fn clear_rotation(&mut self) {
+        self.angle = 0;
+    }
+
+    fn mirror(&self, symmetry: Symmetry, width: u32, height: u32) -> Option<Box<dyn Shape>> {
+        let mut mirrored = *self;
+        mirrored.center = self.center.mirrored(symmetry, width, height);
+        // Reflecting negates the rotation, and rectangles look the same every 180 degrees
+        if symmetry != Symmetry::None {
+            mirrored.angle = (180 - self.angle % 180) % 180;
+        }
+        Some(Box::new(mirrored))
+    }
+
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::Rectangle
+    }
+
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap()
+    }
+
+    fn canonical_key(&self) -> u64 {
+        shape_key(self)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utilities::{get_rng, polygon_contains};
+    use rand::rngs::StdRng;
 
     #[test]
     fn test_get_pixels() {
@@ -186,6 +459,8 @@ mod tests {
             height: 5,
             angle: 0,
             color: Rgba([0, 0, 0, 0]),
+            square: false,
+            feather: 0,
         };
         let expected = vec![
             PrimitivePoint::new(-2, -2),
@@ -217,6 +492,107 @@ mod tests {
         assert_eq!(rect.get_pixels(), expected);
     }
 
+    #[test]
+    fn test_get_pixels_even_size() {
+        // Exactly as wide as the SVG, not one pixel more
+        let rect = Rectangle::new(PrimitivePoint::new(10, 10), 6, 4, 0);
+        let pixels = rect.get_pixels();
+        assert_eq!(pixels.len(), 24);
+        assert!(pixels.contains(&PrimitivePoint::new(7, 8)));
+        assert!(pixels.contains(&PrimitivePoint::new(12, 11)));
+        assert!(!pixels.contains(&PrimitivePoint::new(13, 11)));
+    }
+
+    #[test]
+    fn test_axis_aligned_bounds() {
+        for rect in [
+            Rectangle::new(PrimitivePoint::new(10, 10), 6, 4, 0),
+            Rectangle::new(PrimitivePoint::new(10, 10), 5, 8, 90),
+            Rectangle::new(PrimitivePoint::new(-3, 7), 7, 7, 270),
+        ] {
+            let (min_x, min_y, max_x, max_y) = rect.axis_aligned_bounds().unwrap();
+            let pixels = rect.get_pixels();
+
+            assert_eq!(
+                pixels.len() as i32,
+                (max_x - min_x + 1) * (max_y - min_y + 1)
+            );
+            assert!(pixels
+                .iter()
+                .all(|p| p.x >= min_x && p.x <= max_x && p.y >= min_y && p.y <= max_y));
+        }
+
+        assert!(Rectangle::new(PrimitivePoint::new(10, 10), 5, 8, 45)
+            .axis_aligned_bounds()
+            .is_none());
+    }
+
+    #[test]
+    fn test_new_reduces_angle() {
+        assert_eq!(
+            Rectangle::new(PrimitivePoint::new(0, 0), 5, 5, 225).angle,
+            45
+        );
+        assert!(!Rectangle {
+            angle: 180,
+            ..Rectangle::new(PrimitivePoint::new(0, 0), 5, 5, 0)
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn test_rotated_svg_matches_pixels() {
+        let rect = Rectangle::new(PrimitivePoint::new(20, 20), 16, 6, 45);
+        let pixels = rect.get_pixels();
+
+        // The SVG path's corners, which match the <rect>'s rotation
+        let path = rect.as_svg_path_data(1.0).unwrap();
+        let corners: Vec<(f64, f64)> = path
+            .split(['M', 'L', 'Z'])
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| {
+                let (x, y) = s.trim().split_once(',').unwrap();
+                (x.parse().unwrap(), y.parse().unwrap())
+            })
+            .collect();
+
+        // Sample the center, along both axes, and beyond each end
+        let (sin_a, cos_a) = radians(45.0).sin_cos();
+        for (along, across, inside) in [
+            (0.0, 0.0, true),
+            (6.5, 0.0, true),
+            (-6.5, 0.0, true),
+            (0.0, 2.0, true),
+            (0.0, -2.0, true),
+            (10.0, 0.0, false),
+            (-10.0, 0.0, false),
+            (0.0, 4.5, false),
+            (5.0, -4.5, false),
+        ] {
+            let x = 21.0 + along * cos_a - across * sin_a;
+            let y = 21.0 + along * sin_a + across * cos_a;
+
+            let pixel = PrimitivePoint::new(x.floor() as i32, y.floor() as i32);
+            assert_eq!(pixels.contains(&pixel), inside, "{:?}", pixel);
+            assert_eq!(
+                polygon_contains(&corners, pixel.x as f64 + 0.5, pixel.y as f64 + 0.5),
+                inside,
+                "{:?}",
+                pixel
+            );
+        }
+
+        // And every pixel
+        for x in 0..40 {
+            for y in 0..40 {
+                assert_eq!(
+                    pixels.contains(&PrimitivePoint::new(x, y)),
+                    polygon_contains(&corners, x as f64 + 0.5, y as f64 + 0.5)
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_as_svg() {
         let center = PrimitivePoint::new(0, 0);
@@ -226,6 +602,8 @@ mod tests {
             height: 5,
             angle: 0,
             color: Rgba([0, 0, 0, 128]),
+            square: false,
+            feather: 0,
         };
         let expected = "<rect fill=\"#000000\" fill-opacity=\"0.50196\" x=\"-2\" y=\"-2\" width=\"5\" height=\"5\" transform=\"rotate(0 0.5 0.5)\"/>";
         assert_eq!(rect.as_svg(1.0).as_str(), expected);
@@ -237,8 +615,92 @@ mod tests {
             height: 2,
             angle: 45,
             color: Rgba([128, 15, 240, 128]),
+            square: false,
+            feather: 0,
         };
         let expected = "<rect fill=\"#800FF0\" fill-opacity=\"0.50196\" x=\"0\" y=\"0\" width=\"2\" height=\"2\" transform=\"rotate(45 1 1)\"/>";
         assert_eq!(rect.as_svg(1.0).as_str(), expected);
     }
+
+    #[test]
+    fn test_square_mutate() {
+        let mut rng = get_rng(3);
+        let mut rect = Rectangle::random(100, 100, 1, SizeLimits::default(), &mut rng);
+        rect.set_square(true);
+        assert_eq!(rect.width, rect.height);
+
+        for mode in [MutateMode::Single, MutateMode::Multi] {
+            for _ in 0..50 {
+                rect.mutate(100, 100, SizeLimits::default(), mode, &mut rng);
+                assert_eq!(rect.width, rect.height);
+            }
+        }
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let mut rng = get_rng(42);
+        let limits = SizeLimits::new(Some(0.1), Some(0.2));
+
+        // 10% to 20% of 200 pixels
+        for _ in 0..20 {
+            let mut rect = Rectangle::random(200, 100, 6, limits, &mut rng);
+            for _ in 0..20 {
+                assert!((20..=40).contains(&rect.width));
+                assert!((20..=40).contains(&rect.height));
+                rect.mutate(200, 100, limits, MutateMode::Single, &mut rng);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mutate_mode() {
+        let mut rng = get_rng(42);
+        let changed_fields = |mode: MutateMode, rng: &mut StdRng| {
+            let mut changed = 0;
+            for _ in 0..200 {
+                let mut rect = Rectangle::new(PrimitivePoint::new(100, 50), 40, 30, 45);
+                let before = rect;
+                rect.mutate(200, 100, SizeLimits::default(), mode, rng);
+
+                changed += [
+                    rect.center != before.center,
+                    rect.width != before.width,
+                    rect.height != before.height,
+                    rect.angle != before.angle,
+                ]
+                .iter()
+                .filter(|&&c| c)
+                .count();
+            }
+            changed as f64 / 200.0
+        };
+
+        assert!(changed_fields(MutateMode::Single, &mut rng) <= 1.0);
+        assert!(changed_fields(MutateMode::Multi, &mut rng) > 1.0);
+    }
+
+    #[test]
+    fn test_area() {
+        for angle in [0, 30, 90] {
+            let rect = Rectangle::new(PrimitivePoint::new(50, 50), 40, 30, angle);
+            assert_eq!(rect.area(), 1200.0);
+
+            let pixels = rect.get_pixels().len() as f64;
+            assert!((rect.area() - pixels).abs() / pixels < 0.05);
+        }
+    }
+
+    #[test]
+    fn test_feather() {
+        let mut rect = Rectangle::new(PrimitivePoint::new(20, 20), 20, 12, 0)
+            .with_color(Rgba([255, 0, 0, 200]));
+        rect.set_feather(4);
+        let painted = rect.paint_on(&ImageBuffer::from_pixel(40, 40, Rgba([0, 0, 0, 0])));
+
+        // The alpha ramps up with the distance from the top edge to each pixel's center
+        assert_eq!(painted.get_pixel(20, 14)[3], 25);
+        assert_eq!(painted.get_pixel(20, 15)[3], 75);
+        assert_eq!(painted.get_pixel(20, 20)[3], 200);
+    }
 }
@@ -0,0 +1,102 @@
+use crate::primitive_image::PrimitiveImage;
+use crate::runner::{similarity, RunRecord};
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::time::Duration;
+
+///
+/// Metrics from a run, saved as JSON for dashboards and tracking regressions
+///
+/// Scores are RMSE, from 0 to 255. `score_deltas` is how much each added shape lowered the score,
+/// in the order they were added, and `efficiencies` is that per pixel of each shape's area
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunStats {
+    pub seed: u64,
+    pub num_shapes: usize,
+    pub initial_score: f64,
+    pub final_score: f64,
+    pub similarity: f64,
+    pub score_deltas: Vec<f64>,
+    pub efficiencies: Vec<f64>,
+    pub elapsed_seconds: f64,
+    pub attempts: u32,
+}
+
+impl RunStats {
+    ///
+    /// Collect the metrics for a run that produced `image`
+    ///
+    /// The initial score is the background's alone, so it doesn't depend on appended or seeded
+    /// shapes
+    ///
+    pub fn new(image: &PrimitiveImage, seed: u64, record: &RunRecord, elapsed: Duration) -> Self {
+        let final_score = image.score();
+
+        RunStats {
+            seed,
+            num_shapes: image.color_stats().num_shapes,
+            initial_score: image.initial_score(),
+            final_score,
+            similarity: similarity(final_score),
+            score_deltas: record.score_deltas.clone(),
+            efficiencies: record.efficiencies.clone(),
+            elapsed_seconds: elapsed.as_secs_f64(),
+            attempts: record.attempts,
+        }
+    }
+
+    /// Save the metrics as a JSON object
+    pub fn save_json(&self, path: PathBuf) {
+        let file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(path);
+
+        match file {
+            Ok(file) => serde_json::to_writer_pretty(file, self).unwrap(),
+            Err(err) => panic!("{}", err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{run_with_progress, DEFAULT_MAX_FAILED_ATTEMPTS};
+    use crate::shape::ShapeType;
+    use crate::utilities::{make_test_image, TestPattern};
+    use serde_json::Value;
+
+    #[test]
+    fn test_save_json() {
+        let target = make_test_image(20, 20, TestPattern::Gradient);
+        let mut image = PrimitiveImage::from_image(target, 0, None);
+        let record = run_with_progress(
+            &mut image,
+            3,
+            10,
+            42,
+            ShapeType::Triangle,
+            DEFAULT_MAX_FAILED_ATTEMPTS,
+            None,
+            None,
+        );
+        let stats = RunStats::new(&image, 42, &record, Duration::from_millis(1500));
+
+        let path = std::env::temp_dir().join("primitive_image_test_stats.json");
+        stats.save_json(path.clone());
+        let json: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(json["final_score"].as_f64(), Some(image.score()));
+        assert_eq!(json["seed"].as_u64(), Some(42));
+        assert_eq!(json["num_shapes"].as_u64(), Some(3));
+        assert_eq!(json["elapsed_seconds"].as_f64(), Some(1.5));
+        assert_eq!(json["score_deltas"].as_array().unwrap().len(), 3);
+        assert_eq!(json["efficiencies"].as_array().unwrap().len(), 3);
+        assert!(json["initial_score"].as_f64().unwrap() > stats.final_score);
+    }
+}
@@ -1,43 +1,403 @@
-use crate::cubic_bezier::CubicBezier;
-use crate::ellipse::Ellipse;
 use crate::primitive_image::PrimitiveImage;
-use crate::quadratic_bezier::QuadraticBezier;
-use crate::rectangle::Rectangle;
-use crate::triangle::Triangle;
+use crate::shape::ShapeType;
 use crate::utilities::get_rng;
+use indicatif::ProgressBar;
+use rand::distributions::WeightedIndex;
+use rand::rngs::StdRng;
 use rand::Rng;
+use serde::Serialize;
+use std::io::Write;
 
-pub fn run(image: &mut PrimitiveImage, number_of_shapes: u32, max_age: u32, seed: u64, s: String) {
+///
+/// Everything needed to add shapes one step at a time
+///
+/// Keeps the random number generator between steps, so a run driven by `PrimitiveImage::step`
+/// is as repeatable as one using `run`
+///
+pub struct RunConfig {
+    pub max_age: u32,
+    pub shape: ShapeType,
+    pub rng: StdRng,
+}
+
+impl RunConfig {
+    pub fn new(max_age: u32, seed: u64, shape: ShapeType) -> RunConfig {
+        RunConfig {
+            max_age,
+            shape,
+            rng: get_rng(seed),
+        }
+    }
+}
+
+/// The outcome of a single `PrimitiveImage::step`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StepResult {
+    /// Whether a shape was added
+    pub added: bool,
+    /// The approximation's score after the step
+    pub score: f64,
+    /// The added shape's score improvement per pixel of its area, if one was added
+    pub efficiency: Option<f64>,
+    /// The added shape's type, as --shape names it, if one was added
+    pub shape_type: Option<&'static str>,
+}
+
+///
+/// A line `run_with_progress` writes for each added shape, so other programs can follow a run
+/// without parsing the logs
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShapeEvent {
+    /// How many shapes have been added, including this one
+    pub shape: u32,
+    /// The approximation's score after adding it
+    pub score: f64,
+    #[serde(rename = "type")]
+    pub shape_type: &'static str,
+}
+
+/// What happened during `run_with_progress`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RunRecord {
+    /// The number of shapes added
+    pub added: u32,
+    /// Steps taken, including the ones that failed to add a shape
+    pub attempts: u32,
+    /// How much each added shape lowered the score, in the order they were added
+    pub score_deltas: Vec<f64>,
+    /// How much each added shape lowered the score per pixel of its area
+    pub efficiencies: Vec<f64>,
+}
+
+/// Consecutive failures to add a shape before `run` gives up
+pub const DEFAULT_MAX_FAILED_ATTEMPTS: u32 = 1000;
+/// Failures in a row with one type of a mix before the next shape has to be another type
+const MIX_FAILURE_STREAK: u32 = 3;
+
+///
+/// How often each type in a mix has been added, so `run` can favor the types that are currently
+/// helping
+///
+/// Types are picked with weights from their success rates, smoothed so untried types still get
+/// picked. After `MIX_FAILURE_STREAK` failures in a row with one type, the next pick is another
+///
+pub struct MixStats {
+    pub shape_types: Vec<ShapeType>,
+    attempts: Vec<u32>,
+    successes: Vec<u32>,
+    // The last type tried, and how many times in a row it's failed
+    streak: Option<(usize, u32)>,
+}
+
+impl MixStats {
+    pub fn new(shape_types: Vec<ShapeType>) -> MixStats {
+        if shape_types.is_empty() {
+            panic!("A mix needs at least one shape type");
+        }
+
+        MixStats {
+            attempts: vec![0; shape_types.len()],
+            successes: vec![0; shape_types.len()],
+            shape_types,
+            streak: None,
+        }
+    }
+
+    /// Returns the fraction of tries that added a shape of the type at `index`, smoothed towards 1/2
+    pub fn success_rate(&self, index: usize) -> f64 {
+        (self.successes[index] as f64 + 1.0) / (self.attempts[index] as f64 + 2.0)
+    }
+
+    ///
+    /// Returns the index of the type to try next
+    ///
+    pub fn pick(&self, rng: &mut impl Rng) -> usize {
+        let weights: Vec<f64> = (0..self.shape_types.len())
+            .map(|index| match self.streak {
+                Some((failing, failures))
+                    if failing == index
+                        && failures >= MIX_FAILURE_STREAK
+                        && self.shape_types.len() > 1 =>
+                {
+                    0.0
+                }
+                _ => self.success_rate(index),
+            })
+            .collect();
+
+        rng.sample(WeightedIndex::new(weights).unwrap())
+    }
+
+    /// Record whether trying the type at `index` added a shape
+    pub fn record(&mut self, index: usize, added: bool) {
+        self.attempts[index] += 1;
+
+        if added {
+            self.successes[index] += 1;
+            self.streak = None;
+        } else {
+            self.streak = match self.streak {
+                Some((failing, failures)) if failing == index => Some((index, failures + 1)),
+                _ => Some((index, 1)),
+            };
+        }
+    }
+}
+
+///
+/// Add `number_of_shapes` shapes to `image`, unless `DEFAULT_MAX_FAILED_ATTEMPTS` steps in a row
+/// fail to add one
+///
+/// Returns the number of shapes added
+///
+pub fn run(
+    image: &mut PrimitiveImage,
+    number_of_shapes: u32,
+    max_age: u32,
+    seed: u64,
+    shape: ShapeType,
+) -> u32 {
+    run_with_progress(
+        image,
+        number_of_shapes,
+        max_age,
+        seed,
+        shape,
+        DEFAULT_MAX_FAILED_ATTEMPTS,
+        None,
+        None,
+    )
+    .added
+}
+
+///
+/// Same as `run`, but gives up after `max_failed_attempts` consecutive failures, and advances
+/// `progress` (if there is one) each time a shape is added
+///
+/// The bar's message is the approximation's similarity to the target, as a percentage
+/// Progress is only reported, so the shapes found are the same with or without a bar
+/// For a mix, each shape's type is picked using `MixStats`, favoring types that have been added
+/// Shapes are added in batches of the image's commit batch (see `PrimitiveImage::step_batch`),
+/// and an attempt only fails if a whole batch adds nothing
+/// If there's an `events` writer, a `ShapeEvent` is written to it as a line of JSON for each shape
+/// Returns the shapes added and the attempts it took, as a `RunRecord`
+///
+#[allow(clippy::too_many_arguments)]
+pub fn run_with_progress(
+    image: &mut PrimitiveImage,
+    number_of_shapes: u32,
+    max_age: u32,
+    seed: u64,
+    shape: ShapeType,
+    max_failed_attempts: u32,
+    progress: Option<&ProgressBar>,
+    mut events: Option<&mut dyn Write>,
+) -> RunRecord {
+    let mut record = RunRecord::default();
     let mut n = 0;
+    let mut failed_attempts = 0;
+    let mut score = image.score();
 
-    let mut rng = get_rng(seed);
+    let mut mix = match &shape {
+        ShapeType::Mixed(shape_types) => Some(MixStats::new(shape_types.clone())),
+        _ => None,
+    };
+    let mut config = RunConfig::new(max_age, seed, shape);
 
     while n < number_of_shapes {
-        let res = match s.as_ref() {
-            "TRIANGLE" => image.add_new_shape::<Triangle>(max_age, &mut rng),
-            "QUADRATIC" => image.add_new_shape::<QuadraticBezier>(max_age, &mut rng),
-            "CUBIC" => image.add_new_shape::<CubicBezier>(max_age, &mut rng),
-            "RECTANGLE" => image.add_new_shape::<Rectangle>(max_age, &mut rng),
-            "ELLIPSE" => image.add_new_shape::<Ellipse>(max_age, &mut rng),
-            "MIXED" => {
-                let r = rng.gen_range(0..5);
-                match r {
-                    0 => image.add_new_shape::<Triangle>(max_age, &mut rng),
-                    1 => image.add_new_shape::<QuadraticBezier>(max_age, &mut rng),
-                    2 => image.add_new_shape::<CubicBezier>(max_age, &mut rng),
-                    3 => image.add_new_shape::<Rectangle>(max_age, &mut rng),
-                    4 => image.add_new_shape::<Ellipse>(max_age, &mut rng),
-                    _ => panic!("This should never be reached if the range is set properly!"),
-                }
-            }
-            _ => panic!("Unsupported shape: {}", s),
-        };
+        let picked = mix.as_ref().map(|mix| {
+            let index = mix.pick(&mut config.rng);
+            config.shape = mix.shape_types[index].clone();
+            index
+        });
+
+        let results = image.step_batch(&mut config, number_of_shapes - n);
+        record.attempts += 1;
+
+        if let (Some(mix), Some(index)) = (&mut mix, picked) {
+            mix.record(index, !results.is_empty());
+        }
 
-        if res {
+        for res in results.iter() {
             n += 1;
+            failed_attempts = 0;
+            record.score_deltas.push(score - res.score);
+            record.efficiencies.extend(res.efficiency);
+            score = res.score;
             info!("Added #{}", n);
-        } else {
+
+            if let (Some(events), Some(shape_type)) = (&mut events, res.shape_type) {
+                let event = ShapeEvent {
+                    shape: n,
+                    score: res.score,
+                    shape_type,
+                };
+                serde_json::to_writer(&mut *events, &event).expect("Could not write an event");
+                writeln!(events).expect("Could not write an event");
+            }
+
+            if let Some(progress) = progress {
+                progress.set_message(format!("{:.2}% similar", similarity(res.score)));
+                progress.inc(1);
+            }
+        }
+
+        if results.is_empty() {
             trace!("Failed to add shape (#{})", (n + 1));
+
+            failed_attempts += 1;
+            if failed_attempts >= max_failed_attempts {
+                info!(
+                    "Giving up after {} failed attempts in a row, with {} of {} shapes added",
+                    failed_attempts, n, number_of_shapes
+                );
+                break;
+            }
         }
     }
+
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+
+    record.added = n;
+    record
+}
+
+/// Convert a score (RMSE, from 0 to 255) to a percentage, where 100% is a perfect match
+pub fn similarity(score: f64) -> f64 {
+    100.0 * (1.0 - score / 255.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity(0.0), 100.0);
+        assert_eq!(similarity(255.0), 0.0);
+    }
+
+    #[test]
+    fn test_run_with_progress() {
+        let target = ImageBuffer::from_fn(20, 20, |x, y| {
+            Rgba([(x * 12) as u8, (y * 12) as u8, 128, 255])
+        });
+        let mut image = PrimitiveImage::from_image(target.clone(), 0, None);
+        let progress = ProgressBar::hidden();
+        let initial_score = image.score();
+        let record = run_with_progress(
+            &mut image,
+            3,
+            10,
+            42,
+            ShapeType::Triangle,
+            DEFAULT_MAX_FAILED_ATTEMPTS,
+            Some(&progress),
+            None,
+        );
+        assert_eq!(record.added, 3);
+        assert!(record.attempts >= 3);
+        assert_eq!(record.score_deltas.len(), 3);
+        assert!(record.score_deltas.iter().all(|&delta| delta > 0.0));
+        assert_eq!(record.efficiencies.len(), 3);
+        let total: f64 = record.score_deltas.iter().sum();
+        assert!((initial_score - total - image.score()).abs() < 1e-9);
+        assert_eq!(progress.position(), 3);
+        assert!(progress.is_finished());
+
+        // The bar doesn't change which shapes are found
+        let mut without_bar = PrimitiveImage::from_image(target, 0, None);
+        run(&mut without_bar, 3, 10, 42, ShapeType::Triangle);
+        assert_eq!(without_bar.score(), image.score());
+    }
+
+    #[test]
+    fn test_events() {
+        let target = ImageBuffer::from_fn(20, 20, |x, y| {
+            Rgba([(x * 12) as u8, (y * 12) as u8, 128, 255])
+        });
+        let mut image = PrimitiveImage::from_image(target, 0, None);
+        let mut events = vec![];
+        let record = run_with_progress(
+            &mut image,
+            4,
+            10,
+            42,
+            "MIXED".parse().unwrap(),
+            DEFAULT_MAX_FAILED_ATTEMPTS,
+            None,
+            Some(&mut events),
+        );
+        assert_eq!(record.added, 4);
+
+        // One line per shape, numbered in order, ending with the final score
+        let lines: Vec<serde_json::Value> = String::from_utf8(events)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(line["shape"], i + 1);
+            let shape_type = line["type"].as_str().unwrap();
+            assert!(ShapeType::all().iter().any(|t| t.name() == shape_type));
+        }
+        assert_eq!(lines[3]["score"], image.score());
+    }
+
+    #[test]
+    fn test_mix_stats() {
+        let mut mix = MixStats::new(vec![
+            ShapeType::Triangle,
+            ShapeType::Rectangle,
+            ShapeType::Ellipse,
+        ]);
+        let mut rng = get_rng(42);
+
+        // Only rectangles help
+        let mut rectangles = 0;
+        for _ in 0..300 {
+            let index = mix.pick(&mut rng);
+            let added = mix.shape_types[index] == ShapeType::Rectangle;
+            mix.record(index, added);
+
+            if added {
+                rectangles += 1;
+            }
+        }
+
+        assert!(
+            rectangles > 240,
+            "Only {} of 300 were rectangles",
+            rectangles
+        );
+        assert!(mix.success_rate(1) > mix.success_rate(0));
+    }
+
+    #[test]
+    fn test_mix_failure_streak() {
+        let mut mix = MixStats::new(vec![ShapeType::Triangle, ShapeType::Rectangle]);
+        for _ in 0..MIX_FAILURE_STREAK {
+            mix.record(1, false);
+        }
+
+        let mut rng = get_rng(1);
+        assert!((0..20).all(|_| mix.pick(&mut rng) == 0));
+    }
+
+    #[test]
+    fn test_gives_up() {
+        // The approximation starts out perfect, so no shape can improve it
+        let target = ImageBuffer::from_pixel(20, 20, Rgba([100, 150, 200, 255]));
+        let mut image = PrimitiveImage::from_image(target, 0, Some(Rgba([100, 150, 200, 255])));
+
+        let record = run_with_progress(&mut image, 5, 5, 42, ShapeType::Triangle, 10, None, None);
+        assert_eq!(record.added, 0);
+        assert_eq!(record.attempts, 10);
+        assert_eq!(image.color_stats().num_shapes, 0);
+    }
 }
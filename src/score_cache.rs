@@ -0,0 +1,136 @@
+use std::collections::{HashMap, VecDeque};
+
+///
+/// A small least-recently-used cache of candidate scores, keyed by `Shape::canonical_key`
+///
+/// Hill climbing keeps going back to the best shape and mutating it again, so it often retries
+/// a shape it has already scored. Scores only stay valid while the approximation doesn't change,
+/// so each climb uses its own cache
+///
+pub struct ScoreCache {
+    capacity: usize,
+    // The raw score and the score including penalties, like `Climb`, and when they were last used
+    scores: HashMap<u64, ((f64, f64), u64)>,
+    // Keys with when they were used, from least to most recently. Using a key again pushes it
+    // again instead of searching for it, so entries whose time doesn't match `scores` are stale
+    order: VecDeque<(u64, u64)>,
+    clock: u64,
+    hits: u32,
+}
+
+impl ScoreCache {
+    pub fn new(capacity: usize) -> ScoreCache {
+        if capacity == 0 {
+            panic!("A score cache needs room for at least one score");
+        }
+
+        ScoreCache {
+            capacity,
+            scores: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(2 * capacity),
+            clock: 0,
+            hits: 0,
+        }
+    }
+
+    ///
+    /// Returns the scores cached for `key`, if any, and marks them as the most recently used
+    ///
+    pub fn get(&mut self, key: u64) -> Option<(f64, f64)> {
+        let (scores, _) = *self.scores.get(&key)?;
+
+        self.touch(key);
+        self.hits += 1;
+        Some(scores)
+    }
+
+    ///
+    /// Cache `scores` for `key`, dropping the least recently used scores if the cache is full
+    ///
+    pub fn insert(&mut self, key: u64, scores: (f64, f64)) {
+        if self.scores.insert(key, (scores, self.clock)).is_none()
+            && self.scores.len() > self.capacity
+        {
+            self.evict();
+        }
+        self.touch(key);
+    }
+
+    /// Returns how many lookups found a cached score
+    pub fn hits(&self) -> u32 {
+        self.hits
+    }
+
+    /// Mark `key` as the most recently used
+    fn touch(&mut self, key: u64) {
+        self.clock += 1;
+        if let Some((_, used)) = self.scores.get_mut(&key) {
+            *used = self.clock;
+        }
+        self.order.push_back((key, self.clock));
+
+        // Drop stale entries once they outnumber the live ones, so the order stays small
+        if self.order.len() > 2 * self.capacity {
+            let scores = &self.scores;
+            self.order
+                .retain(|(key, used)| scores.get(key).is_some_and(|&(_, last)| last == *used));
+        }
+    }
+
+    /// Remove the least recently used scores
+    fn evict(&mut self) {
+        while let Some((key, used)) = self.order.pop_front() {
+            if self.scores.get(&key).is_some_and(|&(_, last)| last == used) {
+                self.scores.remove(&key);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get() {
+        let mut cache = ScoreCache::new(2);
+        assert_eq!(cache.get(1), None);
+
+        cache.insert(1, (1.0, 1.5));
+        assert_eq!(cache.get(1), Some((1.0, 1.5)));
+        assert_eq!(cache.hits(), 1);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut cache = ScoreCache::new(2);
+        cache.insert(1, (1.0, 1.0));
+        cache.insert(2, (2.0, 2.0));
+
+        // Using 1 makes 2 the least recently used
+        cache.get(1);
+        cache.insert(3, (3.0, 3.0));
+
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(1), Some((1.0, 1.0)));
+        assert_eq!(cache.get(3), Some((3.0, 3.0)));
+    }
+
+    #[test]
+    fn test_repeated_use() {
+        let mut cache = ScoreCache::new(2);
+        cache.insert(1, (1.0, 1.0));
+        cache.insert(2, (2.0, 2.0));
+
+        // Using 1 over and over leaves stale entries behind, which shouldn't evict it
+        for _ in 0..10 {
+            cache.get(1);
+        }
+        cache.insert(3, (3.0, 3.0));
+
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(1), Some((1.0, 1.0)));
+        assert!(cache.order.len() <= 4);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::bezier_path::BezierPath;
+use crate::cross::Cross;
+use crate::cubic_bezier::CubicBezier;
+use crate::ellipse::Ellipse;
+use crate::heart::Heart;
+use crate::quadratic_bezier::QuadraticBezier;
+use crate::rectangle::Rectangle;
+use crate::shape::{Shape, ShapeType};
+use crate::teardrop::Teardrop;
+use crate::triangle::Triangle;
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+///
+/// Everything needed to re-render an approximation without its target image
+///
+/// `width` and `height` are the dimensions the shapes were generated at, and `scale` is the
+/// factor that was used to get there from the original image
+///
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShapeFile {
+    pub width: u32,
+    pub height: u32,
+    pub scale: f64,
+    #[serde(with = "crate::utilities::rgba_serde")]
+    pub background: Rgba<u8>,
+    pub shapes: Vec<ShapeEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShapeEntry {
+    #[serde(rename = "type")]
+    pub shape_type: ShapeType,
+    pub shape: Value,
+}
+
+impl ShapeEntry {
+    pub fn from_shape(shape: &dyn Shape) -> ShapeEntry {
+        ShapeEntry {
+            shape_type: shape.shape_type(),
+            shape: shape.to_json(),
+        }
+    }
+
+    pub fn to_shape(&self) -> Result<Box<dyn Shape>, serde_json::Error> {
+        let value = self.shape.clone();
+
+        Ok(match self.shape_type {
+            ShapeType::Triangle => Box::new(serde_json::from_value::<Triangle>(value)?),
+            ShapeType::CubicBezier => Box::new(serde_json::from_value::<CubicBezier>(value)?),
+            ShapeType::QuadraticBezier => {
+                Box::new(serde_json::from_value::<QuadraticBezier>(value)?)
+            }
+            ShapeType::Ellipse => Box::new(serde_json::from_value::<Ellipse>(value)?),
+            ShapeType::Rectangle => Box::new(serde_json::from_value::<Rectangle>(value)?),
+            ShapeType::Heart => Box::new(serde_json::from_value::<Heart>(value)?),
+            ShapeType::Teardrop => Box::new(serde_json::from_value::<Teardrop>(value)?),
+            ShapeType::BezierPath => Box::new(serde_json::from_value::<BezierPath>(value)?),
+            ShapeType::Cross => Box::new(serde_json::from_value::<Cross>(value)?),
+            ShapeType::Mixed(_) => {
+                return Err(serde::de::Error::custom("a shape can't have a mixed type"))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point::PrimitivePoint;
+
+    #[test]
+    fn test_round_trip() {
+        let tri = Triangle {
+            path: [
+                PrimitivePoint::new(20, 0),
+                PrimitivePoint::new(0, 40),
+                PrimitivePoint::new(30, 10),
+            ],
+            color: Rgba([240, 64, 15, 128]),
+        };
+
+        let entry = ShapeEntry::from_shape(&tri);
+        assert_eq!(entry.shape_type, ShapeType::Triangle);
+
+        let json = serde_json::to_string(&entry).unwrap();
+        let shape = serde_json::from_str::<ShapeEntry>(&json)
+            .unwrap()
+            .to_shape()
+            .unwrap();
+        assert_eq!(shape.as_svg(1.0), tri.as_svg(1.0));
+    }
+
+    #[test]
+    fn test_mismatched_type() {
+        let entry = ShapeEntry {
+            shape_type: ShapeType::Ellipse,
+            shape: serde_json::json!({"color": [0, 0, 0, 0], "path": []}),
+        };
+        assert!(entry.to_shape().is_err());
+    }
+}
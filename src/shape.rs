@@ -1,32 +1,241 @@
+use crate::mutate_mode::MutateMode;
 use crate::point::PrimitivePoint;
 use crate::primitive_image::PrimitiveImage;
+use crate::symmetry::Symmetry;
+use crate::utilities::{SegmentLimits, SizeLimits};
 use image::ImageBuffer;
 use image::Rgba;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
 use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::Write;
+use std::iter::once;
+use std::str::FromStr;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ShapeType {
     Triangle,
     CubicBezier,
     QuadraticBezier,
     Ellipse,
     Rectangle,
+    Heart,
+    Teardrop,
+    BezierPath,
+    Cross,
+    /// Each shape is one of these types, picked at random. Only used for choosing what to add,
+    /// every shape has one of the other types
+    Mixed(Vec<ShapeType>),
+}
+
+///
+/// Defines `ShapeType::all`, `ShapeType::name`, and `ShapeType::layer_id` from one table of the
+/// types that can be added, their --shape names, and their svg-layers group ids, in the order
+/// `all` returns them
+///
+/// The matches have no wildcard arm, so a new variant fails to compile until it's in the table
+///
+macro_rules! shape_types {
+    ($($variant:ident => $name:literal, $layer_id:literal,)*) => {
+        ///
+        /// Returns every type of shape that can be added, besides mixes
+        ///
+        pub fn all() -> Vec<ShapeType> {
+            vec![$(ShapeType::$variant),*]
+        }
+
+        /// Returns the name --shape takes for this type
+        pub fn name(&self) -> &'static str {
+            match self {
+                $(ShapeType::$variant => $name,)*
+                ShapeType::Mixed(_) => "MIXED",
+            }
+        }
+
+        /// Returns the id of the group svg-layers puts shapes of this type in
+        pub(crate) fn layer_id(&self) -> &'static str {
+            match self {
+                $(ShapeType::$variant => $layer_id,)*
+                ShapeType::Mixed(_) => panic!("Shapes always have a single type"),
+            }
+        }
+    };
+}
+
+impl ShapeType {
+    /// Whether shapes of this type are filled, rather than stroked like curves
+    pub fn is_filled(&self) -> bool {
+        !matches!(
+            self,
+            ShapeType::CubicBezier | ShapeType::QuadraticBezier | ShapeType::BezierPath
+        )
+    }
+
+    shape_types! {
+        Triangle => "TRIANGLE", "triangles",
+        Rectangle => "RECTANGLE", "rectangles",
+        Ellipse => "ELLIPSE", "ellipses",
+        Heart => "HEART", "hearts",
+        Teardrop => "TEARDROP", "teardrops",
+        Cross => "CROSS", "crosses",
+        QuadraticBezier => "QUADRATIC", "quadratic-curves",
+        CubicBezier => "CUBIC", "cubic-curves",
+        BezierPath => "PATH", "bezier-paths",
+    }
+
+    ///
+    /// Returns every name --shape takes, one per line, for --list-shapes
+    ///
+    pub fn list() -> String {
+        ShapeType::all()
+            .iter()
+            .map(ShapeType::name)
+            .chain(once(ShapeType::Mixed(vec![]).name()))
+            .map(|name| format!("{}\n", name))
+            .collect()
+    }
+}
+
+impl FromStr for ShapeType {
+    type Err = String;
+
+    ///
+    /// Parse a shape name as given to --shape
+    ///
+    /// MIXED is triangles, curves, rectangles, and ellipses
+    ///
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_uppercase();
+        if name == "MIXED" {
+            return Ok(ShapeType::Mixed(vec![
+                ShapeType::Triangle,
+                ShapeType::QuadraticBezier,
+                ShapeType::CubicBezier,
+                ShapeType::Rectangle,
+                ShapeType::Ellipse,
+            ]));
+        }
+
+        ShapeType::all()
+            .into_iter()
+            .find(|shape_type| shape_type.name() == name)
+            .ok_or_else(|| format!("Unsupported shape: {} (see --list-shapes)", s))
+    }
 }
 
 pub trait Shape: ShapeClone + Debug {
-    fn mutate(&mut self, width: u32, height: u32, rng: &mut impl Rng)
-    where
+    /// Randomly change the shape, keeping it valid and within `size_limits`. `mode` decides how
+    /// many of its parameters change
+    fn mutate(
+        &mut self,
+        width: u32,
+        height: u32,
+        size_limits: SizeLimits,
+        mode: MutateMode,
+        rng: &mut impl Rng,
+    ) where
         Self: Sized;
     fn get_pixels(&self) -> Vec<PrimitivePoint>;
+    /// Determine if the pixel (`x`, `y`) is one of `get_pixels`, without finding all of them.
+    /// Filled shapes override this to test it directly, curves search their pixels
+    fn contains_pixel(&self, x: i32, y: i32) -> bool {
+        self.get_pixels().contains(&PrimitivePoint::new(x, y))
+    }
+    /// Returns the shape's pixels that are on a `width` x `height` image
+    fn clipped_pixels(&self, width: u32, height: u32) -> Vec<PrimitivePoint> {
+        self.get_pixels()
+            .into_iter()
+            .filter(|p| p.x >= 0 && p.y >= 0 && p.x < width as i32 && p.y < height as i32)
+            .collect()
+    }
+    /// Returns the shape's area in pixels. Shapes with a formula for it override this, curves
+    /// count their pixels
+    fn area(&self) -> f64 {
+        self.get_pixels().len() as f64
+    }
     fn as_svg(&self, scale: f64) -> String;
-    fn paint_on(&self, image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>>;
+    /// Write the shape's SVG element to `writer`, so a document can be streamed instead of built
+    /// as one string
+    fn write_svg(&self, writer: &mut dyn Write, scale: f64) -> io::Result<()> {
+        writer.write_all(self.as_svg(scale).as_bytes())
+    }
+    /// Returns the outline of filled shapes as SVG path data, so they can be merged into one path
+    fn as_svg_path_data(&self, _scale: f64) -> Option<String> {
+        None
+    }
+    /// Blend the shape onto `image` in place
+    fn paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>);
+    /// Returns a copy of `image` with the shape painted on
+    fn paint_on(&self, image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        let mut output = image.clone();
+        self.paint_into(&mut output);
+
+        output
+    }
+    /// Blend the shape onto `image` in place, scaled by `scale` (for rendering at another size)
+    fn scaled_paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, scale: f64);
+    /// Returns a copy of `image` with the shape painted on, scaled by `scale`
     fn scaled_paint_on(
         &self,
         image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
         scale: f64,
-    ) -> ImageBuffer<Rgba<u8>, Vec<u8>>;
+    ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        let mut output = image.clone();
+        self.scaled_paint_into(&mut output, scale);
+
+        output
+    }
     fn set_color_using(&mut self, image: &PrimitiveImage);
+    fn get_color(&self) -> Rgba<u8>;
+    /// Set the width of stroked shapes, in pixels. Filled shapes ignore it
+    fn set_stroke_width(&mut self, _width: u32) {}
+    /// Set how many pixels the alpha of shapes that can be feathered (rectangles and ellipses) fades
+    /// out over at their edges. Other shapes ignore it
+    fn set_feather(&mut self, _feather: u32) {}
+    /// Returns how many pixels the shape's edges fade out over, 0 for hard edges
+    fn feather(&self) -> u32 {
+        0
+    }
+    /// Set how many line segments curves are rasterized with. Other shapes ignore it
+    fn set_segment_limits(&mut self, _segment_limits: SegmentLimits) {}
+    /// Keep the width and height of shapes that have both equal (rectangles). Other shapes ignore it
+    fn set_square(&mut self, _square: bool) {}
+    /// Move the vertices of shapes that have them (triangles) to where `snap` says, if the shape
+    /// stays valid. Other shapes ignore it
+    fn snap_vertices(&mut self, _snap: &mut dyn FnMut(PrimitivePoint) -> PrimitivePoint) {}
+    /// Set the rotation of shapes that can be rotated back to 0. Other shapes ignore it
+    fn clear_rotation(&mut self) {}
+    /// Returns a copy mirrored according to `symmetry` in a `width` x `height` image, if supported
+    fn mirror(&self, _symmetry: Symmetry, _width: u32, _height: u32) -> Option<Box<dyn Shape>> {
+        None
+    }
+    /// Returns the inclusive bounds (min x, min y, max x, max y) of the shape's pixels if they're
+    /// exactly an axis-aligned rectangle, so averages can use the target's integral image
+    fn axis_aligned_bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        None
+    }
+    fn shape_type(&self) -> ShapeType;
+    fn to_json(&self) -> Value;
+    /// Returns a key that's the same for shapes with the same parameters (including color), for
+    /// caching their scores. Usually `shape_key(self)`
+    fn canonical_key(&self) -> u64;
+}
+
+///
+/// Hashes `shape`'s type and fields into a key for `Shape::canonical_key`
+///
+/// This runs for every candidate in the hill climb, so it hashes the fields directly rather than
+/// going through `to_json`
+///
+pub fn shape_key<T: Shape + Hash>(shape: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    shape.shape_type().hash(&mut hasher);
+    shape.hash(&mut hasher);
+    hasher.finish()
 }
 
 pub trait ShapeClone {
@@ -49,5 +258,107 @@ impl Clone for Box<dyn Shape> {
 }
 
 pub trait RandomShape {
-    fn random(width: u32, height: u32, border_extension: i32, rng: &mut impl Rng) -> Self;
+    /// Generate a random shape somewhere in a `width` x `height` image
+    fn random(
+        width: u32,
+        height: u32,
+        border_extension: i32,
+        size_limits: SizeLimits,
+        rng: &mut impl Rng,
+    ) -> Self
+    where
+        Self: Shape + Sized,
+    {
+        let center = PrimitivePoint::random_point(width, height, rng);
+        let mut shape = Self::random_at(center, width, height, border_extension, size_limits, rng);
+        shape.mutate(width, height, size_limits, MutateMode::Single, rng);
+
+        shape
+    }
+    /// Generate a random valid shape around `center`, sized for a `width` x `height` image
+    fn random_at(
+        center: PrimitivePoint,
+        width: u32,
+        height: u32,
+        border_extension: i32,
+        size_limits: SizeLimits,
+        rng: &mut impl Rng,
+    ) -> Self;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ellipse::Ellipse;
+    use crate::heart::Heart;
+    use crate::rectangle::Rectangle;
+    use crate::teardrop::Teardrop;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(ShapeType::from_str("TRIANGLE"), Ok(ShapeType::Triangle));
+        assert_eq!(ShapeType::from_str("cubic"), Ok(ShapeType::CubicBezier));
+        assert!(ShapeType::from_str("SQUARE").is_err());
+
+        match ShapeType::from_str("MIXED") {
+            Ok(ShapeType::Mixed(shape_types)) => {
+                assert_eq!(shape_types.len(), 5);
+                assert!(shape_types.contains(&ShapeType::Ellipse));
+            }
+            other => panic!("Expected a mix, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_all() {
+        let all = ShapeType::all();
+        assert!(!all
+            .iter()
+            .any(|shape_type| matches!(shape_type, ShapeType::Mixed(_))));
+        for (i, shape_type) in all.iter().enumerate() {
+            assert!(!all[i + 1..].contains(shape_type));
+        }
+    }
+
+    #[test]
+    fn test_list() {
+        let list = ShapeType::list();
+        let names: Vec<&str> = list.lines().collect();
+
+        assert_eq!(names.len(), ShapeType::all().len() + 1);
+        for shape_type in ShapeType::all() {
+            assert_eq!(names.iter().filter(|&&n| n == shape_type.name()).count(), 1);
+            assert_eq!(ShapeType::from_str(shape_type.name()), Ok(shape_type));
+        }
+        assert_eq!(names.last(), Some(&"MIXED"));
+    }
+
+    #[test]
+    fn test_canonical_key() {
+        let rect = Rectangle::new(PrimitivePoint::new(10, 10), 8, 6, 30);
+        let mut recolored = rect;
+        recolored.color = Rgba([1, 2, 3, 4]);
+
+        assert_eq!(rect.canonical_key(), rect.clone().canonical_key());
+        assert_ne!(rect.canonical_key(), recolored.canonical_key());
+        assert_ne!(
+            rect.canonical_key(),
+            Rectangle::new(PrimitivePoint::new(10, 10), 8, 6, 31).canonical_key()
+        );
+    }
+
+    #[test]
+    fn test_canonical_key_shape_type() {
+        // Same color, center and size, but a different type
+        let heart = Heart::new(PrimitivePoint::new(10, 10), 8, 0);
+        let ellipse = Ellipse::new(PrimitivePoint::new(10, 10), 8, 8, 0);
+        assert_ne!(heart.canonical_key(), ellipse.canonical_key());
+
+        let teardrop = Teardrop::new(PrimitivePoint::new(10, 10), 8, 2.0, 0);
+        assert_eq!(teardrop.canonical_key(), teardrop.clone().canonical_key());
+        assert_ne!(
+            teardrop.canonical_key(),
+            Teardrop::new(PrimitivePoint::new(10, 10), 8, 2.5, 0).canonical_key()
+        );
+    }
 }
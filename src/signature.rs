@@ -0,0 +1,226 @@
+use crate::utilities::rgb_to_hex;
+use image::{ImageBuffer, Pixel, Rgba};
+use imageproc::drawing::{draw_text_mut, text_size};
+use rusttype::{Font, Scale};
+use std::cmp::max;
+use std::str::FromStr;
+
+/// The font signatures are drawn in, so PNGs look the same everywhere
+const FONT: &[u8] = include_bytes!("../fonts/DejaVuSansMono.ttf");
+/// Font family for SVG signatures, falling back to any monospace font viewers have
+const SVG_FONT_FAMILY: &str = "DejaVu Sans Mono, monospace";
+/// The signature's font size is the image's shorter side divided by this...
+const SIZE_DIVISOR: u32 = 24;
+/// ...but at least this many pixels, so it stays legible on small images
+const MIN_SIZE: u32 = 12;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl FromStr for Corner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "top-left" => Ok(Corner::TopLeft),
+            "top-right" => Ok(Corner::TopRight),
+            "bottom-left" => Ok(Corner::BottomLeft),
+            "bottom-right" => Ok(Corner::BottomRight),
+            _ => Err(format!("Unsupported corner: {}", s)),
+        }
+    }
+}
+
+///
+/// Text drawn in a corner of the output, like an artist's signature
+///
+/// It's only added to saved output, so it doesn't affect the search
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub text: String,
+    pub corner: Corner,
+    pub opacity: f64,
+}
+
+impl Signature {
+    pub fn new(text: String, corner: Corner, opacity: f64) -> Signature {
+        if !(0.0..=1.0).contains(&opacity) {
+            panic!("Signature opacity must be between 0 and 1, not {}", opacity);
+        }
+
+        Signature {
+            text,
+            corner,
+            opacity,
+        }
+    }
+
+    ///
+    /// Draw the signature onto `image` in `color`
+    ///
+    pub fn draw_on(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, color: Rgba<u8>) {
+        let (width, height) = image.dimensions();
+        let font = font();
+        let (size, margin) = size_and_margin(width, height);
+        let scale = Scale::uniform(size as f32);
+
+        let (text_width, _) = text_size(scale, &font, &self.text);
+        let ascent = font.v_metrics(scale).ascent.round() as i32;
+        let x = match self.corner {
+            Corner::TopLeft | Corner::BottomLeft => margin as i32,
+            Corner::TopRight | Corner::BottomRight => width as i32 - margin as i32 - text_width,
+        };
+        // The text is drawn from the top of the font, not its baseline
+        let y = self.baseline(height, margin, ascent) - ascent;
+
+        // Draw onto a transparent layer, so the opacity applies to the text as a whole
+        let mut layer =
+            ImageBuffer::from_pixel(width, height, Rgba([color[0], color[1], color[2], 0]));
+        draw_text_mut(
+            &mut layer,
+            Rgba([color[0], color[1], color[2], 255]),
+            x,
+            y,
+            scale,
+            &font,
+            &self.text,
+        );
+
+        for (pixel, text) in image.pixels_mut().zip(layer.pixels()) {
+            if text[3] > 0 {
+                let alpha = (text[3] as f64 * self.opacity).round() as u8;
+                pixel.blend(&Rgba([text[0], text[1], text[2], alpha]));
+            }
+        }
+    }
+
+    ///
+    /// Returns an SVG `<text>` element for the signature in a `width` x `height` image, in `color`
+    ///
+    /// It's positioned like `draw_on`, though viewers without the font will substitute another
+    ///
+    pub fn as_svg(&self, width: u32, height: u32, color: Rgba<u8>) -> String {
+        let (size, margin) = size_and_margin(width, height);
+        let ascent = font().v_metrics(Scale::uniform(size as f32)).ascent.round() as i32;
+
+        let (x, anchor) = match self.corner {
+            Corner::TopLeft | Corner::BottomLeft => (margin, "start"),
+            Corner::TopRight | Corner::BottomRight => (width - margin, "end"),
+        };
+
+        format!(
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"{}\" font-family=\"{}\" font-size=\"{}\" fill=\"{}\" fill-opacity=\"{:.5}\">{}</text>",
+            x,
+            self.baseline(height, margin, ascent),
+            anchor,
+            SVG_FONT_FAMILY,
+            size,
+            rgb_to_hex(color),
+            self.opacity,
+            escape_xml(&self.text)
+        )
+    }
+
+    /// Returns the y coordinate of the text's baseline in an image `height` pixels tall
+    fn baseline(&self, height: u32, margin: u32, ascent: i32) -> i32 {
+        match self.corner {
+            Corner::TopLeft | Corner::TopRight => margin as i32 + ascent,
+            Corner::BottomLeft | Corner::BottomRight => height as i32 - margin as i32,
+        }
+    }
+}
+
+///
+/// Returns black or white, whichever stands out more against `background`
+///
+pub fn contrasting_color(background: Rgba<u8>) -> Rgba<u8> {
+    if background.to_luma()[0] >= 128 {
+        Rgba([0, 0, 0, 255])
+    } else {
+        Rgba([255, 255, 255, 255])
+    }
+}
+
+fn font() -> Font<'static> {
+    Font::try_from_bytes(FONT).expect("The embedded font is valid")
+}
+
+/// Returns the font size and the distance from the edges for a `width` x `height` image
+fn size_and_margin(width: u32, height: u32) -> (u32, u32) {
+    let size = max(width.min(height) / SIZE_DIVISOR, MIN_SIZE);
+
+    (size, size / 2)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(Corner::from_str("top-left"), Ok(Corner::TopLeft));
+        assert_eq!(Corner::from_str("BOTTOM-RIGHT"), Ok(Corner::BottomRight));
+        assert!(Corner::from_str("middle").is_err());
+    }
+
+    #[test]
+    fn test_draw_on() {
+        let background = Rgba([0, 0, 0, 255]);
+        let mut image = ImageBuffer::from_pixel(200, 100, background);
+        let signature = Signature::new(String::from("sig"), Corner::BottomRight, 0.5);
+        signature.draw_on(&mut image, contrasting_color(background));
+
+        // The text is only in the bottom right corner, and only half as bright as white
+        let drawn: Vec<(u32, u32)> = image
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| **pixel != background)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert!(!drawn.is_empty());
+        assert!(drawn.iter().all(|&(x, y)| x >= 100 && y >= 50));
+        assert!(image.pixels().all(|pixel| pixel[0] <= 128));
+    }
+
+    #[test]
+    fn test_as_svg() {
+        let signature = Signature::new(String::from("A & B"), Corner::TopRight, 0.75);
+        let svg = signature.as_svg(480, 240, Rgba([0, 0, 0, 255]));
+
+        assert!(svg.starts_with("<text x=\"474\" y=\""));
+        assert!(svg.contains("text-anchor=\"end\""));
+        assert!(svg.contains("font-size=\"12\""));
+        assert!(svg.contains("fill-opacity=\"0.75000\""));
+        assert!(svg.ends_with(">A &amp; B</text>"));
+    }
+
+    #[test]
+    fn test_contrasting_color() {
+        assert_eq!(
+            contrasting_color(Rgba([250, 240, 200, 255])),
+            Rgba([0, 0, 0, 255])
+        );
+        assert_eq!(
+            contrasting_color(Rgba([20, 40, 90, 255])),
+            Rgba([255, 255, 255, 255])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Signature opacity must be between 0 and 1")]
+    fn test_opacity_too_big() {
+        Signature::new(String::from("sig"), Corner::TopLeft, 1.5);
+    }
+}
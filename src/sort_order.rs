@@ -0,0 +1,67 @@
+use crate::metrics::luma;
+use crate::shape::Shape;
+use std::str::FromStr;
+
+///
+/// An order to paint shapes in, instead of the order they were found in
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SortOrder {
+    /// Largest first, so small details end up on top
+    AreaDesc,
+    AreaAsc,
+    /// Darkest first, by the luma of each shape's color
+    LuminanceAsc,
+}
+
+impl SortOrder {
+    ///
+    /// Returns the value to sort `shape` by, from first painted to last
+    ///
+    pub fn key(&self, shape: &dyn Shape) -> f64 {
+        match self {
+            SortOrder::AreaDesc => -shape.area(),
+            SortOrder::AreaAsc => shape.area(),
+            SortOrder::LuminanceAsc => luma(&shape.get_color()),
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "area-desc" => Ok(SortOrder::AreaDesc),
+            "area-asc" => Ok(SortOrder::AreaAsc),
+            "luminance-asc" => Ok(SortOrder::LuminanceAsc),
+            _ => Err(format!("Unsupported paint order: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point::PrimitivePoint;
+    use crate::rectangle::Rectangle;
+    use image::Rgba;
+
+    #[test]
+    fn test_key() {
+        let mut small = Rectangle::new(PrimitivePoint::new(10, 10), 2, 2, 0);
+        small.color = Rgba([255, 255, 255, 128]);
+        let mut large = Rectangle::new(PrimitivePoint::new(10, 10), 8, 8, 0);
+        large.color = Rgba([0, 0, 0, 128]);
+
+        assert!(SortOrder::AreaDesc.key(&large) < SortOrder::AreaDesc.key(&small));
+        assert!(SortOrder::AreaAsc.key(&small) < SortOrder::AreaAsc.key(&large));
+        assert!(SortOrder::LuminanceAsc.key(&large) < SortOrder::LuminanceAsc.key(&small));
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("AREA-DESC".parse(), Ok(SortOrder::AreaDesc));
+        assert!("area".parse::<SortOrder>().is_err());
+    }
+}
@@ -0,0 +1,530 @@
+use crate::fill_style::{HATCH_SPACING, HATCH_WIDTH};
+use crate::shape::{Shape, ShapeType};
+use crate::utilities::rgb_to_hex;
+use image::Rgba;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SvgFormat {
+    /// One element per shape
+    Plain,
+    /// Consecutive, non-overlapping, same-colored filled shapes share one `<path>`
+    PathMerge,
+    /// One `<g>` per shape type, for editing the layers separately
+    Layers,
+    /// Filled shapes with the same outline are defined once in `<defs>` and placed with `<use>`
+    Templates,
+}
+
+impl FromStr for SvgFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "svg" => Ok(SvgFormat::Plain),
+            "svg-path-merge" => Ok(SvgFormat::PathMerge),
+            "svg-layers" => Ok(SvgFormat::Layers),
+            "svg-templates" => Ok(SvgFormat::Templates),
+            _ => Err(format!("Unsupported SVG format: {}", s)),
+        }
+    }
+}
+
+///
+/// Returns the SVG elements for `shapes`, merging runs of filled shapes into single `<path>`s
+///
+/// Shapes are only merged with the run before them, so the paint order is unchanged. A shape joins
+/// the run if each of its color channels is within `tolerance` of the run's first shape, and if it
+/// doesn't overlap any shape already in the run (overlapping subpaths wouldn't blend twice)
+/// If `seam_stroke` is set, the paths are also stroked in their fill color (see `seamless_shapes_svg`)
+///
+pub fn merged_shapes_svg(
+    shapes: &[Box<dyn Shape>],
+    scale: f64,
+    tolerance: u8,
+    seam_stroke: Option<f64>,
+) -> String {
+    let mut result = String::new();
+
+    let mut run_color: Option<Rgba<u8>> = None;
+    let mut run_path_data: Vec<String> = vec![];
+    let mut run_bounding_boxes: Vec<[i32; 4]> = vec![];
+
+    for shape in shapes.iter() {
+        let path_data = shape.as_svg_path_data(scale);
+        let bounding_box = bounding_box(&**shape);
+
+        let joins_run = match (run_color, &path_data) {
+            (Some(color), Some(_)) => {
+                colors_within(color, shape.get_color(), tolerance)
+                    && !run_bounding_boxes
+                        .iter()
+                        .any(|other| boxes_overlap(*other, bounding_box))
+            }
+            _ => false,
+        };
+
+        if !joins_run {
+            if let Some(color) = run_color {
+                result += &path_svg(color, &run_path_data, seam_stroke);
+            }
+            run_color = None;
+            run_path_data.clear();
+            run_bounding_boxes.clear();
+        }
+
+        match path_data {
+            Some(path_data) => {
+                run_color = run_color.or_else(|| Some(shape.get_color()));
+                run_path_data.push(path_data);
+                run_bounding_boxes.push(bounding_box);
+            }
+            None => result += &shape.as_svg(scale),
+        }
+    }
+
+    if let Some(color) = run_color {
+        result += &path_svg(color, &run_path_data, seam_stroke);
+    }
+
+    result
+}
+
+///
+/// Returns the SVG elements for `shapes`, with filled shapes stroked in their own color
+///
+/// A `seam_stroke` wide stroke covers the hairline cracks viewers can leave between adjacent
+/// shapes. Curves, which are already strokes, are unchanged
+///
+pub fn seamless_shapes_svg(shapes: &[Box<dyn Shape>], scale: f64, seam_stroke: f64) -> String {
+    shapes
+        .iter()
+        .map(|shape| match shape.as_svg_path_data(scale) {
+            Some(path_data) => path_svg(shape.get_color(), &[path_data], Some(seam_stroke)),
+            None => shape.as_svg(scale),
+        })
+        .collect()
+}
+
+///
+/// Returns the SVG elements for `shapes`, with each type of shape in its own `<g>` layer
+///
+/// Layers are in the order their types first appear, and keep the shapes' order within them. Shapes
+/// of different types that overlap may end up painted in a different order
+/// If `seam_stroke` is set, filled shapes are stroked like `seamless_shapes_svg`
+///
+pub fn layered_shapes_svg(
+    shapes: &[Box<dyn Shape>],
+    scale: f64,
+    seam_stroke: Option<f64>,
+) -> String {
+    let mut layers: Vec<(ShapeType, Vec<Box<dyn Shape>>)> = vec![];
+
+    for shape in shapes.iter() {
+        let shape_type = shape.shape_type();
+        match layers
+            .iter_mut()
+            .find(|(layer_type, _)| *layer_type == shape_type)
+        {
+            Some((_, layer)) => layer.push(shape.clone()),
+            None => layers.push((shape_type, vec![shape.clone()])),
+        }
+    }
+
+    layers
+        .iter()
+        .map(|(shape_type, layer)| {
+            let elements = match seam_stroke {
+                Some(seam_stroke) => seamless_shapes_svg(layer, scale, seam_stroke),
+                None => layer.iter().map(|shape| shape.as_svg(scale)).collect(),
+            };
+            format!("<g id=\"{}\">{}</g>", shape_type.layer_id(), elements)
+        })
+        .collect()
+}
+
+///
+/// Returns the SVG elements for `shapes`, filling each filled shape with hatch lines instead of a
+/// solid color
+///
+/// Each color's lines are a `<pattern>`, defined once, matching `fill_style::hatch`. Curves are
+/// drawn as usual
+///
+pub fn hatched_shapes_svg(shapes: &[Box<dyn Shape>], scale: f64) -> String {
+    let mut colors: Vec<String> = vec![];
+    let mut elements = String::new();
+
+    for shape in shapes.iter() {
+        match shape.as_svg_path_data(scale) {
+            Some(path_data) => {
+                let color = rgb_to_hex(shape.get_color());
+                elements += &format!(
+                    "<path fill=\"url(#hatch-{})\" fill-opacity=\"{:.5}\" d=\"{}\"/>",
+                    &color[1..],
+                    shape.get_color().0[3] as f64 / 255.0,
+                    path_data
+                );
+
+                if !colors.contains(&color) {
+                    colors.push(color);
+                }
+            }
+            None => elements += &shape.as_svg(scale),
+        }
+    }
+
+    let spacing = HATCH_SPACING * scale;
+    let patterns: String = colors
+        .iter()
+        .map(|color| {
+            format!(
+                "<pattern id=\"hatch-{}\" patternUnits=\"userSpaceOnUse\" width=\"{}\" height=\"{}\"><rect width=\"{}\" height=\"{}\" fill=\"{}\"/></pattern>",
+                &color[1..],
+                spacing,
+                spacing,
+                spacing,
+                HATCH_WIDTH * scale,
+                color
+            )
+        })
+        .collect();
+
+    format!("<defs>{}</defs>{}", patterns, elements)
+}
+
+///
+/// Returns the SVG elements for `shapes`, with filled shapes that share an outline defined once
+///
+/// Outlines are compared after moving their first point to the origin, so copies at different
+/// positions (e.g. from symmetry or a seed grid) become one `<path>` in `<defs>`, placed by `<use>`
+/// elements with their own offset and color. Outlines used once, and curves, are drawn as usual
+/// If `seam_stroke` is set, filled shapes are stroked like `seamless_shapes_svg`
+///
+pub fn templated_shapes_svg(
+    shapes: &[Box<dyn Shape>],
+    scale: f64,
+    seam_stroke: Option<f64>,
+) -> String {
+    let outlines: Vec<Option<(String, (f64, f64))>> = shapes
+        .iter()
+        .map(|shape| {
+            shape
+                .as_svg_path_data(scale)
+                .map(|data| normalized_path_data(&data))
+        })
+        .collect();
+
+    let mut uses: HashMap<&str, usize> = HashMap::new();
+    for (outline, _) in outlines.iter().flatten() {
+        *uses.entry(outline).or_insert(0) += 1;
+    }
+
+    let mut templates: Vec<&str> = vec![];
+    let mut elements = String::new();
+
+    for (shape, outline) in shapes.iter().zip(outlines.iter()) {
+        match outline {
+            Some((outline, (x, y))) if uses[outline.as_str()] > 1 => {
+                let index = match templates.iter().position(|other| other == outline) {
+                    Some(index) => index,
+                    None => {
+                        templates.push(outline);
+                        templates.len() - 1
+                    }
+                };
+                let color = shape.get_color();
+                let stroke = match seam_stroke {
+                    Some(width) => format!(
+                        " stroke=\"{}\" stroke-opacity=\"{:.5}\" stroke-width=\"{}\"",
+                        rgb_to_hex(color),
+                        color.0[3] as f64 / 255.0,
+                        width
+                    ),
+                    None => String::new(),
+                };
+                elements += &format!(
+                    "<use href=\"#template-{}\" x=\"{}\" y=\"{}\" fill=\"{}\" fill-opacity=\"{:.5}\"{} />",
+                    index,
+                    coordinate(*x),
+                    coordinate(*y),
+                    rgb_to_hex(color),
+                    color.0[3] as f64 / 255.0,
+                    stroke
+                );
+            }
+            _ => match (seam_stroke, shape.as_svg_path_data(scale)) {
+                (Some(seam_stroke), Some(path_data)) => {
+                    elements += &path_svg(shape.get_color(), &[path_data], Some(seam_stroke))
+                }
+                _ => elements += &shape.as_svg(scale),
+            },
+        }
+    }
+
+    if templates.is_empty() {
+        return elements;
+    }
+
+    let definitions: String = templates
+        .iter()
+        .enumerate()
+        .map(|(index, outline)| format!("<path id=\"template-{}\" d=\"{}\" />", index, outline))
+        .collect();
+
+    format!("<defs>{}</defs>{}", definitions, elements)
+}
+
+///
+/// Returns `path_data` moved so its first point is at the origin, and the offset it was moved by
+///
+/// Only the comma-separated x,y pairs are points; other numbers (e.g. arc radii) are unchanged
+///
+fn normalized_path_data(path_data: &str) -> (String, (f64, f64)) {
+    let mut origin: Option<(f64, f64)> = None;
+
+    let tokens: Vec<String> = path_data
+        .split_whitespace()
+        .map(|token| {
+            let command_length = token
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(token.len());
+            let (command, pair) = token.split_at(command_length);
+
+            let point = pair
+                .split_once(',')
+                .and_then(|(x, y)| Some((x.parse::<f64>().ok()?, y.parse::<f64>().ok()?)));
+
+            match point {
+                Some((x, y)) => {
+                    let (origin_x, origin_y) = *origin.get_or_insert((x, y));
+                    format!(
+                        "{}{},{}",
+                        command,
+                        coordinate(x - origin_x),
+                        coordinate(y - origin_y)
+                    )
+                }
+                None => token.to_string(),
+            }
+        })
+        .collect();
+
+    (tokens.join(" "), origin.unwrap_or((0.0, 0.0)))
+}
+
+/// Format a coordinate with at most 2 decimal places, like the shapes' path data
+fn coordinate(value: f64) -> String {
+    let formatted = format!("{:.2}", value);
+    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+
+    match trimmed {
+        "-0" => String::from("0"),
+        trimmed => String::from(trimmed),
+    }
+}
+
+fn path_svg(color: Rgba<u8>, path_data: &[String], seam_stroke: Option<f64>) -> String {
+    let stroke = match seam_stroke {
+        Some(width) => format!(
+            " stroke=\"{}\" stroke-opacity=\"{:.5}\" stroke-width=\"{}\"",
+            rgb_to_hex(color),
+            color.0[3] as f64 / 255.0,
+            width
+        ),
+        None => String::new(),
+    };
+
+    format!(
+        "<path fill=\"{}\" fill-opacity=\"{:.5}\"{} d=\"{}\" />",
+        rgb_to_hex(color),
+        color.0[3] as f64 / 255.0,
+        stroke,
+        path_data.join(" ")
+    )
+}
+
+fn colors_within(a: Rgba<u8>, b: Rgba<u8>, tolerance: u8) -> bool {
+    a.0.iter()
+        .zip(b.0.iter())
+        .all(|(a, b)| (*a as i32 - *b as i32).abs() <= tolerance as i32)
+}
+
+/// Returns [min_x, min_y, max_x, max_y] of the shape's pixels
+fn bounding_box(shape: &dyn Shape) -> [i32; 4] {
+    let pixels = shape.get_pixels();
+
+    [
+        pixels.iter().map(|p| p.x).min().unwrap_or(0),
+        pixels.iter().map(|p| p.y).min().unwrap_or(0),
+        pixels.iter().map(|p| p.x).max().unwrap_or(-1),
+        pixels.iter().map(|p| p.y).max().unwrap_or(-1),
+    ]
+}
+
+fn boxes_overlap(a: [i32; 4], b: [i32; 4]) -> bool {
+    a[0] <= b[2] && b[0] <= a[2] && a[1] <= b[3] && b[1] <= a[3]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point::PrimitivePoint;
+    use crate::triangle::Triangle;
+
+    fn triangle(offset: i32, color: Rgba<u8>) -> Box<dyn Shape> {
+        Box::new(Triangle {
+            path: [
+                PrimitivePoint::new(offset, offset),
+                PrimitivePoint::new(offset + 10, offset),
+                PrimitivePoint::new(offset, offset + 10),
+            ],
+            color,
+        })
+    }
+
+    #[test]
+    fn test_merge_same_color() {
+        let shapes = vec![
+            triangle(0, Rgba([240, 64, 15, 128])),
+            triangle(20, Rgba([240, 64, 15, 128])),
+        ];
+
+        let expected = "<path fill=\"#F0400F\" fill-opacity=\"0.50196\" d=\"M0,0 L10,0 L0,10 Z M20,20 L30,20 L20,30 Z\" />";
+        assert_eq!(merged_shapes_svg(&shapes, 1.0, 0, None), expected);
+    }
+
+    #[test]
+    fn test_merge_tolerance() {
+        let shapes = vec![
+            triangle(0, Rgba([240, 64, 15, 128])),
+            triangle(20, Rgba([242, 64, 15, 128])),
+        ];
+
+        assert_eq!(
+            merged_shapes_svg(&shapes, 1.0, 0, None)
+                .matches("<path")
+                .count(),
+            2
+        );
+        assert_eq!(
+            merged_shapes_svg(&shapes, 1.0, 2, None)
+                .matches("<path")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_no_merge_overlapping() {
+        let shapes = vec![
+            triangle(0, Rgba([240, 64, 15, 128])),
+            triangle(5, Rgba([240, 64, 15, 128])),
+        ];
+
+        assert_eq!(
+            merged_shapes_svg(&shapes, 1.0, 0, None)
+                .matches("<path")
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_seam_stroke() {
+        let shapes = vec![
+            triangle(0, Rgba([240, 64, 15, 128])),
+            triangle(20, Rgba([240, 64, 15, 128])),
+        ];
+
+        let expected = "<path fill=\"#F0400F\" fill-opacity=\"0.50196\" stroke=\"#F0400F\" stroke-opacity=\"0.50196\" stroke-width=\"0.5\" d=\"M0,0 L10,0 L0,10 Z\" />";
+        let svg = seamless_shapes_svg(&shapes, 1.0, 0.5);
+        assert!(svg.starts_with(expected));
+        assert_eq!(svg.matches("<path").count(), 2);
+
+        assert!(merged_shapes_svg(&shapes, 1.0, 0, Some(0.5)).contains("stroke-width=\"0.5\""));
+    }
+
+    #[test]
+    fn test_layers() {
+        use crate::rectangle::Rectangle;
+
+        let shapes = vec![
+            triangle(0, Rgba([240, 64, 15, 128])),
+            Box::new(Rectangle::new(PrimitivePoint::new(5, 5), 4, 4, 0)) as Box<dyn Shape>,
+            triangle(20, Rgba([0, 64, 15, 128])),
+        ];
+
+        let svg = layered_shapes_svg(&shapes, 1.0, None);
+        assert!(svg.starts_with("<g id=\"triangles\"><polygon"));
+        assert_eq!(svg.matches("<g id=").count(), 2);
+        assert_eq!(svg.matches("<polygon").count(), 2);
+
+        // Both triangles come before the rectangle's layer
+        let rect_layer = svg.find("<g id=\"rectangles\">").unwrap();
+        assert!(svg.rfind("<polygon").unwrap() < rect_layer);
+    }
+
+    #[test]
+    fn test_hatched() {
+        let shapes = vec![
+            triangle(0, Rgba([240, 64, 15, 128])),
+            triangle(20, Rgba([240, 64, 15, 128])),
+            triangle(40, Rgba([0, 64, 15, 128])),
+        ];
+
+        let svg = hatched_shapes_svg(&shapes, 2.0);
+        assert!(svg.starts_with("<defs><pattern id=\"hatch-F0400F\" patternUnits=\"userSpaceOnUse\" width=\"6\" height=\"6\"><rect width=\"6\" height=\"2\" fill=\"#F0400F\"/></pattern>"));
+        assert_eq!(svg.matches("<pattern").count(), 2);
+        assert_eq!(svg.matches("fill=\"url(#hatch-F0400F)\"").count(), 2);
+        assert_eq!(svg.matches("fill=\"url(#hatch-00400F)\"").count(), 1);
+    }
+
+    #[test]
+    fn test_templates() {
+        let shapes = vec![
+            triangle(0, Rgba([240, 64, 15, 128])),
+            triangle(5, Rgba([0, 64, 15, 255])),
+            Box::new(Triangle::from_points(
+                PrimitivePoint::new(0, 0),
+                PrimitivePoint::new(4, 0),
+                PrimitivePoint::new(0, 4),
+            )) as Box<dyn Shape>,
+        ];
+
+        let svg = templated_shapes_svg(&shapes, 1.0, None);
+        assert!(svg.starts_with(
+            "<defs><path id=\"template-0\" d=\"M0,0 L10,0 L0,10 Z\" /></defs><use href=\"#template-0\" x=\"0\" y=\"0\" fill=\"#F0400F\" fill-opacity=\"0.50196\" />"
+        ));
+        assert_eq!(svg.matches("<defs>").count(), 1);
+        assert_eq!(svg.matches("<path id=").count(), 1);
+        assert_eq!(svg.matches("<use href=\"#template-0\"").count(), 2);
+        assert!(svg.contains("x=\"5\" y=\"5\" fill=\"#00400F\""));
+
+        // The smaller triangle is only used once, so it's drawn as usual
+        assert_eq!(svg.matches("<polygon").count(), 1);
+    }
+
+    #[test]
+    fn test_normalized_path_data() {
+        assert_eq!(
+            normalized_path_data("M10.50,20 A3 4 0 1 0 12.25,18 Z"),
+            (String::from("M0,0 A3 4 0 1 0 1.75,-2 Z"), (10.5, 20.0))
+        );
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(SvgFormat::from_str("svg"), Ok(SvgFormat::Plain));
+        assert_eq!(
+            SvgFormat::from_str("svg-path-merge"),
+            Ok(SvgFormat::PathMerge)
+        );
+        assert_eq!(SvgFormat::from_str("svg-layers"), Ok(SvgFormat::Layers));
+        assert_eq!(
+            SvgFormat::from_str("svg-templates"),
+            Ok(SvgFormat::Templates)
+        );
+        assert!(SvgFormat::from_str("png").is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use std::str::FromStr;
+
+///
+/// Which axis (if any) every added shape is mirrored across
+///
+/// Horizontal symmetry mirrors left to right (across the vertical center line), vertical
+/// symmetry mirrors top to bottom
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Symmetry {
+    None,
+    Horizontal,
+    Vertical,
+}
+
+impl Symmetry {
+    ///
+    /// Mirror the pixel coordinate (`x`, `y`) of a `width` x `height` image
+    ///
+    pub fn mirror_coordinates(&self, x: i32, y: i32, width: u32, height: u32) -> (i32, i32) {
+        match self {
+            Symmetry::None => (x, y),
+            Symmetry::Horizontal => (width as i32 - 1 - x, y),
+            Symmetry::Vertical => (x, height as i32 - 1 - y),
+        }
+    }
+}
+
+impl FromStr for Symmetry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "none" => Ok(Symmetry::None),
+            "horizontal" => Ok(Symmetry::Horizontal),
+            "vertical" => Ok(Symmetry::Vertical),
+            _ => Err(format!("Unsupported symmetry: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(Symmetry::from_str("none"), Ok(Symmetry::None));
+        assert_eq!(Symmetry::from_str("Horizontal"), Ok(Symmetry::Horizontal));
+        assert_eq!(Symmetry::from_str("VERTICAL"), Ok(Symmetry::Vertical));
+        assert!(Symmetry::from_str("radial").is_err());
+    }
+
+    #[test]
+    fn test_mirror_coordinates() {
+        assert_eq!(Symmetry::None.mirror_coordinates(2, 3, 10, 20), (2, 3));
+        assert_eq!(
+            Symmetry::Horizontal.mirror_coordinates(2, 3, 10, 20),
+            (7, 3)
+        );
+        assert_eq!(Symmetry::Vertical.mirror_coordinates(2, 3, 10, 20), (2, 16));
+
+        // Mirroring twice is a no-op
+        assert_eq!(
+            Symmetry::Horizontal.mirror_coordinates(7, 3, 10, 20),
+            (2, 3)
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use crate::mutate_mode::MutateMode;
+use crate::point::PrimitivePoint;
+use crate::primitive_image::PrimitiveImage;
+use crate::shape::{shape_key, RandomShape, Shape, ShapeType};
+use crate::symmetry::Symmetry;
+use crate::utilities::{clamp, fill_polygon, polygon_contains, radians, rgb_to_hex, SizeLimits};
+use image::ImageBuffer;
+use image::Pixel;
+use image::Rgba;
+use rand::Rng;
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::max;
+use std::f64::consts::PI;
+use std::hash::{Hash, Hasher};
+
+const MAXIMUM_MUTATION_ATTEMPTS: u32 = 100_000;
+// Points sampled from the rounded end to build the outline that's filled
+const OUTLINE_POINTS: usize = 48;
+// Cubic beziers used for the rounded end in the SVG
+const SVG_ARCS: usize = 4;
+const MIN_ELONGATION: f64 = 1.2;
+const MAX_ELONGATION: f64 = 4.0;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Teardrop {
+    #[serde(with = "crate::utilities::rgba_serde")]
+    pub color: image::Rgba<u8>,
+    center: PrimitivePoint, // The center of the rounded end
+    radius: u32,
+    elongation: f64, // The tip's distance from the center, in radii
+    angle: u32,      // In degrees, clockwise from the tip pointing up
+}
+
+// `f64` isn't `Hash`, so hash the elongation's bits
+impl Hash for Teardrop {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.color.hash(state);
+        self.center.hash(state);
+        self.radius.hash(state);
+        self.elongation.to_bits().hash(state);
+        self.angle.hash(state);
+    }
+}
+
+impl Teardrop {
+    ///
+    /// Create a teardrop whose rounded end is a circle of `radius` around `center`, with its tip
+    /// `elongation * radius` pixels from the center, pointing up rotated clockwise by `angle` degrees
+    ///
+    pub fn new(center: PrimitivePoint, radius: u32, elongation: f64, angle: u32) -> Teardrop {
+        Teardrop {
+            color: Rgba([0, 0, 0, 128]),
+            center,
+            radius,
+            elongation,
+            angle,
+        }
+    }
+
+    ///
+    /// Returns this shape with its color set to `color`, for building shapes by hand
+    ///
+    pub fn with_color(mut self, color: Rgba<u8>) -> Self {
+        self.color = color;
+        self
+    }
+
+    ///
+    /// Determine if this teardrop is valid
+    ///
+    /// A teardrop is valid if it has a visible point, and isn't too big for the image or
+    /// `size_limits` (which bound the rounded end)
+    ///
+    fn is_valid(&self, width: u32, height: u32, size_limits: SizeLimits) -> bool {
+        let (min_radius, max_radius) =
+            size_limits.radius_range(width, height, 1, max_radius(width, height));
+
+        self.radius >= min_radius
+            && self.radius <= max_radius
+            && (MIN_ELONGATION..=MAX_ELONGATION).contains(&self.elongation)
+    }
+
+    ///
+    /// Converts (`x`, `y`), relative to the center with the tip pointing up, to image coordinates
+    ///
+    /// Rotates the same way as `rotate_point`
+    ///
+    fn image_point(&self, x: f64, y: f64) -> (f64, f64) {
+        let (sin_a, cos_a) = radians(self.angle as f64).sin_cos();
+
+        (
+            self.center.x as f64 + x * cos_a - y * sin_a,
+            self.center.y as f64 + x * sin_a + y * cos_a,
+        )
+    }
+
+    ///
+    /// Returns the angle (from the tip's direction, around the center) where the sides of the
+    /// teardrop touch the rounded end
+    ///
+    fn tangent_angle(&self) -> f64 {
+        (1.0 / self.elongation).acos()
+    }
+
+    ///
+    /// Returns the outline in image coordinates: the tip, then `points` points around the rounded
+    /// end from one side to the other
+    ///
+    fn outline(&self, points: usize) -> Vec<(f64, f64)> {
+        let radius = self.radius as f64;
+        let start = self.tangent_angle();
+        let sweep = 2.0 * PI - 2.0 * start;
+
+        let mut outline = vec![self.image_point(0.0, -radius * self.elongation)];
+        for i in 0..(points + 1) {
+            let t = start + sweep * i as f64 / points as f64;
+            outline.push(self.image_point(radius * t.sin(), -radius * t.cos()));
+        }
+
+        outline
+    }
+}
+
+/// The largest radius a teardrop can have in a `width` x `height` image (at least 2, for tiny images)
+fn max_radius(width: u32, height: u32) -> u32 {
+    max(max(width, height) / 4, 2)
+}
+
+impl RandomShape for Teardrop {
+    ///
+    /// Generate a random Teardrop around `center`
+    ///
+    fn random_at(
+        center: PrimitivePoint,
+        width: u32,
+        height: u32,
+        _border_extension: i32,
+        size_limits: SizeLimits,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let (min_radius, max_radius) =
+            size_limits.radius_range(width, height, 1, max(max(width, height) / 8, 2) - 1);
+        let radius = rng.gen_range(min_radius..=max_radius);
+        let elongation = rng.gen_range(1.5..3.0);
+        let angle = rng.gen_range(0..360);
+
+        Teardrop::new(center, radius, elongation, angle)
+    }
+}
+
+impl Shape for Teardrop {
+    fn mutate(
+        &mut self,
+        width: u32,
+        height: u32,
+        size_limits: SizeLimits,
+        mode: MutateMode,
+        rng: &mut impl Rng,
+    ) {
+        let normal = Normal::new(0.0, 5.0 * mode.step_scale()).unwrap();
+        let (min_radius, max_radius) =
+            size_limits.radius_range(width, height, 1, max_radius(width, height));
+        let elongation_normal = Normal::new(0.0, 0.2 * mode.step_scale()).unwrap();
+
+        let mut i = 0;
+        loop {
+            i += 1;
+            for r in mode.parameters(4, rng) {
+                match r {
+                    0 => self.center.mutate(width, height, mode, rng),
+                    1 => {
+                        self.radius = clamp(
+                            self.radius as i32 + (rng.sample(normal) as i32),
+                            min_radius as i32,
+                            max_radius as i32,
+                        ) as u32
+                    }
+                    2 => {
+                        self.elongation = (self.elongation + rng.sample(elongation_normal))
+                            .clamp(MIN_ELONGATION, MAX_ELONGATION)
+                    }
+                    3 => {
+                        self.angle =
+                            (self.angle as i32 + (rng.sample(normal) as i32)).rem_euclid(360) as u32
+                    }
+                    _ => {}
+                }
+            }
+
+            if self.is_valid(width, height, size_limits) {
+                break;
+            }
+            if i > MAXIMUM_MUTATION_ATTEMPTS {
+                panic!("Teardrop: Too many mutation loops!");
+            }
+        }
+    }
+
+    ///
+    /// The circle, minus the sector between the tangent points, plus the kite from them to the tip
+    ///
+    fn area(&self) -> f64 {
+        let radius = self.radius as f64;
+        let tangent_length = (self.elongation * self.elongation - 1.0).sqrt();
+        radius * radius * (PI - self.tangent_angle() + tangent_length)
+    }
+
+    fn contains_pixel(&self, x: i32, y: i32) -> bool {
+        polygon_contains(&self.outline(OUTLINE_POINTS), x as f64, y as f64)
+    }
+
+    fn get_pixels(&self) -> Vec<PrimitivePoint> {
+        fill_polygon(&self.outline(OUTLINE_POINTS))
+    }
+
+    fn as_svg(&self, scale: f64) -> String {
+        format!(
+            "<path fill=\"{}\" fill-opacity=\"{:.5}\" d=\"{}\"/>",
+            rgb_to_hex(self.color),
+            self.color.0[3] as f64 / 255.0,
+            self.as_svg_path_data(scale).unwrap()
+        )
+    }
+
+    fn as_svg_path_data(&self, scale: f64) -> Option<String> {
+        let radius = self.radius as f64;
+        let point = |x: f64, y: f64| {
+            let (x, y) = self.image_point(x, y);
+            (x * scale, y * scale)
+        };
+
+        let tip = point(0.0, -radius * self.elongation);
+        let start = self.tangent_angle();
+        let step = (2.0 * PI - 2.0 * start) / SVG_ARCS as f64;
+        // Distance from each end of an arc to its control point, for the closest cubic bezier
+        let handle = 4.0 / 3.0 * (step / 4.0).tan() * radius;
+
+        let mut path = format!("M{:.2},{:.2}", tip.0, tip.1);
+
+        let first = point(radius * start.sin(), -radius * start.cos());
+        path += &format!(" L{:.2},{:.2}", first.0, first.1);
+
+        for i in 0..SVG_ARCS {
+            let t0 = start + step * i as f64;
+            let t1 = t0 + step;
+
+            let (sin0, cos0) = t0.sin_cos();
+            let (sin1, cos1) = t1.sin_cos();
+
+            let control0 = point(
+                radius * sin0 + handle * cos0,
+                -radius * cos0 + handle * sin0,
+            );
+            let control1 = point(
+                radius * sin1 - handle * cos1,
+                -radius * cos1 - handle * sin1,
+            );
+            let end = point(radius * sin1, -radius * cos1);
+
+            path += &format!(
+                " C{:.2},{:.2} {:.2},{:.2} {:.2},{:.2}",
+                control0.0, control0.1, control1.0, control1.1, end.0, end.1
+            );
+        }
+        path += " Z";
+
+        Some(path)
+    }
+
+    fn paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
+        let (width, height) = image.dimensions();
+
+        for pixel in self.get_pixels().iter() {
+            if pixel.x >= 0 && pixel.y >= 0 && pixel.x < width as i32 && pixel.y < height as i32 {
+                let pix = image.get_pixel_mut(pixel.x as u32, pixel.y as u32);
+                pix.blend(&self.color);
+            }
+        }
+    }
+
+    fn scaled_paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, scale: f64) {
+        let scaled_self = Teardrop {
+            center: PrimitivePoint::new(
+                (self.center.x as f64 * scale) as i32,
+                (self.center.y as f64 * scale) as i32,
+            ),
+            radius: (self.radius as f64 * scale) as u32,
+            color: self.color,
+            elongation: self.elongation,
+            angle: self.angle,
+        };
+
+        scaled_self.paint_into(image);
+    }
+
+    fn set_color_using(&mut self, image: &PrimitiveImage) {
+        self.color = image.color_for_shape(self);
+    }
+
+    fn get_color(&self) -> Rgba<u8> {
+        self.color
+    }
+
+    fn clear_rotation(&mut self) {
+        self.angle = 0;
+    }
+
+    fn mirror(&self, symmetry: Symmetry, width: u32, height: u32) -> Option<Box<dyn Shape>> {
+        let mut mirrored = *self;
+        mirrored.center = self.center.mirrored(symmetry, width, height);
+
+        // Teardrops are symmetric about their axis, so reflecting one only changes where the tip
+        // points
+        mirrored.angle = match symmetry {
+            Symmetry::None => self.angle,
+            Symmetry::Horizontal => (360 - self.angle % 360) % 360,
+            Symmetry::Vertical => (540 - self.angle % 360) % 360,
+        };
+
+        Some(Box::new(mirrored))
+    }
+
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::Teardrop
+    }
+
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap()
+    }
+
+    fn canonical_key(&self) -> u64 {
+        shape_key(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row_width(pixels: &[PrimitivePoint], y: i32) -> usize {
+        pixels.iter().filter(|p| p.y == y).count()
+    }
+
+    #[test]
+    fn test_get_pixels() {
+        let teardrop = Teardrop::new(PrimitivePoint::new(20, 20), 5, 3.0, 0);
+        let pixels = teardrop.get_pixels();
+
+        // The rounded end contains the center, and is as wide as the circle
+        assert!(pixels.contains(&PrimitivePoint::new(20, 20)));
+        assert!(row_width(&pixels, 20) >= 9);
+        assert!(!pixels.contains(&PrimitivePoint::new(20, 26)));
+
+        // The tip, 15 pixels above the center, is narrow
+        assert!(pixels.contains(&PrimitivePoint::new(20, 7)));
+        assert!(row_width(&pixels, 7) <= 3);
+        assert!(!pixels.contains(&PrimitivePoint::new(20, 4)));
+
+        // Rotated a quarter turn, the tip points right
+        let rotated = Teardrop::new(PrimitivePoint::new(20, 20), 5, 3.0, 90);
+        assert!(rotated.get_pixels().contains(&PrimitivePoint::new(33, 20)));
+    }
+
+    #[test]
+    fn test_as_svg() {
+        let mut teardrop = Teardrop::new(PrimitivePoint::new(20, 20), 5, 2.0, 0);
+        teardrop.color = Rgba([240, 64, 15, 128]);
+        let svg = teardrop.as_svg(2.0);
+
+        assert!(
+            svg.starts_with("<path fill=\"#F0400F\" fill-opacity=\"0.50196\" d=\"M40.00,20.00 L")
+        );
+        assert_eq!(svg.matches(" C").count(), SVG_ARCS);
+        assert!(svg.ends_with(" Z\"/>"));
+    }
+
+    #[test]
+    fn test_area() {
+        for elongation in [MIN_ELONGATION, 2.5, MAX_ELONGATION] {
+            let teardrop = Teardrop::new(PrimitivePoint::new(80, 80), 15, elongation, 70);
+
+            let pixels = teardrop.get_pixels().len() as f64;
+            assert!((teardrop.area() - pixels).abs() / pixels < 0.05);
+        }
+    }
+}
@@ -1,20 +1,25 @@
+use crate::mutate_mode::MutateMode;
 use crate::point::PrimitivePoint;
 use crate::primitive_image::PrimitiveImage;
-use crate::shape::{RandomShape, Shape};
-use crate::utilities::rgb_to_hex;
-use image::imageops::overlay;
+use crate::shape::{shape_key, RandomShape, Shape, ShapeType};
+use crate::symmetry::Symmetry;
+use crate::utilities::{points_size, rgb_to_hex, SizeLimits};
 use image::ImageBuffer;
-use image::Rgba;
-use imageproc::drawing::draw_polygon;
+use image::{Pixel, Rgba};
+use imageproc::drawing::{draw_polygon_mut, Canvas};
 use imageproc::point::Point;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::cmp::{max, min};
+use std::collections::HashSet;
 
 const MINIMUM_DEGREES: f64 = 15.0;
 const MAXIMUM_MUTATION_ATTEMPTS: u32 = 100_000_000;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Hash, Serialize, Deserialize)]
 pub struct Triangle {
+    #[serde(with = "crate::utilities::rgba_serde")]
     pub color: image::Rgba<u8>,
     pub path: [PrimitivePoint; 3],
 }
@@ -48,6 +53,23 @@ impl Triangle {
         }
     }
 
+    ///
+    /// Create a triangle with the corners given, for placing one by hand
+    ///
+    /// Unlike random triangles, this isn't checked against `MINIMUM_DEGREES`
+    ///
+    pub fn from_points(p0: PrimitivePoint, p1: PrimitivePoint, p2: PrimitivePoint) -> Triangle {
+        Triangle::new(vec![p0, p1, p2])
+    }
+
+    ///
+    /// Returns this shape with its color set to `color`, for building shapes by hand
+    ///
+    pub fn with_color(mut self, color: Rgba<u8>) -> Self {
+        self.color = color;
+        self
+    }
+
     fn new(vertices: Vec<PrimitivePoint>) -> Self {
         if vertices.len() != 3 {
             panic!("Triangles have 3 vertices, not {}!", vertices.len());
@@ -60,9 +82,37 @@ impl Triangle {
     }
 
     ///
-    /// Determine if this triangle contains the point (`x`, `y`)
+    /// Generate a random valid triangle with every vertex within `radius` pixels of `center`
     ///
-    fn contains_pixel(&self, x: i32, y: i32) -> bool {
+    /// `radius` must be at least 2, so there's room for a valid triangle
+    ///
+    pub fn random_around(center: PrimitivePoint, radius: i32, rng: &mut impl Rng) -> Self {
+        let mut i = 0;
+        loop {
+            i += 1;
+            let tri = Triangle::new(vec![
+                center.random_point_in_radius(radius, rng),
+                center.random_point_in_radius(radius, rng),
+                center.random_point_in_radius(radius, rng),
+            ]);
+
+            if tri.is_valid() {
+                return tri;
+            }
+            if i > MAXIMUM_MUTATION_ATTEMPTS {
+                panic!(
+                    "Triangle: Too many attempts to generate around {:?}!",
+                    center
+                );
+            }
+        }
+    }
+
+    ///
+    /// Determine if the point (`x`, `y`) is on the inside of all three edges. Degenerate triangles
+    /// pass this for their whole line, so callers also check the bounding box
+    ///
+    fn inside_edges(&self, x: i32, y: i32) -> bool {
         let p = PrimitivePoint::new(x, y);
 
         let mut path2: Vec<PrimitivePoint> = self.path.to_vec();
@@ -101,16 +151,42 @@ impl RandomShape for Triangle {
     /// `border_extension` is the maximum distance outside of the border a triangle is allowed to go
     ///     It must be >= 1
     ///
-    fn random(width: u32, height: u32, border_extension: i32, rng: &mut impl Rng) -> Self {
+    fn random(
+        width: u32,
+        height: u32,
+        border_extension: i32,
+        size_limits: SizeLimits,
+        rng: &mut impl Rng,
+    ) -> Self {
+        // Start no bigger than the maximum size
+        let (_, max_size) = size_limits.pixel_range(width, height, 1, 2 * border_extension as u32);
+        let radius = max(max_size as i32 / 2, 1);
+
         let p0 = PrimitivePoint::random_point(width, height, rng);
-        let p1 = p0.random_point_in_radius(border_extension, rng);
-        let p2 = p0.random_point_in_radius(border_extension, rng);
+        let p1 = p0.random_point_in_radius(radius, rng);
+        let p2 = p0.random_point_in_radius(radius, rng);
 
         let mut tri = Triangle::new(vec![p0, p1, p2]);
-        tri.mutate(width, height, rng);
+        tri.mutate(width, height, size_limits, MutateMode::Single, rng);
 
         tri
     }
+
+    ///
+    /// Generate a random valid Triangle with every vertex near `center`
+    ///
+    fn random_at(
+        center: PrimitivePoint,
+        width: u32,
+        height: u32,
+        border_extension: i32,
+        size_limits: SizeLimits,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let (_, max_size) = size_limits.pixel_range(width, height, 1, 2 * border_extension as u32);
+
+        Triangle::random_around(center, max(max_size as i32 / 2, 2), rng)
+    }
 }
 
 impl Shape for Triangle {
@@ -119,15 +195,27 @@ impl Shape for Triangle {
     /// Guarantees that the triangle remains valid
     /// Does not recolor the triangle
     ///
-    fn mutate(&mut self, width: u32, height: u32, rng: &mut impl Rng) {
+    fn mutate(
+        &mut self,
+        width: u32,
+        height: u32,
+        size_limits: SizeLimits,
+        mode: MutateMode,
+        rng: &mut impl Rng,
+    ) {
         let mut i = 0;
         loop {
             i += 1;
-            let r = rng.gen_range(0..3);
-
-            self.path[r].mutate(width, height, rng);
+            let previous = self.path;
+            for r in mode.parameters(3, rng) {
+                self.path[r].mutate(width, height, mode, rng);
+            }
 
-            if self.is_valid() {
+            let allowed = size_limits.allows(points_size(&self.path), width, height);
+            // Once the triangle is an allowed size, don't let it wander away from one
+            if !allowed && size_limits.allows(points_size(&previous), width, height) {
+                self.path = previous;
+            } else if allowed && self.is_valid() {
                 break;
             }
             if i > MAXIMUM_MUTATION_ATTEMPTS {
@@ -136,6 +224,16 @@ impl Shape for Triangle {
         }
     }
 
+    fn area(&self) -> f64 {
+        orient_2d(self.path[0], self.path[1], self.path[2]).abs() as f64 / 2.0
+    }
+
+    fn contains_pixel(&self, x: i32, y: i32) -> bool {
+        let [min, max] = self.bounding_box();
+
+        (min.x..=max.x).contains(&x) && (min.y..=max.y).contains(&y) && self.inside_edges(x, y)
+    }
+
     fn get_pixels(&self) -> Vec<PrimitivePoint> {
         let bounding_box = self.bounding_box();
 
@@ -148,7 +246,7 @@ impl Shape for Triangle {
 
         for x in min_x..(max_x + 1) {
             for y in min_y..(max_y + 1) {
-                if self.contains_pixel(x as i32, y as i32) {
+                if self.inside_edges(x, y) {
                     pixels.push(PrimitivePoint::new(x, y));
                 }
             }
@@ -171,33 +269,26 @@ impl Shape for Triangle {
         )
     }
 
-    //noinspection RsTypeCheck
-    fn paint_on(&self, image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-        let (width, height) = image.dimensions();
-
-        let mut tri_image: ImageBuffer<Rgba<u8>, Vec<u8>> =
-            image::ImageBuffer::from_pixel(width as u32, height as u32, image::Rgba([0, 0, 0, 0]));
-        let mut output = image.clone();
-
-        tri_image = draw_polygon(&tri_image, &(self.get_drawing_points()), self.color);
-
-        overlay(&mut output, &tri_image, 0, 0);
-
-        output
+    fn as_svg_path_data(&self, scale: f64) -> Option<String> {
+        Some(format!(
+            "M{},{} L{},{} L{},{} Z",
+            (self.path[0].x as f64 * scale) as i32,
+            (self.path[0].y as f64 * scale) as i32,
+            (self.path[1].x as f64 * scale) as i32,
+            (self.path[1].y as f64 * scale) as i32,
+            (self.path[2].x as f64 * scale) as i32,
+            (self.path[2].y as f64 * scale) as i32
+        ))
     }
 
     //noinspection RsTypeCheck
-    fn scaled_paint_on(
-        &self,
-        image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-        scale: f64,
-    ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-        let (width, height) = image.dimensions();
-
-        let mut tri_image: ImageBuffer<Rgba<u8>, Vec<u8>> =
-            image::ImageBuffer::from_pixel(width as u32, height as u32, image::Rgba([0, 0, 0, 0]));
-        let mut output = image.clone();
-
+    fn paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
+        let points = self.get_drawing_points();
+        if let Some(points) = drawable_points(points[0], points[1], points[2]) {
+            draw_polygon_mut(&mut BlendOnce::new(image), &points, self.color);
+        }
+    }
+    fn scaled_paint_into(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, scale: f64) {
         let mut p0 = self.path[0];
         let mut p1 = self.path[1];
         let mut p2 = self.path[2];
@@ -209,19 +300,53 @@ impl Shape for Triangle {
         p2.x = (p2.x as f64 * scale) as i32;
         p2.y = (p2.y as f64 * scale) as i32;
 
-        let dp0 = p0.to_drawing_point();
-        let dp1 = p1.to_drawing_point();
-        let dp2 = p2.to_drawing_point();
+        // Scaling down can merge points
+        if let Some(points) = drawable_points(
+            p0.to_drawing_point(),
+            p1.to_drawing_point(),
+            p2.to_drawing_point(),
+        ) {
+            draw_polygon_mut(&mut BlendOnce::new(image), &points, self.color);
+        }
+    }
 
-        tri_image = draw_polygon(&tri_image, &[dp0, dp1, dp2], self.color);
+    fn set_color_using(&mut self, image: &PrimitiveImage) {
+        self.color = image.color_for_shape(self);
+    }
 
-        overlay(&mut output, &tri_image, 0, 0);
+    fn get_color(&self) -> Rgba<u8> {
+        self.color
+    }
 
-        output
+    fn snap_vertices(&mut self, snap: &mut dyn FnMut(PrimitivePoint) -> PrimitivePoint) {
+        let original = self.path;
+        for vertex in self.path.iter_mut() {
+            *vertex = snap(*vertex);
+        }
+
+        if !self.is_valid() {
+            self.path = original;
+        }
     }
 
-    fn set_color_using(&mut self, image: &PrimitiveImage) {
-        self.color = image.target_average_color_in_shape(self);
+    fn mirror(&self, symmetry: Symmetry, width: u32, height: u32) -> Option<Box<dyn Shape>> {
+        let mut mirrored = *self;
+        for point in mirrored.path.iter_mut() {
+            *point = point.mirrored(symmetry, width, height);
+        }
+        Some(Box::new(mirrored))
+    }
+
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::Triangle
+    }
+
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap()
+    }
+
+    fn canonical_key(&self) -> u64 {
+        shape_key(self)
     }
 }
 
@@ -236,9 +361,60 @@ fn orient_2d(p0: PrimitivePoint, p1: PrimitivePoint, p2: PrimitivePoint) -> i32
     (p1.x - p0.x) * (p2.y - p0.y) - (p1.y - p0.y) * (p2.x - p0.x)
 }
 
+///
+/// A canvas that blends each pixel imageproc draws onto `image` once, however many times it's
+/// drawn, the same as drawing on a transparent layer and overlaying it but without the layer
+///
+struct BlendOnce<'a> {
+    image: &'a mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    drawn: HashSet<(u32, u32)>,
+}
+
+impl<'a> BlendOnce<'a> {
+    fn new(image: &'a mut ImageBuffer<Rgba<u8>, Vec<u8>>) -> Self {
+        BlendOnce {
+            image,
+            drawn: HashSet::new(),
+        }
+    }
+}
+
+impl Canvas for BlendOnce<'_> {
+    type Pixel = Rgba<u8>;
+
+    fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> Rgba<u8> {
+        *self.image.get_pixel(x, y)
+    }
+
+    fn draw_pixel(&mut self, x: u32, y: u32, color: Rgba<u8>) {
+        if self.drawn.insert((x, y)) {
+            self.image.get_pixel_mut(x, y).blend(&color);
+        }
+    }
+}
+
+///
+/// Returns the corners in an order imageproc can draw, which panics if the first and last are the
+/// same, or None if they're all the same point
+///
+fn drawable_points(p0: Point<i32>, p1: Point<i32>, p2: Point<i32>) -> Option<[Point<i32>; 3]> {
+    if p0 != p2 {
+        Some([p0, p1, p2])
+    } else if p0 != p1 {
+        Some([p1, p2, p0])
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utilities::get_rng;
 
     #[test]
     fn test_orient_2d() {
@@ -419,6 +595,25 @@ mod tests {
         assert_eq!(tri.get_pixels(), expected);
     }
 
+    #[test]
+    fn test_from_points() {
+        let tri = Triangle::from_points(
+            PrimitivePoint::new(0, 0),
+            PrimitivePoint::new(3, 0),
+            PrimitivePoint::new(0, 3),
+        )
+        .with_color(Rgba([240, 64, 15, 255]));
+
+        let expected =
+            "<polygon fill=\"#F0400F\" fill-opacity=\"1.00000\" points=\"0,0 6,0 0,6\" />";
+        assert_eq!(tri.as_svg(2.0).as_str(), expected);
+        assert_eq!(tri.get_pixels().len(), 10);
+
+        let painted = tri.paint_on(&ImageBuffer::from_pixel(4, 4, Rgba([0, 0, 0, 255])));
+        assert_eq!(*painted.get_pixel(1, 1), Rgba([240, 64, 15, 255]));
+        assert_eq!(*painted.get_pixel(3, 3), Rgba([0, 0, 0, 255]));
+    }
+
     #[test]
     fn test_bounding_box() {
         let p1 = PrimitivePoint::new(5, 5);
@@ -453,5 +648,38 @@ mod tests {
         };
         assert_eq!(tri.contains_pixel(4, 4), false);
         assert_eq!(tri.contains_pixel(1, 1), true);
+
+        // A degenerate triangle only covers the segment between its points, not the whole line
+        let tri = Triangle::from_points(p1, PrimitivePoint::new(2, 2), PrimitivePoint::new(4, 4));
+        assert!(tri.contains_pixel(3, 3));
+        assert!(!tri.contains_pixel(5, 5));
+    }
+
+    #[test]
+    fn test_random_around() {
+        let mut rng = get_rng(42);
+        let center = PrimitivePoint::new(20, 30);
+
+        for _ in 0..20 {
+            let tri = Triangle::random_around(center, 6, &mut rng);
+            assert!(tri.is_valid());
+            for vertex in tri.path.iter() {
+                assert!((vertex.x - center.x).abs() <= 6);
+                assert!((vertex.y - center.y).abs() <= 6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_area() {
+        let triangle = Triangle::new(vec![
+            PrimitivePoint::new(0, 0),
+            PrimitivePoint::new(60, 10),
+            PrimitivePoint::new(20, 50),
+        ]);
+        assert_eq!(triangle.area(), 1400.0);
+
+        let pixels = triangle.get_pixels().len() as f64;
+        assert!((triangle.area() - pixels).abs() / pixels < 0.05);
     }
 }
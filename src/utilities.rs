@@ -1,8 +1,11 @@
 use crate::point::PrimitivePoint;
-use image::Rgba;
+use image::{ImageBuffer, Pixel, Rgba};
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::cmp::{max, min};
+use std::collections::HashMap;
 use std::f64;
+use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Return the square root of x
@@ -53,6 +56,276 @@ pub fn rgb_to_hex(color: Rgba<u8>) -> String {
     format!("#{:02X}{:02X}{:02X}", data[0], data[1], data[2])
 }
 
+///
+/// (De)serialize an `Rgba<u8>` as its four channels, for use with `#[serde(with = "...")]`
+///
+pub mod rgba_serde {
+    use image::Rgba;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(color: &Rgba<u8>, serializer: S) -> Result<S::Ok, S::Error> {
+        color.0.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rgba<u8>, D::Error> {
+        <[u8; 4]>::deserialize(deserializer).map(Rgba)
+    }
+}
+
+///
+/// Widen a 1px line of `pixels` into a band `width` pixels wide by stamping a disc on each pixel
+///
+/// The result is sorted and contains no duplicates
+///
+pub fn thicken(pixels: Vec<PrimitivePoint>, width: u32) -> Vec<PrimitivePoint> {
+    let radius = width as f64 / 2.0;
+    let reach = radius.floor() as i32;
+
+    let mut offsets = vec![];
+    for dx in -reach..(reach + 1) {
+        for dy in -reach..(reach + 1) {
+            if ((dx * dx + dy * dy) as f64) <= radius * radius {
+                offsets.push((dx, dy));
+            }
+        }
+    }
+
+    let mut thick_pixels = Vec::with_capacity(pixels.len() * offsets.len());
+    for pixel in pixels {
+        for (dx, dy) in offsets.iter() {
+            thick_pixels.push(PrimitivePoint::new(pixel.x + dx, pixel.y + dy));
+        }
+    }
+
+    thick_pixels.sort_by(|a, b| a.x.cmp(&b.x).then(a.y.cmp(&b.y)));
+    thick_pixels.dedup();
+
+    thick_pixels
+}
+
+/// Number of subpixels per pixel (along each axis) used for anti-aliasing. Odd, so that every
+/// pixel has a center subpixel
+pub const SUPERSAMPLING: i32 = 3;
+
+/// Map a pixel to the center subpixel of its `SUPERSAMPLING` x `SUPERSAMPLING` block
+pub fn supersample_point(point: PrimitivePoint) -> PrimitivePoint {
+    PrimitivePoint::new(
+        point.x * SUPERSAMPLING + SUPERSAMPLING / 2,
+        point.y * SUPERSAMPLING + SUPERSAMPLING / 2,
+    )
+}
+
+///
+/// Collapse distinct `subpixels` back to pixels, with the fraction of each pixel that's covered
+///
+pub fn supersampled_coverage(subpixels: Vec<PrimitivePoint>) -> Vec<(PrimitivePoint, f64)> {
+    let mut counts: HashMap<PrimitivePoint, u32> = HashMap::new();
+
+    for subpixel in subpixels {
+        let pixel = PrimitivePoint::new(
+            subpixel.x.div_euclid(SUPERSAMPLING),
+            subpixel.y.div_euclid(SUPERSAMPLING),
+        );
+        *counts.entry(pixel).or_insert(0) += 1;
+    }
+
+    let subpixels_per_pixel = (SUPERSAMPLING * SUPERSAMPLING) as f64;
+    let mut coverage = counts
+        .into_iter()
+        .map(|(pixel, count)| (pixel, count as f64 / subpixels_per_pixel))
+        .collect::<Vec<_>>();
+
+    coverage.sort_by(|a, b| a.0.x.cmp(&b.0.x).then(a.0.y.cmp(&b.0.y)));
+
+    coverage
+}
+
+///
+/// Blend `color` onto each pixel in `coverage`, scaling its alpha by how much of the pixel is covered
+///
+pub fn blend_with_coverage(
+    image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    coverage: &[(PrimitivePoint, f64)],
+    color: Rgba<u8>,
+) {
+    let (width, height) = image.dimensions();
+
+    for (pixel, fraction) in coverage.iter() {
+        if pixel.x >= 0 && pixel.y >= 0 && pixel.x < width as i32 && pixel.y < height as i32 {
+            let mut partial_color = color;
+            partial_color[3] = (color[3] as f64 * fraction).round() as u8;
+
+            let pix = image.get_pixel_mut(pixel.x as u32, pixel.y as u32);
+            pix.blend(&partial_color);
+        }
+    }
+}
+
+///
+/// Limits on the number of line segments used to rasterize a bezier curve
+///
+/// By default the count comes from a heuristic based on the curve's length, clamped to
+/// [`min`, `max`]. Setting `fixed` forces an exact count instead. Either way, at least one
+/// segment is always used
+///
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Serialize, Deserialize)]
+pub struct SegmentLimits {
+    pub min: u32,
+    pub max: u32,
+    pub fixed: Option<u32>,
+}
+
+impl Default for SegmentLimits {
+    fn default() -> Self {
+        SegmentLimits {
+            min: 1,
+            max: 1000,
+            fixed: None,
+        }
+    }
+}
+
+impl SegmentLimits {
+    ///
+    /// Returns the number of segments to use for a curve whose length is at most `length_bound`
+    ///
+    /// This is always at least 1, even for curves with no length or a fixed count of 0, so the
+    /// curves' step along t (1 / segments) stays finite and they get at least one pixel
+    ///
+    pub fn segments_for(&self, length_bound: f32) -> u32 {
+        let segments = match self.fixed {
+            Some(fixed) => fixed,
+            // Use hyperbola function to give shorter curves a bias in number of line segments.
+            None => {
+                let heuristic = ((length_bound.powi(2) + 800.0).sqrt() / 8.0) as u32;
+                max(self.min, min(self.max, heuristic))
+            }
+        };
+
+        max(segments, 1)
+    }
+}
+
+///
+/// Optional bounds on how big shapes are, as fractions of the image's largest dimension
+///
+/// A shape's size is how wide it is: the sides of rectangles, the diameters of ellipses, hearts,
+/// and teardrops' rounded ends, and the largest side of the box around the points of triangles
+/// and curves. Unset bounds leave each shape's own defaults
+///
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct SizeLimits {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl SizeLimits {
+    ///
+    /// Panics unless the bounds are greater than 0 and at most 1, with `min` less than `max`
+    ///
+    pub fn new(min: Option<f64>, max: Option<f64>) -> SizeLimits {
+        SizeLimits::try_new(min, max).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    ///
+    /// Like `new`, but returns why the bounds are invalid instead of panicking
+    ///
+    pub fn try_new(min: Option<f64>, max: Option<f64>) -> Result<SizeLimits, String> {
+        for fraction in [min, max].iter().flatten() {
+            if *fraction <= 0.0 || *fraction > 1.0 {
+                return Err(format!(
+                    "Shape sizes must be between 0 and 1, got {}",
+                    fraction
+                ));
+            }
+        }
+        if let (Some(min), Some(max)) = (min, max) {
+            if min >= max {
+                return Err(format!(
+                    "The minimum shape size ({}) must be less than the maximum ({})",
+                    min, max
+                ));
+            }
+        }
+
+        Ok(SizeLimits { min, max })
+    }
+
+    ///
+    /// Returns the inclusive range of sizes, in pixels, for a `width` x `height` image
+    ///
+    /// Unset bounds use `default_min` and `default_max`. The range is never empty, and never
+    /// includes 0
+    ///
+    pub fn pixel_range(
+        &self,
+        width: u32,
+        height: u32,
+        default_min: u32,
+        default_max: u32,
+    ) -> (u32, u32) {
+        self.range(width, height, 1.0, default_min, default_max)
+    }
+
+    ///
+    /// Same as `pixel_range`, but for half sizes (radii), for shapes sized by them
+    ///
+    pub fn radius_range(
+        &self,
+        width: u32,
+        height: u32,
+        default_min: u32,
+        default_max: u32,
+    ) -> (u32, u32) {
+        self.range(width, height, 2.0, default_min, default_max)
+    }
+
+    ///
+    /// Determine if a shape `size` pixels wide is within the limits. Unset bounds allow anything
+    ///
+    pub fn allows(&self, size: u32, width: u32, height: u32) -> bool {
+        let (min_size, max_size) = self.pixel_range(width, height, 0, u32::MAX);
+
+        (self.min.is_none() || size >= min_size) && (self.max.is_none() || size <= max_size)
+    }
+
+    fn range(
+        &self,
+        width: u32,
+        height: u32,
+        divisor: f64,
+        default_min: u32,
+        default_max: u32,
+    ) -> (u32, u32) {
+        let largest = max(width, height) as f64 / divisor;
+        let min_size = self
+            .min
+            .map_or(default_min, |min| (min * largest).ceil() as u32);
+        let max_size = self
+            .max
+            .map_or(default_max, |max| (max * largest).floor() as u32);
+
+        let min_size = max(min_size, 1);
+        (min_size, max(max_size, min_size))
+    }
+}
+
+/// Returns the size of shapes defined by `points`, for `SizeLimits`: the largest side of the box
+/// around them
+pub fn points_size(points: &[PrimitivePoint]) -> u32 {
+    let extent = |coordinate: fn(&PrimitivePoint) -> i32| {
+        let values = points.iter().map(coordinate);
+        values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+    };
+
+    max(extent(|p| p.x), extent(|p| p.y)) as u32
+}
+
+/// Returns the stroke width used by shapes that don't specify one
+pub fn default_stroke_width() -> u32 {
+    1
+}
+
 pub fn rotate_point(point: &mut PrimitivePoint, center: PrimitivePoint, angle: u32) {
     let cos_a = radians(angle as f64).cos();
     let sin_a = radians(angle as f64).sin();
@@ -67,6 +340,250 @@ pub fn rotate_point(point: &mut PrimitivePoint, center: PrimitivePoint, angle: u
     point.y = new_y as i32 + center.y;
 }
 
+///
+/// Convert a pixel to full range ITU-R BT.601 Y'CbCr (as used by JPEG), ignoring alpha
+///
+/// Luma is from 0 to 255, and both chroma channels are centered on 128
+///
+pub fn rgb_to_ycbcr(pixel: &Rgba<u8>) -> [f64; 3] {
+    let r = pixel[0] as f64;
+    let g = pixel[1] as f64;
+    let b = pixel[2] as f64;
+
+    [
+        0.299 * r + 0.587 * g + 0.114 * b,
+        128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b,
+        128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b,
+    ]
+}
+
+///
+/// Tone map a high dynamic range image (e.g. OpenEXR or Radiance HDR) with linear channels to
+/// 8-bit sRGB, using the Reinhard operator
+///
+/// Each color channel c becomes c / (1 + c), which is then sRGB encoded. Alpha is only clamped
+///
+pub fn tone_map(image: &ImageBuffer<Rgba<f32>, Vec<f32>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let to_srgb = |linear: f32| -> u8 {
+        let linear = linear.max(0.0);
+        let mapped = linear / (1.0 + linear);
+        let encoded = if mapped <= 0.0031308 {
+            12.92 * mapped
+        } else {
+            1.055 * mapped.powf(1.0 / 2.4) - 0.055
+        };
+
+        (encoded * 255.0).round() as u8
+    };
+
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y);
+        Rgba([
+            to_srgb(pixel[0]),
+            to_srgb(pixel[1]),
+            to_srgb(pixel[2]),
+            (pixel[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+        ])
+    })
+}
+
+/// A 4x4 Bayer matrix, for ordered dithering
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+///
+/// Convert a 16-bit image to 8 bits with ordered (Bayer) dithering
+///
+/// Rounding each channel would turn smooth 16-bit gradients into flat bands, so each pixel is
+/// rounded up or down by a threshold that varies over a 4x4 tile, keeping the local average.
+/// Levels that are exactly representable in 8 bits (multiples of 257) are unchanged
+///
+pub fn dither_to_rgba8(image: &ImageBuffer<Rgba<u16>, Vec<u16>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let threshold = (BAYER_4X4[(y % 4) as usize][(x % 4) as usize] as f64 + 0.5) / 16.0;
+        let pixel = image.get_pixel(x, y);
+
+        Rgba(pixel.0.map(|channel| {
+            let scaled = channel as f64 / 257.0;
+            (scaled + threshold).floor().min(255.0) as u8
+        }))
+    })
+}
+
+/// Fraction of pixels at each end of the histogram that `stretch_levels` clips, so a few stray
+/// pixels don't keep the rest from being stretched
+const LEVELS_CLIP: f64 = 0.005;
+
+///
+/// How `stretch_levels` finds the range to stretch to 0-255
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AutoLevels {
+    /// Stretch red, green, and blue separately, which also removes color casts
+    Rgb,
+    /// Stretch every channel the same amount, from the range of the luma, keeping colors' balance
+    Luma,
+}
+
+impl FromStr for AutoLevels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "rgb" => Ok(AutoLevels::Rgb),
+            "luma" => Ok(AutoLevels::Luma),
+            _ => Err(format!("Unsupported auto levels: {}", s)),
+        }
+    }
+}
+
+///
+/// Returns `image` with its histogram stretched to the full 0-255 range, for underexposed or flat
+/// sources
+///
+/// The darkest and brightest `LEVELS_CLIP` of the visible pixels are clipped to 0 and 255, and
+/// everything between is stretched linearly. Alpha is unchanged, and channels that are a single
+/// value are left alone
+///
+pub fn stretch_levels(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    auto_levels: AutoLevels,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let visible: Vec<&Rgba<u8>> = image.pixels().filter(|pixel| pixel[3] > 0).collect();
+    let ranges = match auto_levels {
+        AutoLevels::Rgb => [0, 1, 2].map(|c| histogram_range(visible.iter().map(|p| p[c]))),
+        AutoLevels::Luma => [histogram_range(visible.iter().map(|p| p.to_luma()[0])); 3],
+    };
+
+    let mut stretched = image.clone();
+    for pixel in stretched.pixels_mut() {
+        for (channel, &(low, high)) in ranges.iter().enumerate() {
+            if high > low {
+                let value = (pixel[channel] as f64 - low as f64) * 255.0 / (high - low) as f64;
+                pixel[channel] = value.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+
+    stretched
+}
+
+/// Returns the lowest and highest of `values`, ignoring up to `LEVELS_CLIP` of them at each end
+fn histogram_range(values: impl Iterator<Item = u8>) -> (u8, u8) {
+    let mut histogram = [0usize; 256];
+    let mut count = 0;
+    for value in values {
+        histogram[value as usize] += 1;
+        count += 1;
+    }
+
+    let clip = (count as f64 * LEVELS_CLIP) as usize;
+    (
+        past_clip(&histogram, clip, 0..256),
+        past_clip(&histogram, clip, (0..256).rev()),
+    )
+}
+
+/// Returns the first of `levels` where more than `clip` values in `histogram` have been passed
+fn past_clip(histogram: &[usize; 256], clip: usize, levels: impl Iterator<Item = usize>) -> u8 {
+    let mut passed = 0;
+    for level in levels {
+        passed += histogram[level];
+        if passed > clip {
+            return level as u8;
+        }
+    }
+
+    0
+}
+
+///
+/// Returns the pixels inside the polygon `outline`, whose points are in image coordinates
+///
+pub fn fill_polygon(outline: &[(f64, f64)]) -> Vec<PrimitivePoint> {
+    let min_x = outline.iter().map(|p| p.0).fold(f64::MAX, f64::min).floor() as i32;
+    let min_y = outline.iter().map(|p| p.1).fold(f64::MAX, f64::min).floor() as i32;
+    let max_x = outline.iter().map(|p| p.0).fold(f64::MIN, f64::max).ceil() as i32;
+    let max_y = outline.iter().map(|p| p.1).fold(f64::MIN, f64::max).ceil() as i32;
+
+    let mut pixels = vec![];
+
+    for x in min_x..(max_x + 1) {
+        for y in min_y..(max_y + 1) {
+            if polygon_contains(outline, x as f64, y as f64) {
+                pixels.push(PrimitivePoint::new(x, y));
+            }
+        }
+    }
+
+    pixels
+}
+
+///
+/// Determine if (`x`, `y`) is inside the polygon `outline`, using ray casting
+///
+pub fn polygon_contains(outline: &[(f64, f64)], x: f64, y: f64) -> bool {
+    let mut inside = false;
+
+    let mut j = outline.len() - 1;
+    for i in 0..outline.len() {
+        let (xi, yi) = outline[i];
+        let (xj, yj) = outline[j];
+
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+
+        j = i;
+    }
+
+    inside
+}
+
+///
+/// The patterns `make_test_image` can draw
+///
+#[cfg(test)]
+#[derive(Debug, Copy, Clone)]
+pub enum TestPattern {
+    /// Every pixel is this color
+    Solid(Rgba<u8>),
+    /// Red increases left to right, green top to bottom, and blue along the diagonal
+    Gradient,
+    /// Squares `size` pixels wide, alternating between the two colors, starting with the first
+    Checkerboard(u32, Rgba<u8>, Rgba<u8>),
+}
+
+///
+/// Returns an opaque `width` x `height` image of `pattern`, for tests
+///
+#[cfg(test)]
+pub fn make_test_image(
+    width: u32,
+    height: u32,
+    pattern: TestPattern,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    // Spread each gradient over the full range, without dividing by 0 for 1 pixel wide images
+    let x_span = max(width - 1, 1) as f64;
+    let y_span = max(height - 1, 1) as f64;
+
+    ImageBuffer::from_fn(width, height, |x, y| match pattern {
+        TestPattern::Solid(color) => color,
+        TestPattern::Gradient => Rgba([
+            (x as f64 / x_span * 255.0).round() as u8,
+            (y as f64 / y_span * 255.0).round() as u8,
+            ((x as f64 / x_span + y as f64 / y_span) / 2.0 * 255.0).round() as u8,
+            255,
+        ]),
+        TestPattern::Checkerboard(size, first, second) => {
+            if (x / size + y / size) % 2 == 0 {
+                first
+            } else {
+                second
+            }
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -88,6 +605,35 @@ mod tests {
         assert_eq!(clamp(30, -10, 20), 20);
     }
 
+    #[test]
+    fn test_stretch_levels() {
+        // A dim, low contrast gradient, with one stray pixel too few to count
+        let mut image = ImageBuffer::from_fn(100, 10, |x, _| {
+            Rgba([100 + x as u8 / 2, 40 + x as u8 / 4, 60, 255])
+        });
+        image.put_pixel(0, 0, Rgba([0, 0, 0, 255]));
+
+        let stretched = stretch_levels(&image, AutoLevels::Rgb);
+        let range = |c: usize| {
+            let values = stretched.pixels().skip(1).map(|p| p[c]);
+            (values.clone().min().unwrap(), values.max().unwrap())
+        };
+        assert_eq!(range(0), (0, 255));
+        assert_eq!(range(1), (0, 255));
+        // Blue is a single value, so it's left alone
+        assert_eq!(range(2), (60, 60));
+        assert_eq!(stretched.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+
+        // Stretching the luma keeps red brighter than green
+        let stretched = stretch_levels(&image, AutoLevels::Luma);
+        assert!(stretched.pixels().all(|p| p[0] >= p[1]));
+        assert_eq!(stretched.pixels().map(|p| p[0]).max(), Some(255));
+        assert!(stretched.pixels().skip(1).map(|p| p[2]).max() < Some(60));
+
+        assert_eq!(AutoLevels::from_str("LUMA"), Ok(AutoLevels::Luma));
+        assert!(AutoLevels::from_str("hsv").is_err());
+    }
+
     #[test]
     fn test_rgb_to_hex() {
         let result = rgb_to_hex(Rgba([0, 0, 0, 0]));
@@ -103,6 +649,145 @@ mod tests {
         assert_eq!(test, "#000C00");
     }
 
+    #[test]
+    fn test_thicken() {
+        let line = vec![PrimitivePoint::new(0, 0), PrimitivePoint::new(0, 0)];
+        assert_eq!(thicken(line.clone(), 1), vec![PrimitivePoint::new(0, 0)]);
+
+        let expected = vec![
+            PrimitivePoint::new(-1, 0),
+            PrimitivePoint::new(0, -1),
+            PrimitivePoint::new(0, 0),
+            PrimitivePoint::new(0, 1),
+            PrimitivePoint::new(1, 0),
+        ];
+        assert_eq!(thicken(line, 2), expected);
+    }
+
+    #[test]
+    fn test_supersampled_coverage() {
+        // A full block, plus a single subpixel of the pixel to the left
+        let mut subpixels = vec![PrimitivePoint::new(-1, 0)];
+        for x in 0..SUPERSAMPLING {
+            for y in 0..SUPERSAMPLING {
+                subpixels.push(PrimitivePoint::new(x, y));
+            }
+        }
+
+        let expected = vec![
+            (
+                PrimitivePoint::new(-1, 0),
+                1.0 / (SUPERSAMPLING * SUPERSAMPLING) as f64,
+            ),
+            (PrimitivePoint::new(0, 0), 1.0),
+        ];
+        assert_eq!(supersampled_coverage(subpixels), expected);
+    }
+
+    #[test]
+    fn test_segments_for() {
+        let limits = SegmentLimits::default();
+        assert_eq!(limits.segments_for(0.0), 3);
+        assert_eq!(limits.segments_for(80.0), 10);
+
+        let limits = SegmentLimits {
+            min: 5,
+            max: 8,
+            fixed: None,
+        };
+        assert_eq!(limits.segments_for(0.0), 5);
+        assert_eq!(limits.segments_for(80.0), 8);
+
+        let limits = SegmentLimits {
+            min: 5,
+            max: 8,
+            fixed: Some(20),
+        };
+        assert_eq!(limits.segments_for(0.0), 20);
+
+        // Never 0 segments, even if asked for
+        let limits = SegmentLimits {
+            min: 0,
+            max: 0,
+            fixed: None,
+        };
+        assert_eq!(limits.segments_for(0.0), 1);
+        let limits = SegmentLimits {
+            min: 0,
+            max: 0,
+            fixed: Some(0),
+        };
+        assert_eq!(limits.segments_for(0.0), 1);
+    }
+
+    #[test]
+    fn test_tone_map() {
+        let hdr = ImageBuffer::from_fn(4, 1, |x, _| {
+            let value = [0.0, 0.2, 1.0, 50.0][x as usize];
+            Rgba([value, value, value, 1.0])
+        });
+        let mapped = tone_map(&hdr);
+
+        assert_eq!(*mapped.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+        // 1.0 maps to half brightness (in linear light), not white
+        assert_eq!(mapped.get_pixel(2, 0)[0], 188);
+        // Values far above 1.0 are still distinguishable, and nothing overflows
+        assert!(mapped.get_pixel(1, 0)[0] < mapped.get_pixel(2, 0)[0]);
+        assert!(mapped.get_pixel(2, 0)[0] < mapped.get_pixel(3, 0)[0]);
+        assert!(mapped.get_pixel(3, 0)[0] < 255);
+    }
+
+    #[test]
+    fn test_dither_to_rgba8() {
+        // Exact 8-bit levels stay the same
+        let exact =
+            ImageBuffer::from_fn(4, 4, |x, y| Rgba([0, 257 * (x + y) as u16, 65535, 65535]));
+        let dithered = dither_to_rgba8(&exact);
+        for (x, y, pixel) in dithered.enumerate_pixels() {
+            assert_eq!(*pixel, Rgba([0, (x + y) as u8, 255, 255]));
+        }
+
+        // A quarter of the way between two levels, a quarter of each tile rounds up
+        let between = ImageBuffer::from_pixel(4, 4, Rgba([257 * 100 + 64, 0, 0, 65535]));
+        let dithered = dither_to_rgba8(&between);
+        assert_eq!(dithered.pixels().filter(|pixel| pixel[0] == 101).count(), 4);
+        assert_eq!(
+            dithered.pixels().filter(|pixel| pixel[0] == 100).count(),
+            12
+        );
+    }
+
+    #[test]
+    fn test_rgb_to_ycbcr() {
+        let close = |a: [f64; 3], b: [f64; 3]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 0.01);
+
+        assert!(close(
+            rgb_to_ycbcr(&Rgba([0, 0, 0, 255])),
+            [0.0, 128.0, 128.0]
+        ));
+        assert!(close(
+            rgb_to_ycbcr(&Rgba([255, 255, 255, 0])),
+            [255.0, 128.0, 128.0]
+        ));
+        assert!(close(
+            rgb_to_ycbcr(&Rgba([255, 0, 0, 255])),
+            [76.245, 84.972, 255.5]
+        ));
+    }
+
+    #[test]
+    fn test_fill_polygon() {
+        let square = [(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)];
+        let pixels = fill_polygon(&square);
+        assert!(pixels.contains(&PrimitivePoint::new(2, 2)));
+        assert!(!pixels.contains(&PrimitivePoint::new(5, 2)));
+
+        let triangle = [(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)];
+        let pixels = fill_polygon(&triangle);
+        assert!(pixels.contains(&PrimitivePoint::new(2, 2)));
+        assert!(!pixels.contains(&PrimitivePoint::new(8, 8)));
+    }
+
     #[test]
     fn test_rotate_point() {
         let mut p = PrimitivePoint::new(20, 10);
@@ -119,4 +804,93 @@ mod tests {
         rotate_point(&mut p, center, angle);
         assert_eq!(p, expected);
     }
+
+    #[test]
+    fn test_make_test_image() {
+        let black = Rgba([0, 0, 0, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+
+        let checkerboard = make_test_image(6, 4, TestPattern::Checkerboard(2, black, white));
+        assert_eq!(checkerboard.dimensions(), (6, 4));
+        for (x, y, pixel) in checkerboard.enumerate_pixels() {
+            let expected = if (x / 2 + y / 2) % 2 == 0 {
+                black
+            } else {
+                white
+            };
+            assert_eq!(*pixel, expected);
+        }
+        assert_eq!(*checkerboard.get_pixel(1, 1), black);
+        assert_eq!(*checkerboard.get_pixel(2, 1), white);
+        assert_eq!(*checkerboard.get_pixel(2, 2), black);
+        assert_eq!(*checkerboard.get_pixel(5, 3), white);
+
+        let gradient = make_test_image(5, 3, TestPattern::Gradient);
+        assert_eq!(*gradient.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+        assert_eq!(*gradient.get_pixel(4, 2), Rgba([255, 255, 255, 255]));
+        assert_eq!(*gradient.get_pixel(2, 0), Rgba([128, 0, 64, 255]));
+
+        // Tiny images don't divide by 0
+        let single = make_test_image(1, 1, TestPattern::Gradient);
+        assert_eq!(*single.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+
+        assert_eq!(
+            make_test_image(3, 3, TestPattern::Solid(white)),
+            ImageBuffer::from_pixel(3, 3, white)
+        );
+    }
+
+    #[test]
+    fn test_points_size() {
+        let points = [
+            PrimitivePoint::new(3, 10),
+            PrimitivePoint::new(8, 4),
+            PrimitivePoint::new(5, 12),
+        ];
+        assert_eq!(points_size(&points), 8);
+        assert_eq!(points_size(&points[..1]), 0);
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let unset = SizeLimits::default();
+        assert_eq!(unset.pixel_range(100, 50, 5, 49), (5, 49));
+        assert!(unset.allows(0, 100, 50));
+        assert!(unset.allows(1000, 100, 50));
+
+        let limits = SizeLimits::new(Some(0.1), Some(0.25));
+        assert_eq!(limits.pixel_range(100, 50, 5, 49), (10, 25));
+        assert_eq!(limits.radius_range(100, 50, 1, 10), (5, 12));
+        assert!(limits.allows(10, 100, 50));
+        assert!(!limits.allows(9, 100, 50));
+        assert!(!limits.allows(26, 100, 50));
+
+        // Only the set bound replaces the default
+        let max_only = SizeLimits::new(None, Some(0.5));
+        assert_eq!(max_only.pixel_range(100, 50, 5, 80), (5, 50));
+
+        // Tiny images still get a non-empty range without 0
+        assert_eq!(limits.pixel_range(2, 2, 5, 49), (1, 1));
+        assert_eq!(max_only.pixel_range(4, 4, 5, 80), (5, 5));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_size_limits_min_above_max() {
+        SizeLimits::new(Some(0.5), Some(0.2));
+    }
+
+    #[test]
+    fn test_size_limits_try_new() {
+        assert_eq!(
+            SizeLimits::try_new(Some(0.1), None),
+            Ok(SizeLimits {
+                min: Some(0.1),
+                max: None
+            })
+        );
+        assert!(SizeLimits::try_new(Some(0.9), Some(0.1)).is_err());
+        assert!(SizeLimits::try_new(None, Some(1.5)).is_err());
+        assert!(SizeLimits::try_new(Some(0.0), None).is_err());
+    }
 }
@@ -0,0 +1,31 @@
+use image::{ImageBuffer, Rgba};
+use primitive_image::primitive_image::PrimitiveImage;
+use primitive_image::runner::run;
+use std::env::temp_dir;
+use std::fs::remove_file;
+
+#[test]
+fn test_append() {
+    let target = ImageBuffer::from_fn(40, 30, |x, y| {
+        Rgba([(x * 6) as u8, (y * 8) as u8, 128, 255])
+    });
+    let mut image = PrimitiveImage::from_image(target.clone(), 0, None);
+
+    run(&mut image, 5, 20, 42, "MIXED".parse().unwrap());
+
+    let path = temp_dir().join("primitive_image_test_append.json");
+    image.save_to(path.clone());
+
+    let mut appended = PrimitiveImage::from_image(target, 0, None);
+    appended.append_shapes_json(path.clone());
+    remove_file(&path).unwrap();
+
+    // Replaying the shapes reproduces the approximation exactly
+    let loaded_score = appended.score();
+    assert_eq!(loaded_score, image.score());
+
+    run(&mut appended, 3, 20, 7, "MIXED".parse().unwrap());
+
+    assert_eq!(appended.color_stats().num_shapes, 8);
+    assert!(appended.score() <= loaded_score);
+}
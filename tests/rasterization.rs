@@ -0,0 +1,280 @@
+use image::{ImageBuffer, Rgba};
+use primitive_image::bezier_path::{BezierPath, CubicSegment};
+use primitive_image::cross::Cross;
+use primitive_image::cubic_bezier::CubicBezier;
+use primitive_image::ellipse::Ellipse;
+use primitive_image::heart::Heart;
+use primitive_image::mutate_mode::MutateMode;
+use primitive_image::point::PrimitivePoint;
+use primitive_image::quadratic_bezier::QuadraticBezier;
+use primitive_image::rectangle::Rectangle;
+use primitive_image::shape::{RandomShape, Shape};
+use primitive_image::teardrop::Teardrop;
+use primitive_image::triangle::Triangle;
+use primitive_image::utilities::{get_rng, SizeLimits};
+use std::collections::HashSet;
+use std::f64::consts::SQRT_2;
+
+const WIDTH: u32 = 60;
+const HEIGHT: u32 = 40;
+/// Seeds to generate shapes from, for each type
+const SEEDS: u64 = 50;
+/// Mutations to check after generating each shape
+const MUTATIONS: u32 = 10;
+/// How far a shape's pixel count may be from its area, in multiples of the square root of the
+/// area, since rasterizing only loses or gains pixels along the edges
+const AREA_TOLERANCE: f64 = 3.0;
+/// How far around a shape's pixels to check that `contains_pixel` is false
+const CONTAINS_MARGIN: i32 = 3;
+/// How far past the image a shape's points (vertices and centers) can be: `PrimitivePoint::mutate`
+/// keeps them within 5 pixels outside, and the last pixel is one in from the edge
+const POINT_SLACK: f64 = 6.0;
+/// The image's longest side, which bounds the largest shapes
+const LONGEST: f64 = 60.0;
+/// Pixels shapes that can be feathered are feathered over, for `check_feather`
+const FEATHER: u32 = 2;
+
+/// Check every invariant for a shape, and paint it on images of a few sizes
+fn check_shape(shape: &dyn Shape, analytic_area: bool) {
+    let pixels = shape.get_pixels();
+
+    check_contains_pixel(shape, &pixels);
+    check_feather(shape);
+
+    let area = shape.area();
+    assert!(area >= 0.0, "{:?} has an area of {}", shape, area);
+    if analytic_area {
+        let difference = (pixels.len() as f64 - area).abs();
+        assert!(
+            difference <= AREA_TOLERANCE * (area.sqrt() + 1.0),
+            "{:?} has {} pixels, but an area of {}",
+            shape,
+            pixels.len(),
+            area
+        );
+    }
+
+    for (width, height) in [(1, 1), (WIDTH, HEIGHT)] {
+        let image = ImageBuffer::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+        assert_eq!(shape.paint_on(&image).dimensions(), (width, height));
+        for scale in [0.5, 2.0] {
+            assert_eq!(
+                shape.scaled_paint_on(&image, scale).dimensions(),
+                (width, height)
+            );
+        }
+    }
+}
+
+/// Check that `contains_pixel` is true for exactly the shape's pixels, around where they are
+fn check_contains_pixel(shape: &dyn Shape, pixels: &[PrimitivePoint]) {
+    let pixels: HashSet<PrimitivePoint> = pixels.iter().copied().collect();
+    let min_x = pixels.iter().map(|p| p.x).min().unwrap_or(0) - CONTAINS_MARGIN;
+    let min_y = pixels.iter().map(|p| p.y).min().unwrap_or(0) - CONTAINS_MARGIN;
+    let max_x = pixels.iter().map(|p| p.x).max().unwrap_or(0) + CONTAINS_MARGIN;
+    let max_y = pixels.iter().map(|p| p.y).max().unwrap_or(0) + CONTAINS_MARGIN;
+
+    for x in min_x..=max_x {
+        for y in min_y..=max_y {
+            assert_eq!(
+                shape.contains_pixel(x, y),
+                pixels.contains(&PrimitivePoint::new(x, y)),
+                "{:?} disagrees with its pixels about ({}, {})",
+                shape,
+                x,
+                y
+            );
+        }
+    }
+}
+
+///
+/// Check that feathering the shape, if it can be, fades the ends of each of its rows while pixels
+/// well inside look the same as without feathering
+///
+fn check_feather(shape: &dyn Shape) {
+    let mut feathered = shape.clone_box();
+    feathered.set_feather(FEATHER);
+    if feathered.feather() == 0 {
+        return;
+    }
+
+    let clear = ImageBuffer::from_pixel(WIDTH, HEIGHT, Rgba([0, 0, 0, 0]));
+    let hard = shape.paint_on(&clear);
+    let soft = feathered.paint_on(&clear);
+    let alphas = |x: i32, y: i32| {
+        (
+            soft.get_pixel(x as u32, y as u32)[3],
+            hard.get_pixel(x as u32, y as u32)[3],
+        )
+    };
+    // `paint_on` skips the first row and column
+    let painted = |p: &PrimitivePoint| p.x > 0 && p.y > 0;
+
+    // The ends of each row are on the edge, unless they're off the painted part of the image
+    let pixels = feathered.get_pixels();
+    for y in 1..HEIGHT as i32 {
+        let row = pixels.iter().filter(|p| p.y == y);
+        for x in [row.clone().map(|p| p.x).min(), row.map(|p| p.x).max()]
+            .into_iter()
+            .flatten()
+            .filter(|&x| x > 0 && x < WIDTH as i32)
+        {
+            let (soft, hard) = alphas(x, y);
+            assert!(
+                soft < hard,
+                "{:?} doesn't fade at ({}, {})",
+                feathered,
+                x,
+                y
+            );
+        }
+    }
+
+    let pixels = feathered.clipped_pixels(WIDTH, HEIGHT);
+    let distance = FEATHER as i32 + 1;
+    for pixel in pixels.iter().filter(|p| painted(p)) {
+        let surrounded = (-distance..=distance).all(|dx| {
+            (-distance..=distance).all(|dy| feathered.contains_pixel(pixel.x + dx, pixel.y + dy))
+        });
+        let (soft, hard) = alphas(pixel.x, pixel.y);
+        assert!(soft <= hard);
+        if surrounded {
+            assert_eq!(soft, hard, "{:?} fades inside at {:?}", feathered, pixel);
+        }
+    }
+
+    // SVGs blur it instead, so it can't be merged into a path
+    assert!(feathered
+        .as_svg(1.0)
+        .contains(&format!(" filter=\"url(#feather-{})\"", FEATHER)));
+    assert_eq!(feathered.as_svg_path_data(1.0), None);
+}
+
+/// Check that all of the shape's pixels are within `reach` (plus `POINT_SLACK`) of the image
+fn check_bounds(shape: &dyn Shape, reach: f64) {
+    let slack = (POINT_SLACK + reach).ceil() as i32;
+
+    for pixel in shape.get_pixels() {
+        assert!(
+            (-slack..WIDTH as i32 + slack).contains(&pixel.x)
+                && (-slack..HEIGHT as i32 + slack).contains(&pixel.y),
+            "{:?} has a pixel at ({}, {}), more than {} pixels off of the image",
+            shape,
+            pixel.x,
+            pixel.y,
+            slack
+        );
+    }
+}
+
+///
+/// Check random shapes of type `T` generated with no border extension, and the shapes mutating
+/// them leads to
+///
+/// `reach` is the furthest the type's pixels can be from its points (its center, or 0 for shapes
+/// made of vertices) with the default size limits
+///
+fn check_random<T>(analytic_area: bool, reach: f64)
+where
+    T: RandomShape + Shape + Clone + 'static,
+{
+    for seed in 1..=SEEDS {
+        let mut rng = get_rng(seed);
+        let mut shape = T::random(WIDTH, HEIGHT, 0, SizeLimits::default(), &mut rng);
+        check_bounds(&shape, reach);
+        check_shape(&shape, analytic_area);
+
+        for i in 0..MUTATIONS {
+            let mode = if i % 2 == 0 {
+                MutateMode::Single
+            } else {
+                MutateMode::Multi
+            };
+            shape.mutate(WIDTH, HEIGHT, SizeLimits::default(), mode, &mut rng);
+            check_bounds(&shape, reach);
+            check_shape(&shape, analytic_area);
+        }
+    }
+}
+
+#[test]
+fn test_random_triangles() {
+    check_random::<Triangle>(true, 0.0);
+}
+
+#[test]
+fn test_random_rectangles() {
+    // Half the diagonal of the largest square
+    check_random::<Rectangle>(true, LONGEST * SQRT_2 / 2.0);
+}
+
+#[test]
+fn test_random_ellipses() {
+    // Without a maximum size, radii are under a tenth of the image's side
+    check_random::<Ellipse>(true, LONGEST / 10.0);
+}
+
+#[test]
+fn test_random_hearts() {
+    // The bottom tip is 17/16 of the size from the center, and the size is at most half the side
+    check_random::<Heart>(true, 17.0 / 16.0 * LONGEST / 2.0);
+}
+
+#[test]
+fn test_random_teardrops() {
+    // The tip is the radius (at most a quarter of the side) times the elongation (at most 4)
+    check_random::<Teardrop>(true, LONGEST / 4.0 * 4.0);
+}
+
+#[test]
+fn test_random_crosses() {
+    // The corner of an arm at most a quarter of the side long, and as thick as it is long
+    check_random::<Cross>(true, LONGEST / 4.0 * SQRT_2);
+}
+
+#[test]
+fn test_random_curves() {
+    // Curves stay within their control points, and the default stroke is one pixel
+    check_random::<QuadraticBezier>(false, 0.0);
+    check_random::<CubicBezier>(false, 0.0);
+    check_random::<BezierPath>(false, 0.0);
+}
+
+#[test]
+fn test_degenerate_shapes() {
+    let point = PrimitivePoint::new(5, 5);
+    let off_image = PrimitivePoint::new(-100, 200);
+
+    let shapes: Vec<Box<dyn Shape>> = vec![
+        Box::new(Triangle::from_points(point, point, point)),
+        Box::new(Triangle::from_points(
+            point,
+            PrimitivePoint::new(10, 10),
+            PrimitivePoint::new(15, 15),
+        )),
+        Box::new(Triangle::from_points(off_image, off_image, point)),
+        Box::new(Rectangle::new(point, 0, 0, 0)),
+        Box::new(Rectangle::new(off_image, 3, 3, 45)),
+        Box::new(Ellipse::new(point, 0, 0, 0)),
+        Box::new(Ellipse::new(off_image, 4, 2, 30)),
+        Box::new(Heart::new(point, 0, 0)),
+        Box::new(Teardrop::new(point, 0, 1.5, 0)),
+        Box::new(Cross::new(point, 0, 0, 0)),
+        Box::new(QuadraticBezier::new(point, point, point)),
+        Box::new(CubicBezier::new(point, point, point, point)),
+        Box::new(CubicBezier::new(off_image, off_image, off_image, off_image)),
+        Box::new(BezierPath::new(
+            point,
+            vec![CubicSegment {
+                control1: point,
+                control2: point,
+                end: point,
+            }],
+        )),
+    ];
+
+    for shape in shapes.iter() {
+        check_shape(&**shape, false);
+    }
+}
@@ -0,0 +1,24 @@
+use image::{ImageBuffer, Rgba};
+use primitive_image::primitive_image::PrimitiveImage;
+use primitive_image::runner::run;
+use std::env::temp_dir;
+use std::fs::remove_file;
+
+#[test]
+fn test_generate_then_render() {
+    let target = ImageBuffer::from_fn(40, 30, |x, y| {
+        Rgba([(x * 6) as u8, (y * 8) as u8, 128, 255])
+    });
+    let mut image = PrimitiveImage::from_image(target, 0, None);
+
+    run(&mut image, 5, 20, 42, "MIXED".parse().unwrap());
+
+    let path = temp_dir().join("primitive_image_test_generate_then_render.json");
+    image.save_to(path.clone());
+    let loaded = PrimitiveImage::load_shapes_json(path.clone());
+    remove_file(&path).unwrap();
+
+    // Rendering the loaded shapes matches rendering the generated ones, at any size
+    assert_eq!(loaded.render_at(40, 30), image.render_at(40, 30));
+    assert_eq!(loaded.render_at(80, 60), image.render_at(80, 60));
+}